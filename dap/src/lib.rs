@@ -0,0 +1,523 @@
+//! Debug Adapter Protocol server for Dyon.
+//!
+//! Messages are read and written with `Content-Length` headers,
+//! see https://microsoft.github.io/debug-adapter-protocol/.
+//!
+//! The script runs on its own thread with a debugger attached.
+//! While the script is paused, requests that need the runtime,
+//! such as `stackTrace`, `scopes` and `variables`, are handled by the script thread.
+
+extern crate dyon;
+#[macro_use]
+extern crate serde_json;
+
+use std::io::{self, BufRead, Write};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{channel, Sender};
+use std::thread;
+
+use serde_json::Value;
+use dyon::{load, InterruptHandle, Module, Runtime, Variable};
+use dyon::debugger::{self, Breakpoint, DebugHandler, Debugger, Frame, Position, Step};
+use dyon::write::{write_variable, EscapeString};
+
+/// The id of the thread running the script.
+pub const THREAD_ID: u64 = 1;
+
+/// Reads a message, returns `None` at end of stream.
+pub fn read_message<R: BufRead>(r: &mut R) -> io::Result<Option<Value>> {
+    const CONTENT_LENGTH: &'static str = "Content-Length:";
+
+    let mut len: Option<usize> = None;
+    loop {
+        let mut line = String::new();
+        if try!(r.read_line(&mut line)) == 0 { return Ok(None); }
+        let line = line.trim();
+        if line.is_empty() { break; }
+        if line.starts_with(CONTENT_LENGTH) {
+            len = line[CONTENT_LENGTH.len()..].trim().parse().ok();
+        }
+    }
+    let len = match len {
+        None => return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                          "Expected `Content-Length` header")),
+        Some(len) => len,
+    };
+    let mut buf = vec![0; len];
+    try!(r.read_exact(&mut buf));
+    serde_json::from_slice(&buf).map(Some)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+/// Writes a message.
+pub fn write_message<W: Write>(w: &mut W, msg: &Value) -> io::Result<()> {
+    let body = msg.to_string();
+    try!(write!(w, "Content-Length: {}\r\n\r\n{}", body.len(), body));
+    w.flush()
+}
+
+/// Sends messages to the client.
+///
+/// Can be cloned to send messages from several threads.
+#[derive(Clone)]
+pub struct Client {
+    /// The sequence number of the last message, with the output.
+    out: Arc<Mutex<(u64, Box<Write + Send>)>>,
+}
+
+impl Client {
+    /// Creates a new client writing to output.
+    pub fn new(out: Box<Write + Send>) -> Client {
+        Client { out: Arc::new(Mutex::new((0, out))) }
+    }
+
+    fn send(&self, mut msg: Value) {
+        let mut out = self.out.lock().unwrap();
+        out.0 += 1;
+        msg["seq"] = json!(out.0);
+        // The client has disconnected when writing fails.
+        let _ = write_message(&mut out.1, &msg);
+    }
+
+    /// Sends an event.
+    pub fn event(&self, event: &str, body: Value) {
+        self.send(json!({"type": "event", "event": event, "body": body}));
+    }
+
+    /// Sends an `output` event.
+    pub fn output(&self, category: &str, text: &str) {
+        self.event("output", json!({"category": category, "output": text}));
+    }
+
+    /// Sends a successful response to a request.
+    pub fn respond(&self, req: &Value, body: Value) {
+        self.send(json!({
+            "type": "response",
+            "request_seq": req["seq"],
+            "command": req["command"],
+            "success": true,
+            "body": body,
+        }));
+    }
+
+    /// Sends a failed response to a request.
+    pub fn fail(&self, req: &Value, message: &str) {
+        self.send(json!({
+            "type": "response",
+            "request_seq": req["seq"],
+            "command": req["command"],
+            "success": false,
+            "message": message,
+        }));
+    }
+}
+
+/// State shared between the protocol loop and the script thread.
+#[derive(Default)]
+struct Shared {
+    /// Sends requests to the script thread while it is paused.
+    paused: Option<Sender<Value>>,
+    breakpoints: Vec<Breakpoint>,
+    /// Whether breakpoints changed since the script thread last paused.
+    breakpoints_changed: bool,
+    /// Whether the client disconnected.
+    terminated: bool,
+}
+
+impl Shared {
+    fn sync_breakpoints(&mut self, breakpoints: &mut Vec<Breakpoint>) {
+        if self.breakpoints_changed {
+            *breakpoints = self.breakpoints.clone();
+            self.breakpoints_changed = false;
+        }
+    }
+}
+
+/// Runs a debug session until the client disconnects or the input ends.
+pub fn serve<R: BufRead>(mut input: R, client: Client) -> io::Result<()> {
+    let mut session = Session {
+        client: client,
+        shared: Arc::new(Mutex::new(Shared::default())),
+        module: None,
+        stop_on_entry: false,
+        configured: false,
+        interrupt: None,
+    };
+    while let Some(req) = try!(read_message(&mut input)) {
+        if req["type"] != "request" { continue; }
+        if !session.request(&req) { break; }
+    }
+    session.disconnect();
+    Ok(())
+}
+
+/// The state of the protocol loop.
+struct Session {
+    client: Client,
+    shared: Arc<Mutex<Shared>>,
+    /// The module to run when configuration is done.
+    module: Option<Module>,
+    stop_on_entry: bool,
+    configured: bool,
+    /// Interrupts the script, set when the script is started.
+    interrupt: Option<InterruptHandle>,
+}
+
+impl Session {
+    /// Handles a request, returns `false` when disconnecting.
+    fn request(&mut self, req: &Value) -> bool {
+        let client = self.client.clone();
+        match req["command"].as_str().unwrap_or("") {
+            "initialize" => {
+                client.respond(req, json!({
+                    "supportsConfigurationDoneRequest": true,
+                    "supportsEvaluateForHovers": true,
+                }));
+                client.event("initialized", json!({}));
+            }
+            "launch" => {
+                let args = &req["arguments"];
+                let program = match args["program"].as_str() {
+                    None => {
+                        client.fail(req, "Expected `program` argument");
+                        return true;
+                    }
+                    Some(program) => program,
+                };
+                let mut module = Module::new();
+                if let Err(err) = load(program, &mut module) {
+                    client.output("stderr", &format!("{}\n", err));
+                    client.fail(req, &format!("Could not load `{}`", program));
+                    return true;
+                }
+                self.module = Some(module);
+                self.stop_on_entry = args["stopOnEntry"].as_bool().unwrap_or(false);
+                client.respond(req, json!({}));
+                self.start();
+            }
+            "setBreakpoints" => {
+                let file = req["arguments"]["source"]["path"].as_str().unwrap_or("");
+                let lines: Vec<u64> = match req["arguments"]["breakpoints"].as_array() {
+                    Some(list) => list.iter().filter_map(|b| b["line"].as_u64()).collect(),
+                    None => vec![],
+                };
+                {
+                    let mut shared = self.shared.lock().unwrap();
+                    shared.breakpoints.retain(|b| b.file != file);
+                    for &line in &lines {
+                        shared.breakpoints.push(Breakpoint {
+                            file: file.into(),
+                            line: line as usize,
+                        });
+                    }
+                    shared.breakpoints_changed = true;
+                }
+                let list: Vec<Value> = lines.iter()
+                    .map(|&line| json!({"verified": true, "line": line}))
+                    .collect();
+                client.respond(req, json!({"breakpoints": list}));
+            }
+            "setExceptionBreakpoints" => client.respond(req, json!({})),
+            "configurationDone" => {
+                self.configured = true;
+                client.respond(req, json!({}));
+                self.start();
+            }
+            "threads" => client.respond(req, json!({
+                "threads": [{"id": THREAD_ID, "name": "main"}]
+            })),
+            "disconnect" | "terminate" => {
+                client.respond(req, json!({}));
+                return false;
+            }
+            _ => {
+                let shared = self.shared.lock().unwrap();
+                match shared.paused {
+                    Some(ref sender) => { let _ = sender.send(req.clone()); }
+                    None => client.fail(req, "The script is not paused"),
+                }
+            }
+        }
+        true
+    }
+
+    /// Starts the script when it is loaded and configuration is done.
+    fn start(&mut self) {
+        if !self.configured { return; }
+        let module = match self.module.take() {
+            None => return,
+            Some(module) => module,
+        };
+        let interrupt = InterruptHandle::default();
+        self.interrupt = Some(interrupt.clone());
+        let handler = Handler {
+            client: self.client.clone(),
+            shared: self.shared.clone(),
+            entry: self.stop_on_entry,
+        };
+        let breakpoints = {
+            let mut shared = self.shared.lock().unwrap();
+            shared.breakpoints_changed = false;
+            shared.breakpoints.clone()
+        };
+        let stop_on_entry = self.stop_on_entry;
+        let client = self.client.clone();
+        thread::spawn(move || {
+            let mut rt = Runtime::new();
+            rt.interrupt = interrupt;
+            let debugger = if stop_on_entry {
+                let mut debugger = Debugger::new(Box::new(handler));
+                debugger.breakpoints = breakpoints;
+                debugger
+            } else {
+                Debugger::with_breakpoints(Box::new(handler), breakpoints)
+            };
+            rt.set_debugger(Some(debugger));
+            let code = match rt.run(&Arc::new(module)) {
+                Ok(_) => 0,
+                Err(err) => {
+                    client.output("stderr", &format!("{}\n", err));
+                    1
+                }
+            };
+            client.event("exited", json!({"exitCode": code}));
+            client.event("terminated", json!({}));
+        });
+    }
+
+    /// Stops the script.
+    fn disconnect(&mut self) {
+        {
+            let mut shared = self.shared.lock().unwrap();
+            shared.terminated = true;
+            // Dropping the sender stops a paused script.
+            shared.paused = None;
+        }
+        if let Some(ref interrupt) = self.interrupt {
+            interrupt.interrupt();
+        }
+    }
+}
+
+/// Handles pauses on the script thread.
+struct Handler {
+    client: Client,
+    shared: Arc<Mutex<Shared>>,
+    /// Whether the next pause is at entry.
+    entry: bool,
+}
+
+/// Variables of a paused script.
+struct Paused {
+    frames: Vec<Frame>,
+    /// Lists of variables by reference, starting at 1.
+    refs: Vec<Vec<(String, Variable)>>,
+}
+
+impl Paused {
+    /// Stores variables and returns a reference to them.
+    fn reference(&mut self, vars: Vec<(String, Variable)>) -> usize {
+        self.refs.push(vars);
+        self.refs.len()
+    }
+
+    /// Returns a reference to the items of an array or object, 0 otherwise.
+    fn children(&mut self, v: &Variable) -> usize {
+        let vars: Vec<(String, Variable)> = match *v {
+            Variable::Array(ref arr) => arr.iter().enumerate()
+                .map(|(i, v)| (format!("{}", i), v.clone())).collect(),
+            Variable::Object(ref obj) => {
+                let mut vars: Vec<(String, Variable)> = obj.iter()
+                    .map(|(k, v)| ((**k).clone(), v.clone())).collect();
+                vars.sort_by(|a, b| a.0.cmp(&b.0));
+                vars
+            }
+            _ => return 0,
+        };
+        if vars.is_empty() { 0 } else { self.reference(vars) }
+    }
+
+    fn frame_vars(&self, frame: &Frame, currents: bool) -> Vec<(String, Variable)> {
+        let list = if currents { &frame.currents } else { &frame.locals };
+        list.iter().map(|&(ref name, ref v)| ((**name).clone(), v.clone())).collect()
+    }
+}
+
+impl DebugHandler for Handler {
+    fn pause(
+        &mut self,
+        rt: &Runtime,
+        pos: &Position,
+        breakpoints: &mut Vec<Breakpoint>
+    ) -> Step {
+        let (sender, receiver) = channel();
+        {
+            let mut shared = self.shared.lock().unwrap();
+            if shared.terminated { return Step::Stop; }
+            shared.sync_breakpoints(breakpoints);
+            shared.paused = Some(sender);
+        }
+        let reason = if self.entry { "entry" }
+            else if pos.breakpoint { "breakpoint" }
+            else { "step" };
+        self.entry = false;
+        self.client.event("stopped", json!({
+            "reason": reason,
+            "threadId": THREAD_ID,
+            "allThreadsStopped": true,
+        }));
+
+        let mut paused = Paused { frames: debugger::frames(rt), refs: vec![] };
+        loop {
+            let req = match receiver.recv() {
+                Ok(req) => req,
+                // The client disconnected.
+                Err(_) => return Step::Stop,
+            };
+            if let Some(step) = self.request(rt, pos, &mut paused, &req) {
+                {
+                    let mut shared = self.shared.lock().unwrap();
+                    shared.sync_breakpoints(breakpoints);
+                    shared.paused = None;
+                }
+                // Answer requests sent before the script continued.
+                while let Ok(req) = receiver.try_recv() {
+                    self.request(rt, pos, &mut paused, &req);
+                }
+                return step;
+            }
+        }
+    }
+}
+
+impl Handler {
+    /// Handles a request while paused, returns how to continue.
+    fn request(
+        &self,
+        rt: &Runtime,
+        pos: &Position,
+        paused: &mut Paused,
+        req: &Value
+    ) -> Option<Step> {
+        let client = &self.client;
+        let args = &req["arguments"];
+        match req["command"].as_str().unwrap_or("") {
+            "continue" => {
+                client.respond(req, json!({"allThreadsContinued": true}));
+                return Some(Step::Continue);
+            }
+            "next" => {
+                client.respond(req, json!({}));
+                return Some(Step::Over);
+            }
+            "stepIn" => {
+                client.respond(req, json!({}));
+                return Some(Step::Into);
+            }
+            "stepOut" => {
+                client.respond(req, json!({}));
+                return Some(Step::Out);
+            }
+            "stackTrace" => {
+                let list: Vec<Value> = paused.frames.iter().enumerate().rev()
+                    .map(|(i, frame)| {
+                        let (line, column) = pos.frame_lines.get(i).cloned()
+                            .unwrap_or((0, 0));
+                        let path = frame.file.as_ref().map(|f| &***f).unwrap_or("");
+                        let name = path.rsplit(|c| c == '/' || c == '\\')
+                            .next().unwrap_or("");
+                        json!({
+                            "id": i,
+                            "name": &**frame.name,
+                            "source": {"name": name, "path": path},
+                            "line": line,
+                            "column": column,
+                        })
+                    })
+                    .collect();
+                let n = list.len();
+                client.respond(req, json!({"stackFrames": list, "totalFrames": n}));
+            }
+            "scopes" => {
+                let frame = match frame(paused, args) {
+                    None => {
+                        client.fail(req, "Unknown frame");
+                        return None;
+                    }
+                    Some(frame) => frame,
+                };
+                let locals = paused.frame_vars(&frame, false);
+                let currents = paused.frame_vars(&frame, true);
+                let mut scopes = vec![];
+                let n = locals.len();
+                scopes.push(json!({
+                    "name": "Locals",
+                    "variablesReference": paused.reference(locals),
+                    "namedVariables": n,
+                    "expensive": false,
+                }));
+                if !currents.is_empty() {
+                    let n = currents.len();
+                    scopes.push(json!({
+                        "name": "Currents",
+                        "variablesReference": paused.reference(currents),
+                        "namedVariables": n,
+                        "expensive": false,
+                    }));
+                }
+                client.respond(req, json!({"scopes": scopes}));
+            }
+            "variables" => {
+                let id = args["variablesReference"].as_u64().unwrap_or(0) as usize;
+                let vars = match paused.refs.get(id.wrapping_sub(1)) {
+                    None => {
+                        client.fail(req, "Unknown variables reference");
+                        return None;
+                    }
+                    Some(vars) => vars.clone(),
+                };
+                let list: Vec<Value> = vars.iter().map(|&(ref name, ref v)| {
+                    json!({
+                        "name": name,
+                        "value": value(rt, v),
+                        "variablesReference": paused.children(v),
+                    })
+                }).collect();
+                client.respond(req, json!({"variables": list}));
+            }
+            "evaluate" => {
+                let name = args["expression"].as_str().unwrap_or("").trim();
+                let found = frame(paused, args).and_then(|frame| {
+                    frame.locals.iter().chain(&frame.currents).rev()
+                        .find(|&&(ref n, _)| &***n == name)
+                        .map(|&(_, ref v)| v.clone())
+                });
+                match found {
+                    None => client.fail(req, &format!("Could not find `{}`", name)),
+                    Some(v) => client.respond(req, json!({
+                        "result": value(rt, &v),
+                        "variablesReference": paused.children(&v),
+                    })),
+                }
+            }
+            _ => client.fail(req, "Unsupported request"),
+        }
+        None
+    }
+}
+
+/// Returns the frame of `frameId` argument, or the current function if missing.
+fn frame(paused: &Paused, args: &Value) -> Option<Frame> {
+    match args["frameId"].as_u64() {
+        None => paused.frames.last().cloned(),
+        Some(id) => paused.frames.get(id as usize).cloned(),
+    }
+}
+
+/// Formats a value.
+fn value(rt: &Runtime, v: &Variable) -> String {
+    if let Variable::Return = *v { return "<return>".into(); }
+    let mut buf: Vec<u8> = vec![];
+    write_variable(&mut buf, rt, v, EscapeString::Json, 0).unwrap();
+    String::from_utf8_lossy(&buf).into_owned()
+}
@@ -0,0 +1,65 @@
+//! Debug Adapter Protocol server for Dyon, speaking over standard input and output.
+//!
+//! On Unix, the standard output of the script is sent to the client as `output` events.
+
+extern crate dyon_dap;
+#[cfg(unix)]
+extern crate libc;
+
+use std::io::{self, Write};
+use dyon_dap::{serve, Client};
+
+fn main() {
+    let client = Client::new(protocol_output());
+    redirect_stdout(&client);
+    let stdin = io::stdin();
+    if let Err(err) = serve(stdin.lock(), client) {
+        eprintln!("{}", err);
+    }
+    // Do not wait for the script thread.
+    std::process::exit(0);
+}
+
+/// Returns a duplicate of standard output, such that standard output can be redirected.
+#[cfg(unix)]
+fn protocol_output() -> Box<Write + Send> {
+    use std::fs::File;
+    use std::os::unix::io::FromRawFd;
+
+    let fd = unsafe { libc::dup(1) };
+    if fd < 0 { return Box::new(io::stdout()); }
+    Box::new(unsafe { File::from_raw_fd(fd) })
+}
+
+#[cfg(not(unix))]
+fn protocol_output() -> Box<Write + Send> { Box::new(io::stdout()) }
+
+/// Sends standard output of the script to the client.
+#[cfg(unix)]
+fn redirect_stdout(client: &Client) {
+    use std::fs::File;
+    use std::io::Read;
+    use std::os::unix::io::FromRawFd;
+    use std::thread;
+
+    let mut fds = [0; 2];
+    unsafe {
+        if libc::pipe(fds.as_mut_ptr()) != 0 { return; }
+        libc::dup2(fds[1], 1);
+        libc::close(fds[1]);
+    }
+    let mut pipe = unsafe { File::from_raw_fd(fds[0]) };
+    let client = client.clone();
+    thread::spawn(move || {
+        let mut buf = [0; 4096];
+        loop {
+            match pipe.read(&mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(n) => client.output("stdout", &String::from_utf8_lossy(&buf[..n])),
+            }
+        }
+    });
+}
+
+#[cfg(not(unix))]
+fn redirect_stdout(_client: &Client) {}
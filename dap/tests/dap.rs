@@ -0,0 +1,191 @@
+extern crate dyon_dap;
+#[macro_use]
+extern crate serde_json;
+
+use std::io::{self, BufReader, Read, Write};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread;
+use std::time::Duration;
+
+use serde_json::Value;
+use dyon_dap::{read_message, serve, write_message, Client};
+
+/// Reads bytes sent through a channel.
+struct ChannelReader {
+    receiver: Receiver<Vec<u8>>,
+    buf: Vec<u8>,
+}
+
+impl Read for ChannelReader {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        if self.buf.is_empty() {
+            match self.receiver.recv_timeout(Duration::from_secs(10)) {
+                Ok(buf) => self.buf = buf,
+                Err(_) => return Ok(0),
+            }
+        }
+        let n = out.len().min(self.buf.len());
+        out[..n].copy_from_slice(&self.buf[..n]);
+        self.buf.drain(..n);
+        Ok(n)
+    }
+}
+
+/// Writes bytes to a channel.
+struct ChannelWriter(Sender<Vec<u8>>);
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let _ = self.0.send(buf.to_vec());
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> { Ok(()) }
+}
+
+fn pipe() -> (ChannelWriter, BufReader<ChannelReader>) {
+    let (sender, receiver) = channel();
+    (ChannelWriter(sender), BufReader::new(ChannelReader { receiver: receiver, buf: vec![] }))
+}
+
+/// A scripted client talking to the server.
+struct TestClient {
+    input: ChannelWriter,
+    output: BufReader<ChannelReader>,
+    seq: u64,
+}
+
+impl TestClient {
+    fn start() -> TestClient {
+        let (input, server_input) = pipe();
+        let (server_output, output) = pipe();
+        thread::spawn(move || {
+            serve(server_input, Client::new(Box::new(server_output))).unwrap();
+        });
+        TestClient { input: input, output: output, seq: 0 }
+    }
+
+    /// Sends a request and waits for the response.
+    fn request(&mut self, command: &str, args: Value) -> Value {
+        self.seq += 1;
+        write_message(&mut self.input, &json!({
+            "seq": self.seq,
+            "type": "request",
+            "command": command,
+            "arguments": args,
+        })).unwrap();
+        let seq = self.seq;
+        let res = self.expect(|msg| msg["type"] == "response" && msg["request_seq"] == seq);
+        assert_eq!(res["success"], true, "{}", res);
+        res["body"].clone()
+    }
+
+    /// Waits for an event.
+    fn event(&mut self, event: &str) -> Value {
+        self.expect(|msg| msg["type"] == "event" && msg["event"] == event)["body"].clone()
+    }
+
+    /// Skips messages until finding a matching message.
+    fn expect<F: Fn(&Value) -> bool>(&mut self, f: F) -> Value {
+        loop {
+            let msg = read_message(&mut self.output).unwrap()
+                .expect("Server stopped sending messages");
+            if f(&msg) { return msg; }
+        }
+    }
+
+    /// Returns the variables of the current function.
+    fn locals(&mut self, frame: &Value) -> Value {
+        let scopes = self.request("scopes", json!({"frameId": frame["id"]}));
+        assert_eq!(scopes["scopes"][0]["name"], "Locals");
+        let reference = scopes["scopes"][0]["variablesReference"].clone();
+        self.request("variables", json!({"variablesReference": reference}))["variables"].clone()
+    }
+}
+
+fn variable(vars: &Value, name: &str) -> Value {
+    vars.as_array().unwrap().iter().find(|v| v["name"] == name)
+        .expect("Could not find variable").clone()
+}
+
+#[test]
+fn breakpoints_and_stepping() {
+    let file = std::env::temp_dir().join("dyon_dap_test.dyon");
+    let file = file.to_str().unwrap().to_string();
+    std::fs::write(&file, "\
+fn add(a, b) -> {
+    c := a + b
+    return clone(c)
+}
+
+fn main() {
+    x := [1, 2]
+    y := add(x[0], 3)
+}
+").unwrap();
+
+    let mut client = TestClient::start();
+    client.request("initialize", json!({"adapterID": "dyon"}));
+    client.event("initialized");
+    client.request("launch", json!({"program": file}));
+    let bps = client.request("setBreakpoints", json!({
+        "source": {"path": file},
+        "breakpoints": [{"line": 2}],
+    }));
+    assert_eq!(bps["breakpoints"][0]["verified"], true);
+    client.request("configurationDone", json!({}));
+
+    assert_eq!(client.event("stopped")["reason"], "breakpoint");
+    let threads = client.request("threads", json!({}));
+    assert_eq!(threads["threads"][0]["id"], 1);
+
+    let stack = client.request("stackTrace", json!({"threadId": 1}));
+    let frames = stack["stackFrames"].clone();
+    assert_eq!(frames.as_array().unwrap().len(), 2);
+    assert_eq!(frames[0]["name"], "add");
+    assert_eq!(frames[0]["line"], 2);
+    assert_eq!(frames[0]["source"]["path"], json!(file));
+    assert_eq!(frames[1]["name"], "main");
+    assert_eq!(frames[1]["line"], 8);
+
+    let vars = client.locals(&frames[0]);
+    assert_eq!(variable(&vars, "a")["value"], "1");
+    assert_eq!(variable(&vars, "b")["value"], "3");
+
+    let vars = client.locals(&frames[1]);
+    let x = variable(&vars, "x");
+    assert_eq!(x["value"], "[1, 2]");
+    let items = client.request("variables", json!({
+        "variablesReference": x["variablesReference"]
+    }))["variables"].clone();
+    assert_eq!(variable(&items, "1")["value"], "2");
+
+    client.request("next", json!({"threadId": 1}));
+    assert_eq!(client.event("stopped")["reason"], "step");
+    let stack = client.request("stackTrace", json!({"threadId": 1}));
+    assert_eq!(stack["stackFrames"][0]["line"], 3);
+    let c = client.request("evaluate", json!({"expression": "c"}));
+    assert_eq!(c["result"], "4");
+
+    client.request("continue", json!({"threadId": 1}));
+    assert_eq!(client.event("exited")["exitCode"], 0);
+    client.event("terminated");
+    client.request("disconnect", json!({}));
+    let _ = std::fs::remove_file(&file);
+}
+
+#[test]
+fn disconnect_while_paused() {
+    let file = std::env::temp_dir().join("dyon_dap_disconnect.dyon");
+    let file = file.to_str().unwrap().to_string();
+    std::fs::write(&file, "fn main() {\n    loop {}\n}\n").unwrap();
+
+    let mut client = TestClient::start();
+    client.request("initialize", json!({"adapterID": "dyon"}));
+    client.request("launch", json!({"program": file, "stopOnEntry": true}));
+    client.request("configurationDone", json!({}));
+    assert_eq!(client.event("stopped")["reason"], "entry");
+    client.request("disconnect", json!({}));
+    assert_eq!(client.event("exited")["exitCode"], 1);
+    let _ = std::fs::remove_file(&file);
+}
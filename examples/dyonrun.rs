@@ -1,13 +1,186 @@
 extern crate dyon;
 
-use dyon::{error, run};
+use std::io::{self, BufRead, Write};
+use std::sync::Arc;
+use dyon::{error, load, run, warnings, Module, Runtime, Variable};
+use dyon::debugger::{self, Breakpoint, DebugHandler, Debugger, Position, Step};
+use dyon::profiler::Profiler;
+use dyon::coverage::Coverage;
+use dyon::write::{write_variable, EscapeString};
 
 fn main() {
-    let file = std::env::args_os().nth(1)
-        .and_then(|s| s.into_string().ok());
-    if let Some(file) = file {
-        error(run(&file));
+    let mut args = std::env::args_os().skip(1)
+        .filter_map(|s| s.into_string().ok());
+    match (args.next(), args.next()) {
+        (Some(ref flag), Some(ref file)) if flag == "--debug" => {
+            error(debug(file));
+        }
+        (Some(ref flag), Some(ref file)) if flag == "--profile" => {
+            error(profile(file));
+        }
+        (Some(ref flag), Some(ref file)) if flag == "--coverage" => {
+            error(coverage(file));
+        }
+        (Some(file), None) => {
+            error(run(&file));
+        }
+        _ => eprintln!("dyonrun [--debug | --profile | --coverage] <file.dyon>"),
+    }
+}
+
+fn debug(file: &str) -> Result<(), String> {
+    let mut module = Module::new();
+    try!(load(file, &mut module));
+    warnings(&module);
+    let mut rt = Runtime::new();
+    rt.set_debugger(Some(Debugger::new(Box::new(Cli))));
+    println!("Type `h` for help");
+    try!(rt.run(&Arc::new(module)));
+    Ok(())
+}
+
+fn profile(file: &str) -> Result<(), String> {
+    let mut module = Module::new();
+    try!(load(file, &mut module));
+    warnings(&module);
+    let profiler = Profiler::new();
+    let mut rt = Runtime::new();
+    rt.set_profiler(Some(profiler.clone()));
+    let res = rt.run(&Arc::new(module));
+    eprintln!("");
+    profiler.write_text(&mut io::stderr()).unwrap();
+    res.map(|_| ())
+}
+
+fn coverage(file: &str) -> Result<(), String> {
+    use std::fs::File;
+
+    let mut module = Module::new();
+    try!(load(file, &mut module));
+    warnings(&module);
+    let module = Arc::new(module);
+    let coverage = Coverage::new();
+    let mut rt = Runtime::new();
+    rt.set_coverage(Some(coverage.clone()));
+    let res = rt.run(&module);
+    let mut f = try!(File::create("lcov.info").map_err(|err| format!("{}", err)));
+    try!(coverage.write_lcov(&mut f, &module).map_err(|err| format!("{}", err)));
+    eprintln!("Wrote coverage to lcov.info");
+    res.map(|_| ())
+}
+
+const HELP: &'static str = "\
+c               continue
+s               step into
+n               step over
+o               step out
+b [file:]line   add breakpoint
+d [file:]line   delete breakpoint
+bl              list breakpoints
+bt              show call stack
+l               show variables of current function
+p name          print variable
+q               quit";
+
+struct Cli;
+
+impl DebugHandler for Cli {
+    fn pause(
+        &mut self,
+        rt: &Runtime,
+        pos: &Position,
+        breakpoints: &mut Vec<Breakpoint>
+    ) -> Step {
+        let file = pos.file.as_ref().map(|f| &***f).unwrap_or("");
+        if pos.breakpoint { println!("Breakpoint"); }
+        println!("{}:{}:{}", file, pos.line, pos.column);
+        print_line(file, pos.line);
+
+        let stdin = io::stdin();
+        loop {
+            print!("(dyon) ");
+            io::stdout().flush().unwrap();
+            let mut line = String::new();
+            if stdin.lock().read_line(&mut line).unwrap_or(0) == 0 {
+                return Step::Stop;
+            }
+            let mut words = line.split_whitespace();
+            match (words.next(), words.next()) {
+                (Some("c"), None) => return Step::Continue,
+                (Some("s"), None) => return Step::Into,
+                (Some("n"), None) => return Step::Over,
+                (Some("o"), None) => return Step::Out,
+                (Some("q"), None) => return Step::Stop,
+                (Some("b"), Some(arg)) => match breakpoint(file, arg) {
+                    Some(b) => breakpoints.push(b),
+                    None => println!("Expected `[file:]line`"),
+                },
+                (Some("d"), Some(arg)) => match breakpoint(file, arg) {
+                    Some(b) => breakpoints.retain(|x| *x != b),
+                    None => println!("Expected `[file:]line`"),
+                },
+                (Some("bl"), None) => {
+                    for b in breakpoints.iter() {
+                        println!("{}:{}", b.file, b.line);
+                    }
+                }
+                (Some("bt"), None) => {
+                    for (i, frame) in debugger::frames(rt).iter().enumerate().rev() {
+                        let file = frame.file.as_ref().map(|f| &***f).unwrap_or("");
+                        println!("{}: {} ({})", i, frame.name, file);
+                    }
+                }
+                (Some("l"), None) => {
+                    if let Some(frame) = debugger::frames(rt).last() {
+                        for &(ref name, ref v) in frame.locals.iter().chain(&frame.currents) {
+                            print_variable(rt, name, v);
+                        }
+                    }
+                }
+                (Some("p"), Some(name)) => {
+                    let found = debugger::frames(rt).last().and_then(|frame| {
+                        frame.locals.iter().chain(&frame.currents).rev()
+                            .find(|&&(ref n, _)| &***n == name).cloned()
+                    });
+                    match found {
+                        Some((name, v)) => print_variable(rt, &name, &v),
+                        None => println!("Could not find `{}`", name),
+                    }
+                }
+                (Some("h"), None) => println!("{}", HELP),
+                (None, _) => {}
+                _ => println!("Unknown command, type `h` for help"),
+            }
+        }
+    }
+}
+
+fn breakpoint(file: &str, arg: &str) -> Option<Breakpoint> {
+    let (file, line) = match arg.rfind(':') {
+        Some(i) => (&arg[..i], &arg[i + 1..]),
+        None => (file, arg),
+    };
+    line.parse().ok().map(|line| Breakpoint { file: file.into(), line: line })
+}
+
+fn print_line(file: &str, line: usize) {
+    use std::fs::File;
+    use std::io::BufReader;
+
+    if let Ok(f) = File::open(file) {
+        if let Some(Ok(text)) = BufReader::new(f).lines().nth(line - 1) {
+            println!("{:4} {}", line, text);
+        }
+    }
+}
+
+fn print_variable(rt: &Runtime, name: &str, v: &Variable) {
+    let mut out = io::stdout();
+    print!("{} = ", name);
+    if let Variable::Return = *v {
+        print!("<return>");
     } else {
-        eprintln!("dyonrun <file.dyon>");
+        write_variable(&mut out, rt, v, EscapeString::Json, 0).unwrap();
     }
+    println!("");
 }
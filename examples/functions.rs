@@ -22,27 +22,32 @@ fn load_module() -> Option<dyon::Module> {
     module.add(Arc::new("say_hello".into()), say_hello, Dfn {
         lts: vec![],
         tys: vec![],
-        ret: Type::Void
+        ret: Type::Void,
+        defaults: vec![]
     });
     module.add(Arc::new("homer".into()), homer, Dfn {
         lts: vec![],
         tys: vec![],
-        ret: Type::Any
+        ret: Type::Any,
+        defaults: vec![]
     });
     module.add(Arc::new("age".into()), age, Dfn {
         lts: vec![Lt::Default],
         tys: vec![Type::Any],
-        ret: Type::Any
+        ret: Type::Any,
+        defaults: vec![]
     });
     module.add(Arc::new("mr".into()), mr, Dfn {
         lts: vec![Lt::Default; 2],
         tys: vec![Type::Text; 2],
-        ret: Type::Text
+        ret: Type::Text,
+        defaults: vec![]
     });
     module.add(Arc::new("origo".into()), origo, Dfn {
         lts: vec![],
         tys: vec![],
         ret: Type::Object,
+        defaults: vec![],
     });
 
     // Register custom Rust object with an ad-hoc type.
@@ -51,11 +56,13 @@ fn load_module() -> Option<dyon::Module> {
         lts: vec![],
         tys: vec![],
         ret: ty_custom_object.clone(),
+        defaults: vec![],
     });
     module.add(Arc::new("print_custom_object".into()), print_custom_object, Dfn {
         lts: vec![Lt::Default],
         tys: vec![ty_custom_object.clone()],
         ret: Type::Void,
+        defaults: vec![],
     });
     if error(load("source/functions/loader.dyon", &mut module)) {
         None
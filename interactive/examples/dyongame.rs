@@ -95,55 +95,64 @@ fn load_module(file: &str) -> Option<Module> {
     module.add(Arc::new("draw".into()), draw, Dfn {
         lts: vec![Lt::Default],
         tys: vec![Type::array()],
-        ret: Type::Void
+        ret: Type::Void,
+        defaults: vec![]
     });
     module.add(Arc::new("next_event".into()),
         next_event, Dfn {
             lts: vec![],
             tys: vec![],
-            ret: Type::Bool
+            ret: Type::Bool,
+            defaults: vec![]
         });
     module.add(Arc::new("bind_sound__name_file".into()),
         bind_sound__name_file, Dfn {
             lts: vec![Lt::Default; 2],
             tys: vec![Type::Text; 2],
-            ret: Type::Void
+            ret: Type::Void,
+            defaults: vec![]
         });
     module.add(Arc::new("bind_music__name_file".into()),
         bind_music__name_file, Dfn {
             lts: vec![Lt::Default; 2],
             tys: vec![Type::Text; 2],
-            ret: Type::Void
+            ret: Type::Void,
+            defaults: vec![]
         });
     module.add(Arc::new("play_sound__name_repeat_volume".into()),
         play_sound__name_repeat_volume, Dfn {
             lts: vec![Lt::Default; 3],
             tys: vec![Type::Text, Type::F64, Type::F64],
-            ret: Type::Void
+            ret: Type::Void,
+            defaults: vec![]
         });
     module.add(Arc::new("play_sound_forever__name_volume".into()),
         play_sound_forever__name_volume, Dfn {
             lts: vec![Lt::Default; 2],
             tys: vec![Type::Text, Type::F64],
-            ret: Type::Void
+            ret: Type::Void,
+            defaults: vec![]
         });
     module.add(Arc::new("play_music__name_repeat".into()),
         play_music__name_repeat, Dfn {
             lts: vec![Lt::Default; 2],
             tys: vec![Type::Text, Type::F64],
-            ret: Type::Void
+            ret: Type::Void,
+            defaults: vec![]
         });
     module.add(Arc::new("play_music_forever__name".into()),
         play_music_forever__name, Dfn {
             lts: vec![Lt::Default; 1],
             tys: vec![Type::Text],
-            ret: Type::Void
+            ret: Type::Void,
+            defaults: vec![]
         });
     module.add(Arc::new("set_music_volume".into()),
         set_music_volume, Dfn {
             lts: vec![Lt::Default],
             tys: vec![Type::F64],
-            ret: Type::Void
+            ret: Type::Void,
+            defaults: vec![]
         });
     if error(load(file, &mut module)) {
         None
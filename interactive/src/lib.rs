@@ -30,107 +30,126 @@ pub fn add_functions<W, F, C>(module: &mut Module)
     module.add(Arc::new("window_size".into()), window_size::<W>, Dfn {
         lts: vec![],
         tys: vec![],
-        ret: Type::Vec4
+        ret: Type::Vec4,
+        defaults: vec![]
     });
     module.add(Arc::new("window_draw_size".into()), window_draw_size::<W>, Dfn {
         lts: vec![],
         tys: vec![],
-        ret: Type::Vec4
+        ret: Type::Vec4,
+        defaults: vec![]
     });
     module.add(Arc::new("render".into()), render, Dfn {
         lts: vec![],
         tys: vec![],
-        ret: Type::Bool
+        ret: Type::Bool,
+        defaults: vec![]
     });
     module.add(Arc::new("update".into()), update, Dfn {
         lts: vec![],
         tys: vec![],
-        ret: Type::Bool
+        ret: Type::Bool,
+        defaults: vec![]
     });
     module.add(Arc::new("press".into()), press, Dfn {
         lts: vec![],
         tys: vec![],
-        ret: Type::Bool
+        ret: Type::Bool,
+        defaults: vec![]
     });
     module.add(Arc::new("release".into()), release, Dfn {
         lts: vec![],
         tys: vec![],
-        ret: Type::Bool
+        ret: Type::Bool,
+        defaults: vec![]
     });
     module.add(Arc::new("focus".into()), focus, Dfn {
         lts: vec![],
         tys: vec![],
         ret: Type::Bool,
+        defaults: vec![],
     });
     module.add(Arc::new("mouse_cursor".into()), mouse_cursor, Dfn {
         lts: vec![],
         tys: vec![],
-        ret: Type::Bool
+        ret: Type::Bool,
+        defaults: vec![]
     });
     module.add(Arc::new("focus_arg".into()), focus_arg, Dfn {
         lts: vec![],
         tys: vec![],
         ret: Type::Option(Box::new(Type::Bool)),
+        defaults: vec![],
     });
     module.add(Arc::new("mouse_cursor_pos".into()), mouse_cursor_pos, Dfn {
         lts: vec![],
         tys: vec![],
         ret: Type::Option(Box::new(Type::Vec4)),
+        defaults: vec![],
     });
     module.add(Arc::new("set__title".into()),
         set__title::<W>, Dfn {
             lts: vec![Lt::Default],
             tys: vec![Type::Text],
-            ret: Type::Void
+            ret: Type::Void,
+            defaults: vec![]
         });
     module.add(Arc::new("update_dt".into()),
         update_dt, Dfn {
             lts: vec![],
             tys: vec![],
-            ret: Type::Option(Box::new(Type::F64))
+            ret: Type::Option(Box::new(Type::F64)),
+            defaults: vec![]
         });
     module.add(Arc::new("press_keyboard_key".into()),
         press_keyboard_key, Dfn {
             lts: vec![],
             tys: vec![],
-            ret: Type::Option(Box::new(Type::F64))
+            ret: Type::Option(Box::new(Type::F64)),
+            defaults: vec![]
         });
     module.add(Arc::new("release_keyboard_key".into()),
         release_keyboard_key, Dfn {
             lts: vec![],
             tys: vec![],
-            ret: Type::Option(Box::new(Type::F64))
+            ret: Type::Option(Box::new(Type::F64)),
+            defaults: vec![]
         });
     module.add(Arc::new("press_mouse_button".into()),
         press_mouse_button, Dfn {
             lts: vec![],
             tys: vec![],
-            ret: Type::Option(Box::new(Type::F64))
+            ret: Type::Option(Box::new(Type::F64)),
+            defaults: vec![]
         });
     module.add(Arc::new("release_mouse_button".into()),
         release_mouse_button, Dfn {
             lts: vec![],
             tys: vec![],
-            ret: Type::Option(Box::new(Type::F64))
+            ret: Type::Option(Box::new(Type::F64)),
+            defaults: vec![]
         });
     module.add(Arc::new("width__font_size_string".into()),
         width__font_size_string::<C>, Dfn {
             lts: vec![Lt::Default; 3],
             tys: vec![Type::F64, Type::F64, Type::Text],
-            ret: Type::F64
+            ret: Type::F64,
+            defaults: vec![]
         });
     module.add(Arc::new("font_names".into()),
         font_names, Dfn {
             lts: vec![],
             tys: vec![],
-            ret: Type::Array(Box::new(Type::Text))
+            ret: Type::Array(Box::new(Type::Text)),
+            defaults: vec![]
         }
     );
     module.add(Arc::new("load_font".into()),
         load_font::<F, C::Texture>, Dfn {
             lts: vec![Lt::Default],
             tys: vec![Type::Text],
-            ret: Type::Result(Box::new(Type::Text))
+            ret: Type::Result(Box::new(Type::Text)),
+            defaults: vec![]
         }
     );
 }
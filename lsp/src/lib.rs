@@ -0,0 +1,498 @@
+//! Language Server Protocol server for Dyon.
+//!
+//! Messages are read and written with `Content-Length` headers,
+//! see https://microsoft.github.io/language-server-protocol/.
+//!
+//! Diagnostics are published when a document is opened, changed or saved,
+//! using the same parser, lifetime checker and type checker as `load_str`.
+//! Warnings of the type checker are published with warning severity.
+//! Definitions, hover and completion use the functions of the last version
+//! of the document that passed the checks.
+//! Hover and completion show the `///` comments above functions,
+//! which for intrinsics are read from `src/lib.dyon`.
+//!
+//! Files to load before every document, e.g. modules imported with `use`,
+//! are set with `{"imports": [...]}` in the initialization options.
+
+extern crate dyon;
+extern crate range;
+#[macro_use]
+extern crate serde_json;
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+use range::Range;
+use serde_json::Value;
+use dyon::{check_str, load, Dfn, FnIndex, Lt, Module, Prelude};
+use dyon::ast::{use_lookup, Arg, Function, UseLookup};
+
+/// Documentation of the intrinsics, with `///` comments above each function.
+const INTRINSIC_DOCS: &'static str = include_str!("../../src/lib.dyon");
+
+/// Reads a message, returns `None` at end of stream.
+pub fn read_message<R: BufRead>(r: &mut R) -> io::Result<Option<Value>> {
+    const CONTENT_LENGTH: &'static str = "Content-Length:";
+
+    let mut len: Option<usize> = None;
+    loop {
+        let mut line = String::new();
+        if try!(r.read_line(&mut line)) == 0 { return Ok(None); }
+        let line = line.trim();
+        if line.is_empty() { break; }
+        if line.starts_with(CONTENT_LENGTH) {
+            len = line[CONTENT_LENGTH.len()..].trim().parse().ok();
+        }
+    }
+    let len = match len {
+        None => return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                          "Expected `Content-Length` header")),
+        Some(len) => len,
+    };
+    let mut buf = vec![0; len];
+    try!(r.read_exact(&mut buf));
+    serde_json::from_slice(&buf).map(Some)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+/// Writes a message.
+pub fn write_message<W: Write>(w: &mut W, msg: &Value) -> io::Result<()> {
+    let body = msg.to_string();
+    try!(write!(w, "Content-Length: {}\r\n\r\n{}", body.len(), body));
+    w.flush()
+}
+
+/// Runs the server until the client sends `exit` or the input ends.
+pub fn serve<R: BufRead, W: Write>(mut input: R, mut output: W) -> io::Result<()> {
+    let mut server = Server {
+        imports: Module::new(),
+        intrinsics: Prelude::new_intrinsics(),
+        intrinsic_docs: doc_comments(INTRINSIC_DOCS),
+        documents: HashMap::new(),
+    };
+    while let Some(msg) = try!(read_message(&mut input)) {
+        if msg["method"] == "exit" { break; }
+        let mut out = vec![];
+        server.handle(&msg, &mut out);
+        for msg in &out {
+            try!(write_message(&mut output, msg));
+        }
+    }
+    Ok(())
+}
+
+/// An open document.
+struct Document {
+    text: String,
+    /// Functions of the last version that passed the checks.
+    module: Module,
+    /// Functions imported with `use` in the last version that passed the checks.
+    use_lookup: UseLookup,
+}
+
+struct Server {
+    /// Functions loaded before every document.
+    imports: Module,
+    intrinsics: Prelude,
+    /// Documentation of intrinsics by name.
+    intrinsic_docs: HashMap<String, String>,
+    documents: HashMap<String, Document>,
+}
+
+impl Server {
+    fn handle(&mut self, msg: &Value, out: &mut Vec<Value>) {
+        let params = &msg["params"];
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or("").to_string();
+        let result = match msg["method"].as_str().unwrap_or("") {
+            "initialize" => {
+                self.initialize(params, out);
+                json!({
+                    "capabilities": {
+                        "textDocumentSync": 1,
+                        "definitionProvider": true,
+                        "hoverProvider": true,
+                        "completionProvider": {"triggerCharacters": [":"]},
+                    },
+                    "serverInfo": {"name": "dyon-lsp"},
+                })
+            }
+            "shutdown" => Value::Null,
+            "textDocument/didOpen" => {
+                let text = params["textDocument"]["text"].as_str().unwrap_or("");
+                self.documents.insert(uri.clone(), Document {
+                    text: text.into(),
+                    module: self.imports.clone(),
+                    use_lookup: UseLookup::new(),
+                });
+                self.check(&uri, out);
+                return;
+            }
+            "textDocument/didChange" => {
+                // Uses full document sync, so the last change contains the whole text.
+                if let Some(change) = params["contentChanges"].as_array()
+                    .and_then(|changes| changes.last()) {
+                    if let Some(doc) = self.documents.get_mut(&uri) {
+                        doc.text = change["text"].as_str().unwrap_or("").into();
+                    }
+                }
+                self.check(&uri, out);
+                return;
+            }
+            "textDocument/didSave" => {
+                self.check(&uri, out);
+                return;
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                out.push(notification("textDocument/publishDiagnostics",
+                                      json!({"uri": uri, "diagnostics": []})));
+                return;
+            }
+            "textDocument/definition" => self.definition(&uri, &params["position"]),
+            "textDocument/hover" => self.hover(&uri, &params["position"]),
+            "textDocument/completion" => self.completion(&uri),
+            method => {
+                // Ignore notifications that are not supported.
+                if msg["id"].is_null() { return; }
+                out.push(json!({
+                    "jsonrpc": "2.0",
+                    "id": msg["id"],
+                    "error": {
+                        "code": -32601,
+                        "message": format!("Unsupported method `{}`", method),
+                    },
+                }));
+                return;
+            }
+        };
+        out.push(json!({"jsonrpc": "2.0", "id": msg["id"], "result": result}));
+    }
+
+    /// Loads imports from initialization options.
+    fn initialize(&mut self, params: &Value, out: &mut Vec<Value>) {
+        let root = params["rootUri"].as_str().map(uri_to_path);
+        let imports = match params["initializationOptions"]["imports"].as_array() {
+            None => return,
+            Some(imports) => imports.clone(),
+        };
+        for file in imports.iter().filter_map(|file| file.as_str()) {
+            let file = match root {
+                Some(ref root) if Path::new(file).is_relative() => {
+                    Path::new(root).join(file).to_string_lossy().into_owned()
+                }
+                _ => file.into(),
+            };
+            if let Err(err) = load(&file, &mut self.imports) {
+                out.push(notification("window/logMessage", json!({
+                    "type": 1,
+                    "message": format!("Could not load `{}`:\n{}", file, err),
+                })));
+            }
+        }
+    }
+
+    /// Checks a document and publishes diagnostics.
+    fn check(&mut self, uri: &str, out: &mut Vec<Value>) {
+        let doc = match self.documents.get_mut(uri) {
+            None => return,
+            Some(doc) => doc,
+        };
+        let mut module = self.imports.clone();
+        let mut warnings = vec![];
+        let diagnostics = match check_str(&uri_to_path(uri), Arc::new(doc.text.clone()),
+                                          &mut module, &mut warnings) {
+            Ok(data) => {
+                doc.use_lookup = use_lookup(&data, &module);
+                doc.module = module;
+                warnings.iter().map(|warning| diagnostic(&doc.text, warning, WARNING)).collect()
+            }
+            Err(err) => vec![diagnostic(&doc.text, &err, ERROR)],
+        };
+        out.push(notification("textDocument/publishDiagnostics",
+                              json!({"uri": uri, "diagnostics": diagnostics})));
+    }
+
+    fn definition(&self, uri: &str, pos: &Value) -> Value {
+        let f = match self.documents.get(uri).and_then(|doc| {
+            lookup(doc, &word_at(&doc.text, offset(&doc.text, pos)))
+                .map(|i| &doc.module.functions[i])
+        }) {
+            None => return Value::Null,
+            Some(f) => f,
+        };
+        let start = f.source_range.offset;
+        json!({
+            "uri": path_to_uri(&f.file),
+            "range": {
+                "start": position(&f.source, start),
+                "end": position(&f.source, start + f.source_range.length),
+            },
+        })
+    }
+
+    fn hover(&self, uri: &str, pos: &Value) -> Value {
+        let doc = match self.documents.get(uri) {
+            None => return Value::Null,
+            Some(doc) => doc,
+        };
+        let word = word_at(&doc.text, offset(&doc.text, pos));
+        let (sig, docs) = match lookup(doc, &word) {
+            Some(i) => {
+                let f = &doc.module.functions[i];
+                (signature(&f.name, &Dfn::new(f), Some(&f.args)), doc_comment(f))
+            }
+            None => match self.intrinsic(&word) {
+                Some((name, dfn)) => (signature(name, dfn, None), self.intrinsic_doc(name)),
+                None => return Value::Null,
+            }
+        };
+        let mut value = format!("```dyon\n{}\n```", sig);
+        if !docs.is_empty() {
+            value.push_str("\n\n");
+            value.push_str(&docs);
+        }
+        json!({"contents": {"kind": "markdown", "value": value}})
+    }
+
+    fn completion(&self, uri: &str) -> Value {
+        let mut items = vec![];
+        let mut names: HashMap<String, ()> = HashMap::new();
+        let mut item = |label: String, detail: String, docs: String| {
+            if names.insert(label.clone(), ()).is_some() { return; }
+            items.push(json!({
+                "label": label,
+                "kind": 3,
+                "detail": detail,
+                "documentation": docs,
+            }));
+        };
+        if let Some(doc) = self.documents.get(uri) {
+            for f in doc.module.functions.iter().rev() {
+                item(base_name(&f.name).into(),
+                     signature(&f.name, &Dfn::new(f), Some(&f.args)),
+                     doc_comment(f));
+            }
+            for (alias, fns) in &doc.use_lookup.aliases {
+                for (name, &i) in fns {
+                    let f = &doc.module.functions[i];
+                    item(format!("{}::{}", alias, base_name(name)),
+                         signature(name, &Dfn::new(f), Some(&f.args)),
+                         doc_comment(f));
+                }
+            }
+        }
+        for (name, &i) in &self.intrinsics.functions {
+            item(base_name(name).into(),
+                 signature(name, &self.intrinsics.list[i], None),
+                 self.intrinsic_doc(name));
+        }
+        json!(items)
+    }
+
+    /// Finds an intrinsic by name without mutability information.
+    fn intrinsic(&self, word: &str) -> Option<(&str, &Dfn)> {
+        self.intrinsics.functions.iter()
+            .find(|&(name, _)| base_name(name) == word)
+            .map(|(name, &i)| (&***name, &self.intrinsics.list[i]))
+    }
+
+    /// Returns the documentation of an intrinsic, or an empty string.
+    fn intrinsic_doc(&self, name: &str) -> String {
+        self.intrinsic_docs.get(base_name(name)).cloned().unwrap_or(String::new())
+    }
+}
+
+/// Finds the index of a loaded function, resolving `use` aliases.
+fn lookup(doc: &Document, word: &str) -> Option<usize> {
+    if let Some(i) = word.rfind("::") {
+        let (alias, name) = (&word[..i], &word[i + 2..]);
+        return doc.use_lookup.aliases.get(&alias.to_string()).and_then(|fns| {
+            fns.iter().find(|&(f, _)| base_name(f) == name).map(|(_, &i)| i)
+        });
+    }
+    match doc.module.find_function(&Arc::new(word.into()), 0) {
+        FnIndex::Loaded(i) => Some(i as usize),
+        // Functions with mutable arguments have names like `foo(mut,_)`.
+        _ => doc.module.functions.iter().rposition(|f| base_name(&f.name) == word),
+    }
+}
+
+/// Removes mutability information, e.g. `push(mut,_)` becomes `push`.
+fn base_name(name: &str) -> &str {
+    match name.find('(') {
+        None => name,
+        Some(i) => &name[..i],
+    }
+}
+
+/// Returns a signature, e.g. `fn push(mut any, any)`.
+fn signature(name: &str, dfn: &Dfn, args: Option<&[Arg]>) -> String {
+    let mutable: Vec<bool> = match name.find('(') {
+        None => vec![],
+        Some(i) => name[i + 1..name.len() - 1].split(',').map(|s| s == "mut").collect(),
+    };
+    let mut s = format!("fn {}(", base_name(name));
+    for (i, ty) in dfn.tys.iter().enumerate() {
+        if i > 0 { s.push_str(", "); }
+        if mutable.get(i) == Some(&true) { s.push_str("mut "); }
+        if let Some(args) = args {
+            s.push_str(&args[i].name);
+            s.push_str(": ");
+        }
+        match (dfn.lts[i], args) {
+            (Lt::Return, _) => s.push_str("'return "),
+            (Lt::Arg(j), Some(args)) => {
+                s.push('\'');
+                s.push_str(&args[j].name);
+                s.push(' ');
+            }
+            _ => {}
+        }
+        s.push_str(&ty.description());
+    }
+    s.push(')');
+    if dfn.returns() {
+        s.push_str(" -> ");
+        s.push_str(&dfn.ret.description());
+    }
+    s
+}
+
+/// Returns the `///` comment lines above a function.
+fn doc_comment(f: &Function) -> String {
+    let source = &f.source[..f.source_range.offset];
+    let start = source.rfind('\n').map(|i| i + 1).unwrap_or(0);
+    comment_lines(&source[..start])
+}
+
+/// Returns the `///` comments above the functions in a source, by function name.
+fn doc_comments(source: &str) -> HashMap<String, String> {
+    let mut docs = HashMap::new();
+    let mut start = 0;
+    for line in source.split('\n') {
+        if line.starts_with("fn ") {
+            if let Some(end) = line.find('(') {
+                let comment = comment_lines(&source[..start]);
+                if !comment.is_empty() { docs.insert(line[3..end].trim().into(), comment); }
+            }
+        }
+        start += line.len() + 1;
+    }
+    docs
+}
+
+/// Returns the `///` comment lines at the end of a source.
+fn comment_lines(source: &str) -> String {
+    let mut lines: Vec<&str> = source.lines().rev()
+        .map(|line| line.trim())
+        .take_while(|line| line.starts_with("///"))
+        .map(|line| {
+            let line = &line[3..];
+            if line.starts_with(' ') { &line[1..] } else { line }
+        })
+        .collect();
+    lines.reverse();
+    lines.join("\n")
+}
+
+/// Returns the word at offset, including `::` for imported functions.
+fn word_at(text: &str, offset: usize) -> String {
+    let is_word = |c: char| c.is_alphanumeric() || c == '_' || c == ':';
+    let start = text[..offset].char_indices().rev()
+        .take_while(|&(_, c)| is_word(c))
+        .last().map(|(i, _)| i).unwrap_or(offset);
+    let end = text[offset..].char_indices()
+        .find(|&(_, c)| !is_word(c))
+        .map(|(i, _)| offset + i).unwrap_or(text.len());
+    text[start..end].trim_matches(':').into()
+}
+
+/// Converts a byte offset to a position with line and UTF-16 character.
+fn position(text: &str, offset: usize) -> Value {
+    let mut line = 0;
+    let mut character = 0;
+    for (i, c) in text.char_indices() {
+        if i >= offset { break; }
+        if c == '\n' {
+            line += 1;
+            character = 0;
+        } else {
+            character += c.len_utf16();
+        }
+    }
+    json!({"line": line, "character": character})
+}
+
+/// Converts a position with line and UTF-16 character to a byte offset.
+fn offset(text: &str, pos: &Value) -> usize {
+    let line = pos["line"].as_u64().unwrap_or(0);
+    let character = pos["character"].as_u64().unwrap_or(0) as usize;
+    let mut current = 0;
+    let mut i = 0;
+    for l in text.split('\n') {
+        if current == line {
+            let mut n = 0;
+            for (j, c) in l.char_indices() {
+                if n >= character { return i + j; }
+                n += c.len_utf16();
+            }
+            return i + l.len();
+        }
+        current += 1;
+        i += l.len() + 1;
+    }
+    text.len()
+}
+
+/// `DiagnosticSeverity.Error`.
+const ERROR: u64 = 1;
+/// `DiagnosticSeverity.Warning`.
+const WARNING: u64 = 2;
+
+/// Creates a diagnostic from a message with its range in the text.
+fn diagnostic(text: &str, msg: &Range<String>, severity: u64) -> Value {
+    json!({
+        "range": {
+            "start": position(text, msg.offset),
+            "end": position(text, msg.offset + msg.length),
+        },
+        "severity": severity,
+        "source": "dyon",
+        "message": msg.data,
+    })
+}
+
+fn notification(method: &str, params: Value) -> Value {
+    json!({"jsonrpc": "2.0", "method": method, "params": params})
+}
+
+/// Converts a `file://` URI to a path.
+fn uri_to_path(uri: &str) -> String {
+    let path = if uri.starts_with("file://") { &uri[7..] } else { uri };
+    // Decode percent escapes, e.g. `%20` for space.
+    let bytes = path.as_bytes();
+    let mut res = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let hex = ::std::str::from_utf8(&bytes[i + 1..i + 3]).ok()
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+            if let Some(b) = hex {
+                res.push(b);
+                i += 3;
+                continue;
+            }
+        }
+        res.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&res).into_owned()
+}
+
+/// Converts a path to a `file://` URI.
+fn path_to_uri(path: &str) -> String {
+    if path.starts_with("file://") { return path.into(); }
+    format!("file://{}", path.replace('%', "%25").replace(' ', "%20"))
+}
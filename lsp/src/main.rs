@@ -0,0 +1,13 @@
+//! Language Server Protocol server for Dyon, speaking over standard input and output.
+
+extern crate dyon_lsp;
+
+use std::io;
+
+fn main() {
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    if let Err(err) = dyon_lsp::serve(stdin.lock(), stdout.lock()) {
+        eprintln!("{}", err);
+    }
+}
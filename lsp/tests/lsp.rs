@@ -0,0 +1,166 @@
+extern crate dyon_lsp;
+#[macro_use]
+extern crate serde_json;
+
+use serde_json::Value;
+use dyon_lsp::{read_message, serve, write_message};
+
+/// Runs the server on a script of messages, returns the messages sent by the server.
+fn run(script: &[Value]) -> Vec<Value> {
+    let mut input: Vec<u8> = vec![];
+    for msg in script {
+        write_message(&mut input, msg).unwrap();
+    }
+    let mut output: Vec<u8> = vec![];
+    serve(&input[..], &mut output).unwrap();
+    let mut output = &output[..];
+    let mut res = vec![];
+    while let Some(msg) = read_message(&mut output).unwrap() {
+        res.push(msg);
+    }
+    res
+}
+
+fn request(id: u64, method: &str, params: Value) -> Value {
+    json!({"jsonrpc": "2.0", "id": id, "method": method, "params": params})
+}
+
+fn notification(method: &str, params: Value) -> Value {
+    json!({"jsonrpc": "2.0", "method": method, "params": params})
+}
+
+fn response(msgs: &[Value], id: u64) -> Value {
+    msgs.iter().find(|msg| msg["id"] == id).expect("Missing response")["result"].clone()
+}
+
+fn position(uri: &str, line: u64, character: u64) -> Value {
+    json!({"textDocument": {"uri": uri}, "position": {"line": line, "character": character}})
+}
+
+const BROKEN: &'static str = "\
+fn add(a: f64, b: f64) -> f64 {
+    c := a + b
+    return c
+}
+";
+
+const FIXED: &'static str = "\
+use math::{square} as m
+
+/// Adds two numbers.
+/// Returns the sum.
+fn add(a: f64, b: f64) -> f64 {
+    return a + b
+}
+
+fn main() {
+    println(add(1, m::square(2)))
+}
+";
+
+#[test]
+fn diagnostics_definition_hover_completion() {
+    let math = std::env::temp_dir().join("dyon_lsp_math.dyon");
+    let math = math.to_str().unwrap().to_string();
+    std::fs::write(&math, "\
+ns math
+
+/// Squares a number.
+fn square(x: f64) -> f64 {
+    return x * x
+}
+").unwrap();
+    let uri = "file:///project/main.dyon";
+
+    let msgs = run(&[
+        request(1, "initialize", json!({"initializationOptions": {"imports": [math]}})),
+        notification("initialized", json!({})),
+        notification("textDocument/didOpen", json!({"textDocument": {
+            "uri": uri, "languageId": "dyon", "version": 1, "text": BROKEN
+        }})),
+        notification("textDocument/didChange", json!({
+            "textDocument": {"uri": uri, "version": 2},
+            "contentChanges": [{"text": FIXED}],
+        })),
+        request(2, "textDocument/definition", position(uri, 9, 13)),
+        request(3, "textDocument/definition", position(uri, 9, 23)),
+        request(4, "textDocument/hover", position(uri, 9, 13)),
+        request(5, "textDocument/hover", position(uri, 9, 6)),
+        request(6, "textDocument/hover", position(uri, 9, 22)),
+        request(7, "textDocument/completion", position(uri, 9, 0)),
+        request(8, "shutdown", Value::Null),
+        notification("exit", Value::Null),
+    ]);
+    let _ = std::fs::remove_file(&math);
+
+    let caps = response(&msgs, 1)["capabilities"].clone();
+    assert_eq!(caps["hoverProvider"], true);
+
+    let diagnostics: Vec<&Value> = msgs.iter()
+        .filter(|msg| msg["method"] == "textDocument/publishDiagnostics")
+        .collect();
+    assert_eq!(diagnostics.len(), 2);
+    let error = &diagnostics[0]["params"]["diagnostics"][0];
+    assert_eq!(error["range"]["start"], json!({"line": 2, "character": 11}));
+    assert!(error["message"].as_str().unwrap().contains("does not live long enough"));
+    assert_eq!(diagnostics[1]["params"]["diagnostics"], json!([]));
+
+    let def = response(&msgs, 2);
+    assert_eq!(def["uri"], uri);
+    assert_eq!(def["range"]["start"]["line"], 4);
+
+    let def = response(&msgs, 3);
+    assert_eq!(def["uri"], format!("file://{}", math));
+    assert_eq!(def["range"]["start"]["line"], 3);
+
+    let hover = response(&msgs, 4)["contents"]["value"].as_str().unwrap().to_string();
+    assert!(hover.contains("fn add(a: f64, b: f64) -> f64"), "{}", hover);
+    assert!(hover.contains("Adds two numbers.\nReturns the sum."), "{}", hover);
+
+    let hover = response(&msgs, 5)["contents"]["value"].as_str().unwrap().to_string();
+    assert!(hover.contains("fn println(any)"), "{}", hover);
+    assert!(hover.contains("Prints out variable to standard output, adding newline character."),
+        "{}", hover);
+
+    let hover = response(&msgs, 6)["contents"]["value"].as_str().unwrap().to_string();
+    assert!(hover.contains("fn square(x: f64) -> f64"), "{}", hover);
+    assert!(hover.contains("Squares a number."), "{}", hover);
+
+    let items = response(&msgs, 7);
+    let labels: Vec<&str> = items.as_array().unwrap().iter()
+        .map(|item| item["label"].as_str().unwrap()).collect();
+    assert!(labels.contains(&"add"));
+    assert!(labels.contains(&"sqrt"));
+    assert!(labels.contains(&"m::square"));
+    let sqrt = items.as_array().unwrap().iter().find(|item| item["label"] == "sqrt").unwrap();
+    assert!(sqrt["documentation"].as_str().unwrap().contains("square root"), "{}", sqrt);
+}
+
+#[test]
+fn warnings() {
+    let uri = "file:///project/match.dyon";
+    let msgs = run(&[
+        request(1, "initialize", json!({})),
+        notification("textDocument/didOpen", json!({"textDocument": {
+            "uri": uri, "languageId": "dyon", "version": 1, "text": "\
+fn first(a: opt[f64]) -> f64 {
+    return match a {
+        some(x) if x > 0 => clone(x)
+        none() => 0
+    }
+}
+"
+        }})),
+        request(2, "shutdown", Value::Null),
+        notification("exit", Value::Null),
+    ]);
+
+    let diagnostics: Vec<&Value> = msgs.iter()
+        .filter(|msg| msg["method"] == "textDocument/publishDiagnostics")
+        .collect();
+    assert_eq!(diagnostics.len(), 1);
+    let warning = &diagnostics[0]["params"]["diagnostics"][0];
+    assert_eq!(warning["severity"], 2);
+    assert_eq!(warning["range"]["start"]["line"], 1);
+    assert!(warning["message"].as_str().unwrap().contains("Missing arm for `some(_)`"));
+}
@@ -8,6 +8,7 @@ use super::{
     Call,
     CallClosure,
     Expression,
+    ForIn,
     ForN,
     Id,
     Item,
@@ -81,7 +82,7 @@ fn infer_expr(
             let n = infer_expr(&arr_fill.n, name, decls);
             if n.is_some() { return n; }
         }
-        Return(ref ret_expr) => {
+        Return(ref ret_expr) | Yield(ref ret_expr) => {
             let res = infer_expr(ret_expr, name, decls);
             if res.is_some() { return res; }
         }
@@ -92,7 +93,7 @@ fn infer_expr(
             let res = infer_block(block, name, decls);
             if res.is_some() { return res; }
         }
-        Go(ref go) => {
+        Go(ref go) | Coroutine(ref go) => {
             let res = infer_call(&go.call, name, decls);
             if res.is_some() { return res; }
         }
@@ -152,6 +153,11 @@ fn infer_expr(
         LinkFor(ref for_n_expr) => {
             return infer_for_n(for_n_expr, name, decls)
         }
+        ForIn(ref for_in_expr) | SumIn(ref for_in_expr) | MinIn(ref for_in_expr) |
+        MaxIn(ref for_in_expr) | SiftIn(ref for_in_expr) | AnyIn(ref for_in_expr) |
+        AllIn(ref for_in_expr) => {
+            return infer_for_in(for_in_expr, name, decls)
+        }
         If(ref if_expr) => {
             let res = infer_expr(&if_expr.cond, name, decls);
             if res.is_some() { return res; }
@@ -169,6 +175,25 @@ fn infer_expr(
                 if res.is_some() { return res; }
             }
         }
+        Match(ref match_expr) => {
+            let res = infer_expr(&match_expr.expr, name, decls);
+            if res.is_some() { return res; }
+            for arm in &match_expr.arms {
+                if arm.pattern.binds(name) { continue; }
+                if let Some(ref guard) = arm.guard {
+                    let res = infer_expr(guard, name, decls);
+                    if res.is_some() { return res; }
+                }
+                let res = infer_expr(&arm.expr, name, decls);
+                if res.is_some() { return res; }
+            }
+        }
+        EnumValue(ref enum_value) => {
+            if let Some(ref payload) = enum_value.payload {
+                let res = infer_expr(payload, name, decls);
+                if res.is_some() { return res; }
+            }
+        }
         Compare(ref cmp_expr) => {
             let left = infer_expr(&cmp_expr.left, name, decls);
             if left.is_some() { return left; }
@@ -293,6 +318,28 @@ fn infer_for_n(
     res
 }
 
+fn infer_for_in(
+    for_in_expr: &ForIn,
+    name: &str,
+    decls: &mut Vec<Arc<String>>
+) -> Option<Item> {
+    let res = infer_expr(&for_in_expr.expr, name, decls);
+    if res.is_some() { return res; }
+    // Check for declaration of same name.
+    if &**for_in_expr.name == name ||
+       for_in_expr.key.as_ref().map(|key| &***key == name) == Some(true) {
+        return None;
+    }
+    let st = decls.len();
+    decls.push(for_in_expr.name.clone());
+    if let Some(ref key) = for_in_expr.key {
+        decls.push(key.clone());
+    }
+    let res = infer_block(&for_in_expr.block, name, decls);
+    decls.truncate(st);
+    res
+}
+
 fn infer_block(
     block: &Block,
     name: &str,
@@ -5,6 +5,8 @@ use range::Range;
 use piston_meta::bootstrap::Convert;
 use piston_meta::MetaData;
 
+use bytecode;
+use DefaultValue;
 use FnIndex;
 use Module;
 use Prelude;
@@ -42,18 +44,183 @@ pub fn convert(
         Function::from_meta_data(&namespace, &file, &source, "fn", convert, ignored) {
             convert.update(range);
             module.register(function);
+        } else if let Ok((range, enum_decl)) = Enum::from_meta_data(convert, ignored) {
+            convert.update(range);
+            module.register_enum(enum_decl);
+        } else if let Ok((range, global)) =
+        Global::from_meta_data(&namespace, &file, &source, "const", convert, ignored) {
+            convert.update(range);
+            module.register_global(global);
+        } else if let Ok((range, global)) =
+        Global::from_meta_data(&namespace, &file, &source, "global", convert, ignored) {
+            convert.update(range);
+            module.register_global(global);
         } else if convert.remaining_data_len() > 0 {
             return Err(());
         } else {
             break;
         }
     }
+    // Inline constants declared with literals in functions not resolved yet.
+    let literals: Vec<(Arc<String>, Variable)> = module.globals.iter()
+        .filter_map(|g| g.literal().map(|val| (g.name.clone(), val)))
+        .collect();
+    for f in &mut module.functions {
+        if f.resolved.get() { continue; }
+        for &(ref name, ref val) in &literals {
+            // Arguments and current objects shadow constants.
+            if f.args.iter().any(|arg| &arg.name == name) ||
+               f.currents.iter().any(|current| &current.name == name) { continue; }
+            f.block = replace::variable_block(&f.block, name, val);
+        }
+    }
     for (i, f) in module.functions.iter().enumerate() {
         f.resolve_locals(i, module, &use_lookup);
     }
+    // Globals are evaluated by functions placed after the loaded functions.
+    let n = module.functions.len();
+    let mut j = 0;
+    for g in &module.globals {
+        if g.position.get().is_some() { continue; }
+        g.init.resolve_locals(n + j, module, &use_lookup);
+        g.position.set(Some((n, j)));
+        j += 1;
+    }
     Ok(())
 }
 
+/// Compiles functions to bytecode, after the types are refined.
+pub fn compile(module: &mut Module) {
+    for i in 0..module.functions.len() {
+        if module.functions[i].bytecode.is_some() { continue; }
+        let chunk = bytecode::compile(&module.functions[i], i, false, module);
+        module.functions[i].bytecode = chunk.map(|chunk| Arc::new(chunk));
+        let chunk = bytecode::compile(&module.functions[i], i, true, module);
+        module.functions[i].bytecode_hooks = chunk.map(|chunk| Arc::new(chunk));
+    }
+}
+
+/// Returns the lookup of imported functions in meta data of a source.
+pub fn use_lookup(data: &[Range<MetaData>], module: &Module) -> UseLookup {
+    let mut convert = Convert::new(data);
+    let mut ignored = vec![];
+    if let Ok((range, _)) = Namespace::from_meta_data(convert, &mut ignored) {
+        convert.update(range);
+    }
+    if let Ok((_, val)) = Uses::from_meta_data(convert, &mut ignored) {
+        UseLookup::from_uses_module(&val, module)
+    } else {
+        UseLookup::new()
+    }
+}
+
+/// Turns calls with a declared enum as alias into enum values, e.g. `Shape::Circle(2)`,
+/// and items with a variant into enum values, e.g. `Shape::Square`.
+///
+/// The syntax parses `A::b(x)` as a call, such that uppercase module aliases work.
+/// The enums are declared in the source or in the module it is loaded into.
+pub fn enum_calls(data: &mut [Range<MetaData>], module: &Module) {
+    fn rename(d: &mut Range<MetaData>, name: &str) {
+        let name = Arc::new(name.into());
+        d.data = match d.data {
+            MetaData::StartNode(_) => MetaData::StartNode(name),
+            MetaData::EndNode(_) => MetaData::EndNode(name),
+            MetaData::String(_, ref val) => MetaData::String(name, val.clone()),
+            ref x => x.clone(),
+        };
+    }
+
+    for i in 0..data.len().saturating_sub(3) {
+        let is_item = match (&data[i].data, &data[i + 1].data,
+                             &data[i + 2].data, &data[i + 3].data) {
+            (&MetaData::StartNode(ref kind), &MetaData::String(ref n, _),
+             &MetaData::String(ref v, _), &MetaData::EndNode(_)) => {
+                &**kind == "item" && &**n == "name" && &**v == "variant"
+            }
+            _ => false
+        };
+        if is_item {
+            rename(&mut data[i], "enum_value");
+            rename(&mut data[i + 1], "enum");
+            rename(&mut data[i + 3], "enum_value");
+        }
+    }
+
+    let mut enums: Vec<Arc<String>> = module.enums.iter().map(|e| e.name.clone()).collect();
+    for i in 1..data.len() {
+        if let (&MetaData::StartNode(ref kind), &MetaData::String(ref n, ref val)) =
+            (&data[i - 1].data, &data[i].data) {
+            if &**kind == "enum" && &**n == "name" { enums.push(val.clone()); }
+        }
+    }
+    if enums.len() == 0 { return; }
+
+    let mut i = 0;
+    while i + 2 < data.len() {
+        let is_call = match (&data[i].data, &data[i + 1].data) {
+            (&MetaData::StartNode(ref kind), &MetaData::String(ref n, ref val)) => {
+                &**kind == "call" && &**n == "alias" && enums.contains(val)
+            }
+            _ => false
+        };
+        // The call of `go` and `coroutine` must stay a call.
+        let in_go = i > 0 && match data[i - 1].data {
+            MetaData::StartNode(ref kind) => &**kind == "go" || &**kind == "coroutine",
+            _ => false
+        };
+        if !is_call || in_go {
+            i += 1;
+            continue;
+        }
+        // Find the end of the call and its arguments.
+        let mut depth = 0;
+        let mut args = vec![];
+        let mut mutable = false;
+        let mut end = i;
+        for j in i..data.len() {
+            match data[j].data {
+                MetaData::StartNode(ref kind) => {
+                    depth += 1;
+                    if depth == 2 && &**kind == "call_arg" { args.push(j); }
+                }
+                MetaData::EndNode(_) => {
+                    depth -= 1;
+                    if depth == 0 {
+                        end = j;
+                        break;
+                    }
+                }
+                MetaData::Bool(ref n, _) if depth == 2 && &**n == "mut" => mutable = true,
+                _ => {}
+            }
+        }
+        if args.len() != 1 || mutable {
+            i = end + 1;
+            continue;
+        }
+        rename(&mut data[i], "enum_value");
+        rename(&mut data[i + 1], "enum");
+        rename(&mut data[i + 2], "variant");
+        rename(&mut data[end], "enum_value");
+        // The argument ends right before the call.
+        rename(&mut data[args[0]], "payload");
+        rename(&mut data[end - 1], "payload");
+        i = end + 1;
+    }
+}
+
+/// Parses an integer literal without the `i64` suffix, e.g. `1_000` or `0xff`.
+///
+/// Hexadecimal literals can use all 64 bits, e.g. `0xffffffffffffffff == -1i64`.
+pub fn parse_i64(text: &str) -> Option<i64> {
+    let text: String = text.chars().filter(|&c| c != '_').collect();
+    if text.starts_with("0x") {
+        u64::from_str_radix(&text[2..], 16).ok().map(|v| v as i64)
+    } else {
+        text.parse().ok()
+    }
+}
+
 /// Used to resolve calls to imported functions.
 pub struct UseLookup {
     pub aliases: HashMap<Arc<String>, HashMap<Arc<String>, usize>>,
@@ -281,6 +448,207 @@ impl UseImport {
     }
 }
 
+/// Enum declaration, e.g. `enum Shape { Circle(f64), Empty }`.
+#[derive(Debug, Clone)]
+pub struct Enum {
+    pub name: Arc<String>,
+    pub variants: Vec<Variant>,
+    pub source_range: Range,
+}
+
+impl Enum {
+    pub fn from_meta_data(
+        mut convert: Convert,
+        ignored: &mut Vec<Range>
+    ) -> Result<(Range, Enum), ()> {
+        let start = convert.clone();
+        let node = "enum";
+        let start_range = try!(convert.start_node(node));
+        convert.update(start_range);
+
+        let mut name: Option<Arc<String>> = None;
+        let mut variants = vec![];
+        loop {
+            if let Ok(range) = convert.end_node(node) {
+                convert.update(range);
+                break;
+            } else if let Ok((range, val)) = convert.meta_string("name") {
+                convert.update(range);
+                name = Some(val);
+            } else if let Ok((range, val)) = Variant::from_meta_data(convert, ignored) {
+                convert.update(range);
+                variants.push(val);
+            } else {
+                let range = convert.ignore();
+                convert.update(range);
+                ignored.push(range);
+            }
+        }
+
+        let name = try!(name.ok_or(()));
+        Ok((convert.subtract(start), Enum {
+            name: name,
+            variants: variants,
+            source_range: convert.source(start).unwrap(),
+        }))
+    }
+
+    /// Finds variant by name.
+    pub fn variant(&self, name: &str) -> Option<&Variant> {
+        self.variants.iter().find(|v| &**v.name == name)
+    }
+}
+
+/// Variant of an enum, with type of payload if any.
+#[derive(Debug, Clone)]
+pub struct Variant {
+    pub name: Arc<String>,
+    pub ty: Option<Type>,
+}
+
+impl Variant {
+    pub fn from_meta_data(
+        mut convert: Convert,
+        ignored: &mut Vec<Range>
+    ) -> Result<(Range, Variant), ()> {
+        let start = convert.clone();
+        let node = "variant";
+        let start_range = try!(convert.start_node(node));
+        convert.update(start_range);
+
+        let mut name: Option<Arc<String>> = None;
+        let mut ty: Option<Type> = None;
+        loop {
+            if let Ok(range) = convert.end_node(node) {
+                convert.update(range);
+                break;
+            } else if let Ok((range, val)) = convert.meta_string("name") {
+                convert.update(range);
+                name = Some(val);
+            } else if let Ok((range, val)) = Type::from_meta_data("type", convert, ignored) {
+                convert.update(range);
+                ty = Some(val);
+            } else {
+                let range = convert.ignore();
+                convert.update(range);
+                ignored.push(range);
+            }
+        }
+
+        let name = try!(name.ok_or(()));
+        Ok((convert.subtract(start), Variant {
+            name: name,
+            ty: ty,
+        }))
+    }
+}
+
+/// Constant or global declaration, e.g. `const TAU = 2 * 3.14` or `global count = 0`.
+///
+/// The value is evaluated by calling a function returning the expression.
+/// Constants are evaluated once when loaded.
+/// Globals are evaluated by each runtime the first time it runs the module.
+/// Globals can be changed, but each thread started by `go` gets its own copy.
+#[derive(Debug, Clone)]
+pub struct Global {
+    pub name: Arc<String>,
+    /// Whether the value can be changed.
+    pub mutable: bool,
+    /// Function evaluating the value.
+    pub init: Function,
+    /// The number of functions in the module when loaded,
+    /// and the position of the function evaluating the value after them.
+    /// Set when resolved.
+    pub position: Cell<Option<(usize, usize)>>,
+    /// Value of a constant, evaluated when loaded.
+    pub value: Option<Variable>,
+    pub source_range: Range,
+}
+
+impl Global {
+    pub fn from_meta_data(
+        namespace: &Arc<Vec<Arc<String>>>,
+        file: &Arc<String>,
+        source: &Arc<String>,
+        node: &str,
+        mut convert: Convert,
+        ignored: &mut Vec<Range>
+    ) -> Result<(Range, Global), ()> {
+        let start = convert.clone();
+        let start_range = try!(convert.start_node(node));
+        convert.update(start_range);
+
+        let mut name: Option<Arc<String>> = None;
+        let mut ty: Option<Type> = None;
+        let mut expr: Option<Expression> = None;
+        loop {
+            if let Ok(range) = convert.end_node(node) {
+                convert.update(range);
+                break;
+            } else if let Ok((range, val)) = convert.meta_string("name") {
+                convert.update(range);
+                name = Some(val);
+            } else if let Ok((range, val)) = Type::from_meta_data("type", convert, ignored) {
+                convert.update(range);
+                ty = Some(val);
+            } else if let Ok((range, val)) = Expression::from_meta_data(
+                file, source, "expr", convert, ignored) {
+                convert.update(range);
+                expr = Some(val);
+            } else {
+                let range = convert.ignore();
+                convert.update(range);
+                ignored.push(range);
+            }
+        }
+
+        let name = try!(name.ok_or(()));
+        let expr = try!(expr.ok_or(()));
+        let source_range = convert.source(start).unwrap();
+        Ok((convert.subtract(start), Global {
+            name: name.clone(),
+            mutable: node == "global",
+            init: Function {
+                namespace: namespace.clone(),
+                resolved: Cell::new(false),
+                name: name,
+                file: file.clone(),
+                source: source.clone(),
+                args: vec![],
+                currents: vec![],
+                block: Block {
+                    expressions: vec![Expression::Return(Box::new(expr))],
+                    source_range: source_range,
+                },
+                ret: ty.unwrap_or(Type::Any),
+                source_range: source_range,
+                bytecode: None,
+                bytecode_hooks: None,
+            },
+            position: Cell::new(None),
+            value: None,
+            source_range: source_range,
+        }))
+    }
+
+    /// Returns the value of a constant declared with a literal, e.g. `const N = 3`.
+    ///
+    /// Such constants are inlined in functions.
+    pub fn literal(&self) -> Option<Variable> {
+        if self.mutable { return None; }
+        if let Expression::Return(ref expr) = self.init.block.expressions[0] {
+            match **expr {
+                Expression::Number(ref num) => return Some(Variable::f64(num.num)),
+                Expression::Bool(ref b) => return Some(Variable::bool(b.val)),
+                Expression::Text(ref text) => return Some(Variable::Text(text.text.clone())),
+                Expression::Variable(_, ref val) => return Some(val.clone()),
+                _ => {}
+            }
+        }
+        None
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Function {
     pub namespace: Arc<Vec<Arc<String>>>,
@@ -293,6 +661,10 @@ pub struct Function {
     pub ret: Type,
     pub resolved: Cell<bool>,
     pub source_range: Range,
+    /// Body compiled to bytecode, if supported.
+    pub bytecode: Option<Arc<bytecode::Chunk>>,
+    /// Body compiled to bytecode with instructions for the debugger, profiler and coverage.
+    pub bytecode_hooks: Option<Arc<bytecode::Chunk>>,
 }
 
 impl Function {
@@ -388,11 +760,19 @@ impl Function {
             block: block,
             ret: ret,
             source_range: convert.source(start).unwrap(),
+            bytecode: None,
+            bytecode_hooks: None,
         }))
     }
 
     pub fn returns(&self) -> bool { self.ret != Type::Void }
 
+    /// Returns `true` if the function can be called with `n` arguments,
+    /// using default values for the omitted trailing arguments.
+    pub fn accepts(&self, n: usize) -> bool {
+        n <= self.args.len() && self.args[n..].iter().all(|arg| arg.default.is_some())
+    }
+
     pub fn resolve_locals(&self, relative: usize, module: &Module, use_lookup: &UseLookup) {
         if self.resolved.get() { return; }
         let mut stack: Vec<Option<Arc<String>>> = vec![];
@@ -645,6 +1025,8 @@ pub struct Arg {
     pub ty: Type,
     pub source_range: Range,
     pub mutable: bool,
+    /// The value used when the argument is omitted.
+    pub default: Option<DefaultValue>,
 }
 
 impl Arg {
@@ -659,6 +1041,7 @@ impl Arg {
         let mut lifetime: Option<Arc<String>> = None;
         let mut ty: Option<Type> = None;
         let mut mutable = false;
+        let mut default: Option<DefaultValue> = None;
         loop {
             if let Ok(range) = convert.end_node(node) {
                 convert.update(range);
@@ -676,6 +1059,10 @@ impl Arg {
                     "type", convert, ignored) {
                 convert.update(range);
                 ty = Some(val);
+            } else if let Ok((range, val)) = Arg::default_from_meta_data(
+                    convert, ignored) {
+                convert.update(range);
+                default = Some(val);
             } else {
                 let range = convert.ignore();
                 convert.update(range);
@@ -694,8 +1081,44 @@ impl Arg {
             ty: ty,
             source_range: convert.source(start).unwrap(),
             mutable: mutable,
+            default: default,
         }))
     }
+
+    fn default_from_meta_data(mut convert: Convert, ignored: &mut Vec<Range>)
+    -> Result<(Range, DefaultValue), ()> {
+        let start = convert.clone();
+        let node = "default";
+        let start_range = try!(convert.start_node(node));
+        convert.update(start_range);
+
+        let mut val: Option<DefaultValue> = None;
+        loop {
+            if let Ok(range) = convert.end_node(node) {
+                convert.update(range);
+                break;
+            } else if let Ok((range, v)) = convert.meta_f64("num") {
+                convert.update(range);
+                val = Some(DefaultValue::F64(v));
+            } else if let Ok((range, v)) = convert.meta_string("i64") {
+                convert.update(range);
+                val = Some(DefaultValue::I64(try!(parse_i64(&v).ok_or(()))));
+            } else if let Ok((range, v)) = convert.meta_bool("bool") {
+                convert.update(range);
+                val = Some(DefaultValue::Bool(v));
+            } else if let Ok((range, v)) = convert.meta_string("text") {
+                convert.update(range);
+                val = Some(DefaultValue::Text(v));
+            } else {
+                let range = convert.ignore();
+                convert.update(range);
+                ignored.push(range);
+            }
+        }
+
+        let val = try!(val.ok_or(()));
+        Ok((convert.subtract(start), val))
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -813,6 +1236,10 @@ pub enum Expression {
     Continue(Continue),
     Block(Block),
     Go(Box<Go>),
+    /// Creates a suspended coroutine from a function call.
+    Coroutine(Box<Go>),
+    /// Suspends a coroutine with a value.
+    Yield(Box<Expression>),
     // TODO: Check size, perhaps use `Box<Call>`?
     Call(Call),
     Item(Item),
@@ -834,7 +1261,19 @@ pub enum Expression {
     Any(Box<ForN>),
     All(Box<ForN>),
     LinkFor(Box<ForN>),
+    /// Loops over the elements of a collection, e.g. `for x in arr { ... }`.
+    ForIn(Box<ForIn>),
+    SumIn(Box<ForIn>),
+    MinIn(Box<ForIn>),
+    MaxIn(Box<ForIn>),
+    SiftIn(Box<ForIn>),
+    AnyIn(Box<ForIn>),
+    AllIn(Box<ForIn>),
     If(Box<If>),
+    /// Match expression with pattern arms.
+    Match(Box<Match>),
+    /// Enum variant with payload if any, e.g. `Shape::Circle(2)`.
+    EnumValue(Box<EnumValue>),
     Compare(Box<Compare>),
     UnOp(Box<UnOpExpression>),
     Norm(Box<Norm>),
@@ -890,6 +1329,10 @@ impl Expression {
                     file, source, "return", convert, ignored) {
                 convert.update(range);
                 result = Some(Expression::Return(Box::new(val)));
+            } else if let Ok((range, val)) = Expression::from_meta_data(
+                    file, source, "yield", convert, ignored) {
+                convert.update(range);
+                result = Some(Expression::Yield(Box::new(val)));
             } else if let Ok((range, _)) = convert.meta_bool("return_void") {
                 convert.update(range);
                 result = Some(Expression::ReturnVoid(
@@ -932,6 +1375,14 @@ impl Expression {
                     text: val,
                     source_range: convert.source(start).unwrap(),
                 }));
+            } else if let Ok((range, val)) = convert.meta_string("i64") {
+                convert.update(range);
+                if let Some(v) = parse_i64(&val) {
+                    result = Some(Expression::Variable(
+                        convert.source(start).unwrap(), Variable::I64(v)));
+                } else {
+                    return Err(());
+                }
             } else if let Ok((range, val)) = convert.meta_f64("num") {
                 convert.update(range);
                 result = Some(Expression::Number(Number {
@@ -967,6 +1418,10 @@ impl Expression {
                     file, source, convert, ignored) {
                 convert.update(range);
                 result = Some(Expression::Go(Box::new(val)));
+            } else if let Ok((range, val)) = Go::coroutine_from_meta_data(
+                    file, source, convert, ignored) {
+                convert.update(range);
+                result = Some(Expression::Coroutine(Box::new(val)));
             } else if let Ok((range, val)) = Call::from_meta_data(
                     file, source, convert, ignored) {
                 convert.update(range);
@@ -1027,6 +1482,34 @@ impl Expression {
                     file, source, "link_for", convert, ignored) {
                 convert.update(range);
                 result = Some(Expression::LinkFor(Box::new(val)));
+            } else if let Ok((range, val)) = ForIn::from_meta_data(
+                    file, source, "for_in", convert, ignored) {
+                convert.update(range);
+                result = Some(Expression::ForIn(Box::new(val)));
+            } else if let Ok((range, val)) = ForIn::from_meta_data(
+                    file, source, "sum_in", convert, ignored) {
+                convert.update(range);
+                result = Some(Expression::SumIn(Box::new(val)));
+            } else if let Ok((range, val)) = ForIn::from_meta_data(
+                    file, source, "min_in", convert, ignored) {
+                convert.update(range);
+                result = Some(Expression::MinIn(Box::new(val)));
+            } else if let Ok((range, val)) = ForIn::from_meta_data(
+                    file, source, "max_in", convert, ignored) {
+                convert.update(range);
+                result = Some(Expression::MaxIn(Box::new(val)));
+            } else if let Ok((range, val)) = ForIn::from_meta_data(
+                    file, source, "sift_in", convert, ignored) {
+                convert.update(range);
+                result = Some(Expression::SiftIn(Box::new(val)));
+            } else if let Ok((range, val)) = ForIn::from_meta_data(
+                    file, source, "any_in", convert, ignored) {
+                convert.update(range);
+                result = Some(Expression::AnyIn(Box::new(val)));
+            } else if let Ok((range, val)) = ForIn::from_meta_data(
+                    file, source, "all_in", convert, ignored) {
+                convert.update(range);
+                result = Some(Expression::AllIn(Box::new(val)));
             } else if let Ok((range, val)) = Loop::from_meta_data(
                     file, source, convert, ignored) {
                 convert.update(range);
@@ -1035,6 +1518,14 @@ impl Expression {
                     file, source, convert, ignored) {
                 convert.update(range);
                 result = Some(Expression::If(Box::new(val)));
+            } else if let Ok((range, val)) = Match::from_meta_data(
+                    file, source, convert, ignored) {
+                convert.update(range);
+                result = Some(Expression::Match(Box::new(val)));
+            } else if let Ok((range, val)) = EnumValue::from_meta_data(
+                    file, source, convert, ignored) {
+                convert.update(range);
+                result = Some(Expression::EnumValue(Box::new(val)));
             } else if let Ok((range, val)) = Compare::from_meta_data(
                     file, source, convert, ignored) {
                 convert.update(range);
@@ -1091,6 +1582,8 @@ impl Expression {
             Continue(ref c) => c.source_range,
             Block(ref bl) => bl.source_range,
             Go(ref go) => go.source_range,
+            Coroutine(ref go) => go.source_range,
+            Yield(ref expr) => expr.source_range(),
             Call(ref call) => call.source_range,
             Item(ref it) => it.source_range,
             BinOp(ref binop) => binop.source_range,
@@ -1111,7 +1604,16 @@ impl Expression {
             Any(ref for_n_expr) => for_n_expr.source_range,
             All(ref for_n_expr) => for_n_expr.source_range,
             LinkFor(ref for_n_expr) => for_n_expr.source_range,
+            ForIn(ref for_in_expr) => for_in_expr.source_range,
+            SumIn(ref for_in_expr) => for_in_expr.source_range,
+            MinIn(ref for_in_expr) => for_in_expr.source_range,
+            MaxIn(ref for_in_expr) => for_in_expr.source_range,
+            SiftIn(ref for_in_expr) => for_in_expr.source_range,
+            AnyIn(ref for_in_expr) => for_in_expr.source_range,
+            AllIn(ref for_in_expr) => for_in_expr.source_range,
             If(ref if_expr) => if_expr.source_range,
+            Match(ref match_expr) => match_expr.source_range,
+            EnumValue(ref enum_value) => enum_value.source_range,
             Compare(ref comp) => comp.source_range,
             Norm(ref norm) => norm.source_range,
             UnOp(ref unop) => unop.source_range,
@@ -1154,8 +1656,13 @@ impl Expression {
             Continue(_) => {}
             Block(ref bl) =>
                 bl.resolve_locals(relative, stack, closure_stack, module, use_lookup),
-            Go(ref go) =>
+            Go(ref go) | Coroutine(ref go) =>
                 go.resolve_locals(relative, stack, closure_stack, module, use_lookup),
+            Yield(ref expr) => {
+                let st = stack.len();
+                expr.resolve_locals(relative, stack, closure_stack, module, use_lookup);
+                stack.truncate(st);
+            }
             Call(ref call) =>
                 call.resolve_locals(relative, stack, closure_stack, module, use_lookup),
             Item(ref it) =>
@@ -1193,8 +1700,19 @@ impl Expression {
                 for_n_expr.resolve_locals(relative, stack, closure_stack, module, use_lookup),
             LinkFor(ref for_n_expr) =>
                 for_n_expr.resolve_locals(relative, stack, closure_stack, module, use_lookup),
+            ForIn(ref for_in_expr) | SumIn(ref for_in_expr) | MinIn(ref for_in_expr) |
+            MaxIn(ref for_in_expr) | SiftIn(ref for_in_expr) | AnyIn(ref for_in_expr) |
+            AllIn(ref for_in_expr) =>
+                for_in_expr.resolve_locals(relative, stack, closure_stack, module, use_lookup),
             If(ref if_expr) =>
                 if_expr.resolve_locals(relative, stack, closure_stack, module, use_lookup),
+            Match(ref match_expr) =>
+                match_expr.resolve_locals(relative, stack, closure_stack, module, use_lookup),
+            EnumValue(ref enum_value) => {
+                if let Some(ref payload) = enum_value.payload {
+                    payload.resolve_locals(relative, stack, closure_stack, module, use_lookup)
+                }
+            }
             Compare(ref comp) =>
                 comp.resolve_locals(relative, stack, closure_stack, module, use_lookup),
             Norm(ref norm) =>
@@ -1525,6 +2043,12 @@ impl Add {
             } else if let Ok((range, _)) = convert.meta_bool("||") {
                 convert.update(range);
                 ops.push(BinOp::OrElse);
+            } else if let Ok((range, _)) = convert.meta_bool("|") {
+                convert.update(range);
+                ops.push(BinOp::BitOr);
+            } else if let Ok((range, _)) = convert.meta_bool("xor") {
+                convert.update(range);
+                ops.push(BinOp::BitXor);
             } else {
                 let range = convert.ignore();
                 convert.update(range);
@@ -1614,6 +2138,15 @@ impl Mul {
             } else if let Ok((range, _)) = convert.meta_bool("&&") {
                 convert.update(range);
                 ops.push(BinOp::AndAlso);
+            } else if let Ok((range, _)) = convert.meta_bool("&") {
+                convert.update(range);
+                ops.push(BinOp::BitAnd);
+            } else if let Ok((range, _)) = convert.meta_bool("<<") {
+                convert.update(range);
+                ops.push(BinOp::Shl);
+            } else if let Ok((range, _)) = convert.meta_bool(">>") {
+                convert.update(range);
+                ops.push(BinOp::Shr);
             } else {
                 let range = convert.ignore();
                 convert.update(range);
@@ -1719,6 +2252,11 @@ pub enum BinOp {
     Pow,
     OrElse,
     AndAlso,
+    BitAnd,
+    BitOr,
+    BitXor,
+    Shl,
+    Shr,
 }
 
 impl BinOp {
@@ -1734,6 +2272,20 @@ impl BinOp {
             BinOp::Pow => "^",
             BinOp::OrElse => "||",
             BinOp::AndAlso => "&&",
+            BinOp::BitAnd => "&",
+            BinOp::BitOr => "|",
+            BinOp::BitXor => "xor",
+            BinOp::Shl => "<<",
+            BinOp::Shr => ">>",
+        }
+    }
+
+    /// Returns `true` for operators that only work on integers.
+    pub fn is_bitwise(self) -> bool {
+        match self {
+            BinOp::BitAnd | BinOp::BitOr | BinOp::BitXor |
+            BinOp::Shl | BinOp::Shr => true,
+            _ => false
         }
     }
 
@@ -1794,6 +2346,8 @@ pub struct Item {
     pub name: Arc<String>,
     pub stack_id: Cell<Option<usize>>,
     pub static_stack_id: Cell<Option<usize>>,
+    /// Index of constant or global in module, when not a local.
+    pub global: Cell<Option<usize>>,
     pub current: bool,
     pub try: bool,
     pub ids: Vec<Id>,
@@ -1809,6 +2363,7 @@ impl Item {
             current: false,
             stack_id: Cell::new(None),
             static_stack_id: Cell::new(None),
+            global: Cell::new(None),
             try: false,
             ids: vec![],
             try_ids: vec![],
@@ -1823,6 +2378,7 @@ impl Item {
             current: self.current,
             stack_id: Cell::new(None),
             static_stack_id: Cell::new(None),
+            global: Cell::new(None),
             try: self.try,
             ids: self.ids.iter().take(n).map(|id| id.clone()).collect(),
             try_ids: {
@@ -1899,6 +2455,7 @@ impl Item {
             name: name,
             stack_id: Cell::new(None),
             static_stack_id: Cell::new(None),
+            global: Cell::new(None),
             current: current,
             try: try,
             ids: ids,
@@ -1926,6 +2483,9 @@ impl Item {
                 }
             }
         }
+        if self.static_stack_id.get().is_none() && !self.current {
+            self.global.set(module.find_global(&self.name));
+        }
         for id in &self.ids {
             if id.resolve_locals(relative, stack, closure_stack, module, use_lookup) {
                 stack.push(None);
@@ -1943,13 +2503,32 @@ pub struct Go {
 
 impl Go {
     pub fn from_meta_data(
+        file: &Arc<String>,
+        source: &Arc<String>,
+        convert: Convert,
+        ignored: &mut Vec<Range>
+    ) -> Result<(Range, Go), ()> {
+        Go::node_from_meta_data("go", file, source, convert, ignored)
+    }
+
+    /// Reads a function call that creates a coroutine.
+    pub fn coroutine_from_meta_data(
+        file: &Arc<String>,
+        source: &Arc<String>,
+        convert: Convert,
+        ignored: &mut Vec<Range>
+    ) -> Result<(Range, Go), ()> {
+        Go::node_from_meta_data("coroutine", file, source, convert, ignored)
+    }
+
+    fn node_from_meta_data(
+        node: &str,
         file: &Arc<String>,
         source: &Arc<String>,
         mut convert: Convert,
         ignored: &mut Vec<Range>
     ) -> Result<(Range, Go), ()> {
         let start = convert.clone();
-        let node = "go";
         let start_range = try!(convert.start_node(node));
         convert.update(start_range);
 
@@ -2160,15 +2739,25 @@ impl Call {
         module: &Module,
         use_lookup: &UseLookup,
     ) {
+        use prelude::omits_args;
+
         let st = stack.len();
         let f_index = if let Some(ref alias) = self.alias {
-            if let Some(&i) = use_lookup.aliases.get(alias).and_then(|map| map.get(&self.name)) {
+            let n = self.arg_len();
+            if let Some(&i) = use_lookup.aliases.get(alias).and_then(|map| {
+                map.get(&self.name).or_else(|| map.iter()
+                    .find(|&(name, &i)| {
+                        let f = &module.functions[i];
+                        omits_args(&self.name, name, f.args.len(), n) && f.accepts(n)
+                    })
+                    .map(|(_, i)| i))
+            }) {
                 FnIndex::Loaded(i as isize - relative as isize)
             } else {
                 FnIndex::None
             }
         } else {
-            module.find_function(&self.name, relative)
+            module.find_function_defaults(&self.name, self.arg_len(), relative)
         };
         self.f_index.set(f_index);
         match f_index {
@@ -2178,7 +2767,8 @@ impl Call {
                     stack.push(None);
                 }
             }
-            FnIndex::ExternalVoid(_) | FnIndex::ExternalReturn(_) => {
+            FnIndex::ExternalVoid(_) | FnIndex::ExternalReturn(_) |
+            FnIndex::ExternalDefaults(_) => {
                 // Don't push return since last value in block
                 // is used as return value.
             }
@@ -3099,6 +3689,10 @@ pub struct ForN {
     pub end: Expression,
     pub block: Block,
     pub label: Option<Arc<String>>,
+    /// Whether the loop is split across worker threads.
+    pub parallel: bool,
+    /// Whether `sum` or `prod` adds up `i64` values, set after type checking.
+    pub i64_acc: Cell<bool>,
     pub source_range: Range,
 }
 
@@ -3117,6 +3711,7 @@ impl ForN {
         let mut indices: Vec<(Arc<String>, Option<Expression>, Option<Expression>)> = vec![];
         let mut block: Option<Block> = None;
         let mut label: Option<Arc<String>> = None;
+        let mut parallel = false;
         loop {
             if let Ok(range) = convert.end_node(node) {
                 convert.update(range);
@@ -3128,6 +3723,9 @@ impl ForN {
             } else if let Ok((range, val)) = convert.meta_string("label") {
                 convert.update(range);
                 label = Some(val);
+            } else if let Ok((range, val)) = convert.meta_bool("par") {
+                convert.update(range);
+                parallel = val;
             } else if let Ok((range, val)) = convert.meta_string("name") {
                 convert.update(range);
                 let mut start_expr: Option<Expression> = None;
@@ -3155,6 +3753,7 @@ impl ForN {
             convert.subtract(start),
             convert.source(start).unwrap(),
             label,
+            parallel,
             &indices,
             block
         )
@@ -3165,6 +3764,7 @@ impl ForN {
         range: Range,
         source_range: Range,
         label: Option<Arc<String>>,
+        parallel: bool,
         indices: &[(Arc<String>, Option<Expression>, Option<Expression>)],
         mut block: Option<Block>
     ) -> Result<(Range, ForN), ()> {
@@ -3180,6 +3780,8 @@ impl ForN {
                 range,
                 source_range,
                 None,
+                // Only the outer loop is parallel.
+                false,
                 &indices[1..],
                 block
             ));
@@ -3216,6 +3818,8 @@ impl ForN {
             end: end_expr,
             block: block,
             label: label,
+            parallel: parallel,
+            i64_acc: Cell::new(false),
             source_range: source_range,
         }))
     }
@@ -3241,14 +3845,114 @@ impl ForN {
     }
 }
 
+/// Loops over the elements of an array, the keys of an object or the items of a link.
+///
+/// When there are two names, e.g. `for k, v in obj`, the first is the index or key.
 #[derive(Debug, Clone)]
-pub struct Loop {
+pub struct ForIn {
+    /// Name of index or key, if any.
+    pub key: Option<Arc<String>>,
+    /// Name of element.
+    pub name: Arc<String>,
+    /// Whether changes to the element are written back to the collection.
+    pub mutable: bool,
+    /// The collection to loop over.
+    pub expr: Expression,
     pub block: Block,
     pub label: Option<Arc<String>>,
+    /// Whether `sum` adds up `i64` values, set after type checking.
+    pub i64_acc: Cell<bool>,
     pub source_range: Range,
 }
 
-impl Loop {
+impl ForIn {
+    pub fn from_meta_data(
+        file: &Arc<String>,
+        source: &Arc<String>,
+        node: &str,
+        mut convert: Convert,
+        ignored: &mut Vec<Range>)
+    -> Result<(Range, ForIn), ()> {
+        let start = convert.clone();
+        let start_range = try!(convert.start_node(node));
+        convert.update(start_range);
+
+        let mut names: Vec<Arc<String>> = vec![];
+        let mut mutable = false;
+        let mut expr: Option<Expression> = None;
+        let mut block: Option<Block> = None;
+        let mut label: Option<Arc<String>> = None;
+        loop {
+            if let Ok(range) = convert.end_node(node) {
+                convert.update(range);
+                break;
+            } else if let Ok((range, val)) = Expression::from_meta_data(
+                    file, source, "in", convert, ignored) {
+                convert.update(range);
+                expr = Some(val);
+            } else if let Ok((range, val)) = Block::from_meta_data(
+                    file, source, "block", convert, ignored) {
+                convert.update(range);
+                block = Some(val);
+            } else if let Ok((range, val)) = convert.meta_string("label") {
+                convert.update(range);
+                label = Some(val);
+            } else if let Ok((range, val)) = convert.meta_string("name") {
+                convert.update(range);
+                names.push(val);
+            } else if let Ok((range, val)) = convert.meta_bool("mut") {
+                convert.update(range);
+                mutable = val;
+            } else {
+                let range = convert.ignore();
+                convert.update(range);
+                ignored.push(range);
+            }
+        }
+
+        let name = try!(names.pop().ok_or(()));
+        let expr = try!(expr.ok_or(()));
+        let block = try!(block.ok_or(()));
+        Ok((convert.subtract(start), ForIn {
+            key: names.pop(),
+            name: name,
+            mutable: mutable,
+            expr: expr,
+            block: block,
+            label: label,
+            i64_acc: Cell::new(false),
+            source_range: convert.source(start).unwrap(),
+        }))
+    }
+
+    pub fn resolve_locals(
+        &self,
+        relative: usize,
+        stack: &mut Vec<Option<Arc<String>>>,
+        closure_stack: &mut Vec<usize>,
+        module: &Module,
+        use_lookup: &UseLookup,
+    ) {
+        let st = stack.len();
+        self.expr.resolve_locals(relative, stack, closure_stack, module, use_lookup);
+        stack.truncate(st);
+        if let Some(ref key) = self.key {
+            stack.push(Some(key.clone()));
+        }
+        stack.push(Some(self.name.clone()));
+        self.block.resolve_locals(relative, stack, closure_stack, module, use_lookup);
+        stack.truncate(st);
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Loop {
+    pub block: Block,
+    pub label: Option<Arc<String>>,
+    pub source_range: Range,
+}
+
+impl Loop {
     pub fn from_meta_data(
         file: &Arc<String>,
         source: &Arc<String>,
@@ -3486,6 +4190,374 @@ impl If {
     }
 }
 
+/// Match expression.
+#[derive(Debug, Clone)]
+pub struct Match {
+    /// The value to match.
+    pub expr: Expression,
+    /// The arms, tried in order.
+    pub arms: Vec<MatchArm>,
+    pub source_range: Range,
+}
+
+impl Match {
+    pub fn from_meta_data(
+        file: &Arc<String>,
+        source: &Arc<String>,
+        mut convert: Convert,
+        ignored: &mut Vec<Range>)
+    -> Result<(Range, Match), ()> {
+        let start = convert.clone();
+        let node = "match";
+        let start_range = try!(convert.start_node(node));
+        convert.update(start_range);
+
+        let mut expr: Option<Expression> = None;
+        let mut arms: Vec<MatchArm> = vec![];
+        loop {
+            if let Ok(range) = convert.end_node(node) {
+                convert.update(range);
+                break;
+            } else if let Ok((range, val)) = Expression::from_meta_data(
+                file, source, "expr", convert, ignored) {
+                convert.update(range);
+                expr = Some(val);
+            } else if let Ok((range, val)) = MatchArm::from_meta_data(
+                file, source, convert, ignored) {
+                convert.update(range);
+                arms.push(val);
+            } else {
+                let range = convert.ignore();
+                convert.update(range);
+                ignored.push(range);
+            }
+        }
+
+        let expr = try!(expr.ok_or(()));
+        Ok((convert.subtract(start), Match {
+            expr: expr,
+            arms: arms,
+            source_range: convert.source(start).unwrap(),
+        }))
+    }
+
+    pub fn resolve_locals(
+        &self,
+        relative: usize,
+        stack: &mut Vec<Option<Arc<String>>>,
+        closure_stack: &mut Vec<usize>,
+        module: &Module,
+        use_lookup: &UseLookup,
+    ) {
+        let st = stack.len();
+        self.expr.resolve_locals(relative, stack, closure_stack, module, use_lookup);
+        stack.truncate(st);
+        for arm in &self.arms {
+            // Bound variables are pushed on the stack in the order they appear in the pattern.
+            arm.pattern.resolve_locals(stack);
+            if let Some(ref guard) = arm.guard {
+                let st = stack.len();
+                guard.resolve_locals(relative, stack, closure_stack, module, use_lookup);
+                stack.truncate(st);
+            }
+            arm.expr.resolve_locals(relative, stack, closure_stack, module, use_lookup);
+            stack.truncate(st);
+        }
+    }
+}
+
+/// Enum variant expression, e.g. `Shape::Circle(2)`.
+#[derive(Debug, Clone)]
+pub struct EnumValue {
+    /// The name of the enum.
+    pub name: Arc<String>,
+    pub variant: Arc<String>,
+    pub payload: Option<Expression>,
+    pub source_range: Range,
+}
+
+impl EnumValue {
+    pub fn from_meta_data(
+        file: &Arc<String>,
+        source: &Arc<String>,
+        mut convert: Convert,
+        ignored: &mut Vec<Range>)
+    -> Result<(Range, EnumValue), ()> {
+        let start = convert.clone();
+        let node = "enum_value";
+        let start_range = try!(convert.start_node(node));
+        convert.update(start_range);
+
+        let mut name: Option<Arc<String>> = None;
+        let mut variant: Option<Arc<String>> = None;
+        let mut payload: Option<Expression> = None;
+        loop {
+            if let Ok(range) = convert.end_node(node) {
+                convert.update(range);
+                break;
+            } else if let Ok((range, val)) = convert.meta_string("enum") {
+                convert.update(range);
+                name = Some(val);
+            } else if let Ok((range, val)) = convert.meta_string("variant") {
+                convert.update(range);
+                variant = Some(val);
+            } else if let Ok((range, val)) = Expression::from_meta_data(
+                file, source, "payload", convert, ignored) {
+                convert.update(range);
+                payload = Some(val);
+            } else {
+                let range = convert.ignore();
+                convert.update(range);
+                ignored.push(range);
+            }
+        }
+
+        let name = try!(name.ok_or(()));
+        let variant = try!(variant.ok_or(()));
+        Ok((convert.subtract(start), EnumValue {
+            name: name,
+            variant: variant,
+            payload: payload,
+            source_range: convert.source(start).unwrap(),
+        }))
+    }
+}
+
+/// An arm of a match expression.
+#[derive(Debug, Clone)]
+pub struct MatchArm {
+    pub pattern: Pattern,
+    /// Condition that must be true for the arm to be taken.
+    pub guard: Option<Expression>,
+    pub expr: Expression,
+    pub source_range: Range,
+}
+
+impl MatchArm {
+    pub fn from_meta_data(
+        file: &Arc<String>,
+        source: &Arc<String>,
+        mut convert: Convert,
+        ignored: &mut Vec<Range>)
+    -> Result<(Range, MatchArm), ()> {
+        let start = convert.clone();
+        let node = "arm";
+        let start_range = try!(convert.start_node(node));
+        convert.update(start_range);
+
+        let mut pattern: Option<Pattern> = None;
+        let mut guard: Option<Expression> = None;
+        let mut expr: Option<Expression> = None;
+        loop {
+            if let Ok(range) = convert.end_node(node) {
+                convert.update(range);
+                break;
+            } else if let Ok((range, val)) = Pattern::from_meta_data(
+                "pattern", convert, ignored) {
+                convert.update(range);
+                pattern = Some(val);
+            } else if let Ok((range, val)) = Expression::from_meta_data(
+                file, source, "guard", convert, ignored) {
+                convert.update(range);
+                guard = Some(val);
+            } else if let Ok((range, val)) = Expression::from_meta_data(
+                file, source, "expr", convert, ignored) {
+                convert.update(range);
+                expr = Some(val);
+            } else {
+                let range = convert.ignore();
+                convert.update(range);
+                ignored.push(range);
+            }
+        }
+
+        let pattern = try!(pattern.ok_or(()));
+        let expr = try!(expr.ok_or(()));
+        Ok((convert.subtract(start), MatchArm {
+            pattern: pattern,
+            guard: guard,
+            expr: expr,
+            source_range: convert.source(start).unwrap(),
+        }))
+    }
+}
+
+/// A pattern in a match arm.
+#[derive(Debug, Clone)]
+pub enum Pattern {
+    /// Matches anything, `_`.
+    Wildcard,
+    /// Matches anything and binds the value to a name.
+    Bind(Arc<String>),
+    Number(f64),
+    /// Matches numbers in the range `[start, end)`.
+    Range(f64, f64),
+    Text(Arc<String>),
+    Bool(bool),
+    Some(Box<Pattern>),
+    None,
+    Ok(Box<Pattern>),
+    /// Matches the message of an error.
+    Err(Box<Pattern>),
+    /// Matches objects with the fields, ignoring other fields.
+    Object(Vec<(Arc<String>, Pattern)>),
+    /// Matches enum variant and payload, e.g. `Shape::Circle(r)`.
+    Enum(Arc<String>, Arc<String>, Option<Box<Pattern>>),
+}
+
+impl Pattern {
+    pub fn from_meta_data(
+        node: &str,
+        mut convert: Convert,
+        ignored: &mut Vec<Range>)
+    -> Result<(Range, Pattern), ()> {
+        let start = convert.clone();
+        let start_range = try!(convert.start_node(node));
+        convert.update(start_range);
+
+        let mut result: Option<Pattern> = None;
+        let mut range_start: Option<f64> = None;
+        let mut fields: Option<Vec<(Arc<String>, Pattern)>> = None;
+        let mut enum_name: Option<Arc<String>> = None;
+        let mut payload: Option<Pattern> = None;
+        loop {
+            if let Ok(range) = convert.end_node(node) {
+                convert.update(range);
+                break;
+            } else if let Ok((range, val)) = convert.meta_string("enum") {
+                convert.update(range);
+                enum_name = Some(val);
+            } else if let Ok((range, val)) = convert.meta_string("variant") {
+                convert.update(range);
+                result = Some(Pattern::Enum(try!(enum_name.clone().ok_or(())), val, None));
+            } else if let Ok((range, val)) = Pattern::from_meta_data(
+                "payload_pattern", convert, ignored) {
+                convert.update(range);
+                payload = Some(val);
+            } else if let Ok((range, val)) = convert.meta_string("name") {
+                convert.update(range);
+                result = Some(if &**val == "_" { Pattern::Wildcard } else { Pattern::Bind(val) });
+            } else if let Ok((range, val)) = convert.meta_f64("num") {
+                convert.update(range);
+                result = Some(Pattern::Number(val));
+            } else if let Ok((range, val)) = convert.meta_f64("range_start") {
+                convert.update(range);
+                range_start = Some(val);
+            } else if let Ok((range, val)) = convert.meta_f64("range_end") {
+                convert.update(range);
+                result = Some(Pattern::Range(try!(range_start.ok_or(())), val));
+            } else if let Ok((range, val)) = convert.meta_string("text") {
+                convert.update(range);
+                result = Some(Pattern::Text(val));
+            } else if let Ok((range, val)) = convert.meta_bool("bool") {
+                convert.update(range);
+                result = Some(Pattern::Bool(val));
+            } else if let Ok((range, _)) = convert.meta_bool("none") {
+                convert.update(range);
+                result = Some(Pattern::None);
+            } else if let Ok((range, val)) = Pattern::from_meta_data("some", convert, ignored) {
+                convert.update(range);
+                result = Some(Pattern::Some(Box::new(val)));
+            } else if let Ok((range, val)) = Pattern::from_meta_data("ok", convert, ignored) {
+                convert.update(range);
+                result = Some(Pattern::Ok(Box::new(val)));
+            } else if let Ok((range, val)) = Pattern::from_meta_data("err", convert, ignored) {
+                convert.update(range);
+                result = Some(Pattern::Err(Box::new(val)));
+            } else if let Ok((range, _)) = convert.meta_bool("object") {
+                convert.update(range);
+                fields = Some(vec![]);
+            } else if let Ok((range, val)) = Pattern::field_from_meta_data(convert, ignored) {
+                convert.update(range);
+                if let Some(ref mut fields) = fields {
+                    fields.push(val);
+                }
+            } else {
+                let range = convert.ignore();
+                convert.update(range);
+                ignored.push(range);
+            }
+        }
+
+        if let Some(fields) = fields {
+            result = Some(Pattern::Object(fields));
+        }
+        if let (Some(Pattern::Enum(_, _, ref mut p)), Some(payload)) = (result.as_mut(), payload) {
+            *p = Some(Box::new(payload));
+        }
+        let result = try!(result.ok_or(()));
+        Ok((convert.subtract(start), result))
+    }
+
+    fn field_from_meta_data(
+        mut convert: Convert,
+        ignored: &mut Vec<Range>)
+    -> Result<(Range, (Arc<String>, Pattern)), ()> {
+        let start = convert.clone();
+        let node = "field";
+        let start_range = try!(convert.start_node(node));
+        convert.update(start_range);
+
+        let mut key: Option<Arc<String>> = None;
+        let mut pattern: Option<Pattern> = None;
+        loop {
+            if let Ok(range) = convert.end_node(node) {
+                convert.update(range);
+                break;
+            } else if let Ok((range, val)) = convert.meta_string("key") {
+                convert.update(range);
+                key = Some(val);
+            } else if let Ok((range, val)) = convert.meta_string("name") {
+                // Binds field to variable with same name.
+                convert.update(range);
+                key = Some(val.clone());
+                pattern = Some(Pattern::Bind(val));
+            } else if let Ok((range, val)) = Pattern::from_meta_data(
+                "pattern", convert, ignored) {
+                convert.update(range);
+                pattern = Some(val);
+            } else {
+                let range = convert.ignore();
+                convert.update(range);
+                ignored.push(range);
+            }
+        }
+
+        let key = try!(key.ok_or(()));
+        let pattern = try!(pattern.ok_or(()));
+        Ok((convert.subtract(start), (key, pattern)))
+    }
+
+    /// Returns `true` if the pattern binds a variable with the name.
+    pub fn binds(&self, name: &str) -> bool {
+        match *self {
+            Pattern::Bind(ref n) => &**n == name,
+            Pattern::Some(ref p) | Pattern::Ok(ref p) | Pattern::Err(ref p) => p.binds(name),
+            Pattern::Object(ref fields) => fields.iter().any(|&(_, ref p)| p.binds(name)),
+            Pattern::Enum(_, _, ref p) => p.as_ref().map(|p| p.binds(name)).unwrap_or(false),
+            Pattern::Wildcard | Pattern::Number(_) | Pattern::Range(_, _) |
+            Pattern::Text(_) | Pattern::Bool(_) | Pattern::None => false
+        }
+    }
+
+    /// Pushes the names of bound variables on the stack.
+    pub fn resolve_locals(&self, stack: &mut Vec<Option<Arc<String>>>) {
+        match *self {
+            Pattern::Bind(ref name) => stack.push(Some(name.clone())),
+            Pattern::Some(ref p) | Pattern::Ok(ref p) | Pattern::Err(ref p) =>
+                p.resolve_locals(stack),
+            Pattern::Object(ref fields) => {
+                for &(_, ref p) in fields { p.resolve_locals(stack); }
+            }
+            Pattern::Enum(_, _, ref p) => {
+                if let Some(ref p) = *p { p.resolve_locals(stack); }
+            }
+            Pattern::Wildcard | Pattern::Number(_) | Pattern::Range(_, _) |
+            Pattern::Text(_) | Pattern::Bool(_) | Pattern::None => {}
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Compare {
     pub op: CompareOp,
@@ -11,12 +11,16 @@ use super::{
     Compare,
     Expression,
     For,
+    ForIn,
     ForN,
     Go,
     Id,
     If,
     Item,
     Link,
+    EnumValue,
+    Match,
+    MatchArm,
     Object,
     Norm,
     Number,
@@ -25,19 +29,24 @@ use super::{
     Vec4,
     TryExpr,
 };
+use Variable;
 
 /// Replaces an item with a number.
-/// Returns `(true, new_expression)` if item found declared with same name.
-/// Returns `(false, cloned_expression)` if there was no item with same name.
-/// The flag is used to just clone the rest of expressions in a block.
 pub fn number(expr: &Expression, name: &Arc<String>, val: f64) -> Expression {
+    variable(expr, name, &Variable::f64(val))
+}
+
+/// Replaces an item with a value.
+/// Numbers are replaced with number expressions.
+/// Stops replacing in a block after a declaration with same name.
+pub fn variable(expr: &Expression, name: &Arc<String>, val: &Variable) -> Expression {
     use super::Expression as E;
 
     match *expr {
         E::Link(ref link_expr) => {
             let mut new_items: Vec<Expression> = vec![];
             for item in &link_expr.items {
-                new_items.push(number(item, name, val));
+                new_items.push(variable(item, name, val));
             }
             E::Link(Link {
                 items: new_items,
@@ -48,22 +57,25 @@ pub fn number(expr: &Expression, name: &Arc<String>, val: f64) -> Expression {
         E::BinOp(ref bin_op_expr) => {
             E::BinOp(Box::new(BinOpExpression {
                 op: bin_op_expr.op,
-                left: number(&bin_op_expr.left, name, val),
-                right: number(&bin_op_expr.right, name, val),
+                left: variable(&bin_op_expr.left, name, val),
+                right: variable(&bin_op_expr.right, name, val),
                 source_range: bin_op_expr.source_range,
             }))
         }
         E::Item(ref item) => {
             if &item.name == name {
-                E::Number(Number {
-                    num: val,
-                    source_range: item.source_range,
-                })
+                match *val {
+                    Variable::F64(num, _) => E::Number(Number {
+                        num: num,
+                        source_range: item.source_range,
+                    }),
+                    _ => E::Variable(item.source_range, val.clone())
+                }
             } else {
                 let mut new_ids: Vec<Id> = vec![];
                 for id in &item.ids {
                     if let &Id::Expression(ref expr) = id {
-                        new_ids.push(Id::Expression(number(expr, name, val)));
+                        new_ids.push(Id::Expression(variable(expr, name, val)));
                     } else {
                         new_ids.push(id.clone());
                     }
@@ -73,6 +85,7 @@ pub fn number(expr: &Expression, name: &Arc<String>, val: f64) -> Expression {
                     current: item.current,
                     stack_id: item.stack_id.clone(),
                     static_stack_id: item.static_stack_id.clone(),
+                    global: item.global.clone(),
                     try: item.try.clone(),
                     ids: new_ids,
                     try_ids: item.try_ids.clone(),
@@ -81,13 +94,13 @@ pub fn number(expr: &Expression, name: &Arc<String>, val: f64) -> Expression {
             }
         }
         E::Block(ref block) => {
-            E::Block(number_block(block, name, val))
+            E::Block(variable_block(block, name, val))
         }
         E::Assign(ref assign_expr) => {
             E::Assign(Box::new(Assign {
                 op: assign_expr.op.clone(),
-                left: number(&assign_expr.left, name, val),
-                right: number(&assign_expr.right, name, val),
+                left: variable(&assign_expr.left, name, val),
+                right: variable(&assign_expr.right, name, val),
                 source_range: assign_expr.source_range,
             }))
         }
@@ -95,7 +108,7 @@ pub fn number(expr: &Expression, name: &Arc<String>, val: f64) -> Expression {
             let mut new_key_values: Vec<(Arc<String>, Expression)> = vec![];
             for key_value in &obj_expr.key_values {
                 new_key_values.push((key_value.0.clone(),
-                    number(&key_value.1, name, val)));
+                    variable(&key_value.1, name, val)));
             }
             E::Object(Box::new(Object {
                 key_values: new_key_values,
@@ -103,12 +116,12 @@ pub fn number(expr: &Expression, name: &Arc<String>, val: f64) -> Expression {
             }))
         }
         E::Call(ref call_expr) => {
-            E::Call(number_call(call_expr, name, val))
+            E::Call(variable_call(call_expr, name, val))
         }
         E::Array(ref array_expr) => {
             let mut new_items: Vec<Expression> = vec![];
             for item in &array_expr.items {
-                new_items.push(number(item, name, val));
+                new_items.push(variable(item, name, val));
             }
             E::Array(Box::new(Array {
                 items: new_items,
@@ -117,20 +130,29 @@ pub fn number(expr: &Expression, name: &Arc<String>, val: f64) -> Expression {
         }
         E::ArrayFill(ref array_fill_expr) => {
             E::ArrayFill(Box::new(ArrayFill {
-                fill: number(&array_fill_expr.fill, name, val),
-                n: number(&array_fill_expr.n, name, val),
+                fill: variable(&array_fill_expr.fill, name, val),
+                n: variable(&array_fill_expr.n, name, val),
                 source_range: array_fill_expr.source_range,
             }))
         }
         E::Return(ref ret_expr) => {
-            E::Return(Box::new(number(ret_expr, name, val)))
+            E::Return(Box::new(variable(ret_expr, name, val)))
+        }
+        E::Yield(ref expr) => {
+            E::Yield(Box::new(variable(expr, name, val)))
         }
         E::ReturnVoid(_) => expr.clone(),
         E::Break(_) => expr.clone(),
         E::Continue(_) => expr.clone(),
         E::Go(ref go) => {
             E::Go(Box::new(Go {
-                call: number_call(&go.call, name, val),
+                call: variable_call(&go.call, name, val),
+                source_range: go.source_range,
+            }))
+        }
+        E::Coroutine(ref go) => {
+            E::Coroutine(Box::new(Go {
+                call: variable_call(&go.call, name, val),
                 source_range: go.source_range,
             }))
         }
@@ -138,7 +160,7 @@ pub fn number(expr: &Expression, name: &Arc<String>, val: f64) -> Expression {
         E::Vec4(ref vec4_expr) => {
             let mut new_args: Vec<Expression> = vec![];
             for arg in &vec4_expr.args {
-                new_args.push(number(arg, name, val));
+                new_args.push(variable(arg, name, val));
             }
             E::Vec4(Vec4 {
                 args: new_args,
@@ -155,7 +177,7 @@ pub fn number(expr: &Expression, name: &Arc<String>, val: f64) -> Expression {
                         init = Some(Expression::Assign(Box::new(Assign {
                             op: assign_expr.op.clone(),
                             left: assign_expr.left.clone(),
-                            right: number(&assign_expr.right, name, val),
+                            right: variable(&assign_expr.right, name, val),
                             source_range: assign_expr.source_range,
                         })));
                     }
@@ -173,115 +195,163 @@ pub fn number(expr: &Expression, name: &Arc<String>, val: f64) -> Expression {
             } else {
                 E::For(Box::new(For {
                     label: for_expr.label.clone(),
-                    init: number(&for_expr.init, name, val),
-                    cond: number(&for_expr.cond, name, val),
-                    step: number(&for_expr.step, name, val),
-                    block: number_block(&for_expr.block, name, val),
+                    init: variable(&for_expr.init, name, val),
+                    cond: variable(&for_expr.cond, name, val),
+                    step: variable(&for_expr.step, name, val),
+                    block: variable_block(&for_expr.block, name, val),
                     source_range: for_expr.source_range,
                 }))
             }
         }
         E::ForN(ref for_n_expr) => {
-            E::ForN(Box::new(number_for_n(for_n_expr, name, val)))
+            E::ForN(Box::new(variable_for_n(for_n_expr, name, val)))
         }
         E::Sum(ref for_n_expr) => {
-            E::Sum(Box::new(number_for_n(for_n_expr, name, val)))
+            E::Sum(Box::new(variable_for_n(for_n_expr, name, val)))
         }
         E::SumVec4(ref for_n_expr) => {
-            E::SumVec4(Box::new(number_for_n(for_n_expr, name, val)))
+            E::SumVec4(Box::new(variable_for_n(for_n_expr, name, val)))
         }
         E::Prod(ref for_n_expr) => {
-            E::Prod(Box::new(number_for_n(for_n_expr, name, val)))
+            E::Prod(Box::new(variable_for_n(for_n_expr, name, val)))
         }
         E::ProdVec4(ref for_n_expr) => {
-            E::ProdVec4(Box::new(number_for_n(for_n_expr, name, val)))
+            E::ProdVec4(Box::new(variable_for_n(for_n_expr, name, val)))
         }
         E::Min(ref for_n_expr) => {
-            E::Min(Box::new(number_for_n(for_n_expr, name, val)))
+            E::Min(Box::new(variable_for_n(for_n_expr, name, val)))
         }
         E::Max(ref for_n_expr) => {
-            E::Max(Box::new(number_for_n(for_n_expr, name, val)))
+            E::Max(Box::new(variable_for_n(for_n_expr, name, val)))
         }
         E::Sift(ref for_n_expr) => {
-            E::Sift(Box::new(number_for_n(for_n_expr, name, val)))
+            E::Sift(Box::new(variable_for_n(for_n_expr, name, val)))
         }
         E::Any(ref for_n_expr) => {
-            E::Any(Box::new(number_for_n(for_n_expr, name, val)))
+            E::Any(Box::new(variable_for_n(for_n_expr, name, val)))
         }
         E::All(ref for_n_expr) => {
-            E::All(Box::new(number_for_n(for_n_expr, name, val)))
+            E::All(Box::new(variable_for_n(for_n_expr, name, val)))
         }
         E::LinkFor(ref for_n_expr) => {
-            E::LinkFor(Box::new(number_for_n(for_n_expr, name, val)))
+            E::LinkFor(Box::new(variable_for_n(for_n_expr, name, val)))
+        }
+        E::ForIn(ref for_in_expr) => {
+            E::ForIn(Box::new(variable_for_in(for_in_expr, name, val)))
+        }
+        E::SumIn(ref for_in_expr) => {
+            E::SumIn(Box::new(variable_for_in(for_in_expr, name, val)))
+        }
+        E::MinIn(ref for_in_expr) => {
+            E::MinIn(Box::new(variable_for_in(for_in_expr, name, val)))
+        }
+        E::MaxIn(ref for_in_expr) => {
+            E::MaxIn(Box::new(variable_for_in(for_in_expr, name, val)))
+        }
+        E::SiftIn(ref for_in_expr) => {
+            E::SiftIn(Box::new(variable_for_in(for_in_expr, name, val)))
+        }
+        E::AnyIn(ref for_in_expr) => {
+            E::AnyIn(Box::new(variable_for_in(for_in_expr, name, val)))
+        }
+        E::AllIn(ref for_in_expr) => {
+            E::AllIn(Box::new(variable_for_in(for_in_expr, name, val)))
         }
         E::If(ref if_expr) => {
             let mut new_else_if_conds: Vec<Expression> = vec![];
             for else_if_cond in &if_expr.else_if_conds {
-                new_else_if_conds.push(number(else_if_cond, name, val));
+                new_else_if_conds.push(variable(else_if_cond, name, val));
             }
             let mut new_else_if_blocks: Vec<Block> = vec![];
             for else_if_block in &if_expr.else_if_blocks {
-                new_else_if_blocks.push(number_block(else_if_block, name, val));
+                new_else_if_blocks.push(variable_block(else_if_block, name, val));
             }
             E::If(Box::new(If {
-                cond: number(&if_expr.cond, name, val),
-                true_block: number_block(&if_expr.true_block, name, val),
+                cond: variable(&if_expr.cond, name, val),
+                true_block: variable_block(&if_expr.true_block, name, val),
                 else_if_conds: new_else_if_conds,
                 else_if_blocks: new_else_if_blocks,
                 else_block: if_expr.else_block.as_ref()
-                    .map(|else_block| number_block(else_block, name, val)),
+                    .map(|else_block| variable_block(else_block, name, val)),
                 source_range: if_expr.source_range,
             }))
         }
+        E::Match(ref match_expr) => {
+            let new_arms = match_expr.arms.iter().map(|arm| {
+                if arm.pattern.binds(name) {
+                    arm.clone()
+                } else {
+                    MatchArm {
+                        pattern: arm.pattern.clone(),
+                        guard: arm.guard.as_ref().map(|guard| variable(guard, name, val)),
+                        expr: variable(&arm.expr, name, val),
+                        source_range: arm.source_range,
+                    }
+                }
+            }).collect();
+            E::Match(Box::new(Match {
+                expr: variable(&match_expr.expr, name, val),
+                arms: new_arms,
+                source_range: match_expr.source_range,
+            }))
+        }
+        E::EnumValue(ref enum_value) => {
+            E::EnumValue(Box::new(EnumValue {
+                name: enum_value.name.clone(),
+                variant: enum_value.variant.clone(),
+                payload: enum_value.payload.as_ref().map(|payload| variable(payload, name, val)),
+                source_range: enum_value.source_range,
+            }))
+        }
         E::Compare(ref cmp_expr) => {
             E::Compare(Box::new(Compare {
                 op: cmp_expr.op.clone(),
-                left: number(&cmp_expr.left, name, val),
-                right: number(&cmp_expr.right, name, val),
+                left: variable(&cmp_expr.left, name, val),
+                right: variable(&cmp_expr.right, name, val),
                 source_range: cmp_expr.source_range,
             }))
         }
         E::Norm(ref norm) => {
             E::Norm(Box::new(Norm {
-                expr: number(&norm.expr, name, val),
+                expr: variable(&norm.expr, name, val),
                 source_range: norm.source_range,
             }))
         }
         E::UnOp(ref unop_expr) => {
             E::UnOp(Box::new(UnOpExpression {
                 op: unop_expr.op.clone(),
-                expr: number(&unop_expr.expr, name, val),
+                expr: variable(&unop_expr.expr, name, val),
                 source_range: unop_expr.source_range,
             }))
         }
         E::Variable(_, _) => expr.clone(),
-        E::Try(ref expr) => E::Try(Box::new(number(expr, name, val))),
+        E::Try(ref expr) => E::Try(Box::new(variable(expr, name, val))),
         E::Swizzle(ref swizzle_expr) => {
             E::Swizzle(Box::new(Swizzle {
                 sw0: swizzle_expr.sw0.clone(),
                 sw1: swizzle_expr.sw1.clone(),
                 sw2: swizzle_expr.sw2.clone(),
                 sw3: swizzle_expr.sw3.clone(),
-                expr: number(&swizzle_expr.expr, name, val),
+                expr: variable(&swizzle_expr.expr, name, val),
                 source_range: swizzle_expr.source_range,
             }))
         }
         E::Closure(_) => expr.clone(),
         E::CallClosure(ref call_expr) => {
-            E::CallClosure(Box::new(number_call_closure(call_expr, name, val)))
+            E::CallClosure(Box::new(variable_call_closure(call_expr, name, val)))
         }
         E::Grab(_) => expr.clone(),
         E::TryExpr(ref try_expr) => E::TryExpr(Box::new(TryExpr {
-            expr: number(&try_expr.expr, name, val),
+            expr: variable(&try_expr.expr, name, val),
             source_range: try_expr.source_range
         }))
     }
 }
 
-fn number_call(call_expr: &Call, name: &Arc<String>, val: f64) -> Call {
+fn variable_call(call_expr: &Call, name: &Arc<String>, val: &Variable) -> Call {
     let mut new_args: Vec<Expression> = vec![];
     for arg in &call_expr.args {
-        new_args.push(number(arg, name, val));
+        new_args.push(variable(arg, name, val));
     }
     Call {
         alias: call_expr.alias.clone(),
@@ -293,10 +363,14 @@ fn number_call(call_expr: &Call, name: &Arc<String>, val: f64) -> Call {
     }
 }
 
-fn number_call_closure(call_expr: &CallClosure, name: &Arc<String>, val: f64) -> CallClosure {
+fn variable_call_closure(
+    call_expr: &CallClosure,
+    name: &Arc<String>,
+    val: &Variable
+) -> CallClosure {
     let mut new_args: Vec<Expression> = vec![];
     for arg in &call_expr.args {
-        new_args.push(number(arg, name, val));
+        new_args.push(variable(arg, name, val));
     }
     CallClosure {
         item: call_expr.item.clone(),
@@ -305,7 +379,7 @@ fn number_call_closure(call_expr: &CallClosure, name: &Arc<String>, val: f64) ->
     }
 }
 
-fn number_block(block: &Block, name: &Arc<String>, val: f64) -> Block {
+pub fn variable_block(block: &Block, name: &Arc<String>, val: &Variable) -> Block {
     let mut new_expressions: Vec<Expression> = vec![];
     let mut just_clone = false;
     for expr in &block.expressions {
@@ -319,7 +393,7 @@ fn number_block(block: &Block, name: &Arc<String>, val: f64) -> Block {
                         new_expressions.push(Expression::Assign(Box::new(Assign {
                             op: assign_expr.op.clone(),
                             left: assign_expr.left.clone(),
-                            right: number(&assign_expr.right, name, val),
+                            right: variable(&assign_expr.right, name, val),
                             source_range: assign_expr.source_range,
                         })));
                         just_clone = true;
@@ -327,7 +401,7 @@ fn number_block(block: &Block, name: &Arc<String>, val: f64) -> Block {
                     }
                 }
             }
-            new_expressions.push(number(expr, name, val));
+            new_expressions.push(variable(expr, name, val));
         }
     }
     Block {
@@ -336,7 +410,27 @@ fn number_block(block: &Block, name: &Arc<String>, val: f64) -> Block {
     }
 }
 
-fn number_for_n(for_n_expr: &ForN, name: &Arc<String>, val: f64) -> ForN {
+fn variable_for_in(for_in_expr: &ForIn, name: &Arc<String>, val: &Variable) -> ForIn {
+    if &for_in_expr.name == name || for_in_expr.key.as_ref() == Some(name) {
+        ForIn {
+            expr: variable(&for_in_expr.expr, name, val),
+            ..for_in_expr.clone()
+        }
+    } else {
+        ForIn {
+            key: for_in_expr.key.clone(),
+            name: for_in_expr.name.clone(),
+            mutable: for_in_expr.mutable,
+            expr: variable(&for_in_expr.expr, name, val),
+            block: variable_block(&for_in_expr.block, name, val),
+            label: for_in_expr.label.clone(),
+            i64_acc: for_in_expr.i64_acc.clone(),
+            source_range: for_in_expr.source_range,
+        }
+    }
+}
+
+fn variable_for_n(for_n_expr: &ForN, name: &Arc<String>, val: &Variable) -> ForN {
     if &for_n_expr.name == name {
         for_n_expr.clone()
     } else {
@@ -344,9 +438,11 @@ fn number_for_n(for_n_expr: &ForN, name: &Arc<String>, val: f64) -> ForN {
             label: for_n_expr.label.clone(),
             name: for_n_expr.name.clone(),
             start: for_n_expr.start.as_ref()
-                .map(|start| number(start, name, val)),
-            end: number(&for_n_expr.end, name, val),
-            block: number_block(&for_n_expr.block, name, val),
+                .map(|start| variable(start, name, val)),
+            end: variable(&for_n_expr.end, name, val),
+            block: variable_block(&for_n_expr.block, name, val),
+            parallel: for_n_expr.parallel,
+            i64_acc: for_n_expr.i64_acc.clone(),
             source_range: for_n_expr.source_range,
         }
     }
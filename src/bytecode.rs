@@ -0,0 +1,1010 @@
+//! Compiles function bodies to bytecode.
+//!
+//! Locals are resolved to slots relative to the function frame,
+//! such that the runtime can look them up directly on the stack.
+//! Expressions that are not supported by the bytecode are evaluated
+//! by the tree-walking interpreter, using the same stack layout.
+//!
+//! Functions are also compiled with instructions for the debugger, profiler and coverage,
+//! which run while any of them is attached.
+
+use std::sync::Arc;
+use range::Range;
+
+use ast;
+use ast::{AssignOp, Expression};
+use FnIndex;
+use Module;
+use Variable;
+
+/// Stores the lengths of the stacks relative to the function frame.
+#[derive(Debug, Clone, Copy)]
+pub struct Scope {
+    /// Length of stack.
+    pub stack: usize,
+    /// Length of local stack.
+    pub locals: usize,
+    /// Length of current stack.
+    pub currents: usize,
+    /// Length of operand stack.
+    pub operands: usize,
+}
+
+/// Jump information for a loop.
+#[derive(Debug, Clone)]
+pub struct Loop {
+    /// The loop label.
+    pub label: Option<Arc<String>>,
+    /// Where to jump on `break`.
+    pub end: usize,
+    /// Where to jump on `continue`.
+    pub next: usize,
+    /// The scope inside the loop.
+    pub scope: Scope,
+}
+
+/// Kind of short loop that accumulates a value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Acc {
+    Sum,
+    Prod,
+    SumVec4,
+    ProdVec4,
+    Min,
+    Max,
+    Any,
+    All,
+    Sift,
+}
+
+/// Where an item starts before looking up its properties.
+#[derive(Debug, Clone, Copy)]
+pub enum Base {
+    /// Local by slot.
+    Local(usize),
+    /// Constant or global by index in module.
+    Global(usize),
+}
+
+/// Bytecode instruction.
+///
+/// Instructions work on an operand stack of optional values.
+/// An expression leaves exactly one operand on the operand stack,
+/// which is `None` when the expression does not return a value.
+#[derive(Debug, Clone)]
+pub enum Op {
+    /// Pushes a constant.
+    Const(Variable),
+    /// Pushes a number.
+    Number(f64),
+    /// Pushes no value.
+    Void,
+    /// Pushes a reference to a local by slot.
+    Local(usize),
+    /// Pushes a reference to a constant or global by index in module.
+    Global(usize),
+    /// Looks up the properties of an item and pushes the value.
+    ///
+    /// The values of computed properties are on the stack.
+    Item(Base, usize),
+    /// Evaluates an expression using the tree-walking interpreter.
+    Eval(usize),
+    /// Removes the top operand.
+    Pop,
+    /// Removes the top operand, which must be no value.
+    Nothing(Range, &'static str),
+    /// Declares a new local using the top operand.
+    Declare {
+        name: Arc<String>,
+        current: bool,
+        range: Range,
+    },
+    /// Uses an assignment operator, except `:=`, on a local.
+    AssignLocal {
+        op: AssignOp,
+        slot: usize,
+        left: Range,
+        right: Range,
+    },
+    /// Uses an assignment operator on an item.
+    ///
+    /// The values of computed properties are on the stack.
+    AssignItem {
+        op: AssignOp,
+        base: Base,
+        item: usize,
+        left: Range,
+        right: Range,
+    },
+    /// Unary operator.
+    UnOp(ast::UnOp, Range),
+    /// Binary operator.
+    BinOp(ast::BinOp, Range),
+    /// Computes the norm of a vec4.
+    Norm(Range),
+    /// Short-circuits a lazy binary operator by jumping past the right argument.
+    Lazy(ast::BinOp, Range, usize),
+    /// Compares two operands.
+    Compare {
+        op: ast::CompareOp,
+        range: Range,
+        left: Range,
+        right: Range,
+    },
+    /// Jumps to instruction.
+    Jump(usize),
+    /// Jumps to instruction if the top operand equals the bool.
+    JumpIf(bool, usize, Range, &'static str),
+    /// Truncates the stacks to a scope.
+    Truncate(Scope),
+    /// Pushes return value placeholder before arguments of a call.
+    PushReturn,
+    /// Moves the top operand to the stack, which must be a value.
+    Push(Range, &'static str),
+    /// Creates a vec4 from the top four values on the stack.
+    Vec4([Range; 4]),
+    /// Creates an array from the top values on the stack.
+    Array(usize, Range),
+    /// Creates an object from the top values on the stack, in order of keys.
+    Object(Vec<(Arc<String>, Range)>, Range),
+    /// Calls a loaded function with arguments on the stack.
+    Call(usize),
+    /// Calls an intrinsic with arguments on the stack.
+    CallIntrinsic(usize, usize),
+    /// Calls an external function with arguments on the stack.
+    CallExternal(usize),
+    /// Calls an external function with arguments on the stack,
+    /// followed by default values of the omitted arguments.
+    CallExternalDefaults(usize, usize),
+    /// Returns from function with the top operand.
+    Return(Range),
+    /// Returns from function without a value.
+    ReturnVoid,
+    /// Suspends the coroutine with the top operand.
+    Yield(Range),
+    /// Breaks out of a loop.
+    Break(usize),
+    /// Continues to next iteration of a loop.
+    Continue(usize),
+    /// Breaks out of a loop that is not in the bytecode.
+    BreakOut(Option<Arc<String>>),
+    /// Continues a loop that is not in the bytecode.
+    ContinueOut(Option<Arc<String>>),
+    /// Initializes counter of `for` loop from start and end operands.
+    ///
+    /// The end operand is kept on the operand stack.
+    ForNInit {
+        name: Arc<String>,
+        range: Range,
+    },
+    /// Jumps to end when counter reaches the end operand.
+    ForNCond {
+        slot: usize,
+        end: usize,
+        range: Range,
+    },
+    /// Increments counter of `for` loop and jumps to start
+    /// when counter is less than the end operand.
+    ForNStep {
+        slot: usize,
+        start: usize,
+        range: Range,
+    },
+    /// Accumulates body value of short loop.
+    Accumulate(Acc, Range),
+    /// Records an executed expression for coverage.
+    CoverExpression(Range),
+    /// Records an executed block for coverage.
+    CoverBlock(Range),
+    /// Records a taken branch of an `if` expression for coverage.
+    CoverBranch {
+        range: Range,
+        branches: usize,
+        branch: usize,
+    },
+    /// Pauses in the debugger before an expression in a block.
+    Pause(Range),
+    /// Starts timing a loop for the profiler.
+    EnterLoop(Range),
+    /// Stops timing a loop for the profiler.
+    ExitLoop(Range),
+    /// Keeps body value of `min`, `max`, `any` or `all` loop,
+    /// with the counter added to the secret.
+    ///
+    /// Jumps to end when the value of `any` or `all` is known.
+    Pick {
+        acc: Acc,
+        slot: usize,
+        end: usize,
+        range: Range,
+    },
+}
+
+/// Function body compiled to bytecode.
+#[derive(Debug)]
+pub struct Chunk {
+    /// The instructions.
+    pub ops: Vec<Op>,
+    /// Source range of the expression that emitted each instruction.
+    pub ranges: Vec<Range>,
+    /// Number of stack slots for return value, arguments and currents.
+    pub frame: usize,
+    /// Expressions evaluated by the tree-walking interpreter,
+    /// with indices to the loops that `break` and `continue` can jump to.
+    ///
+    /// Uses `None` when the value is required,
+    /// such that `break` and `continue` is an error.
+    pub exprs: Vec<(Expression, Option<Vec<usize>>)>,
+    /// Calls to functions.
+    pub calls: Vec<ast::Call>,
+    /// Items with properties.
+    pub items: Vec<ast::Item>,
+    /// Loops.
+    pub loops: Vec<Loop>,
+}
+
+// SAFETY: The chunk is never changed after compilation.
+// The values of `Op::Const` are literals or constants copied from `Expression::Variable`,
+// the `f_index` cells of calls are only set when the calls are created,
+// and the cells of items are not used, since items are resolved to slots.
+unsafe impl Sync for Chunk {}
+
+struct Compiler<'a> {
+    module: &'a Module,
+    relative: usize,
+    /// Whether to emit instructions for the debugger, profiler and coverage.
+    hooks: bool,
+    ops: Vec<Op>,
+    ranges: Vec<Range>,
+    /// Source range of the expression being compiled.
+    range: Range,
+    exprs: Vec<(Expression, Option<Vec<usize>>)>,
+    calls: Vec<ast::Call>,
+    items: Vec<ast::Item>,
+    loops: Vec<Loop>,
+    /// Indices of surrounding loops.
+    loop_ids: Vec<usize>,
+    /// Whether `break` and `continue` can jump to surrounding loops.
+    flow: bool,
+    /// Names of stack slots, `None` for anonymous slots.
+    stack: Vec<Option<Arc<String>>>,
+    locals: usize,
+    currents: usize,
+    operands: usize,
+    /// The last instruction that is a jump target.
+    label: usize,
+}
+
+/// Compiles body of function, with instructions for the debugger, profiler and coverage
+/// when `hooks` is `true`.
+///
+/// Returns `None` if the function can not be compiled,
+/// in which case it is executed by the tree-walking interpreter.
+pub fn compile(f: &ast::Function, relative: usize, hooks: bool, module: &Module) -> Option<Chunk> {
+    let mut c = Compiler {
+        module: module,
+        relative: relative,
+        hooks: hooks,
+        ops: vec![],
+        ranges: vec![],
+        range: f.source_range,
+        exprs: vec![],
+        calls: vec![],
+        items: vec![],
+        loops: vec![],
+        loop_ids: vec![],
+        flow: true,
+        stack: vec![],
+        locals: 0,
+        currents: 0,
+        operands: 0,
+        label: 0,
+    };
+    if f.returns() {
+        c.declare(Arc::new("return".into()));
+    }
+    for arg in &f.args {
+        c.declare(arg.name.clone());
+    }
+    for current in &f.currents {
+        c.declare(current.name.clone());
+    }
+    let frame = c.stack.len();
+    if c.block_expressions(&f.block).is_err() { return None; }
+    Some(Chunk {
+        ops: c.ops,
+        ranges: c.ranges,
+        frame: frame,
+        exprs: c.exprs,
+        calls: c.calls,
+        items: c.items,
+        loops: c.loops,
+    })
+}
+
+/// Finds the loop that a `break` or `continue` refers to.
+///
+/// The loop ids are ordered from outer to inner loop.
+pub fn find_loop(
+    loops: &[Loop],
+    loop_ids: &[usize],
+    label: &Option<Arc<String>>
+) -> Option<usize> {
+    for &id in loop_ids.iter().rev() {
+        match *label {
+            None => return Some(id),
+            Some(ref label) => {
+                if loops[id].label.as_ref() == Some(label) {
+                    return Some(id);
+                }
+            }
+        }
+    }
+    None
+}
+
+/// Returns the number of computed properties of an item.
+pub fn computed_ids(item: &ast::Item) -> usize {
+    item.ids.iter().filter(|id| {
+        if let ast::Id::Expression(_) = **id { true } else { false }
+    }).count()
+}
+
+/// Returns `true` if any expression is a swizzle, which pushes several arguments.
+fn swizzles(exprs: &[Expression]) -> bool {
+    exprs.iter().any(|expr| if let Expression::Swizzle(_) = *expr { true } else { false })
+}
+
+/// Returns `true` if expression declares a new local.
+fn declares(expr: &Expression) -> bool {
+    match *expr {
+        Expression::Assign(ref assign) => {
+            if let Expression::Item(ref item) = assign.left {
+                item.ids.len() == 0 && assign.op == AssignOp::Assign
+            } else {
+                false
+            }
+        }
+        Expression::Try(ref expr) => declares(expr),
+        _ => false
+    }
+}
+
+impl<'a> Compiler<'a> {
+    fn scope(&self) -> Scope {
+        Scope {
+            stack: self.stack.len(),
+            locals: self.locals,
+            currents: self.currents,
+            operands: self.operands,
+        }
+    }
+
+    fn declare(&mut self, name: Arc<String>) {
+        self.stack.push(Some(name));
+        self.locals += 1;
+    }
+
+    fn slot(&self, name: &Arc<String>) -> Result<usize, ()> {
+        for (i, n) in self.stack.iter().enumerate().rev() {
+            if let Some(ref n) = *n {
+                if &**n == &**name { return Ok(i); }
+            }
+        }
+        Err(())
+    }
+
+    fn base(&self, item: &ast::Item) -> Result<Base, ()> {
+        match item.global.get() {
+            Some(ind) => Ok(Base::Global(ind)),
+            None => Ok(Base::Local(try!(self.slot(&item.name)))),
+        }
+    }
+
+    fn emit(&mut self, op: Op) -> usize {
+        self.ops.push(op);
+        self.ranges.push(self.range);
+        self.ops.len() - 1
+    }
+
+    /// Emits truncation of stacks if there are new locals since scope.
+    fn truncate(&mut self, scope: Scope) {
+        if self.stack.len() != scope.stack || self.currents != scope.currents {
+            self.emit(Op::Truncate(scope));
+            self.stack.truncate(scope.stack);
+            self.locals = scope.locals;
+            self.currents = scope.currents;
+        }
+    }
+
+    /// Returns the next instruction as a jump target.
+    fn label(&mut self) -> usize {
+        self.label = self.ops.len();
+        self.label
+    }
+
+    fn set_jump(&mut self, ind: usize, target: usize) {
+        match self.ops[ind] {
+            Op::Lazy(_, _, ref mut x) |
+            Op::Jump(ref mut x) |
+            Op::JumpIf(_, ref mut x, _, _) |
+            Op::ForNCond { end: ref mut x, .. } |
+            Op::Pick { end: ref mut x, .. } => *x = target,
+            _ => panic!("Expected jump instruction")
+        }
+    }
+
+    /// Removes the top operand using `Pop` or `Nothing`.
+    ///
+    /// When the operand is pushed by `Void`, the instruction is removed instead.
+    fn discard(&mut self, op: Op) {
+        let n = self.ops.len();
+        let void = if let Some(&Op::Void) = self.ops.last() {
+            self.label < n - 1
+        } else { false };
+        if void {
+            self.ops.pop();
+            self.ranges.pop();
+        } else { self.emit(op); }
+        self.operands -= 1;
+    }
+
+    /// Compiles an expression and moves the value to the stack.
+    fn push(&mut self, expr: &Expression, msg: &'static str) -> Result<(), ()> {
+        try!(self.value(expr));
+        self.emit(Op::Push(expr.source_range(), msg));
+        self.operands -= 1;
+        self.stack.push(None);
+        Ok(())
+    }
+
+    /// Pushes the values of computed properties of an item to the stack.
+    fn item_ids(&mut self, item: &ast::Item) -> Result<(), ()> {
+        for id in &item.ids {
+            if let ast::Id::Expression(ref expr) = *id {
+                try!(self.push(expr, "Expected something for index"));
+            }
+        }
+        Ok(())
+    }
+
+    /// Compiles expressions of a block, without truncating the stacks.
+    fn block_expressions(&mut self, block: &ast::Block) -> Result<(), ()> {
+        if self.hooks { self.emit(Op::CoverBlock(block.source_range)); }
+        if block.expressions.len() == 0 {
+            self.emit(Op::Void);
+            self.operands += 1;
+        }
+        let n = block.expressions.len();
+        for (i, expr) in block.expressions.iter().enumerate() {
+            if self.hooks { self.emit(Op::Pause(expr.source_range())); }
+            try!(self.expression(expr));
+            if i + 1 < n { self.discard(Op::Pop); }
+        }
+        Ok(())
+    }
+
+    fn block(&mut self, block: &ast::Block) -> Result<(), ()> {
+        let scope = self.scope();
+        try!(self.block_expressions(block));
+        self.truncate(scope);
+        Ok(())
+    }
+
+    fn loop_body(&mut self, block: &ast::Block) -> Result<(), ()> {
+        let prev = self.flow;
+        self.flow = true;
+        let res = self.block(block);
+        self.flow = prev;
+        res
+    }
+
+    /// Compiles an expression with a flow context.
+    fn expression_flow(&mut self, expr: &Expression, flow: bool) -> Result<(), ()> {
+        let prev = self.flow;
+        self.flow = flow;
+        let res = self.expression(expr);
+        self.flow = prev;
+        res
+    }
+
+    /// Compiles an expression that does not declare new locals,
+    /// where the value is required.
+    fn value(&mut self, expr: &Expression) -> Result<(), ()> {
+        let st = self.stack.len();
+        let cu = self.currents;
+        try!(self.expression_flow(expr, false));
+        if self.stack.len() != st || self.currents != cu { return Err(()); }
+        Ok(())
+    }
+
+    fn eval(&mut self, expr: &Expression) -> Result<(), ()> {
+        if declares(expr) { return Err(()); }
+        // The tree-walking interpreter records coverage of the expression.
+        if let Some(&Op::CoverExpression(_)) = self.ops.last() {
+            self.ops.pop();
+            self.ranges.pop();
+        }
+        let loop_ids = if self.flow { Some(self.loop_ids.clone()) } else { None };
+        self.exprs.push((expr.clone(), loop_ids));
+        let ind = self.exprs.len() - 1;
+        self.emit(Op::Eval(ind));
+        self.operands += 1;
+        Ok(())
+    }
+
+    fn expression(&mut self, expr: &Expression) -> Result<(), ()> {
+        let prev = self.range;
+        self.range = expr.source_range();
+        if self.hooks { self.emit(Op::CoverExpression(self.range)); }
+        let res = self.expression_ops(expr);
+        self.range = prev;
+        res
+    }
+
+    fn expression_ops(&mut self, expr: &Expression) -> Result<(), ()> {
+        use ast::Expression::*;
+
+        match *expr {
+            Number(ref num) => {
+                self.emit(Op::Number(num.num));
+                self.operands += 1;
+            }
+            Bool(ref b) => {
+                self.emit(Op::Const(::Variable::bool(b.val)));
+                self.operands += 1;
+            }
+            Text(ref text) => {
+                self.emit(Op::Const(::Variable::Text(text.text.clone())));
+                self.operands += 1;
+            }
+            Variable(_, ref var) => {
+                self.emit(Op::Const(var.clone()));
+                self.operands += 1;
+            }
+            Item(ref item) => {
+                if item.try { return self.eval(expr); }
+                let base = try!(self.base(item));
+                if item.ids.len() == 0 {
+                    match base {
+                        Base::Local(slot) => self.emit(Op::Local(slot)),
+                        Base::Global(ind) => self.emit(Op::Global(ind)),
+                    };
+                } else {
+                    let st = self.stack.len();
+                    try!(self.item_ids(item));
+                    self.items.push(item.clone());
+                    let ind = self.items.len() - 1;
+                    self.emit(Op::Item(base, ind));
+                    self.stack.truncate(st);
+                }
+                self.operands += 1;
+            }
+            Block(ref block) => try!(self.block(block)),
+            Return(ref ret) => {
+                try!(self.value(ret));
+                // The operand is counted as the value of the expression.
+                self.emit(Op::Return(expr.source_range()));
+            }
+            ReturnVoid(_) => {
+                self.emit(Op::ReturnVoid);
+                self.operands += 1;
+            }
+            Yield(ref val) => {
+                try!(self.value(val));
+                // The operand is counted as the value of the expression.
+                self.emit(Op::Yield(expr.source_range()));
+            }
+            Break(ref b) => {
+                if !self.flow { return Err(()); }
+                match find_loop(&self.loops, &self.loop_ids, &b.label) {
+                    Some(id) => self.emit(Op::Break(id)),
+                    None => self.emit(Op::BreakOut(b.label.clone())),
+                };
+                self.operands += 1;
+            }
+            Continue(ref c) => {
+                if !self.flow { return Err(()); }
+                match find_loop(&self.loops, &self.loop_ids, &c.label) {
+                    Some(id) => self.emit(Op::Continue(id)),
+                    None => self.emit(Op::ContinueOut(c.label.clone())),
+                };
+                self.operands += 1;
+            }
+            UnOp(ref unop) => {
+                try!(self.value(&unop.expr));
+                self.emit(Op::UnOp(unop.op, unop.source_range));
+            }
+            BinOp(ref binop) => {
+                try!(self.value(&binop.left));
+                let lazy = match binop.op {
+                    ast::BinOp::AndAlso | ast::BinOp::OrElse =>
+                        Some(self.emit(Op::Lazy(binop.op, binop.source_range, 0))),
+                    _ => None
+                };
+                try!(self.value(&binop.right));
+                self.emit(Op::BinOp(binop.op, binop.source_range));
+                self.operands -= 1;
+                if let Some(ind) = lazy {
+                    let target = self.label();
+                    self.set_jump(ind, target);
+                }
+            }
+            Norm(ref norm) => {
+                try!(self.value(&norm.expr));
+                self.emit(Op::Norm(norm.source_range));
+            }
+            Vec4(ref vec4) => {
+                if vec4.args.len() != 4 || swizzles(&vec4.args) { return self.eval(expr); }
+                let st = self.stack.len();
+                for arg in &vec4.args {
+                    try!(self.push(arg, "Expected something from vec4 argument"));
+                }
+                self.emit(Op::Vec4([
+                    vec4.args[0].source_range(),
+                    vec4.args[1].source_range(),
+                    vec4.args[2].source_range(),
+                    vec4.args[3].source_range(),
+                ]));
+                self.operands += 1;
+                self.stack.truncate(st);
+            }
+            Array(ref arr) => {
+                let st = self.stack.len();
+                for item in &arr.items {
+                    try!(self.push(item, "Expected something"));
+                }
+                self.emit(Op::Array(arr.items.len(), arr.source_range));
+                self.operands += 1;
+                self.stack.truncate(st);
+            }
+            Object(ref obj) => {
+                let st = self.stack.len();
+                for &(_, ref val) in &obj.key_values {
+                    try!(self.push(val, "Expected something"));
+                }
+                let keys = obj.key_values.iter()
+                    .map(|&(ref key, ref val)| (key.clone(), val.source_range()))
+                    .collect();
+                self.emit(Op::Object(keys, obj.source_range));
+                self.operands += 1;
+                self.stack.truncate(st);
+            }
+            Compare(ref compare) => {
+                try!(self.value(&compare.left));
+                try!(self.value(&compare.right));
+                self.emit(Op::Compare {
+                    op: compare.op,
+                    range: compare.source_range,
+                    left: compare.left.source_range(),
+                    right: compare.right.source_range(),
+                });
+                self.operands -= 1;
+            }
+            Assign(ref assign) => try!(self.assign(expr, assign)),
+            If(ref if_expr) => try!(self.if_expr(if_expr)),
+            For(ref for_expr) => try!(self.for_expr(for_expr)),
+            ForN(ref for_n_expr) => try!(self.for_n_expr(for_n_expr, None)),
+            Sum(ref for_n_expr) | Min(ref for_n_expr) | Max(ref for_n_expr) |
+            Sift(ref for_n_expr) | Any(ref for_n_expr) | All(ref for_n_expr)
+                if for_n_expr.parallel => try!(self.eval(expr)),
+            Sum(ref for_n_expr) => try!(self.for_n_expr(for_n_expr, Some(Acc::Sum))),
+            Prod(ref for_n_expr) => try!(self.for_n_expr(for_n_expr, Some(Acc::Prod))),
+            SumVec4(ref for_n_expr) => try!(self.for_n_expr(for_n_expr, Some(Acc::SumVec4))),
+            ProdVec4(ref for_n_expr) => try!(self.for_n_expr(for_n_expr, Some(Acc::ProdVec4))),
+            Min(ref for_n_expr) => try!(self.for_n_expr(for_n_expr, Some(Acc::Min))),
+            Max(ref for_n_expr) => try!(self.for_n_expr(for_n_expr, Some(Acc::Max))),
+            Any(ref for_n_expr) => try!(self.for_n_expr(for_n_expr, Some(Acc::Any))),
+            All(ref for_n_expr) => try!(self.for_n_expr(for_n_expr, Some(Acc::All))),
+            Sift(ref for_n_expr) => try!(self.for_n_expr(for_n_expr, Some(Acc::Sift))),
+            Call(ref call) => try!(self.call(expr, call)),
+            _ => try!(self.eval(expr)),
+        }
+        Ok(())
+    }
+
+    fn assign(&mut self, expr: &Expression, assign: &ast::Assign) -> Result<(), ()> {
+        let item = match assign.left {
+            Expression::Item(ref item) if item.ids.len() == 0 &&
+                item.global.get().is_none() &&
+                (assign.op == AssignOp::Assign || !item.try) => item,
+            Expression::Item(ref item) if !item.try &&
+                (item.ids.len() > 0 || assign.op != AssignOp::Assign) => {
+                try!(self.value(&assign.right));
+                let base = try!(self.base(item));
+                let st = self.stack.len();
+                try!(self.item_ids(item));
+                self.items.push(item.clone());
+                let ind = self.items.len() - 1;
+                self.emit(Op::AssignItem {
+                    op: assign.op,
+                    base: base,
+                    item: ind,
+                    left: assign.left.source_range(),
+                    right: assign.right.source_range(),
+                });
+                self.operands -= 1;
+                self.stack.truncate(st);
+                self.emit(Op::Void);
+                self.operands += 1;
+                return Ok(());
+            }
+            _ => return self.eval(expr),
+        };
+        if assign.op == AssignOp::Assign {
+            try!(self.value(&assign.right));
+            self.emit(Op::Declare {
+                name: item.name.clone(),
+                current: item.current,
+                range: assign.right.source_range(),
+            });
+            self.operands -= 1;
+            self.declare(item.name.clone());
+            if item.current { self.currents += 1; }
+        } else {
+            let slot = try!(self.slot(&item.name));
+            try!(self.value(&assign.right));
+            self.emit(Op::AssignLocal {
+                op: assign.op,
+                slot: slot,
+                left: assign.left.source_range(),
+                right: assign.right.source_range(),
+            });
+            self.operands -= 1;
+        }
+        self.emit(Op::Void);
+        self.operands += 1;
+        Ok(())
+    }
+
+    fn if_expr(&mut self, if_expr: &ast::If) -> Result<(), ()> {
+        let mut ends = vec![];
+        try!(self.value(&if_expr.cond));
+        let mut next = self.emit(Op::JumpIf(false, 0, if_expr.cond.source_range(),
+            "Expected bool from if condition"));
+        self.operands -= 1;
+        self.cover_branch(if_expr, 0);
+        try!(self.block(&if_expr.true_block));
+        self.operands -= 1;
+        ends.push(self.emit(Op::Jump(0)));
+        for (i, (cond, body)) in if_expr.else_if_conds.iter()
+            .zip(if_expr.else_if_blocks.iter()).enumerate() {
+            let target = self.label();
+            self.set_jump(next, target);
+            try!(self.value(cond));
+            next = self.emit(Op::JumpIf(false, 0, cond.source_range(),
+                "Expected bool from else if condition"));
+            self.operands -= 1;
+            self.cover_branch(if_expr, i + 1);
+            try!(self.block(body));
+            self.operands -= 1;
+            ends.push(self.emit(Op::Jump(0)));
+        }
+        let target = self.label();
+        self.set_jump(next, target);
+        self.cover_branch(if_expr, if_expr.else_if_blocks.len() + 1);
+        if let Some(ref block) = if_expr.else_block {
+            try!(self.block(block));
+        } else {
+            self.emit(Op::Void);
+            self.operands += 1;
+        }
+        let target = self.label();
+        for ind in ends {
+            self.set_jump(ind, target);
+        }
+        Ok(())
+    }
+
+    /// Emits coverage of a taken branch of an `if` expression.
+    fn cover_branch(&mut self, if_expr: &ast::If, branch: usize) {
+        if self.hooks {
+            self.emit(Op::CoverBranch {
+                range: if_expr.source_range,
+                branches: if_expr.else_if_blocks.len() + 2,
+                branch: branch,
+            });
+        }
+    }
+
+    fn for_expr(&mut self, for_expr: &ast::For) -> Result<(), ()> {
+        let range = self.range;
+        if self.hooks { self.emit(Op::EnterLoop(range)); }
+        let prev = self.scope();
+        try!(self.expression_flow(&for_expr.init, false));
+        self.discard(Op::Nothing(for_expr.init.source_range(),
+            "Expected nothing from for init"));
+        let scope = self.scope();
+        let id = self.loops.len();
+        self.loops.push(Loop {
+            label: for_expr.label.clone(),
+            end: 0,
+            next: 0,
+            scope: scope,
+        });
+
+        // The condition is checked at the end of the loop.
+        let cond = if let Expression::Bool(ast::Bool { val: true, .. }) = for_expr.cond {
+            None
+        } else {
+            Some(self.emit(Op::Jump(0)))
+        };
+        let start = self.label();
+        self.loop_ids.push(id);
+        try!(self.loop_body(&for_expr.block));
+        self.discard(Op::Pop);
+        self.loop_ids.pop();
+
+        let next = self.label();
+        try!(self.value(&for_expr.step));
+        self.discard(Op::Nothing(for_expr.step.source_range(),
+            "Expected nothing from for step"));
+        if let Some(ind) = cond {
+            let target = self.label();
+            self.set_jump(ind, target);
+            try!(self.value(&for_expr.cond));
+            self.emit(Op::JumpIf(true, start, for_expr.cond.source_range(),
+                "Expected bool from for condition"));
+            self.operands -= 1;
+        } else {
+            self.emit(Op::Jump(start));
+        }
+
+        let end = self.label();
+        self.loops[id].end = end;
+        self.loops[id].next = next;
+        self.truncate(prev);
+        if self.hooks { self.emit(Op::ExitLoop(range)); }
+        self.emit(Op::Void);
+        self.operands += 1;
+        Ok(())
+    }
+
+    fn for_n_expr(&mut self, for_n_expr: &ast::ForN, acc: Option<Acc>) -> Result<(), ()> {
+        let loop_range = self.range;
+        if self.hooks { self.emit(Op::EnterLoop(loop_range)); }
+        let prev = self.scope();
+        let i64_acc = for_n_expr.i64_acc.get();
+        let start = match acc {
+            None => None,
+            Some(Acc::Sum) => Some(if i64_acc { Variable::I64(0) } else { Variable::f64(0.0) }),
+            Some(Acc::Prod) => Some(if i64_acc { Variable::I64(1) } else { Variable::f64(1.0) }),
+            Some(Acc::SumVec4) => Some(Variable::Vec4([0.0; 4])),
+            Some(Acc::ProdVec4) => Some(Variable::Vec4([1.0; 4])),
+            Some(Acc::Min) | Some(Acc::Max) => Some(Variable::f64(::std::f64::NAN)),
+            Some(Acc::Any) => Some(Variable::bool(false)),
+            Some(Acc::All) => Some(Variable::bool(true)),
+            Some(Acc::Sift) => Some(Variable::Array(Arc::new(vec![]))),
+        };
+        if let Some(start) = start {
+            self.emit(Op::Const(start));
+            self.operands += 1;
+        }
+        if let Some(ref start) = for_n_expr.start {
+            try!(self.value(start));
+        } else {
+            self.emit(Op::Const(Variable::f64(0.0)));
+            self.operands += 1;
+        }
+        try!(self.value(&for_n_expr.end));
+        self.emit(Op::ForNInit {
+            name: for_n_expr.name.clone(),
+            range: for_n_expr.end.source_range(),
+        });
+        self.operands -= 1;
+        self.declare(for_n_expr.name.clone());
+        let slot = self.stack.len() - 1;
+        let scope = self.scope();
+        let id = self.loops.len();
+        self.loops.push(Loop {
+            label: for_n_expr.label.clone(),
+            end: 0,
+            next: 0,
+            scope: scope,
+        });
+
+        let cond = self.emit(Op::ForNCond {
+            slot: slot,
+            end: 0,
+            range: for_n_expr.source_range,
+        });
+        let start = self.label();
+        self.loop_ids.push(id);
+        try!(self.loop_body(&for_n_expr.block));
+        let range = for_n_expr.block.source_range;
+        let pick = match acc {
+            None => {
+                self.discard(Op::Pop);
+                None
+            }
+            Some(acc @ Acc::Min) | Some(acc @ Acc::Max) |
+            Some(acc @ Acc::Any) | Some(acc @ Acc::All) => {
+                self.operands -= 1;
+                Some(self.emit(Op::Pick { acc: acc, slot: slot, end: 0, range: range }))
+            }
+            Some(acc) => {
+                self.emit(Op::Accumulate(acc, range));
+                self.operands -= 1;
+                None
+            }
+        };
+        self.loop_ids.pop();
+
+        let next = self.label();
+        self.emit(Op::ForNStep {
+            slot: slot,
+            start: start,
+            range: for_n_expr.source_range,
+        });
+
+        let end = self.label();
+        self.set_jump(cond, end);
+        if let Some(ind) = pick { self.set_jump(ind, end); }
+        self.loops[id].end = end;
+        self.loops[id].next = next;
+        self.truncate(prev);
+        if self.hooks { self.emit(Op::ExitLoop(loop_range)); }
+        // Remove end operand.
+        self.emit(Op::Pop);
+        self.operands -= 1;
+        if acc.is_none() {
+            self.emit(Op::Void);
+            self.operands += 1;
+        }
+        Ok(())
+    }
+
+    fn call(&mut self, expr: &Expression, call: &ast::Call) -> Result<(), ()> {
+        if swizzles(&call.args) { return self.eval(expr); }
+        let st = self.stack.len();
+        let op = match call.f_index.get() {
+            FnIndex::Loaded(f_index) => {
+                let module = self.module;
+                let f = &module.functions[(f_index + self.relative as isize) as usize];
+                if !f.accepts(call.args.len()) { return self.eval(expr); }
+                if f.returns() {
+                    self.emit(Op::PushReturn);
+                    self.stack.push(None);
+                }
+                for arg in &call.args {
+                    try!(self.push(arg, "Expected something. \
+                        Check that expression returns a value."));
+                }
+                // Push default values of omitted arguments.
+                for arg in &f.args[call.args.len()..] {
+                    self.emit(Op::Const(arg.default.as_ref().unwrap().to_variable()));
+                    self.emit(Op::Push(arg.source_range, "Expected something"));
+                    self.stack.push(None);
+                }
+                Op::Call(self.calls.len())
+            }
+            FnIndex::Intrinsic(index) => {
+                for arg in &call.args {
+                    try!(self.push(arg, "Expected something. \
+                        Expression did not return a value."));
+                }
+                Op::CallIntrinsic(index, self.calls.len())
+            }
+            FnIndex::ExternalVoid(_) | FnIndex::ExternalReturn(_) => {
+                for arg in &call.args {
+                    try!(self.push(arg, "Expected something. \
+                        Expression did not return a value."));
+                }
+                Op::CallExternal(self.calls.len())
+            }
+            FnIndex::ExternalDefaults(index) => {
+                for arg in &call.args {
+                    try!(self.push(arg, "Expected something. \
+                        Expression did not return a value."));
+                }
+                Op::CallExternalDefaults(index, self.calls.len())
+            }
+            FnIndex::None => return self.eval(expr)
+        };
+        self.calls.push(call.clone());
+        self.emit(op);
+        self.operands += 1;
+        self.stack.truncate(st);
+        Ok(())
+    }
+}
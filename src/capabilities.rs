@@ -0,0 +1,155 @@
+//! Capabilities for sandboxing scripts.
+//!
+//! Intrinsics that access the file system, network, standard input,
+//! threads or sleep require a capability.
+//! Scripts using a function that is not allowed fail type checking,
+//! and the same check is done at runtime when the function is reached
+//! dynamically, e.g. through `call` or `call_ret`.
+
+use std::path::{Path, PathBuf};
+
+/// A capability required by some intrinsics.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Capability {
+    /// Read files.
+    FsRead,
+    /// Write files.
+    FsWrite,
+    /// Access the network.
+    Network,
+    /// Read from standard input.
+    Stdin,
+    /// Start threads with `go` or `par` loops.
+    Threads,
+    /// Sleep the current thread.
+    Sleep,
+}
+
+impl Capability {
+    /// Returns the name used in error messages.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Capability::FsRead => "fs-read",
+            Capability::FsWrite => "fs-write",
+            Capability::Network => "network",
+            Capability::Stdin => "stdin",
+            Capability::Threads => "threads",
+            Capability::Sleep => "sleep",
+        }
+    }
+}
+
+/// Intrinsics and the capabilities they require.
+const INTRINSICS: &'static [(&'static str, &'static [Capability])] = &[
+    ("load", &[Capability::FsRead]),
+    ("load__source_imports", &[Capability::FsRead]),
+    ("load__meta_file", &[Capability::FsRead]),
+    ("load_string__file", &[Capability::FsRead]),
+    ("load_data__file", &[Capability::FsRead]),
+    ("save__string_file", &[Capability::FsWrite]),
+    ("save__data_file", &[Capability::FsWrite]),
+    ("download__url_file", &[Capability::Network, Capability::FsWrite]),
+    ("load__meta_url", &[Capability::Network, Capability::FsRead]),
+    ("load_string__url", &[Capability::Network]),
+    ("read_line", &[Capability::Stdin]),
+    ("read_number", &[Capability::Stdin]),
+    ("sleep", &[Capability::Sleep]),
+];
+
+/// Stores the capabilities of a module.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Capabilities {
+    pub fs_read: bool,
+    pub fs_write: bool,
+    /// Directories where files can be read and written, `None` for any directory.
+    pub fs_roots: Option<Vec<PathBuf>>,
+    pub network: bool,
+    pub stdin: bool,
+    pub threads: bool,
+    pub sleep: bool,
+}
+
+impl Default for Capabilities {
+    fn default() -> Capabilities { Capabilities::all() }
+}
+
+impl Capabilities {
+    /// Allows everything.
+    pub fn all() -> Capabilities {
+        Capabilities {
+            fs_read: true,
+            fs_write: true,
+            fs_roots: None,
+            network: true,
+            stdin: true,
+            threads: true,
+            sleep: true,
+        }
+    }
+
+    /// Allows nothing.
+    pub fn none() -> Capabilities {
+        Capabilities {
+            fs_read: false,
+            fs_write: false,
+            fs_roots: None,
+            network: false,
+            stdin: false,
+            threads: false,
+            sleep: false,
+        }
+    }
+
+    /// Returns `true` if capability is allowed.
+    pub fn has(&self, cap: Capability) -> bool {
+        match cap {
+            Capability::FsRead => self.fs_read,
+            Capability::FsWrite => self.fs_write,
+            Capability::Network => self.network,
+            Capability::Stdin => self.stdin,
+            Capability::Threads => self.threads,
+            Capability::Sleep => self.sleep,
+        }
+    }
+
+    /// Returns the first capability required by intrinsic that is not allowed.
+    pub fn denies(&self, intrinsic: &str) -> Option<Capability> {
+        for &(name, caps) in INTRINSICS {
+            if name == intrinsic {
+                return caps.iter().find(|&&cap| !self.has(cap)).cloned();
+            }
+        }
+        None
+    }
+
+    /// Returns `true` if file is inside one of the allowed directories.
+    ///
+    /// Uses the parent directory to resolve `..` and symbolic links
+    /// when the file does not exist.
+    pub fn allows_path(&self, file: &str) -> bool {
+        let roots = match self.fs_roots {
+            None => return true,
+            Some(ref roots) => roots,
+        };
+        let path = Path::new(file);
+        let full = match path.canonicalize() {
+            Ok(full) => full,
+            Err(_) => match (path.parent(), path.file_name()) {
+                (Some(parent), Some(name)) => {
+                    let parent = if parent.as_os_str().is_empty() {
+                        Path::new(".")
+                    } else { parent };
+                    match parent.canonicalize() {
+                        Ok(parent) => parent.join(name),
+                        Err(_) => return false,
+                    }
+                }
+                _ => return false,
+            }
+        };
+        roots.iter().any(|root| match root.canonicalize() {
+            Ok(root) => full.starts_with(root),
+            Err(_) => false,
+        })
+    }
+}
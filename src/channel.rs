@@ -0,0 +1,196 @@
+//! Channels for sending values between threads started by `go`.
+//!
+//! A channel is created with `chan()` and shared by passing it as argument to `go` calls.
+//! Values are deep cloned when sent, such that no references are shared between threads.
+//! Receiving from a closed channel returns the remaining values, then `none()`.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::Duration;
+
+use Variable;
+
+/// Wakes up a thread waiting on several channels, see `Channel::select`.
+struct Signal {
+    ready: Mutex<bool>,
+    cond: Condvar,
+}
+
+impl Signal {
+    fn notify(&self) {
+        *self.ready.lock().unwrap() = true;
+        self.cond.notify_one();
+    }
+}
+
+struct State {
+    queue: VecDeque<Variable>,
+    closed: bool,
+    /// Threads waiting on this channel among others.
+    waiters: Vec<Arc<Signal>>,
+}
+
+struct Inner {
+    state: Mutex<State>,
+    cond: Condvar,
+}
+
+/// A queue of values shared between threads.
+///
+/// Clones refer to the same channel.
+#[derive(Clone)]
+pub struct Channel {
+    inner: Arc<Inner>,
+}
+
+impl Channel {
+    /// Creates a new open channel.
+    pub fn new() -> Channel {
+        Channel {
+            inner: Arc::new(Inner {
+                state: Mutex::new(State {
+                    queue: VecDeque::new(),
+                    closed: false,
+                    waiters: vec![],
+                }),
+                cond: Condvar::new(),
+            })
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<State> {
+        self.inner.state.lock().unwrap()
+    }
+
+    /// Sends a value to the channel.
+    ///
+    /// Returns an error if the channel is closed.
+    pub fn send(&self, v: Variable) -> Result<(), String> {
+        let mut state = self.lock();
+        if state.closed {
+            return Err("Can not send to closed channel".into());
+        }
+        state.queue.push_back(v);
+        self.inner.cond.notify_one();
+        for waiter in &state.waiters { waiter.notify(); }
+        Ok(())
+    }
+
+    /// Receives the next value, waiting until there is one.
+    ///
+    /// Returns `None` when the channel is closed and empty.
+    pub fn recv(&self) -> Option<Variable> {
+        let mut state = self.lock();
+        loop {
+            if let Some(v) = state.queue.pop_front() { return Some(v); }
+            if state.closed { return None; }
+            state = self.inner.cond.wait(state).unwrap();
+        }
+    }
+
+    /// Receives the next value, waiting until there is one or the timeout.
+    ///
+    /// Returns `None` if no value arrived before the timeout.
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<Option<Variable>> {
+        let mut state = self.lock();
+        let mut timed_out = false;
+        loop {
+            if let Some(v) = state.queue.pop_front() { return Some(Some(v)); }
+            if state.closed { return Some(None); }
+            if timed_out { return None; }
+            let (s, res) = self.inner.cond.wait_timeout(state, timeout).unwrap();
+            state = s;
+            timed_out = res.timed_out();
+        }
+    }
+
+    /// Receives the next value without waiting.
+    pub fn try_recv(&self) -> Option<Variable> {
+        self.lock().queue.pop_front()
+    }
+
+    /// Closes the channel, waking up threads waiting to receive.
+    pub fn close(&self) {
+        let mut state = self.lock();
+        state.closed = true;
+        self.inner.cond.notify_all();
+        for waiter in &state.waiters { waiter.notify(); }
+    }
+
+    /// Returns `true` if the channel is closed.
+    pub fn is_closed(&self) -> bool {
+        self.lock().closed
+    }
+
+    /// Receives the next value from the first channel that has one,
+    /// waiting until any of the channels has a value.
+    ///
+    /// Returns the index of the channel with the value,
+    /// or `None` when all channels are closed and empty.
+    pub fn select(chans: &[Channel]) -> Option<(usize, Variable)> {
+        Channel::select_until(chans, None).expect("Expected value when waiting without timeout")
+    }
+
+    /// Same as `select`, but returns `None` if no value arrived before the timeout.
+    pub fn select_timeout(
+        chans: &[Channel],
+        timeout: Duration
+    ) -> Option<Option<(usize, Variable)>> {
+        Channel::select_until(chans, Some(timeout))
+    }
+
+    fn select_until(
+        chans: &[Channel],
+        timeout: Option<Duration>
+    ) -> Option<Option<(usize, Variable)>> {
+        let signal = Arc::new(Signal {
+            ready: Mutex::new(false),
+            cond: Condvar::new(),
+        });
+        let mut timed_out = false;
+        loop {
+            // Register before checking for values, such that no value is missed.
+            let mut res = None;
+            let mut all_closed = true;
+            for (i, ch) in chans.iter().enumerate() {
+                let mut state = ch.lock();
+                if let Some(v) = state.queue.pop_front() {
+                    res = Some(Some((i, v)));
+                    break;
+                }
+                if !state.closed { all_closed = false; }
+                state.waiters.push(signal.clone());
+            }
+            if res.is_none() && all_closed { res = Some(None); }
+            if res.is_none() {
+                let mut ready = signal.ready.lock().unwrap();
+                while !*ready {
+                    match timeout {
+                        None => ready = signal.cond.wait(ready).unwrap(),
+                        Some(timeout) => {
+                            let (r, t) = signal.cond.wait_timeout(ready, timeout).unwrap();
+                            ready = r;
+                            if t.timed_out() {
+                                timed_out = true;
+                                break;
+                            }
+                        }
+                    }
+                }
+                *ready = false;
+            }
+            for ch in chans {
+                ch.lock().waiters.retain(|w| !Arc::ptr_eq(w, &signal));
+            }
+            if res.is_some() { return res; }
+            if timed_out { return None; }
+        }
+    }
+}
+
+impl fmt::Debug for Channel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "channel")
+    }
+}
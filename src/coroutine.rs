@@ -0,0 +1,133 @@
+//! Coroutines created with `coroutine` and suspended with `yield`.
+//!
+//! A coroutine has its own runtime, which runs on the thread resuming it.
+//! `yield` suspends the functions of the coroutine like `Runtime::suspend`,
+//! keeping the stack, locals and currents until the coroutine is resumed.
+//! Arguments and yielded values are deep cloned, such that no references
+//! are shared between the coroutine and the code resuming it.
+//!
+//! Only functions compiled to bytecode can be suspended,
+//! so `yield` inside a closure or a call made by an external function stops with an error.
+//!
+//! External functions can suspend the runtime of a coroutine with `Runtime::suspend`,
+//! and the host can with `Coroutine::suspend`, which takes effect at the next safe point,
+//! e.g. inside a loop.
+//!
+//! Since the coroutine has its own runtime, the runtime resuming it is never suspended,
+//! and changes to its globals are not seen by the coroutine.
+
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use ast;
+use Module;
+use Variable;
+use runtime::{InterruptHandle, Runtime};
+
+pub use runtime::Status;
+
+struct State {
+    rt: Runtime,
+    module: Arc<Module>,
+    call: ast::Call,
+    /// Whether the call is made by the host, e.g. `Runtime::coroutine_str`.
+    loader: bool,
+    started: bool,
+    done: bool,
+}
+
+struct Inner {
+    state: Mutex<State>,
+    /// Requests the runtime of the coroutine to suspend.
+    interrupt: InterruptHandle,
+}
+
+/// A function call that can be suspended and resumed.
+///
+/// Clones refer to the same coroutine.
+#[derive(Clone)]
+pub struct Coroutine {
+    inner: Arc<Inner>,
+}
+
+impl Coroutine {
+    /// Creates a suspended coroutine calling a function on its own runtime.
+    ///
+    /// The function is called when the coroutine is resumed the first time.
+    pub(crate) fn new(
+        rt: Runtime,
+        call: ast::Call,
+        loader: bool,
+        module: Arc<Module>
+    ) -> Coroutine {
+        Coroutine {
+            inner: Arc::new(Inner {
+                interrupt: rt.interrupt_handle(),
+                state: Mutex::new(State {
+                    rt: rt,
+                    module: module,
+                    call: call,
+                    loader: loader,
+                    started: false,
+                    done: false,
+                }),
+            }),
+        }
+    }
+
+    /// Requests the coroutine to suspend at the next safe point.
+    ///
+    /// This can be called from another thread while the coroutine is running,
+    /// e.g. to limit the time spent per frame.
+    /// When called before `run`, the coroutine suspends soon after resuming.
+    pub fn suspend(&self) {
+        self.inner.interrupt.suspend();
+    }
+
+    /// Runs the coroutine until it yields, is suspended or returns.
+    ///
+    /// Returns the yielded value, or the returned value if the function returns one.
+    /// Returns an error if the coroutine fails, is running or has completed.
+    pub fn resume(&self) -> Result<Option<Variable>, String> {
+        Ok(match try!(self.run()) {
+            Status::Yielded(v) => Some(v),
+            Status::Suspended | Status::OutOfFuel => None,
+            Status::Done(v) => v,
+        })
+    }
+
+    /// Runs the coroutine until it yields, is suspended or returns.
+    ///
+    /// Returns an error if the coroutine fails, is running or has completed.
+    pub fn run(&self) -> Result<Status, String> {
+        let mut state = try!(self.inner.state.try_lock()
+            .map_err(|_| String::from("The coroutine is already running")));
+        let state = &mut *state;
+        if state.done {
+            return Err("The coroutine has already completed".into());
+        }
+        let start = !state.started;
+        state.started = true;
+        let res = state.rt.run_coroutine(&state.call, state.loader, start, &state.module);
+        match res {
+            Ok(Status::Done(_)) | Err(_) => state.done = true,
+            _ => {}
+        }
+        res
+    }
+
+    /// Returns `true` if the coroutine has completed.
+    pub fn is_done(&self) -> bool {
+        match self.inner.state.try_lock() {
+            Ok(state) => state.done,
+            // The coroutine is running.
+            Err(_) => false,
+        }
+    }
+}
+
+impl fmt::Debug for Coroutine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "coroutine")
+    }
+}
@@ -0,0 +1,263 @@
+//! Code coverage support.
+//!
+//! Records how many times expressions, blocks such as loop bodies,
+//! and branches of `if` expressions are executed.
+//! While coverage is recorded, functions compiled to bytecode
+//! run with instructions that record it.
+//! Threads started by `go` record to the coverage of the runtime starting them.
+
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+use range::Range;
+
+use ast;
+use Module;
+
+/// Recorded execution counts.
+#[derive(Debug, Clone, Default)]
+pub struct Counts {
+    /// Execution counts of expressions by file and range.
+    pub expressions: HashMap<(Arc<String>, Range), u64>,
+    /// Execution counts of blocks by file and range.
+    ///
+    /// This includes function bodies, loop bodies and branches.
+    pub blocks: HashMap<(Arc<String>, Range), u64>,
+    /// Counts of taken branches of `if` expressions by file and range.
+    ///
+    /// The first branch is the `if` block, followed by each `else if` block,
+    /// and last the `else` block, which is counted also when there is none.
+    pub branches: HashMap<(Arc<String>, Range), Vec<u64>>,
+}
+
+/// Records code coverage, shared with threads started by `go`.
+#[derive(Clone, Debug, Default)]
+pub struct Coverage {
+    counts: Arc<Mutex<Counts>>,
+}
+
+impl Coverage {
+    /// Creates a new coverage recorder.
+    pub fn new() -> Coverage { Coverage::default() }
+
+    /// Returns a copy of the recorded counts.
+    pub fn counts(&self) -> Counts {
+        self.counts.lock().unwrap().clone()
+    }
+
+    /// Removes the recorded counts.
+    pub fn clear(&self) {
+        *self.counts.lock().unwrap() = Counts::default();
+    }
+
+    /// Records an executed expression.
+    pub fn expression(&self, file: &Arc<String>, range: Range) {
+        let mut counts = self.counts.lock().unwrap();
+        *counts.expressions.entry((file.clone(), range)).or_insert(0) += 1;
+    }
+
+    /// Records an executed block.
+    pub fn block(&self, file: &Arc<String>, range: Range) {
+        let mut counts = self.counts.lock().unwrap();
+        *counts.blocks.entry((file.clone(), range)).or_insert(0) += 1;
+    }
+
+    /// Records a taken branch of an `if` expression by its range and number of branches.
+    pub fn branch(&self, file: &Arc<String>, range: Range, branches: usize, branch: usize) {
+        let mut counts = self.counts.lock().unwrap();
+        let taken = counts.branches.entry((file.clone(), range))
+            .or_insert_with(|| vec![0; branches]);
+        taken[branch] += 1;
+    }
+
+    /// Writes the coverage of functions in a module in lcov format.
+    ///
+    /// Lines, branches and functions that were not executed are reported with zero counts.
+    pub fn write_lcov<W: Write>(&self, w: &mut W, module: &Module) -> io::Result<()> {
+        let counts = self.counts.lock().unwrap();
+
+        let mut files: BTreeMap<&Arc<String>, Vec<&ast::Function>> = BTreeMap::new();
+        for f in &module.functions {
+            files.entry(&f.file).or_insert(vec![]).push(f);
+        }
+        for (file, mut functions) in files {
+            functions.sort_by_key(|f| f.source_range.offset);
+            let lines = match functions.first() {
+                None => continue,
+                Some(f) => Lines::new(&f.source),
+            };
+            let key = |range: Range| (file.clone(), range);
+            try!(writeln!(w, "TN:"));
+            try!(writeln!(w, "SF:{}", file));
+
+            let mut hit = 0;
+            for f in &functions {
+                try!(writeln!(w, "FN:{},{}", lines.line(f.source_range.offset), f.name));
+            }
+            for f in &functions {
+                let n = counts.blocks.get(&key(f.block.source_range)).cloned().unwrap_or(0);
+                if n > 0 { hit += 1; }
+                try!(writeln!(w, "FNDA:{},{}", n, f.name));
+            }
+            try!(writeln!(w, "FNF:{}", functions.len()));
+            try!(writeln!(w, "FNH:{}", hit));
+
+            let mut line_counts: BTreeMap<usize, u64> = BTreeMap::new();
+            let mut ifs: Vec<&ast::If> = vec![];
+            for f in &functions {
+                for expr in &f.block.expressions {
+                    walk(expr, &mut |expr| {
+                        let range = expr.source_range();
+                        let n = counts.expressions.get(&key(range)).cloned().unwrap_or(0);
+                        let count = line_counts.entry(lines.line(range.offset)).or_insert(0);
+                        if n > *count { *count = n; }
+                        if let ast::Expression::If(ref if_expr) = *expr {
+                            ifs.push(if_expr);
+                        }
+                    });
+                }
+            }
+
+            let (mut found, mut hit) = (0, 0);
+            for (i, if_expr) in ifs.iter().enumerate() {
+                let line = lines.line(if_expr.source_range.offset);
+                let taken = counts.branches.get(&key(if_expr.source_range));
+                for branch in 0..if_expr.else_if_blocks.len() + 2 {
+                    found += 1;
+                    match taken {
+                        None => try!(writeln!(w, "BRDA:{},{},{},-", line, i, branch)),
+                        Some(taken) => {
+                            if taken[branch] > 0 { hit += 1; }
+                            try!(writeln!(w, "BRDA:{},{},{},{}", line, i, branch, taken[branch]));
+                        }
+                    }
+                }
+            }
+            try!(writeln!(w, "BRF:{}", found));
+            try!(writeln!(w, "BRH:{}", hit));
+
+            for (line, n) in &line_counts {
+                try!(writeln!(w, "DA:{},{}", line, n));
+            }
+            try!(writeln!(w, "LF:{}", line_counts.len()));
+            try!(writeln!(w, "LH:{}", line_counts.values().filter(|&&n| n > 0).count()));
+            try!(writeln!(w, "end_of_record"));
+        }
+        Ok(())
+    }
+}
+
+/// Start offsets of lines in a source.
+struct Lines(Vec<usize>);
+
+impl Lines {
+    fn new(source: &str) -> Lines {
+        let mut starts = vec![0];
+        for (i, c) in source.bytes().enumerate() {
+            if c == b'\n' { starts.push(i + 1); }
+        }
+        Lines(starts)
+    }
+
+    /// Returns the line number of offset, starting at 1.
+    fn line(&self, offset: usize) -> usize {
+        match self.0.binary_search(&offset) {
+            Ok(i) => i + 1,
+            Err(i) => i,
+        }
+    }
+}
+
+/// Calls a function on an expression and all expressions inside it.
+pub fn walk<'a, F: FnMut(&'a ast::Expression)>(expr: &'a ast::Expression, f: &mut F) {
+    use ast::Expression::*;
+
+    fn walk_block<'a, F: FnMut(&'a ast::Expression)>(block: &'a ast::Block, f: &mut F) {
+        for expr in &block.expressions { walk(expr, f); }
+    }
+
+    fn walk_item<'a, F: FnMut(&'a ast::Expression)>(item: &'a ast::Item, f: &mut F) {
+        for id in &item.ids {
+            if let ast::Id::Expression(ref expr) = *id { walk(expr, f); }
+        }
+    }
+
+    f(expr);
+    match *expr {
+        Link(ref link) => for expr in &link.items { walk(expr, f); },
+        Object(ref obj) => for &(_, ref expr) in &obj.key_values { walk(expr, f); },
+        Array(ref arr) => for expr in &arr.items { walk(expr, f); },
+        ArrayFill(ref array_fill) => {
+            walk(&array_fill.fill, f);
+            walk(&array_fill.n, f);
+        }
+        Return(ref expr) | Yield(ref expr) | Try(ref expr) => walk(expr, f),
+        Block(ref block) => walk_block(block, f),
+        Go(ref go) | Coroutine(ref go) => for expr in &go.call.args { walk(expr, f); },
+        Call(ref call) => for expr in &call.args { walk(expr, f); },
+        Item(ref item) => walk_item(item, f),
+        BinOp(ref binop) => {
+            walk(&binop.left, f);
+            walk(&binop.right, f);
+        }
+        Assign(ref assign) => {
+            walk(&assign.left, f);
+            walk(&assign.right, f);
+        }
+        For(ref for_expr) => {
+            walk(&for_expr.init, f);
+            walk(&for_expr.cond, f);
+            walk(&for_expr.step, f);
+            walk_block(&for_expr.block, f);
+        }
+        ForN(ref for_n_expr) | Sum(ref for_n_expr) | SumVec4(ref for_n_expr) |
+        Prod(ref for_n_expr) | ProdVec4(ref for_n_expr) | Min(ref for_n_expr) |
+        Max(ref for_n_expr) | Sift(ref for_n_expr) | Any(ref for_n_expr) |
+        All(ref for_n_expr) | LinkFor(ref for_n_expr) => {
+            if let Some(ref start) = for_n_expr.start { walk(start, f); }
+            walk(&for_n_expr.end, f);
+            walk_block(&for_n_expr.block, f);
+        }
+        ForIn(ref for_in_expr) | SumIn(ref for_in_expr) | MinIn(ref for_in_expr) |
+        MaxIn(ref for_in_expr) | SiftIn(ref for_in_expr) | AnyIn(ref for_in_expr) |
+        AllIn(ref for_in_expr) => {
+            walk(&for_in_expr.expr, f);
+            walk_block(&for_in_expr.block, f);
+        }
+        If(ref if_expr) => {
+            walk(&if_expr.cond, f);
+            walk_block(&if_expr.true_block, f);
+            for (cond, block) in if_expr.else_if_conds.iter().zip(&if_expr.else_if_blocks) {
+                walk(cond, f);
+                walk_block(block, f);
+            }
+            if let Some(ref block) = if_expr.else_block { walk_block(block, f); }
+        }
+        Match(ref match_expr) => {
+            walk(&match_expr.expr, f);
+            for arm in &match_expr.arms {
+                if let Some(ref guard) = arm.guard { walk(guard, f); }
+                walk(&arm.expr, f);
+            }
+        }
+        EnumValue(ref enum_value) => {
+            if let Some(ref payload) = enum_value.payload { walk(payload, f); }
+        }
+        Compare(ref compare) => {
+            walk(&compare.left, f);
+            walk(&compare.right, f);
+        }
+        UnOp(ref unop) => walk(&unop.expr, f),
+        Norm(ref norm) => walk(&norm.expr, f),
+        Swizzle(ref sw) => walk(&sw.expr, f),
+        Closure(ref closure) => walk(&closure.expr, f),
+        CallClosure(ref call) => {
+            walk_item(&call.item, f);
+            for expr in &call.args { walk(expr, f); }
+        }
+        Grab(ref grab) => walk(&grab.expr, f),
+        TryExpr(ref try_expr) => walk(&try_expr.expr, f),
+        ReturnVoid(_) | Break(_) | Continue(_) | Text(_) | Number(_) |
+        Vec4(_) | Bool(_) | Variable(_, _) => {}
+    }
+}
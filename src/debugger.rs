@@ -0,0 +1,239 @@
+//! Debugger support.
+//!
+//! The runtime pauses before executing an expression in a block
+//! when hitting a breakpoint or when stepping.
+//! While a debugger is attached, functions compiled to bytecode
+//! run with instructions for pausing.
+//! Threads started by `go` run without the debugger.
+
+use std::sync::Arc;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use range::Range;
+
+use Module;
+use Runtime;
+use Variable;
+
+/// How to continue after pausing.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Step {
+    /// Run until next breakpoint.
+    Continue,
+    /// Pause at next expression, entering function calls.
+    Into,
+    /// Pause at next expression in the same or calling function.
+    Over,
+    /// Pause at next expression in the calling function.
+    Out,
+    /// Stop execution with an error.
+    Stop,
+}
+
+/// A breakpoint at a line in a file.
+///
+/// The file matches when both paths lead to the same file,
+/// or when one path ends with all the components of the other,
+/// e.g. `/home/user/src/main.dyon` matches `src/main.dyon`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Breakpoint {
+    pub file: String,
+    /// The line number, starting at 1.
+    pub line: usize,
+}
+
+/// Where the runtime paused.
+#[derive(Debug, Clone)]
+pub struct Position {
+    pub file: Option<Arc<String>>,
+    /// The line number, starting at 1.
+    pub line: usize,
+    /// The column number in bytes, starting at 1.
+    pub column: usize,
+    pub range: Range,
+    /// Number of function calls on the call stack.
+    pub depth: usize,
+    /// Whether the runtime paused because of a breakpoint.
+    pub breakpoint: bool,
+    /// Line and column of the current expression in each function
+    /// on the call stack, `(0, 0)` if not known.
+    pub frame_lines: Vec<(usize, usize)>,
+}
+
+/// A function call on the call stack, with its variables.
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub name: Arc<String>,
+    pub file: Option<Arc<String>>,
+    pub locals: Vec<(Arc<String>, Variable)>,
+    pub currents: Vec<(Arc<String>, Variable)>,
+}
+
+/// Implemented by debugger front ends.
+pub trait DebugHandler: Send {
+    /// Called when the runtime pauses.
+    ///
+    /// Breakpoints can be changed before continuing.
+    fn pause(
+        &mut self,
+        rt: &Runtime,
+        pos: &Position,
+        breakpoints: &mut Vec<Breakpoint>
+    ) -> Step;
+}
+
+/// Stores debugger state of a runtime.
+pub struct Debugger {
+    pub breakpoints: Vec<Breakpoint>,
+    handler: Box<DebugHandler>,
+    /// The step mode with the call depth where stepping started.
+    step: Option<(Step, usize)>,
+    /// Start offsets of lines by source.
+    lines: HashMap<usize, Vec<usize>>,
+    /// Line and column of the current expression in each function.
+    frame_lines: Vec<(usize, usize)>,
+    /// Canonical paths of files, `None` if the file was not found.
+    paths: HashMap<String, Option<PathBuf>>,
+}
+
+impl Debugger {
+    /// Creates a new debugger that pauses at first expression.
+    pub fn new(handler: Box<DebugHandler>) -> Debugger {
+        Debugger {
+            breakpoints: vec![],
+            handler: handler,
+            step: Some((Step::Into, 0)),
+            lines: HashMap::new(),
+            frame_lines: vec![],
+            paths: HashMap::new(),
+        }
+    }
+
+    /// Creates a new debugger that runs until hitting a breakpoint.
+    pub fn with_breakpoints(
+        handler: Box<DebugHandler>,
+        breakpoints: Vec<Breakpoint>
+    ) -> Debugger {
+        Debugger {
+            breakpoints: breakpoints,
+            handler: handler,
+            step: None,
+            lines: HashMap::new(),
+            frame_lines: vec![],
+            paths: HashMap::new(),
+        }
+    }
+
+    /// Returns line and column of offset in source, starting at 1.
+    fn line_column(&mut self, source: &Arc<String>, offset: usize) -> (usize, usize) {
+        let lines = self.lines.entry(&**source as *const String as usize)
+            .or_insert_with(|| {
+                let mut lines = vec![0];
+                for (i, c) in source.bytes().enumerate() {
+                    if c == b'\n' { lines.push(i + 1); }
+                }
+                lines
+            });
+        let line = match lines.binary_search(&offset) {
+            Ok(i) => i,
+            Err(i) => i - 1,
+        };
+        (line + 1, offset - lines[line] + 1)
+    }
+
+    fn is_breakpoint(&mut self, file: &Option<Arc<String>>, line: usize) -> bool {
+        match *file {
+            None => false,
+            Some(ref file) => {
+                let paths = &mut self.paths;
+                self.breakpoints.iter()
+                    .any(|b| b.line == line && same_file(paths, file, &b.file))
+            }
+        }
+    }
+}
+
+/// Returns `true` if two paths lead to the same file.
+///
+/// Canonical paths are cached, since files are compared while running.
+fn same_file(paths: &mut HashMap<String, Option<PathBuf>>, a: &str, b: &str) -> bool {
+    let mut canonical = |file: &str| paths.entry(file.into())
+        .or_insert_with(|| Path::new(file).canonicalize().ok()).clone();
+    if let (Some(a), Some(b)) = (canonical(a), canonical(b)) {
+        return a == b;
+    }
+    let (a, b) = (Path::new(a), Path::new(b));
+    a.ends_with(b) || b.ends_with(a)
+}
+
+/// Pauses if there is a breakpoint or when stepping.
+///
+/// Called before executing an expression in a block.
+pub fn pause(
+    debugger: &mut Debugger,
+    rt: &Runtime,
+    range: Range,
+    module: &Module
+) -> Result<(), String> {
+    let depth = rt.call_stack.len();
+    let f = match rt.call_stack.last() {
+        None => return Ok(()),
+        Some(call) => &module.functions[call.index],
+    };
+    let (line, column) = debugger.line_column(&f.source, range.offset);
+    debugger.frame_lines.resize(depth, (0, 0));
+    debugger.frame_lines[depth - 1] = (line, column);
+    let file = Some(f.file.clone());
+    let breakpoint = debugger.is_breakpoint(&file, line);
+    let stepping = match debugger.step {
+        None => false,
+        Some((Step::Into, _)) => true,
+        Some((Step::Over, d)) => depth <= d,
+        Some((Step::Out, d)) => depth < d,
+        Some((Step::Continue, _)) | Some((Step::Stop, _)) => false,
+    };
+    if !breakpoint && !stepping { return Ok(()); }
+
+    let pos = Position {
+        file: file,
+        line: line,
+        column: column,
+        range: range,
+        depth: depth,
+        breakpoint: breakpoint,
+        frame_lines: debugger.frame_lines.clone(),
+    };
+    let step = debugger.handler.pause(rt, &pos, &mut debugger.breakpoints);
+    debugger.step = match step {
+        Step::Continue => None,
+        Step::Stop => return Err(module.error(range,
+            &format!("{}\nStopped by debugger", rt.stack_trace()), rt)),
+        x => Some((x, depth)),
+    };
+    Ok(())
+}
+
+/// Returns the function calls on the call stack, with their variables.
+pub fn frames(rt: &Runtime) -> Vec<Frame> {
+    let n = rt.call_stack.len();
+    let mut frames = Vec::with_capacity(n);
+    for (i, call) in rt.call_stack.iter().enumerate() {
+        let (local_end, current_end) = if i + 1 < n {
+            (rt.call_stack[i + 1].local_len, rt.call_stack[i + 1].current_len)
+        } else {
+            (rt.local_stack.len(), rt.current_stack.len())
+        };
+        let vars = |list: &[(Arc<String>, usize)]| -> Vec<(Arc<String>, Variable)> {
+            list.iter().map(|&(ref name, ind)| {
+                (name.clone(), rt.resolve(&rt.stack[ind]).deep_clone(&rt.stack))
+            }).collect()
+        };
+        frames.push(Frame {
+            name: call.fn_name.clone(),
+            file: call.file.clone(),
+            locals: vars(&rt.local_stack[call.local_len..local_end]),
+            currents: vars(&rt.current_stack[call.current_len..current_end]),
+        });
+    }
+    frames
+}
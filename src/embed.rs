@@ -1,4 +1,6 @@
-use std::sync::Arc;
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
 
 use Error;
 use Object;
@@ -12,6 +14,31 @@ pub fn obj_field<T: PopVariable>(rt: &Runtime, obj: &Object, name: &str) -> Resu
     PopVariable::pop_var(rt, var)
 }
 
+/// Implemented by Rust types that are named in Dyon.
+///
+/// External functions declare these types with `Type::Rust`,
+/// such that the type checker can tell them apart.
+/// Values are pushed as `rust_object` and popped by downcasting.
+pub trait RustType: Any + Clone {
+    /// The name of the type in Dyon.
+    fn name() -> &'static str;
+}
+
+lazy_static! {
+    /// Names of Rust types that have been pushed to a runtime.
+    static ref RUST_TYPES: Mutex<HashMap<TypeId, Arc<String>>> = Mutex::new(HashMap::new());
+}
+
+/// Returns the name of a Rust object,
+/// or `None` if its type is unknown or the object is locked.
+pub fn rust_type_name(obj: &RustObject) -> Option<Arc<String>> {
+    let id = match obj.try_lock() {
+        Ok(guard) => (*guard).type_id(),
+        Err(_) => return None,
+    };
+    RUST_TYPES.lock().unwrap().get(&id).cloned()
+}
+
 /// Implemented by types that can be popped from the runtime stack.
 pub trait PopVariable: Sized {
     /// Converts variable to self.
@@ -48,6 +75,19 @@ impl PopVariable for RustObject {
     }
 }
 
+impl<T: RustType> PopVariable for T {
+    fn pop_var(rt: &Runtime, var: &Variable) -> Result<Self, String> {
+        if let &Variable::RustObject(ref robj) = var {
+            let guard = try!(robj.lock().map_err(|err|
+                format!("{}\nCan not lock Rust object mutex:\n{}", rt.stack_trace(), err)));
+            if let Some(val) = guard.downcast_ref::<T>() {
+                return Ok(val.clone());
+            }
+        }
+        Err(rt.expected(var, T::name()))
+    }
+}
+
 impl PopVariable for bool {
     fn pop_var(rt: &Runtime, var: &Variable) -> Result<Self, String> {
         if let &Variable::Bool(b, _) = var {
@@ -118,6 +158,27 @@ impl PopVariable for f64 {
     }
 }
 
+impl PopVariable for i64 {
+    fn pop_var(rt: &Runtime, var: &Variable) -> Result<Self, String> {
+        if let &Variable::I64(n) = var {
+            Ok(n)
+        } else {
+            Err(rt.expected(var, "i64"))
+        }
+    }
+}
+
+/// Uses the same bits as `i64`, such that e.g. hashes round-trip unchanged.
+impl PopVariable for u64 {
+    fn pop_var(rt: &Runtime, var: &Variable) -> Result<Self, String> {
+        if let &Variable::I64(n) = var {
+            Ok(n as u64)
+        } else {
+            Err(rt.expected(var, "i64"))
+        }
+    }
+}
+
 impl<T: PopVariable> PopVariable for Option<T> {
     fn pop_var(rt: &Runtime, var: &Variable) -> Result<Self, String> {
         if let &Variable::Option(ref s) = var {
@@ -252,6 +313,14 @@ impl PushVariable for RustObject {
     }
 }
 
+impl<T: RustType> PushVariable for T {
+    fn push_var(&self) -> Variable {
+        RUST_TYPES.lock().unwrap().entry(TypeId::of::<T>())
+            .or_insert_with(|| Arc::new(T::name().into()));
+        Variable::RustObject(Arc::new(Mutex::new(self.clone())))
+    }
+}
+
 impl PushVariable for bool {
     fn push_var(&self) -> Variable { Variable::bool(*self) }
 }
@@ -272,6 +341,15 @@ impl PushVariable for f64 {
     fn push_var(&self) -> Variable { Variable::f64(*self) }
 }
 
+impl PushVariable for i64 {
+    fn push_var(&self) -> Variable { Variable::I64(*self) }
+}
+
+/// Uses the same bits as `i64`, such that e.g. hashes round-trip unchanged.
+impl PushVariable for u64 {
+    fn push_var(&self) -> Variable { Variable::I64(*self as i64) }
+}
+
 impl PushVariable for str {
     fn push_var(&self) -> Variable { Variable::Text(Arc::new(self.into())) }
 }
@@ -16,6 +16,7 @@ pub enum Grabbed {
     Block(ast::Block),
     Item(ast::Item),
     ForN(ast::ForN),
+    ForIn(ast::ForIn),
 }
 
 pub fn grab_expr(
@@ -51,6 +52,13 @@ pub fn grab_expr(
                     x => return x,
                 }))), Flow::Continue))
         }
+        &E::Yield(ref expr) => {
+            Ok((Grabbed::Expression(E::Yield(
+                Box::new(match grab_expr(level, rt, expr, side, module) {
+                    Ok((Grabbed::Expression(x), Flow::Continue)) => x,
+                    x => return x,
+                }))), Flow::Continue))
+        }
         &E::Try(ref expr) => {
             Ok((Grabbed::Expression(E::Try(
                 Box::new(match grab_expr(level, rt, expr, side, module) {
@@ -178,9 +186,57 @@ pub fn grab_expr(
                 source_range: if_expr.source_range.clone(),
             }))), Flow::Continue))
         },
-        &E::Go(ref go) => {
+        &E::Match(ref match_expr) => {
+            Ok((Grabbed::Expression(E::Match(Box::new(ast::Match {
+                expr: match grab_expr(level, rt, &match_expr.expr, side, module) {
+                    Ok((Grabbed::Expression(x), Flow::Continue)) => x,
+                    x => return x,
+                },
+                arms: {
+                    let mut new_arms = vec![];
+                    for arm in &match_expr.arms {
+                        new_arms.push(ast::MatchArm {
+                            pattern: arm.pattern.clone(),
+                            guard: match arm.guard {
+                                None => None,
+                                Some(ref guard) => {
+                                    match grab_expr(level, rt, guard, side, module) {
+                                        Ok((Grabbed::Expression(x), Flow::Continue)) => Some(x),
+                                        x => return x,
+                                    }
+                                }
+                            },
+                            expr: match grab_expr(level, rt, &arm.expr, side, module) {
+                                Ok((Grabbed::Expression(x), Flow::Continue)) => x,
+                                x => return x,
+                            },
+                            source_range: arm.source_range,
+                        });
+                    }
+                    new_arms
+                },
+                source_range: match_expr.source_range,
+            }))), Flow::Continue))
+        },
+        &E::EnumValue(ref enum_value) => {
+            Ok((Grabbed::Expression(E::EnumValue(Box::new(ast::EnumValue {
+                name: enum_value.name.clone(),
+                variant: enum_value.variant.clone(),
+                payload: match enum_value.payload {
+                    None => None,
+                    Some(ref payload) => {
+                        match grab_expr(level, rt, payload, side, module) {
+                            Ok((Grabbed::Expression(x), Flow::Continue)) => Some(x),
+                            x => return x,
+                        }
+                    }
+                },
+                source_range: enum_value.source_range,
+            }))), Flow::Continue))
+        },
+        &E::Go(ref go) | &E::Coroutine(ref go) => {
             let call = &go.call;
-            Ok((Grabbed::Expression(E::Go(Box::new(ast::Go {
+            let go = Box::new(ast::Go {
                 call: ast::Call {
                     alias: call.alias.clone(),
                     name: call.name.clone(),
@@ -199,7 +255,11 @@ pub fn grab_expr(
                     custom_source: call.custom_source.clone(),
                 },
                 source_range: go.source_range.clone(),
-            }))), Flow::Continue))
+            });
+            Ok((Grabbed::Expression(match *expr {
+                E::Go(_) => E::Go(go),
+                _ => E::Coroutine(go),
+            }), Flow::Continue))
         }
         &E::Call(ref call) => {
             Ok((Grabbed::Expression(E::Call(ast::Call {
@@ -287,6 +347,48 @@ pub fn grab_expr(
             }
             x => return x,
         },
+        &E::ForIn(ref for_in) => match grab_for_in(level, rt, for_in, side, module) {
+            Ok((Grabbed::ForIn(x), Flow::Continue)) => {
+                Ok((Grabbed::Expression(E::ForIn(Box::new(x))), Flow::Continue))
+            }
+            x => return x,
+        },
+        &E::SumIn(ref for_in) => match grab_for_in(level, rt, for_in, side, module) {
+            Ok((Grabbed::ForIn(x), Flow::Continue)) => {
+                Ok((Grabbed::Expression(E::SumIn(Box::new(x))), Flow::Continue))
+            }
+            x => return x,
+        },
+        &E::MinIn(ref for_in) => match grab_for_in(level, rt, for_in, side, module) {
+            Ok((Grabbed::ForIn(x), Flow::Continue)) => {
+                Ok((Grabbed::Expression(E::MinIn(Box::new(x))), Flow::Continue))
+            }
+            x => return x,
+        },
+        &E::MaxIn(ref for_in) => match grab_for_in(level, rt, for_in, side, module) {
+            Ok((Grabbed::ForIn(x), Flow::Continue)) => {
+                Ok((Grabbed::Expression(E::MaxIn(Box::new(x))), Flow::Continue))
+            }
+            x => return x,
+        },
+        &E::SiftIn(ref for_in) => match grab_for_in(level, rt, for_in, side, module) {
+            Ok((Grabbed::ForIn(x), Flow::Continue)) => {
+                Ok((Grabbed::Expression(E::SiftIn(Box::new(x))), Flow::Continue))
+            }
+            x => return x,
+        },
+        &E::AnyIn(ref for_in) => match grab_for_in(level, rt, for_in, side, module) {
+            Ok((Grabbed::ForIn(x), Flow::Continue)) => {
+                Ok((Grabbed::Expression(E::AnyIn(Box::new(x))), Flow::Continue))
+            }
+            x => return x,
+        },
+        &E::AllIn(ref for_in) => match grab_for_in(level, rt, for_in, side, module) {
+            Ok((Grabbed::ForIn(x), Flow::Continue)) => {
+                Ok((Grabbed::Expression(E::AllIn(Box::new(x))), Flow::Continue))
+            }
+            x => return x,
+        },
         &E::SumVec4(ref for_n) => match grab_for_n(level, rt, for_n, side, module) {
             Ok((Grabbed::ForN(x), Flow::Continue)) => {
                 Ok((Grabbed::Expression(E::SumVec4(Box::new(x))), Flow::Continue))
@@ -478,6 +580,7 @@ fn grab_item(
         name: item.name.clone(),
         stack_id: item.stack_id.clone(),
         static_stack_id: item.static_stack_id.clone(),
+        global: item.global.clone(),
         current: item.current.clone(),
         try: item.try.clone(),
         ids: {
@@ -501,6 +604,31 @@ fn grab_item(
     }), Flow::Continue))
 }
 
+fn grab_for_in(
+    level: u16,
+    rt: &mut Runtime,
+    for_in: &ast::ForIn,
+    side: Side,
+    module: &Arc<Module>,
+) -> Result<(Grabbed, Flow), String> {
+    Ok((Grabbed::ForIn(ast::ForIn {
+        key: for_in.key.clone(),
+        name: for_in.name.clone(),
+        mutable: for_in.mutable,
+        expr: match grab_expr(level, rt, &for_in.expr, side, module) {
+            Ok((Grabbed::Expression(x), Flow::Continue)) => x,
+            x => return x,
+        },
+        block: match grab_block(level, rt, &for_in.block, side, module) {
+            Ok((Grabbed::Block(x), Flow::Continue)) => x,
+            x => return x,
+        },
+        label: for_in.label.clone(),
+        i64_acc: for_in.i64_acc.clone(),
+        source_range: for_in.source_range.clone(),
+    }), Flow::Continue))
+}
+
 fn grab_for_n(
     level: u16,
     rt: &mut Runtime,
@@ -528,6 +656,8 @@ fn grab_for_n(
             x => return x,
         },
         label: for_n.label.clone(),
+        parallel: for_n.parallel,
+        i64_acc: for_n.i64_acc.clone(),
         source_range: for_n.source_range.clone()
     }), Flow::Continue))
 }
@@ -95,6 +95,17 @@ fn expr(
     }
     // Number.
     if let Some(range) = read.number(&NUMBER_SETTINGS) {
+        // Integer.
+        if let Some(suffix) = read.consume(range.length).tag("i64") {
+            use ast::parse_i64;
+
+            if let Some(val) = parse_i64(&read.raw_string(range.length)) {
+                *read = read.consume(range.length + suffix.length);
+                return Ok(Variable::I64(val));
+            } else {
+                return Err(error(range, "Expected integer in range of `i64`", data));
+            }
+        }
         match read.parse_number(&NUMBER_SETTINGS, range.length) {
             Ok(val) => {
                 *read = read.consume(range.length);
@@ -112,9 +123,53 @@ fn expr(
         *read = read.consume(range.length);
         return Ok(Variable::bool(true));
     }
+    // Enum variant.
+    if read.src.chars().next().map(|c| c.is_uppercase()).unwrap_or(false) {
+        return enum_variant(read, strings, data);
+    }
     Err(error(read.start(), "Reached end of file", data))
 }
 
+fn enum_variant(
+    read: &mut ReadToken,
+    strings: &mut Strings,
+    data: &str
+) -> Result<Variable, String> {
+    use EnumVariant;
+
+    let (range, _) = read.until_any_or_whitespace(SEPS);
+    let name = read.raw_string(range.length);
+    *read = read.consume(range.length);
+    if let Some(range) = read.tag("::") {
+        *read = read.consume(range.length);
+    } else {
+        return Err(error(read.start(), "Expected `::`", data));
+    }
+    let (range, _) = read.until_any_or_whitespace(SEPS);
+    if range.length == 0 {
+        return Err(error(range, "Expected variant name", data));
+    }
+    let variant = read.raw_string(range.length);
+    *read = read.consume(range.length);
+    let mut payload = None;
+    if let Some(range) = read.tag("(") {
+        *read = read.consume(range.length);
+        opt_w(read);
+        payload = Some(try!(expr(read, strings, data)));
+        opt_w(read);
+        if let Some(range) = read.tag(")") {
+            *read = read.consume(range.length);
+        } else {
+            return Err(error(read.start(), "Expected `)`", data));
+        }
+    }
+    Ok(Variable::Enum(Box::new(EnumVariant {
+        name: Arc::new(name),
+        variant: Arc::new(variant),
+        payload: payload,
+    })))
+}
+
 fn object(
     read: &mut ReadToken,
     strings: &mut Strings,
@@ -11,7 +11,8 @@ pub fn check(f: &ast::Function, args: &Array) -> Result<(), String> {
         for (i, arg) in f.args.iter().enumerate() {
             map.insert(arg.name.clone(), i);
         }
-        for (i, arg) in f.args.iter().enumerate() {
+        // Omitted arguments use default values that are not references.
+        for (i, arg) in f.args.iter().enumerate().take(args.len()) {
             if let Some(ref lt) = arg.lifetime {
                 if let Variable::Ref(_) = args[i] {
                     if &**lt == "return" { continue; }
@@ -21,6 +22,7 @@ pub fn check(f: &ast::Function, args: &Array) -> Result<(), String> {
                 }
                 match map.get(lt) {
                     None => return Err(format!("Something wrong with lifetime `{}`", lt)),
+                    Some(&ind) if ind >= args.len() => continue,
                     Some(&ind) => {
                         let mut left = None;
                         let mut right = None;
@@ -59,6 +61,7 @@ fn min_ref(v: &Variable, min: &mut Option<usize>) {
         &Variable::Return => {}
         &Variable::Bool(_, _) => {}
         &Variable::F64(_, _) => {}
+        &Variable::I64(_) => {}
         &Variable::Vec4(_) => {}
         &Variable::Text(_) => {}
         &Variable::Link(_) => {}
@@ -66,7 +69,11 @@ fn min_ref(v: &Variable, min: &mut Option<usize>) {
         &Variable::RustObject(_) => {}
         &Variable::Option(_) => {}
         &Variable::Result(_) => {}
+        &Variable::Enum(_) => {}
         &Variable::Thread(_) => {}
+        &Variable::Coroutine(_) => {}
+        &Variable::Channel(_) => {}
+        &Variable::Shared(_) => {}
         &Variable::Array(ref arr) => {
             for v in arr.iter() {
                 min_ref(v, min);
@@ -12,6 +12,7 @@ use Error;
 use Module;
 use Variable;
 use Type;
+use embed;
 use TINVOTS;
 
 mod io;
@@ -113,6 +114,22 @@ const META__SYNTAX_IN_STRING: usize = 89;
 const MODULE__IN_STRING_IMPORTS: usize = 90;
 const LOAD_STRING__URL: usize = 91;
 const PARSE_NUMBER: usize = 92;
+const RESUME: usize = 93;
+const IS_DONE: usize = 94;
+const I64: usize = 95;
+const F64: usize = 96;
+const CHAN: usize = 97;
+const SEND: usize = 98;
+const RECV: usize = 99;
+const TRY_RECV: usize = 100;
+const CLOSE: usize = 101;
+const SELECT: usize = 102;
+const SHARED: usize = 103;
+const READ: usize = 104;
+const WRITE: usize = 105;
+const UPDATE: usize = 106;
+const WAIT_ALL: usize = 107;
+const CANCEL: usize = 108;
 
 const TABLE: &'static [(usize, fn(
         &mut Runtime,
@@ -213,14 +230,40 @@ const TABLE: &'static [(usize, fn(
     (MODULE__IN_STRING_IMPORTS, module__in_string_imports),
     (LOAD_STRING__URL, load_string__url),
     (PARSE_NUMBER, parse_number),
+    (RESUME, resume),
+    (IS_DONE, is_done),
+    (I64, i64),
+    (F64, f64),
+    (CHAN, chan),
+    (SEND, send),
+    (RECV, recv),
+    (TRY_RECV, try_recv),
+    (CLOSE, close),
+    (SELECT, select),
+    (SHARED, shared),
+    (READ, read),
+    (WRITE, write),
+    (UPDATE, update),
+    (WAIT_ALL, wait_all),
+    (CANCEL, cancel),
 ];
 
+/// Returns the declaration of an intrinsic.
+pub fn dfn(index: usize) -> &'static Dfn {
+    lazy_static! {
+        static ref STANDARD: Vec<Dfn> = Prelude::new_intrinsics().list;
+    }
+
+    &STANDARD[index]
+}
+
 pub fn standard(f: &mut Prelude) {
     let sarg = |f: &mut Prelude, name: &str, index: usize, ty: Type, ret: Type| {
         f.intrinsic(Arc::new(name.into()), index, Dfn {
             lts: vec![Lt::Default],
             tys: vec![ty],
-            ret: ret
+            ret: ret,
+            defaults: vec![]
         });
     };
 
@@ -231,22 +274,26 @@ pub fn standard(f: &mut Prelude) {
     f.intrinsic(Arc::new("why".into()), WHY, Dfn {
         lts: vec![Lt::Default],
         tys: vec![Type::Secret(Box::new(Type::Bool))],
-        ret: Type::array()
+        ret: Type::array(),
+        defaults: vec![]
     });
     f.intrinsic(Arc::new("where".into()), WHERE, Dfn {
         lts: vec![Lt::Default],
         tys: vec![Type::Secret(Box::new(Type::F64))],
-        ret: Type::array()
+        ret: Type::array(),
+        defaults: vec![]
     });
     f.intrinsic(Arc::new("explain_why".into()), EXPLAIN_WHY, Dfn {
         lts: vec![Lt::Default; 2],
         tys: vec![Type::Bool, Type::Any],
-        ret: Type::Secret(Box::new(Type::Bool))
+        ret: Type::Secret(Box::new(Type::Bool)),
+        defaults: vec![]
     });
     f.intrinsic(Arc::new("explain_where".into()), EXPLAIN_WHERE, Dfn {
         lts: vec![Lt::Default; 2],
         tys: vec![Type::F64, Type::Any],
-        ret: Type::Secret(Box::new(Type::F64))
+        ret: Type::Secret(Box::new(Type::F64)),
+        defaults: vec![]
     });
     sarg(f, "println", PRINTLN, Type::Any, Type::Void);
     sarg(f, "print", PRINT, Type::Any, Type::Void);
@@ -254,18 +301,21 @@ pub fn standard(f: &mut Prelude) {
     f.intrinsic(Arc::new("debug".into()), DEBUG, Dfn {
         lts: vec![],
         tys: vec![],
-        ret: Type::Void
+        ret: Type::Void,
+        defaults: vec![]
     });
     f.intrinsic(Arc::new("backtrace".into()), BACKTRACE, Dfn {
         lts: vec![],
         tys: vec![],
-        ret: Type::Void
+        ret: Type::Void,
+        defaults: vec![]
     });
     sarg(f, "sleep", SLEEP, Type::F64, Type::Void);
     f.intrinsic(Arc::new("random".into()), RANDOM, Dfn {
         lts: vec![],
         tys: vec![],
-        ret: Type::F64
+        ret: Type::F64,
+        defaults: vec![]
     });
     sarg(f, "head", HEAD, Type::Link, Type::Any);
     sarg(f, "tail", TAIL, Type::Link, Type::Link);
@@ -274,30 +324,35 @@ pub fn standard(f: &mut Prelude) {
     f.intrinsic(Arc::new("read_line".into()), READ_LINE, Dfn {
         lts: vec![],
         tys: vec![],
-        ret: Type::Text
+        ret: Type::Text,
+        defaults: vec![]
     });
     sarg(f, "len", LEN, Type::array(), Type::F64);
     f.intrinsic(Arc::new("push_ref(mut,_)".into()), PUSH_REF, Dfn {
         lts: vec![Lt::Default, Lt::Arg(0)],
         tys: vec![Type::array(), Type::Any],
-        ret: Type::Void
+        ret: Type::Void,
+        defaults: vec![]
     });
     f.intrinsic(Arc::new("push(mut,_)".into()), PUSH, Dfn {
         lts: vec![Lt::Default; 2],
         tys: vec![Type::array(), Type::Any],
-        ret: Type::Void
+        ret: Type::Void,
+        defaults: vec![]
     });
     f.intrinsic(Arc::new("pop(mut)".into()), POP, Dfn {
         lts: vec![Lt::Return],
         tys: vec![Type::array()],
-        ret: Type::Any
+        ret: Type::Any,
+        defaults: vec![]
     });
     sarg(f, "reverse(mut)", REVERSE, Type::array(), Type::Void);
     sarg(f, "clear(mut)", CLEAR, Type::array(), Type::Void);
     f.intrinsic(Arc::new("swap(mut,_,_)".into()), SWAP, Dfn {
         lts: vec![Lt::Default; 3],
         tys: vec![Type::array(), Type::F64, Type::F64],
-        ret: Type::Void
+        ret: Type::Void,
+        defaults: vec![]
     });
     sarg(f, "trim", TRIM, Type::Text, Type::Text);
     sarg(f, "trim_left", TRIM_LEFT, Type::Text, Type::Text);
@@ -327,27 +382,32 @@ pub fn standard(f: &mut Prelude) {
     f.intrinsic(Arc::new("load__source_imports".into()), LOAD__SOURCE_IMPORTS, Dfn {
         lts: vec![Lt::Default; 2],
         tys: vec![Type::Text, Type::array()],
-        ret: Type::result()
+        ret: Type::result(),
+        defaults: vec![]
     });
     f.intrinsic(Arc::new("call".into()), CALL, Dfn {
         lts: vec![Lt::Default; 3],
         tys: vec![Type::Any, Type::Text, Type::array()],
-        ret: Type::Void
+        ret: Type::Void,
+        defaults: vec![]
     });
     f.intrinsic(Arc::new("call_ret".into()), CALL_RET, Dfn {
         lts: vec![Lt::Default; 3],
         tys: vec![Type::Any, Type::Text, Type::array()],
-        ret: Type::Any
+        ret: Type::Any,
+        defaults: vec![]
     });
     f.intrinsic(Arc::new("functions".into()), FUNCTIONS, Dfn {
         lts: vec![],
         tys: vec![],
-        ret: Type::Any
+        ret: Type::Any,
+        defaults: vec![]
     });
     f.intrinsic(Arc::new("none".into()), NONE, Dfn {
         lts: vec![],
         tys: vec![],
-        ret: Type::option()
+        ret: Type::option(),
+        defaults: vec![]
     });
     sarg(f, "some", SOME, Type::Any, Type::option());
     sarg(f, "unwrap", UNWRAP, Type::Any, Type::Any);
@@ -361,58 +421,68 @@ pub fn standard(f: &mut Prelude) {
     f.intrinsic(Arc::new("s".into()), S, Dfn {
         lts: vec![Lt::Default; 2],
         tys: vec![Type::Vec4, Type::F64],
-        ret: Type::F64
+        ret: Type::F64,
+        defaults: vec![]
     });
     sarg(f, "dir__angle", DIR__ANGLE, Type::F64, Type::Vec4);
     f.intrinsic(Arc::new("load__meta_file".into()), LOAD__META_FILE, Dfn {
         lts: vec![Lt::Default; 2],
         tys: vec![Type::Text; 2],
-        ret: Type::Result(Box::new(Type::Array(Box::new(Type::array()))))
+        ret: Type::Result(Box::new(Type::Array(Box::new(Type::array())))),
+        defaults: vec![]
     });
     f.intrinsic(Arc::new("load__meta_url".into()), LOAD__META_URL, Dfn {
         lts: vec![Lt::Default; 2],
         tys: vec![Type::Text; 2],
-        ret: Type::Result(Box::new(Type::Array(Box::new(Type::array()))))
+        ret: Type::Result(Box::new(Type::Array(Box::new(Type::array())))),
+        defaults: vec![]
     });
     f.intrinsic(Arc::new("download__url_file".into()), DOWNLOAD__URL_FILE, Dfn {
         lts: vec![Lt::Default; 2],
         tys: vec![Type::Text; 2],
-        ret: Type::Result(Box::new(Type::Text))
+        ret: Type::Result(Box::new(Type::Text)),
+        defaults: vec![]
     });
     f.intrinsic(Arc::new("save__string_file".into()), SAVE__STRING_FILE, Dfn {
         lts: vec![Lt::Default; 2],
         tys: vec![Type::Text; 2],
-        ret: Type::Result(Box::new(Type::Text))
+        ret: Type::Result(Box::new(Type::Text)),
+        defaults: vec![]
     });
     sarg(f, "load_string__file", LOAD_STRING__FILE, Type::Text, Type::Result(Box::new(Type::Text)));
     sarg(f, "join__thread", JOIN__THREAD, Type::thread(), Type::Result(Box::new(Type::Any)));
     f.intrinsic(Arc::new("save__data_file".into()), SAVE__DATA_FILE, Dfn {
         lts: vec![Lt::Default; 2],
         tys: vec![Type::Any, Type::Text],
-        ret: Type::Result(Box::new(Type::Text))
+        ret: Type::Result(Box::new(Type::Text)),
+        defaults: vec![]
     });
     sarg(f, "json_from_meta_data", JSON_FROM_META_DATA, Type::Array(Box::new(Type::array())), Type::Text);
     f.intrinsic(Arc::new("has".into()), HAS, Dfn {
         lts: vec![Lt::Default; 2],
         tys: vec![Type::Object, Type::Text],
-        ret: Type::Bool
+        ret: Type::Bool,
+        defaults: vec![]
     });
     sarg(f, "chars", CHARS, Type::Text, Type::Array(Box::new(Type::Text)));
     f.intrinsic(Arc::new("now".into()), NOW, Dfn {
         lts: vec![],
         tys: vec![],
-        ret: Type::F64
+        ret: Type::F64,
+        defaults: vec![]
     });
     sarg(f, "is_nan", IS_NAN, Type::F64, Type::Bool);
     f.intrinsic(Arc::new("atan2".into()), ATAN2, Dfn {
         lts: vec![Lt::Default; 2],
         tys: vec![Type::F64; 2],
-        ret: Type::F64
+        ret: Type::F64,
+        defaults: vec![]
     });
     f.intrinsic(Arc::new("unwrap_or".into()), UNWRAP_OR, Dfn {
         lts: vec![Lt::Default; 2],
         tys: vec![Type::Any, Type::Any],
-        ret: Type::Any
+        ret: Type::Any,
+        defaults: vec![]
     });
     sarg(f, "tip", TIP, Type::Link, Type::Option(Box::new(Type::Any)));
     sarg(f, "neck", NECK, Type::Link, Type::Link);
@@ -423,27 +493,74 @@ pub fn standard(f: &mut Prelude) {
         ERRSTR__STRING_START_LEN_MSG, Dfn {
             lts: vec![Lt::Default; 4],
             tys: vec![Type::Text, Type::F64, Type::F64, Type::Text],
-            ret: Type::Text
+            ret: Type::Text,
+            defaults: vec![]
         });
     f.intrinsic(Arc::new("syntax__in_string".into()),
         SYNTAX__IN_STRING, Dfn {
             lts: vec![Lt::Default; 2],
             tys: vec![Type::Text; 2],
-            ret: Type::Result(Box::new(Type::Any))
+            ret: Type::Result(Box::new(Type::Any)),
+            defaults: vec![]
         });
     f.intrinsic(Arc::new("meta__syntax_in_string".into()),
         META__SYNTAX_IN_STRING, Dfn {
             lts: vec![Lt::Default; 3],
             tys: vec![Type::Any, Type::Text, Type::Text],
-            ret: Type::Result(Box::new(Type::Array(Box::new(Type::array()))))
+            ret: Type::Result(Box::new(Type::Array(Box::new(Type::array())))),
+            defaults: vec![]
         });
     f.intrinsic(Arc::new("module__in_string_imports".into()), MODULE__IN_STRING_IMPORTS, Dfn {
         lts: vec![Lt::Default; 3],
         tys: vec![Type::Text, Type::Text, Type::array()],
-        ret: Type::result()
+        ret: Type::result(),
+        defaults: vec![]
     });
     sarg(f, "load_string__url", LOAD_STRING__URL, Type::Text, Type::Result(Box::new(Type::Text)));
     sarg(f, "parse_number", PARSE_NUMBER, Type::Text, Type::Option(Box::new(Type::F64)));
+    sarg(f, "resume", RESUME, Type::coroutine(), Type::option());
+    sarg(f, "is_done", IS_DONE, Type::coroutine(), Type::Bool);
+    sarg(f, "i64", I64, Type::F64, Type::I64);
+    sarg(f, "f64", F64, Type::I64, Type::F64);
+    f.intrinsic(Arc::new("chan".into()), CHAN, Dfn {
+        lts: vec![],
+        tys: vec![],
+        ret: Type::channel(),
+        defaults: vec![]
+    });
+    f.intrinsic(Arc::new("send".into()), SEND, Dfn {
+        lts: vec![Lt::Default; 2],
+        tys: vec![Type::channel(), Type::Any],
+        ret: Type::Void,
+        defaults: vec![]
+    });
+    sarg(f, "recv", RECV, Type::channel(), Type::option());
+    sarg(f, "try_recv", TRY_RECV, Type::channel(), Type::option());
+    sarg(f, "close", CLOSE, Type::channel(), Type::Void);
+    sarg(f, "select", SELECT, Type::Array(Box::new(Type::channel())),
+         Type::Option(Box::new(Type::object())));
+    sarg(f, "shared", SHARED, Type::Any, Type::shared());
+    sarg(f, "read", READ, Type::shared(), Type::Any);
+    f.intrinsic(Arc::new("write".into()), WRITE, Dfn {
+        lts: vec![Lt::Default; 2],
+        tys: vec![Type::shared(), Type::Any],
+        ret: Type::Void,
+        defaults: vec![]
+    });
+    f.intrinsic(Arc::new("update".into()), UPDATE, Dfn {
+        lts: vec![Lt::Default; 2],
+        tys: vec![Type::shared(), Type::Closure(Box::new(Dfn {
+            lts: vec![Lt::Default],
+            tys: vec![Type::Any],
+            ret: Type::Any,
+            defaults: vec![]
+        }))],
+        ret: Type::Any,
+        defaults: vec![]
+    });
+    sarg(f, "wait_all", WAIT_ALL, Type::Array(Box::new(Type::thread())),
+         Type::Array(Box::new(Type::result())));
+    sarg(f, "cancel", CANCEL, Type::thread(), Type::Void);
 }
 
 pub fn call_standard(
@@ -462,10 +579,20 @@ pub fn call_standard(
                     rt.stack_trace()), rt))
         };
     }
+    let expect = try!(call_pushed(rt, index, call, module));
+    Ok((expect, Flow::Continue))
+}
+
+/// Calls an intrinsic after the arguments are pushed on the stack.
+pub fn call_pushed(
+    rt: &mut Runtime,
+    index: usize,
+    call: &ast::Call,
+    module: &Arc<Module>
+) -> Result<Option<Variable>, String> {
     let (ind, f) = TABLE[index];
     debug_assert!(ind == index);
-    let expect = try!((f)(rt, call, module));
-    Ok((expect, Flow::Continue))
+    (f)(rt, call, module)
 }
 
 fn x(
@@ -833,9 +960,11 @@ fn sleep(
     call: &ast::Call,
     module: &Arc<Module>,
 ) -> Result<Option<Variable>, String> {
+    use std::cmp::min;
     use std::thread::sleep;
-    use std::time::Duration;
+    use std::time::{Duration, Instant};
 
+    try!(rt.require_intrinsic(call, module));
     let v = rt.stack.pop().expect(TINVOTS);
     let v = match rt.resolve(&v) {
         &Variable::F64(b, _) => b,
@@ -844,7 +973,13 @@ fn sleep(
     };
     let secs = v as u64;
     let nanos = (v.fract() * 1.0e9) as u32;
-    sleep(Duration::new(secs, nanos));
+    let end = Instant::now() + Duration::new(secs, nanos);
+    try!(rt.wait(call.source_range, module, |slice| {
+        let now = Instant::now();
+        if now >= end { return Some(()); }
+        sleep(min(slice, end - now));
+        None
+    }));
     Ok(None)
 }
 
@@ -951,6 +1086,9 @@ fn push_ref(
     call: &ast::Call,
     module: &Arc<Module>,
 ) -> Result<Option<Variable>, String> {
+    use std::mem::size_of;
+
+    try!(rt.alloc(size_of::<Variable>(), call.source_range, module));
     let item = rt.stack.pop().expect(TINVOTS);
     let v = rt.stack.pop().expect(TINVOTS);
 
@@ -979,6 +1117,9 @@ fn push(
     call: &ast::Call,
     module: &Arc<Module>,
 ) -> Result<Option<Variable>, String> {
+    use std::mem::size_of;
+
+    try!(rt.alloc(size_of::<Variable>(), call.source_range, module));
     let item = rt.stack.pop().expect(TINVOTS);
     let item = rt.resolve(&item).deep_clone(&rt.stack);
     let v = rt.stack.pop().expect(TINVOTS);
@@ -1127,12 +1268,13 @@ fn swap(
 }
 
 fn read_line(
-    _rt: &mut Runtime,
-    _call: &ast::Call,
-    _module: &Arc<Module>,
+    rt: &mut Runtime,
+    call: &ast::Call,
+    module: &Arc<Module>,
 ) -> Result<Option<Variable>, String> {
     use std::io::{self, Write};
 
+    try!(rt.require_intrinsic(call, module));
     let mut input = String::new();
     io::stdout().flush().unwrap();
     let error = match io::stdin().read_line(&mut input) {
@@ -1156,6 +1298,7 @@ fn read_number(
 ) -> Result<Option<Variable>, String> {
     use std::io::{self, Write};
 
+    try!(rt.require_intrinsic(call, module));
     let err = rt.stack.pop().expect(TINVOTS);
     let err = match rt.resolve(&err) {
         &Variable::Text(ref t) => t.clone(),
@@ -1375,6 +1518,7 @@ fn _typeof(
     Ok(Some(match rt.resolve(&v) {
         &Variable::Text(_) => rt.text_type.clone(),
         &Variable::F64(_, _) => rt.f64_type.clone(),
+        &Variable::I64(_) => rt.i64_type.clone(),
         &Variable::Vec4(_) => rt.vec4_type.clone(),
         &Variable::Return => rt.return_type.clone(),
         &Variable::Bool(_, _) => rt.bool_type.clone(),
@@ -1383,10 +1527,17 @@ fn _typeof(
         &Variable::Link(_) => rt.link_type.clone(),
         &Variable::Ref(_) => rt.ref_type.clone(),
         &Variable::UnsafeRef(_) => rt.unsafe_ref_type.clone(),
-        &Variable::RustObject(_) => rt.rust_object_type.clone(),
+        &Variable::RustObject(ref obj) => match embed::rust_type_name(obj) {
+            Some(name) => Variable::Text(name),
+            None => rt.rust_object_type.clone(),
+        },
         &Variable::Option(_) => rt.option_type.clone(),
         &Variable::Result(_) => rt.result_type.clone(),
+        &Variable::Enum(ref enum_variant) => Variable::Text(enum_variant.name.clone()),
         &Variable::Thread(_) => rt.thread_type.clone(),
+        &Variable::Coroutine(_) => rt.coroutine_type.clone(),
+        &Variable::Channel(_) => rt.channel_type.clone(),
+        &Variable::Shared(_) => rt.shared_type.clone(),
         &Variable::Closure(_, _) => rt.closure_type.clone(),
     }))
 }
@@ -1418,10 +1569,13 @@ fn load(
 ) -> Result<Option<Variable>, String> {
     use load;
 
+    try!(rt.require_intrinsic(call, module));
     let v = rt.stack.pop().expect(TINVOTS);
     let v = match rt.resolve(&v) {
         &Variable::Text(ref text) => {
+            try!(rt.require_path(text, call.args[0].source_range(), module));
             let mut m = Module::new_intrinsics(module.intrinsics.clone());
+            m.capabilities = module.capabilities.clone();
             for f in &module.ext_prelude {
                 m.add(f.name.clone(), f.f, f.p.clone());
             }
@@ -1451,9 +1605,11 @@ fn load__source_imports(
 ) -> Result<Option<Variable>, String> {
     use load;
 
+    try!(rt.require_intrinsic(call, module));
     let modules = rt.stack.pop().expect(TINVOTS);
     let source = rt.stack.pop().expect(TINVOTS);
     let mut new_module = Module::new_intrinsics(module.intrinsics.clone());
+    new_module.capabilities = module.capabilities.clone();
     for f in &module.ext_prelude {
         new_module.add(f.name.clone(), f.f, f.p.clone());
     }
@@ -1494,6 +1650,7 @@ fn load__source_imports(
     }
     let v = match rt.resolve(&source) {
         &Variable::Text(ref text) => {
+            try!(rt.require_path(text, call.args[0].source_range(), module));
             if let Err(err) = load(text, &mut new_module) {
                 Variable::Result(Err(Box::new(Error {
                     message: Variable::Text(Arc::new(
@@ -1535,6 +1692,7 @@ fn module__in_string_imports(
                 &rt.expected(x, "str"), rt))
     };
     let mut new_module = Module::new_intrinsics(module.intrinsics.clone());
+    new_module.capabilities = module.capabilities.clone();
     for f in &module.ext_prelude {
         new_module.add(f.name.clone(), f.f, f.p.clone());
     }
@@ -1624,7 +1782,7 @@ fn _call(
             match f_index {
                 FnIndex::Loaded(f_index) => {
                     let f = &m.functions[f_index as usize];
-                    if f.args.len() != args.len() {
+                    if !f.accepts(args.len()) {
                         return Err(module.error(
                             call.args[2].source_range(),
                             &format!(
@@ -1637,7 +1795,8 @@ fn _call(
                         &format!("{}\n{}", err, rt.stack_trace()), rt)));
                 }
                 FnIndex::Intrinsic(_) | FnIndex::None |
-                FnIndex::ExternalVoid(_) | FnIndex::ExternalReturn(_) =>
+                FnIndex::ExternalVoid(_) | FnIndex::ExternalReturn(_) |
+                FnIndex::ExternalDefaults(_) =>
                     return Err(module.error(
                             call.args[1].source_range(),
                             &format!(
@@ -1656,7 +1815,11 @@ fn _call(
                 source_range: call.source_range,
             };
 
-            try!(rt.call(&call, &m));
+            // Restrict capabilities of called module to those of the caller.
+            rt.caller_capabilities.push(module.capabilities.clone());
+            let res = rt.call(&call, &m);
+            rt.caller_capabilities.pop();
+            try!(res);
         }
         None => return Err(module.error(call.args[0].source_range(),
                     &format!("{}\nExpected `Module`",
@@ -1701,7 +1864,7 @@ fn call_ret(
             match f_index {
                 FnIndex::Loaded(f_index) => {
                     let f = &m.functions[f_index as usize];
-                    if f.args.len() != args.len() {
+                    if !f.accepts(args.len()) {
                         return Err(module.error(
                             call.args[2].source_range(),
                             &format!(
@@ -1714,7 +1877,8 @@ fn call_ret(
                         &format!("{}\n{}", err, rt.stack_trace()), rt)));
                 }
                 FnIndex::Intrinsic(_) | FnIndex::None |
-                FnIndex::ExternalVoid(_) | FnIndex::ExternalReturn(_) =>
+                FnIndex::ExternalVoid(_) | FnIndex::ExternalReturn(_) |
+                FnIndex::ExternalDefaults(_) =>
                     return Err(module.error(
                         call.args[1].source_range(),
                         &format!(
@@ -1733,7 +1897,11 @@ fn call_ret(
                 source_range: call.source_range,
             };
 
-            try!(rt.call(&call, &m)).0
+            // Restrict capabilities of called module to those of the caller.
+            rt.caller_capabilities.push(module.capabilities.clone());
+            let res = rt.call(&call, &m);
+            rt.caller_capabilities.pop();
+            try!(res).0
         }
         None => return Err(module.error(call.args[0].source_range(),
             &format!("{}\nExpected `Module`", rt.stack_trace()), rt))
@@ -1998,6 +2166,8 @@ fn load__meta_file(
     call: &ast::Call,
     module: &Arc<Module>,
 ) -> Result<Option<Variable>, String> {
+    try!(rt.require_intrinsic(call, module));
+
     let file = rt.stack.pop().expect(TINVOTS);
     let meta = rt.stack.pop().expect(TINVOTS);
     let file = match rt.resolve(&file) {
@@ -2010,6 +2180,8 @@ fn load__meta_file(
         x => return Err(module.error(call.args[0].source_range(),
                         &rt.expected(x, "str"), rt))
     };
+    try!(rt.require_path(&meta, call.args[0].source_range(), module));
+    try!(rt.require_path(&file, call.args[1].source_range(), module));
     let res = meta::load_meta_file(&**meta, &**file);
     Ok(Some(Variable::Result(match res {
         Ok(res) => Ok(Box::new(Variable::Array(Arc::new(res)))),
@@ -2025,6 +2197,8 @@ fn load__meta_url(
     call: &ast::Call,
     module: &Arc<Module>,
 ) -> Result<Option<Variable>, String> {
+    try!(rt.require_intrinsic(call, module));
+
     let url = rt.stack.pop().expect(TINVOTS);
     let meta = rt.stack.pop().expect(TINVOTS);
     let url = match rt.resolve(&url) {
@@ -2037,6 +2211,7 @@ fn load__meta_url(
         x => return Err(module.error(call.args[0].source_range(),
                         &rt.expected(x, "str"), rt))
     };
+    try!(rt.require_path(&meta, call.args[0].source_range(), module));
     let res = meta::load_meta_url(&**meta, &**url);
     Ok(Some(Variable::Result(match res {
         Ok(res) => Ok(Box::new(Variable::Array(Arc::new(res)))),
@@ -2122,6 +2297,8 @@ fn download__url_file(
     call: &ast::Call,
     module: &Arc<Module>,
 ) -> Result<Option<Variable>, String> {
+    try!(rt.require_intrinsic(call, module));
+
     let file = rt.stack.pop().expect(TINVOTS);
     let url = rt.stack.pop().expect(TINVOTS);
     let file = match rt.resolve(&file) {
@@ -2134,6 +2311,7 @@ fn download__url_file(
         x => return Err(module.error(call.args[0].source_range(),
                         &rt.expected(x, "str"), rt))
     };
+    try!(rt.require_path(&file, call.args[1].source_range(), module));
 
     let res = meta::download_url_to_file(&**url, &**file);
     Ok(Some(Variable::Result(match res {
@@ -2154,6 +2332,7 @@ fn save__string_file(
     use std::io::Write;
     use std::error::Error as StdError;
 
+    try!(rt.require_intrinsic(call, module));
     let file = rt.stack.pop().expect(TINVOTS);
     let text = rt.stack.pop().expect(TINVOTS);
     let file = match rt.resolve(&file) {
@@ -2166,6 +2345,7 @@ fn save__string_file(
         x => return Err(module.error(call.args[0].source_range(),
                         &rt.expected(x, "str"), rt))
     };
+    try!(rt.require_path(&file, call.args[1].source_range(), module));
 
     Ok(Some(Variable::Result(match File::create(&**file) {
         Ok(mut f) => {
@@ -2193,12 +2373,14 @@ fn load_string__file(
     use std::io::Read;
     use std::error::Error as StdError;
 
+    try!(rt.require_intrinsic(call, module));
     let file = rt.stack.pop().expect(TINVOTS);
     let file = match rt.resolve(&file) {
         &Variable::Text(ref file) => file.clone(),
         x => return Err(module.error(call.args[0].source_range(),
                         &rt.expected(x, "str"), rt))
     };
+    try!(rt.require_path(&file, call.args[0].source_range(), module));
 
     Ok(Some(Variable::Result(match File::open(&**file) {
         Ok(mut f) => {
@@ -2227,6 +2409,8 @@ fn load_string__url(
     call: &ast::Call,
     module: &Arc<Module>,
 ) -> Result<Option<Variable>, String> {
+    try!(rt.require_intrinsic(call, module));
+
     let url = rt.stack.pop().expect(TINVOTS);
     let url = match rt.resolve(&url) {
         &Variable::Text(ref url) => url.clone(),
@@ -2249,40 +2433,305 @@ fn load_string__url(
 
 fn join__thread(
     rt: &mut Runtime,
-    _call: &ast::Call,
-    _module: &Arc<Module>,
+    call: &ast::Call,
+    module: &Arc<Module>,
 ) -> Result<Option<Variable>, String> {
     use Thread;
 
     let thread = rt.stack.pop().expect(TINVOTS);
-    let handle_res = Thread::invalidate_handle(rt, thread);
-    let v = Variable::Result({
-        match handle_res {
-            Ok(handle) => {
-                match handle.join() {
-                    Ok(res) => match res {
-                        Ok(res) => Ok(Box::new(res)),
-                        Err(err) => Err(Box::new(Error {
-                            message: Variable::Text(Arc::new(err)),
-                            trace: vec![]
-                        }))
-                    },
-                    Err(_err) => Err(Box::new(Error {
-                        message: Variable::Text(Arc::new(
-                            "Thread did not exit successfully".into())),
-                        trace: vec![]
-                    }))
+    let res = match Thread::invalidate_handle(rt, thread) {
+        Ok(task) => try!(rt.join_task(&task, call.source_range, module)),
+        Err(err) => Err(err)
+    };
+    Ok(Some(thread_result(res)))
+}
+
+fn thread_result(res: Result<Variable, String>) -> Variable {
+    Variable::Result(match res {
+        Ok(res) => Ok(Box::new(res)),
+        Err(err) => Err(Box::new(Error {
+            message: Variable::Text(Arc::new(err)),
+            trace: vec![]
+        }))
+    })
+}
+
+fn wait_all(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    module: &Arc<Module>,
+) -> Result<Option<Variable>, String> {
+    let threads = rt.stack.pop().expect(TINVOTS);
+    let mut arr = match rt.resolve(&threads) {
+        &Variable::Array(ref arr) => arr.clone(),
+        x => return Err(module.error(call.args[0].source_range(),
+                &rt.expected(x, "array"), rt))
+    };
+    // Take the tasks out of the threads, such that joining them later fails.
+    let mut tasks = Vec::with_capacity(arr.len());
+    for v in Arc::make_mut(&mut arr).iter_mut() {
+        match *v {
+            Variable::Thread(ref mut th) => tasks.push(th.task.take()),
+            ref x => return Err(module.error(call.args[0].source_range(),
+                    &rt.expected(x, "thread"), rt))
+        }
+    }
+    if let Variable::Ref(ind) = threads {
+        rt.stack[ind] = Variable::Array(arr);
+    }
+    let mut res = Vec::with_capacity(tasks.len());
+    for task in tasks {
+        res.push(thread_result(match task {
+            None => Err("The Thread has already been invalidated".into()),
+            Some(task) => try!(rt.join_task(&task, call.source_range, module))
+        }));
+    }
+    Ok(Some(Variable::Array(Arc::new(res))))
+}
+
+fn cancel(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    module: &Arc<Module>,
+) -> Result<Option<Variable>, String> {
+    let thread = rt.stack.pop().expect(TINVOTS);
+    match rt.resolve(&thread) {
+        &Variable::Thread(ref th) => {
+            if let Some(ref task) = th.task { task.cancel(); }
+        }
+        x => return Err(module.error(call.args[0].source_range(),
+                &rt.expected(x, "thread"), rt))
+    }
+    Ok(None)
+}
+
+fn resume(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    module: &Arc<Module>,
+) -> Result<Option<Variable>, String> {
+    let co = rt.stack.pop().expect(TINVOTS);
+    let co = match rt.resolve(&co) {
+        &Variable::Coroutine(ref co) => co.clone(),
+        x => return Err(module.error(call.args[0].source_range(),
+                &rt.expected(x, "coroutine"), rt))
+    };
+    match co.resume() {
+        Ok(v) => Ok(Some(Variable::Option(v.map(Box::new)))),
+        Err(err) => Err(module.error(call.source_range,
+                &format!("{}\n{}", rt.stack_trace(), err), rt))
+    }
+}
+
+fn is_done(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    module: &Arc<Module>,
+) -> Result<Option<Variable>, String> {
+    let co = rt.stack.pop().expect(TINVOTS);
+    Ok(Some(match rt.resolve(&co) {
+        &Variable::Coroutine(ref co) => Variable::bool(co.is_done()),
+        x => return Err(module.error(call.args[0].source_range(),
+                &rt.expected(x, "coroutine"), rt))
+    }))
+}
+
+fn chan(
+    _rt: &mut Runtime,
+    _call: &ast::Call,
+    _module: &Arc<Module>,
+) -> Result<Option<Variable>, String> {
+    use channel::Channel;
+
+    Ok(Some(Variable::Channel(Channel::new())))
+}
+
+fn send(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    module: &Arc<Module>,
+) -> Result<Option<Variable>, String> {
+    let v = rt.stack.pop().expect(TINVOTS);
+    // Values are deep cloned to not share references between threads.
+    let v = v.deep_clone(&rt.stack);
+    let ch = rt.stack.pop().expect(TINVOTS);
+    let ch = match rt.resolve(&ch) {
+        &Variable::Channel(ref ch) => ch.clone(),
+        x => return Err(module.error(call.args[0].source_range(),
+                &rt.expected(x, "channel"), rt))
+    };
+    match ch.send(v) {
+        Ok(()) => Ok(None),
+        Err(err) => Err(module.error(call.source_range,
+                &format!("{}\n{}", rt.stack_trace(), err), rt))
+    }
+}
+
+fn recv(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    module: &Arc<Module>,
+) -> Result<Option<Variable>, String> {
+    let ch = rt.stack.pop().expect(TINVOTS);
+    let ch = match rt.resolve(&ch) {
+        &Variable::Channel(ref ch) => ch.clone(),
+        x => return Err(module.error(call.args[0].source_range(),
+                &rt.expected(x, "channel"), rt))
+    };
+    let res = try!(rt.wait(call.source_range, module, |slice| ch.recv_timeout(slice)));
+    Ok(Some(Variable::Option(res.map(Box::new))))
+}
+
+fn try_recv(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    module: &Arc<Module>,
+) -> Result<Option<Variable>, String> {
+    let ch = rt.stack.pop().expect(TINVOTS);
+    Ok(Some(match rt.resolve(&ch) {
+        &Variable::Channel(ref ch) => Variable::Option(ch.try_recv().map(Box::new)),
+        x => return Err(module.error(call.args[0].source_range(),
+                &rt.expected(x, "channel"), rt))
+    }))
+}
+
+fn close(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    module: &Arc<Module>,
+) -> Result<Option<Variable>, String> {
+    let ch = rt.stack.pop().expect(TINVOTS);
+    match rt.resolve(&ch) {
+        &Variable::Channel(ref ch) => ch.close(),
+        x => return Err(module.error(call.args[0].source_range(),
+                &rt.expected(x, "channel"), rt))
+    }
+    Ok(None)
+}
+
+fn select(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    module: &Arc<Module>,
+) -> Result<Option<Variable>, String> {
+    use std::collections::HashMap;
+    use channel::Channel;
+
+    let chans = rt.stack.pop().expect(TINVOTS);
+    let chans: Vec<Channel> = match rt.resolve(&chans) {
+        &Variable::Array(ref arr) => {
+            let mut chans = Vec::with_capacity(arr.len());
+            for v in arr.iter() {
+                match rt.resolve(v) {
+                    &Variable::Channel(ref ch) => chans.push(ch.clone()),
+                    x => return Err(module.error(call.args[0].source_range(),
+                            &rt.expected(x, "channel"), rt))
                 }
             }
-            Err(err) => {
-                Err(Box::new(Error {
-                    message: Variable::Text(Arc::new(err)),
-                    trace: vec![]
-                }))
-            }
+            chans
+        }
+        x => return Err(module.error(call.args[0].source_range(),
+                &rt.expected(x, "array"), rt))
+    };
+    let res = try!(rt.wait(call.source_range, module,
+                           |slice| Channel::select_timeout(&chans, slice)));
+    Ok(Some(Variable::Option(res.map(|(i, v)| {
+        let mut obj = HashMap::new();
+        obj.insert(Arc::new("index".into()), Variable::f64(i as f64));
+        obj.insert(Arc::new("value".into()), v);
+        Box::new(Variable::Object(Arc::new(obj)))
+    }))))
+}
+
+fn shared(
+    rt: &mut Runtime,
+    _call: &ast::Call,
+    _module: &Arc<Module>,
+) -> Result<Option<Variable>, String> {
+    use shared::Shared;
+
+    let v = rt.stack.pop().expect(TINVOTS);
+    // Values are deep cloned to not share references between threads.
+    let v = v.deep_clone(&rt.stack);
+    Ok(Some(Variable::Shared(Shared::new(v))))
+}
+
+// Gets a shared value that is not being updated by the runtime.
+fn shared_arg(
+    rt: &Runtime,
+    v: &Variable,
+    call: &ast::Call,
+    module: &Arc<Module>,
+) -> Result<::shared::Shared, String> {
+    let sh = match rt.resolve(v) {
+        &Variable::Shared(ref sh) => sh.clone(),
+        x => return Err(module.error(call.args[0].source_range(),
+                &rt.expected(x, "shared"), rt))
+    };
+    if rt.updating.iter().any(|u| u.ptr_eq(&sh)) {
+        return Err(module.error(call.source_range,
+            &format!("{}\nCan not use shared value while updating it", rt.stack_trace()), rt));
+    }
+    Ok(sh)
+}
+
+fn read(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    module: &Arc<Module>,
+) -> Result<Option<Variable>, String> {
+    let sh = rt.stack.pop().expect(TINVOTS);
+    let sh = try!(shared_arg(rt, &sh, call, module));
+    match sh.read() {
+        Ok(v) => Ok(Some(v)),
+        Err(err) => Err(module.error(call.source_range,
+                &format!("{}\n{}", rt.stack_trace(), err), rt))
+    }
+}
+
+fn write(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    module: &Arc<Module>,
+) -> Result<Option<Variable>, String> {
+    let v = rt.stack.pop().expect(TINVOTS);
+    let v = v.deep_clone(&rt.stack);
+    let sh = rt.stack.pop().expect(TINVOTS);
+    let sh = try!(shared_arg(rt, &sh, call, module));
+    match sh.write(v) {
+        Ok(()) => Ok(None),
+        Err(err) => Err(module.error(call.source_range,
+                &format!("{}\n{}", rt.stack_trace(), err), rt))
+    }
+}
+
+fn update(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    module: &Arc<Module>,
+) -> Result<Option<Variable>, String> {
+    let f = rt.stack.pop().expect(TINVOTS);
+    let sh = rt.stack.pop().expect(TINVOTS);
+    let sh = try!(shared_arg(rt, &sh, call, module));
+    // Errors from the closure are already formatted.
+    let mut called = false;
+    rt.updating.push(sh.clone());
+    let res = sh.update(|v| {
+        called = true;
+        match try!(rt.call_closure_args(&f, vec![v], call.source_range, module)) {
+            Some(v) => Ok(v.deep_clone(&rt.stack)),
+            None => Err(module.error(call.args[1].source_range(),
+                &format!("{}\nExpected closure to return a value", rt.stack_trace()), rt))
         }
     });
-    Ok(Some(v))
+    rt.updating.pop();
+    match res {
+        Ok(v) => Ok(Some(v)),
+        Err(err) => if called { Err(err) } else {
+            Err(module.error(call.source_range,
+                &format!("{}\n{}", rt.stack_trace(), err), rt))
+        }
+    }
 }
 
 fn load_data__file(
@@ -2290,12 +2739,15 @@ fn load_data__file(
     call: &ast::Call,
     module: &Arc<Module>,
 ) -> Result<Option<Variable>, String> {
+    try!(rt.require_intrinsic(call, module));
+
     let file = rt.stack.pop().expect(TINVOTS);
     let file = match rt.resolve(&file) {
         &Variable::Text(ref t) => t.clone(),
         x => return Err(module.error(call.args[0].source_range(),
                         &rt.expected(x, "string"), rt))
     };
+    try!(rt.require_path(&file, call.args[0].source_range(), module));
     let res = match data::load_file(&file) {
         Ok(data) => Ok(Box::new(data)),
         Err(err) => Err(Box::new(super::Error {
@@ -2318,12 +2770,14 @@ fn save__data_file(
     use std::io::BufWriter;
     use write::{write_variable, EscapeString};
 
+    try!(rt.require_intrinsic(call, module));
     let file = rt.stack.pop().expect(TINVOTS);
     let file = match rt.resolve(&file) {
         &Variable::Text(ref t) => t.clone(),
         x => return Err(module.error(call.args[1].source_range(),
                         &rt.expected(x, "string"), rt))
     };
+    try!(rt.require_path(&file, call.args[1].source_range(), module));
     let data = rt.stack.pop().expect(TINVOTS);
 
     let mut f = match File::create(&**file) {
@@ -2499,3 +2953,38 @@ fn is_nan(
     };
     Ok(Some(Variable::bool(v.is_nan())))
 }
+
+fn i64(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    module: &Arc<Module>,
+) -> Result<Option<Variable>, String> {
+    let v = rt.stack.pop().expect(TINVOTS);
+    let v = match rt.resolve(&v) {
+        &Variable::F64(v, _) => v,
+        &Variable::I64(v) => return Ok(Some(Variable::I64(v))),
+        x => return Err(module.error(call.args[0].source_range(),
+                        &rt.expected(x, "number"), rt))
+    };
+    if v.is_nan() {
+        return Err(module.error(call.args[0].source_range(),
+            &format!("{}\nCan not convert NaN to `i64`", rt.stack_trace()), rt));
+    }
+    // Rounds toward zero and saturates at the minimum and maximum `i64`.
+    Ok(Some(Variable::I64(v as i64)))
+}
+
+fn f64(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    module: &Arc<Module>,
+) -> Result<Option<Variable>, String> {
+    let v = rt.stack.pop().expect(TINVOTS);
+    let v = match rt.resolve(&v) {
+        &Variable::I64(v) => v as f64,
+        &Variable::F64(v, _) => v,
+        x => return Err(module.error(call.args[0].source_range(),
+                        &rt.expected(x, "i64"), rt))
+    };
+    Ok(Some(Variable::f64(v)))
+}
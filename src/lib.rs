@@ -8,16 +8,19 @@ extern crate read_token;
 extern crate reqwest;
 #[macro_use]
 extern crate lazy_static;
+#[cfg(target_os = "linux")]
+extern crate libc;
 
 use std::any::Any;
 use std::fmt;
 use std::thread::JoinHandle;
 use std::sync::{Arc, Mutex};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use range::Range;
 use piston_meta::MetaData;
 
 pub mod ast;
+pub mod bytecode;
 pub mod runtime;
 pub mod lifetime;
 pub mod intrinsics;
@@ -28,14 +31,24 @@ pub mod link;
 pub mod macros;
 pub mod vec4;
 pub mod write;
+pub mod capabilities;
+pub mod debugger;
+pub mod profiler;
+pub mod coverage;
+pub mod coroutine;
+pub mod channel;
+pub mod shared;
+pub mod pool;
 
 mod grab;
+mod stack_guard;
 
-pub use runtime::Runtime;
-pub use prelude::{Lt, Prelude, Dfn};
+pub use runtime::{InterruptHandle, Limits, Runtime, Status};
+pub use prelude::{DefaultValue, Lt, Prelude, Dfn};
 pub use ty::Type;
 pub use link::Link;
 pub use vec4::Vec4;
+pub use capabilities::{Capabilities, Capability};
 
 /// A common error message when there is no value on the stack.
 pub const TINVOTS: &'static str = "There is no value on the stack";
@@ -54,13 +67,18 @@ pub struct Error {
 
 #[derive(Clone)]
 pub struct Thread {
-    pub handle: Option<Arc<Mutex<JoinHandle<Result<Variable, String>>>>>,
+    pub task: Option<Arc<pool::Task>>,
 }
 
 impl Thread {
     pub fn new(handle: JoinHandle<Result<Variable, String>>) -> Thread {
+        Thread::from_task(Arc::new(pool::Task::from_handle(handle)))
+    }
+
+    /// Creates a thread from a task running on a thread pool.
+    pub fn from_task(task: Arc<pool::Task>) -> Thread {
         Thread {
-            handle: Some(Arc::new(Mutex::new(handle)))
+            task: Some(task)
         }
     }
 
@@ -69,14 +87,12 @@ impl Thread {
     pub fn invalidate_handle(
         rt: &mut Runtime,
         var: Variable
-    ) -> Result<JoinHandle<Result<Variable, String>>, String> {
-        use std::error::Error;
-
+    ) -> Result<Arc<pool::Task>, String> {
         let thread = match var {
             Variable::Ref(ind) => {
                 use std::mem::replace;
 
-                match replace(&mut rt.stack[ind], Variable::Thread(Thread { handle: None })) {
+                match replace(&mut rt.stack[ind], Variable::Thread(Thread { task: None })) {
                     Variable::Thread(th) => th,
                     x => return Err(rt.expected(&x, "Thread"))
                 }
@@ -84,15 +100,10 @@ impl Thread {
             Variable::Thread(thread) => thread,
             x => return Err(rt.expected(&x, "Thread"))
         };
-        let handle = match thread.handle {
-            None => return Err("The Thread has already been invalidated".into()),
-            Some(thread) => thread
-        };
-        let mutex = try!(Arc::try_unwrap(handle).map_err(|_|
-            format!("{}\nCan not access Thread because there is \
-            more than one reference to it", rt.stack_trace())));
-        mutex.into_inner().map_err(|err|
-            format!("{}\nCan not lock Thread mutex:\n{}", rt.stack_trace(), err.description()))
+        match thread.task {
+            None => Err("The Thread has already been invalidated".into()),
+            Some(task) => Ok(task)
+        }
     }
 }
 
@@ -112,6 +123,15 @@ pub struct ClosureEnvironment {
     pub relative: usize,
 }
 
+/// Stores a variant of an enum declared in Dyon, with payload if any.
+#[derive(Debug, Clone)]
+pub struct EnumVariant {
+    /// The name of the enum.
+    pub name: Arc<String>,
+    pub variant: Arc<String>,
+    pub payload: Option<Variable>,
+}
+
 impl fmt::Debug for ClosureEnvironment {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "ClosureEnvironment")
@@ -124,6 +144,7 @@ pub enum Variable {
     Return,
     Bool(bool, Option<Box<Vec<Variable>>>),
     F64(f64, Option<Box<Vec<Variable>>>),
+    I64(i64),
     Vec4([f32; 4]),
     Text(Arc<String>),
     Array(Array),
@@ -133,7 +154,13 @@ pub enum Variable {
     RustObject(RustObject),
     Option(Option<Box<Variable>>),
     Result(Result<Box<Variable>, Box<Error>>),
+    Enum(Box<EnumVariant>),
     Thread(Thread),
+    Coroutine(coroutine::Coroutine),
+    /// A channel for sending values between threads.
+    Channel(channel::Channel),
+    /// A lock protected value shared between threads.
+    Shared(shared::Shared),
     // Stores closure AST, relative function index.
     Closure(Arc<ast::Closure>, Box<ClosureEnvironment>),
 }
@@ -157,6 +184,7 @@ impl Variable {
 
         match *self {
             F64(_, _) => self.clone(),
+            I64(_) => self.clone(),
             Vec4(_) => self.clone(),
             Return => self.clone(),
             Bool(_, _) => self.clone(),
@@ -188,7 +216,13 @@ impl Variable {
             Result(Ok(ref ok)) => Result(Ok(ok.clone())),
             // `err(x)` always uses deep clone, so it does not contain references.
             Result(Err(ref err)) => Result(Err(err.clone())),
+            // Enum payloads always use deep clone, so they do not contain references.
+            Enum(_) => self.clone(),
             Thread(_) => self.clone(),
+            Coroutine(_) => self.clone(),
+            // Channels are shared between threads.
+            Channel(_) => self.clone(),
+            Shared(_) => self.clone(),
             Closure(_, _) => self.clone(),
         }
     }
@@ -200,9 +234,12 @@ impl PartialEq for Variable {
             (&Variable::Return, _) => false,
             (&Variable::Bool(a, _), &Variable::Bool(b, _)) => a == b,
             (&Variable::F64(a, _), &Variable::F64(b, _)) => a == b,
+            (&Variable::I64(a), &Variable::I64(b)) => a == b,
             (&Variable::Text(ref a), &Variable::Text(ref b)) => a == b,
             (&Variable::Object(ref a), &Variable::Object(ref b)) => a == b,
             (&Variable::Array(ref a), &Variable::Array(ref b)) => a == b,
+            (&Variable::Enum(ref a), &Variable::Enum(ref b)) =>
+                a.name == b.name && a.variant == b.variant && a.payload == b.payload,
             (&Variable::Ref(_), _) => false,
             (&Variable::UnsafeRef(_), _) => false,
             (&Variable::RustObject(_), _) => false,
@@ -219,6 +256,9 @@ pub enum FnIndex {
     Loaded(isize),
     ExternalVoid(FnExternalRef),
     ExternalReturn(FnExternalRef),
+    /// External function called without some of the last arguments,
+    /// by index in the extended prelude.
+    ExternalDefaults(usize),
 }
 
 /// Used to store direct reference to external function.
@@ -258,25 +298,73 @@ pub struct Module {
     pub functions: Vec<ast::Function>,
     pub ext_prelude: Vec<FnExternal>,
     pub intrinsics: Arc<HashMap<Arc<String>, usize>>,
+    /// Capabilities allowed for intrinsics.
+    pub capabilities: Arc<Capabilities>,
+    /// Warnings from type checking of loaded sources.
+    pub warnings: Vec<String>,
+    /// Enums declared in loaded sources.
+    pub enums: Vec<ast::Enum>,
+    /// Constants and globals declared in loaded sources.
+    pub globals: Vec<ast::Global>,
+    /// Identifies the module and its clones,
+    /// used by runtimes to keep the values of globals.
+    id: usize,
 }
 
 impl Module {
     pub fn new() -> Module {
-        Module::new_intrinsics(Arc::new(Prelude::new_intrinsics().functions))
+        lazy_static! {
+            static ref INTRINSICS: Arc<HashMap<Arc<String>, usize>> =
+                Arc::new(Prelude::new_intrinsics().functions);
+        }
+
+        Module::new_intrinsics(INTRINSICS.clone())
     }
 
     pub fn new_intrinsics(intrinsics: Arc<HashMap<Arc<String>, usize>>) -> Module {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        lazy_static! {
+            static ref NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+        }
+
         Module {
             functions: vec![],
             ext_prelude: vec![],
             intrinsics: intrinsics,
+            capabilities: Arc::new(Capabilities::all()),
+            warnings: vec![],
+            enums: vec![],
+            globals: vec![],
+            id: NEXT_ID.fetch_add(1, Ordering::SeqCst),
         }
     }
 
+    /// Sets capabilities allowed for intrinsics.
+    ///
+    /// Should be set before loading source,
+    /// such that type checking fails for functions that are not allowed.
+    pub fn set_capabilities(&mut self, capabilities: Capabilities) {
+        self.capabilities = Arc::new(capabilities);
+    }
+
     pub fn register(&mut self, function: ast::Function) {
         self.functions.push(function);
     }
 
+    pub fn register_enum(&mut self, enum_decl: ast::Enum) {
+        self.enums.push(enum_decl);
+    }
+
+    pub fn register_global(&mut self, global: ast::Global) {
+        self.globals.push(global);
+    }
+
+    /// Finds constant or global by name, the last declared first.
+    pub fn find_global(&self, name: &Arc<String>) -> Option<usize> {
+        self.globals.iter().rposition(|g| &g.name == name)
+    }
+
     /// Find function relative another function index.
     pub fn find_function(&self, name: &Arc<String>, relative: usize) -> FnIndex {
         for (i, f) in self.functions.iter().enumerate().rev() {
@@ -299,8 +387,56 @@ impl Module {
         }
     }
 
+    /// Find function relative another function index,
+    /// falling back to functions that can be called by omitting arguments with default values.
+    pub fn find_function_defaults(&self, name: &Arc<String>, n: usize, relative: usize) -> FnIndex {
+        use prelude::omits_args;
+
+        match self.find_function(name, relative) {
+            FnIndex::None => {}
+            FnIndex::ExternalVoid(_) | FnIndex::ExternalReturn(_) => {}
+            x => return x
+        }
+        for (i, f) in self.functions.iter().enumerate().rev() {
+            if omits_args(name, &f.name, f.args.len(), n) && f.accepts(n) {
+                return FnIndex::Loaded(i as isize - relative as isize);
+            }
+        }
+        match self.find_external(name, n) {
+            None => FnIndex::None,
+            Some(i) => {
+                let f = &self.ext_prelude[i];
+                if n < f.p.tys.len() {
+                    FnIndex::ExternalDefaults(i)
+                } else if f.p.returns() {
+                    FnIndex::ExternalReturn(FnExternalRef(f.f))
+                } else {
+                    FnIndex::ExternalVoid(FnExternalRef(f.f))
+                }
+            }
+        }
+    }
+
+    /// Finds external function by name, called with `n` arguments.
+    ///
+    /// Returns the index in `ext_prelude`,
+    /// falling back to functions that can be called by omitting arguments with default values.
+    pub fn find_external(&self, name: &Arc<String>, n: usize) -> Option<usize> {
+        use prelude::omits_args;
+
+        self.ext_prelude.iter().rposition(|f| &f.name == name).or_else(|| {
+            self.ext_prelude.iter().enumerate().rev()
+                .find(|&(_, f)| omits_args(name, &f.name, f.p.tys.len(), n) && f.p.accepts(n))
+                .map(|(i, _)| i)
+        })
+    }
+
     pub fn error(&self, range: Range, msg: &str, rt: &Runtime) -> String {
-        self.error_fnindex(range, msg, rt.call_stack.last().unwrap().index)
+        match rt.call_stack.last() {
+            Some(call) => self.error_fnindex(range, msg, call.index),
+            // Called from Rust, e.g. an intrinsic called by `Runtime::call_str_ret`.
+            None => msg.trim_start().into()
+        }
     }
 
     pub fn error_fnindex(&self, range: Range, msg: &str, fnindex: usize) -> String {
@@ -331,12 +467,42 @@ impl Module {
             p: prelude_function,
         });
     }
+
+    /// Adds a new extended prelude function,
+    /// with default values of the last arguments.
+    ///
+    /// Calls can omit trailing arguments with default values,
+    /// e.g. `play(name: "bell")` calls `play__name_volume`.
+    /// Only numbers, bools, strings and vec4 are allowed as default values.
+    pub fn add_with_defaults(
+        &mut self,
+        name: Arc<String>,
+        f: fn(&mut Runtime) -> Result<(), String>,
+        mut prelude_function: Dfn,
+        defaults: Vec<Variable>
+    ) -> Result<(), String> {
+        if defaults.len() > prelude_function.tys.len() {
+            return Err(format!("Expected at most {} default values for `{}`",
+                               prelude_function.tys.len(), name));
+        }
+        prelude_function.defaults = vec![];
+        for v in &defaults {
+            match DefaultValue::from_variable(v) {
+                Some(val) => prelude_function.defaults.push(val),
+                None => return Err(format!("Expected number, bool, string or vec4 \
+                                            as default value for `{}`", name))
+            }
+        }
+        self.add(name, f, prelude_function);
+        Ok(())
+    }
 }
 
 /// Runs a program using a source file.
 pub fn run(source: &str) -> Result<(), String> {
-    let mut module = Module::new_intrinsics(Arc::new(Prelude::new_intrinsics().functions));
+    let mut module = Module::new();
     try!(load(source, &mut module));
+    warnings(&module);
     let mut runtime = runtime::Runtime::new();
     try!(runtime.run(&Arc::new(module)));
     Ok(())
@@ -344,8 +510,9 @@ pub fn run(source: &str) -> Result<(), String> {
 
 /// Runs a program from a string.
 pub fn run_str(source: &str, d: Arc<String>) -> Result<(), String> {
-    let mut module = Module::new_intrinsics(Arc::new(Prelude::new_intrinsics().functions));
+    let mut module = Module::new();
     try!(load_str(source, d, &mut module));
+    warnings(&module);
     let mut runtime = runtime::Runtime::new();
     try!(runtime.run(&Arc::new(module)));
     Ok(())
@@ -370,22 +537,15 @@ pub fn load(source: &str, module: &mut Module) -> Result<(), String> {
 /// - module - The module to load the source
 pub fn load_str(source: &str, d: Arc<String>, module: &mut Module) -> Result<(), String> {
     use std::thread;
-    use piston_meta::{parse_errstr, syntax_errstr, Syntax};
+    use piston_meta::parse_errstr;
 
-    lazy_static! {
-        static ref SYNTAX_RULES: Result<Syntax, String> = {
-            let syntax = include_str!("../assets/syntax.txt");
-            syntax_errstr(syntax)
-        };
-    }
-
-    let syntax_rules = try!(SYNTAX_RULES.as_ref()
-        .map_err(|err| err.clone()));
+    let syntax_rules = try!(syntax_rules());
 
     let mut data = vec![];
     try!(parse_errstr(syntax_rules, &d, &mut data).map_err(
         |err| format!("In `{}:`\n{}", source, err)
     ));
+    ast::enum_calls(&mut data, module);
 
     let check_data = data.clone();
     let prelude = Arc::new(Prelude::from_module(module));
@@ -393,21 +553,24 @@ pub fn load_str(source: &str, d: Arc<String>, module: &mut Module) -> Result<(),
     // Do lifetime checking in parallel directly on meta data.
     let handle = thread::spawn(move || {
         let check_data = check_data;
-        lifetime::check(&check_data, &prelude)
+        let mut warnings = vec![];
+        lifetime::check(&check_data, &prelude, &mut warnings)
+            .map(|checked| (checked, warnings))
     });
 
     // Convert to AST.
     let mut ignored = vec![];
+    let loaded = (module.functions.len(), module.globals.len());
+    let enums = module.enums.len();
     let conv_res = ast::convert(Arc::new(source.into()), d.clone(), &data, &mut ignored, module);
 
     // Check that lifetime checking succeeded.
     match handle.join().unwrap() {
-        Ok(refined_rets) => {
-            for (name, ty) in &refined_rets {
-                if let FnIndex::Loaded(f_index) = module.find_function(name, 0) {
-                    let f = &mut module.functions[f_index as usize];
-                    f.ret = ty.clone();
-                }
+        Ok(((refined_rets, global_types, i64_loops), warnings)) => {
+            refine_types(module, loaded, &refined_rets, &global_types, &i64_loops);
+            ast::compile(module);
+            for warning in &warnings {
+                module.warnings.push(format_warning(source, &d, warning));
             }
         }
         Err(err_msg) => {
@@ -425,7 +588,143 @@ pub fn load_str(source: &str, d: Arc<String>, module: &mut Module) -> Result<(),
         }
     }
 
-    check_ignored_meta_data(&conv_res, source, &d, &data, &ignored)
+    try!(check_ignored_meta_data(&conv_res, source, &d, &data, &ignored));
+    eval_consts(module, loaded, enums)
+}
+
+/// Puts refined return types of functions and types of constants and globals in the AST.
+///
+/// Marks `sum` and `prod` loops adding up `i64` values in the functions and globals
+/// starting at `loaded`, which are the ones in the checked source.
+fn refine_types(
+    module: &mut Module,
+    loaded: (usize, usize),
+    refined_rets: &HashMap<Arc<String>, Type>,
+    global_types: &HashMap<Arc<String>, Type>,
+    i64_loops: &HashSet<usize>
+) {
+    use ast::Expression as E;
+
+    let mut mark = |expr: &ast::Expression| match *expr {
+        E::Sum(ref for_n) | E::Prod(ref for_n) => {
+            if i64_loops.contains(&for_n.source_range.offset) { for_n.i64_acc.set(true); }
+        }
+        E::SumIn(ref for_in) => {
+            if i64_loops.contains(&for_in.source_range.offset) { for_in.i64_acc.set(true); }
+        }
+        _ => {}
+    };
+    let blocks = module.functions[loaded.0..].iter().map(|f| &f.block)
+        .chain(module.globals[loaded.1..].iter().map(|g| &g.init.block));
+    for block in blocks {
+        for expr in &block.expressions { coverage::walk(expr, &mut mark); }
+    }
+
+    for (name, ty) in refined_rets {
+        if let FnIndex::Loaded(f_index) = module.find_function(name, 0) {
+            module.functions[f_index as usize].ret = ty.clone();
+        }
+    }
+    for (name, ty) in global_types {
+        if let Some(i) = module.find_global(name) {
+            let g = &mut module.globals[i];
+            if g.init.ret == Type::Any { g.init.ret = ty.clone(); }
+        }
+    }
+}
+
+fn format_warning(source: &str, d: &Arc<String>, warning: &Range<String>) -> String {
+    use std::io::Write;
+    use piston_meta::ParseErrorHandler;
+
+    let mut buf: Vec<u8> = vec![];
+    writeln!(&mut buf, "In `{}`:\n", source).unwrap();
+    ParseErrorHandler::new(d)
+        .write_msg(&mut buf, warning.range(), &warning.data)
+        .unwrap();
+    String::from_utf8(buf).unwrap()
+}
+
+/// Returns the syntax rules of Dyon.
+fn syntax_rules() -> Result<&'static piston_meta::Syntax, String> {
+    use piston_meta::{syntax_errstr, Syntax};
+
+    lazy_static! {
+        static ref SYNTAX_RULES: Result<Syntax, String> = {
+            let syntax = include_str!("../assets/syntax.txt");
+            syntax_errstr(syntax)
+        };
+    }
+
+    SYNTAX_RULES.as_ref().map_err(|err| err.clone())
+}
+
+/// Checks a source for errors without running it, for editors and other tools.
+///
+/// Parses the source, checks lifetimes and types, and adds its functions to the module.
+/// Unlike `load_str`, an error is returned with its range in the source,
+/// and warnings are pushed to `warnings` with their ranges, as well as to the module.
+/// Constants are not evaluated.
+/// Returns the meta data of the source.
+pub fn check_str(
+    source: &str,
+    d: Arc<String>,
+    module: &mut Module,
+    warnings: &mut Vec<Range<String>>
+) -> Result<Vec<Range<MetaData>>, Range<String>> {
+    use piston_meta::parse;
+
+    let syntax_rules = try!(syntax_rules().map_err(|err| Range::empty(0).wrap(err)));
+    let mut data = vec![];
+    try!(parse(syntax_rules, &d, &mut data).map_err(|err| {
+        let (range, err) = err.decouple();
+        range.wrap(format!("{}", err))
+    }));
+    ast::enum_calls(&mut data, module);
+
+    let prelude = Prelude::from_module(module);
+    let mut new_warnings = vec![];
+    let (refined_rets, global_types, i64_loops) =
+        try!(lifetime::check(&data, &prelude, &mut new_warnings));
+    for warning in &new_warnings {
+        module.warnings.push(format_warning(source, &d, warning));
+    }
+    warnings.extend(new_warnings);
+
+    let mut ignored = vec![];
+    let loaded = (module.functions.len(), module.globals.len());
+    let conv_res = ast::convert(Arc::new(source.into()), d, &data, &mut ignored, module);
+    if let Some(range) = ignored.first() {
+        return Err(data[range.iter()][0].range().wrap("Could not understand this".into()));
+    }
+    if conv_res.is_err() {
+        return Err(Range::empty(0).wrap("Conversion error".into()));
+    }
+    refine_types(module, loaded, &refined_rets, &global_types, &i64_loops);
+    ast::compile(module);
+    Ok(data)
+}
+
+/// Evaluates the constants declared after the first `loaded.1` constants and globals.
+///
+/// Failing to evaluate a constant is a load error,
+/// which removes the functions, enums, constants and globals of the source.
+fn eval_consts(module: &mut Module, loaded: (usize, usize), enums: usize) -> Result<(), String> {
+    if module.globals[loaded.1..].iter().all(|g| g.mutable) { return Ok(()); }
+    match Runtime::eval_consts(module) {
+        Ok(values) => {
+            for (g, val) in module.globals.iter_mut().zip(values.into_iter()) {
+                if g.value.is_none() { g.value = val; }
+            }
+            Ok(())
+        }
+        Err(err) => {
+            module.functions.truncate(loaded.0);
+            module.enums.truncate(enums);
+            module.globals.truncate(loaded.1);
+            Err(err)
+        }
+    }
 }
 
 /// Loads a source from meta data.
@@ -438,9 +737,13 @@ pub fn load_meta(
 ) -> Result<(), String> {
     // Convert to AST.
     let mut ignored = vec![];
+    let loaded = (module.functions.len(), module.globals.len());
+    let enums = module.enums.len();
     let conv_res = ast::convert(Arc::new(source.into()), d.clone(), &data, &mut ignored, module);
+    ast::compile(module);
 
-    check_ignored_meta_data(&conv_res, source, &d, data, &ignored)
+    try!(check_ignored_meta_data(&conv_res, source, &d, data, &ignored));
+    eval_consts(module, loaded, enums)
 }
 
 fn check_ignored_meta_data(
@@ -479,8 +782,17 @@ fn check_ignored_meta_data(
     Ok(())
 }
 
+/// Reports warnings of a module to standard output.
+pub fn warnings(module: &Module) {
+    for warning in &module.warnings {
+        println!("");
+        println!(" --- WARNING --- ");
+        println!("{}", warning);
+    }
+}
+
 /// Reports and error to standard output.
-pub fn error(res: Result<(), String>) -> bool {
+pub fn error<T>(res: Result<T, String>) -> bool {
     match res {
         Err(err) => {
             println!("");
@@ -488,7 +800,7 @@ pub fn error(res: Result<(), String>) -> bool {
             println!("{}", err);
             true
         }
-        Ok(()) => false
+        Ok(_) => false
     }
 }
 
@@ -510,6 +822,7 @@ mod tests {
         Return,
         Bool(bool, Option<Box<Vec<Variable>>>),
         F64(f64, Option<Box<Vec<Variable>>>),
+        I64(i64),
         Vec4([f32; 4]),
         Text(Arc<String>),
         Array(Array),
@@ -519,7 +832,11 @@ mod tests {
         RustObject(RustObject),
         Option(Option<Box<Variable>>),
         Result(Result<Box<Variable>, Box<Error>>),
+        Enum(Box<EnumVariant>),
         Thread(Thread),
+        Coroutine(coroutine::Coroutine),
+        Channel(channel::Channel),
+        Shared(shared::Shared),
         */
 
         println!("Link {}", size_of::<Box<Link>>());
@@ -51,6 +51,22 @@ pub enum Kind {
     Step,
     Compare,
     If,
+    Match,
+    Arm,
+    Guard,
+    Pattern,
+    SomePattern,
+    OkPattern,
+    ErrPattern,
+    FieldPattern,
+    PayloadPattern,
+    Enum,
+    Variant,
+    EnumValue,
+    Payload,
+    Const,
+    Global,
+    Default,
     TrueBlock,
     ElseBlock,
     Loop,
@@ -71,6 +87,8 @@ pub enum Kind {
     RetType,
     ReturnVoid,
     Go,
+    Coroutine,
+    Yield,
     Swizzle,
     Sw0,
     Sw1,
@@ -79,6 +97,14 @@ pub enum Kind {
     Link,
     LinkFor,
     LinkItem,
+    ForIn,
+    SumIn,
+    MinIn,
+    MaxIn,
+    SiftIn,
+    AnyIn,
+    AllIn,
+    In,
     Closure,
     CallClosure,
     ClosureType,
@@ -142,6 +168,22 @@ impl Kind {
             "step" => Kind::Step,
             "compare" => Kind::Compare,
             "if" => Kind::If,
+            "match" => Kind::Match,
+            "arm" => Kind::Arm,
+            "guard" => Kind::Guard,
+            "pattern" => Kind::Pattern,
+            "some" => Kind::SomePattern,
+            "ok" => Kind::OkPattern,
+            "err" => Kind::ErrPattern,
+            "field" => Kind::FieldPattern,
+            "payload_pattern" => Kind::PayloadPattern,
+            "enum" => Kind::Enum,
+            "variant" => Kind::Variant,
+            "enum_value" => Kind::EnumValue,
+            "payload" => Kind::Payload,
+            "const" => Kind::Const,
+            "global" => Kind::Global,
+            "default" => Kind::Default,
             "true_block" => Kind::TrueBlock,
             "else_block" => Kind::ElseBlock,
             "loop" => Kind::Loop,
@@ -162,6 +204,8 @@ impl Kind {
             "ret_type" => Kind::RetType,
             "return_void" => Kind::ReturnVoid,
             "go" => Kind::Go,
+            "coroutine" => Kind::Coroutine,
+            "yield" => Kind::Yield,
             "swizzle" => Kind::Swizzle,
             "sw0" => Kind::Sw0,
             "sw1" => Kind::Sw1,
@@ -170,6 +214,14 @@ impl Kind {
             "link" => Kind::Link,
             "link_for" => Kind::LinkFor,
             "link_item" => Kind::LinkItem,
+            "for_in" => Kind::ForIn,
+            "sum_in" => Kind::SumIn,
+            "min_in" => Kind::MinIn,
+            "max_in" => Kind::MaxIn,
+            "sift_in" => Kind::SiftIn,
+            "any_in" => Kind::AnyIn,
+            "all_in" => Kind::AllIn,
+            "in" => Kind::In,
             "closure" => Kind::Closure,
             "call_closure" => Kind::CallClosure,
             "named_call_closure" => Kind::CallClosure,
@@ -193,6 +245,16 @@ impl Kind {
         }
     }
 
+    /// A loop over the elements of a collection.
+    pub fn is_decl_in_loop(&self) -> bool {
+        use self::Kind::*;
+
+        match *self {
+            ForIn | SumIn | MinIn | MaxIn | SiftIn | AnyIn | AllIn => true,
+            _ => false
+        }
+    }
+
     /// An un-loop has fixed range and replaces variable in body.
     pub fn is_decl_un_loop(&self) -> bool {
         match *self {
@@ -201,6 +263,17 @@ impl Kind {
         }
     }
 
+    /// A pattern in a match arm, which might bind variables.
+    pub fn is_pattern(&self) -> bool {
+        use self::Kind::*;
+
+        match *self {
+            Pattern | SomePattern | OkPattern |
+            ErrPattern | FieldPattern | PayloadPattern => true,
+            _ => false
+        }
+    }
+
     pub fn is_block(&self) -> bool {
         use self::Kind::*;
 
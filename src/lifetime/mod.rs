@@ -9,9 +9,10 @@ use self::kind::Kind;
 use self::node::{convert_meta_data, Node};
 use self::lt::{arg_lifetime, compare_lifetimes, Lifetime};
 
-use prelude::{Lt, Prelude};
+use prelude::{omits_args, Lt, Prelude};
 use ast::{AssignOp, UseLookup};
 
+use Capability;
 use Type;
 
 mod kind;
@@ -20,11 +21,14 @@ mod lt;
 mod typecheck;
 
 /// Checks lifetime constraints and does type checking.
-/// Returns refined return types of functions to put in AST.
+/// Returns refined return types of functions and types of constants and globals to put in AST.
+/// Warnings, e.g. about non-exhaustive matches, are pushed to `warnings`.
 pub fn check(
     data: &[Range<MetaData>],
-    prelude: &Prelude
-) -> Result<HashMap<Arc<String>, Type>, Range<String>> {
+    prelude: &Prelude,
+    warnings: &mut Vec<Range<String>>
+) -> Result<(HashMap<Arc<String>, Type>, HashMap<Arc<String>, Type>, HashSet<usize>),
+            Range<String>> {
     let mut nodes: Vec<Node> = vec![];
     try!(convert_meta_data(&mut nodes, data));
 
@@ -79,10 +83,26 @@ pub fn check(
     let calls: Vec<usize> = nodes.iter().enumerate()
         .filter(|&(_, n)| n.kind == Kind::Call).map(|(i, _)| i).collect();
 
+    // Check that starting threads is allowed.
+    if !prelude.capabilities.has(Capability::Threads) {
+        if let Some(node) = nodes.iter().find(|n| n.kind == Kind::Go) {
+            return Err(node.source.wrap(format!("`go` requires capability `{}`",
+                Capability::Threads.name())));
+        }
+        if let Some(node) = nodes.iter().find(|n| n.parallel) {
+            return Err(node.source.wrap(format!("`par` requires capability `{}`",
+                Capability::Threads.name())));
+        }
+    }
+
     // Collect indices to returns.
     let returns: Vec<usize> = nodes.iter().enumerate()
         .filter(|&(_, n)| n.kind == Kind::Return).map(|(i, _)| i).collect();
 
+    // Collect indices to yields.
+    let yields: Vec<usize> = nodes.iter().enumerate()
+        .filter(|&(_, n)| n.kind == Kind::Yield).map(|(i, _)| i).collect();
+
     // Collect indices to expressions in mathematical declared functions.
     let math_expr: Vec<usize> = nodes.iter().enumerate()
         .filter(|&(_, n)| {
@@ -185,6 +205,23 @@ pub fn check(
         .map(|(i, _)| i)
         .collect();
 
+    // Collect indices to constants and globals.
+    let globals: Vec<usize> = nodes.iter().enumerate()
+        .filter(|&(_, n)| n.kind == Kind::Const || n.kind == Kind::Global)
+        .map(|(i, _)| i)
+        .collect();
+
+    // Check for duplicate constants and globals.
+    for (k, &i) in globals.iter().enumerate() {
+        if globals[..k].iter().any(|&j| nodes[j].name() == nodes[i].name()) {
+            return Err(nodes[i].source.wrap(
+                format!("Duplicate global `{}`", nodes[i].name().expect("Expected name"))));
+        }
+    }
+
+    // Stores items referring to constants declared in other sources.
+    let mut prelude_consts: HashSet<usize> = HashSet::new();
+
     // Link items to their declaration.
     for &i in &items {
         // When `return` is used as variable one does not need to link.
@@ -200,8 +237,10 @@ pub fn check(
         let mut grab = 0;
 
         'search: loop {
+            // The collection of an in-loop is evaluated before declaring the names.
             if nodes[parent].kind.is_decl_loop() ||
-               nodes[parent].kind.is_decl_un_loop() {
+               nodes[parent].kind.is_decl_un_loop() ||
+               nodes[parent].kind.is_decl_in_loop() && nodes[child].kind != Kind::In {
                 let my_name = nodes[i].name().unwrap();
                 for name in &nodes[parent].names {
                     if name == my_name {
@@ -211,6 +250,17 @@ pub fn check(
                 }
             }
 
+            if nodes[parent].kind == Kind::Arm {
+                let my_name = nodes[i].name().unwrap();
+                if let Some(pattern) = nodes[parent].children.iter()
+                    .find(|&&j| nodes[j].kind == Kind::Pattern) {
+                    if let Some(j) = find_binding(&nodes, *pattern, my_name) {
+                        it = Some(j);
+                        break 'search;
+                    }
+                }
+            }
+
             let me = nodes[parent].children.binary_search(&child)
                 .expect("Expected parent to contain child");
             let children = &nodes[parent].children[..me];
@@ -276,7 +326,9 @@ pub fn check(
             Some(it) => nodes[i].declaration = Some(it),
             None => {
                 if nodes[parent].kind != Kind::Fn &&
-                   nodes[parent].kind != Kind::Closure {
+                   nodes[parent].kind != Kind::Closure &&
+                   nodes[parent].kind != Kind::Const &&
+                   nodes[parent].kind != Kind::Global {
                     panic!("Top parent is not a function");
                 }
                 if nodes[i].name().is_none() {
@@ -297,14 +349,41 @@ pub fn check(
                         break;
                     }
                 }
+                if found.is_none() {
+                    // Search among constants and globals.
+                    // The value of a constant or global can only use those declared before.
+                    let mut top = parent;
+                    while let Some(p) = nodes[top].parent { top = p; }
+                    let before = match nodes[top].kind {
+                        Kind::Const | Kind::Global => top,
+                        _ => nodes.len()
+                    };
+                    found = globals.iter().cloned()
+                        .find(|&j| j < before && nodes[j].name() == nodes[i].name());
+                    // Constants are evaluated when loaded, before globals.
+                    if let Some(j) = found {
+                        if nodes[top].kind == Kind::Const && nodes[j].kind == Kind::Global {
+                            return Err(nodes[i].source.wrap(format!(
+                                "Constant can not use global `{}`",
+                                nodes[i].name().expect("Expected name"))));
+                        }
+                    }
+                }
                 match found {
                     Some(j) => {
                         nodes[i].declaration = Some(j);
                     }
                     None => {
-                        return Err(nodes[i].source.wrap(
-                            format!("Could not find declaration of `{}`",
-                            nodes[i].name().expect("Expected name"))));
+                        let name = nodes[i].name().expect("Expected name").clone();
+                        if let Some(&(mutable, ref ty)) = prelude.globals.get(&name) {
+                            if !nodes[i].item_ids() {
+                                nodes[i].ty = Some(nodes[i].inner_type(ty));
+                            }
+                            if !mutable { prelude_consts.insert(i); }
+                        } else {
+                            return Err(nodes[i].source.wrap(
+                                format!("Could not find declaration of `{}`", name)));
+                        }
                     }
                 }
             }
@@ -340,11 +419,16 @@ pub fn check(
         }
     }
 
+    // Stores number of arguments with default values with same index as `functions`.
+    let mut function_defaults = Vec::with_capacity(functions.len());
+
     // Check for duplicate function arguments.
+    // Default values are only allowed for trailing arguments.
     let mut arg_names: HashSet<Arc<String>> = HashSet::new();
     for &f in &functions {
         arg_names.clear();
         let mut n = 0;
+        let mut defaults = 0;
         for &i in nodes[f].children.iter().filter(|&&i| nodes[i].kind == Kind::Arg) {
             let name = nodes[i].name().expect("Expected name");
             if arg_names.contains(name) {
@@ -353,9 +437,16 @@ pub fn check(
             } else {
                 arg_names.insert(name.clone());
             }
+            if nodes[i].find_child_by_kind(&nodes, Kind::Default).is_some() {
+                defaults += 1;
+            } else if defaults > 0 {
+                return Err(nodes[i].source.wrap(
+                    format!("Expected default value for argument `{}`", name)));
+            }
             n += 1;
         }
         function_args.push(n);
+        function_defaults.push(defaults);
     }
 
     // Check for duplicate functions and build name to index map.
@@ -406,8 +497,17 @@ pub fn check(
 
         let node = &mut nodes[c];
         let name = node.name().expect("Expected name").clone();
+        // Checks whether a function can be called by omitting arguments with default values.
+        let accepts = |i: usize| n <= function_args[i] &&
+                                 function_args[i] - n <= function_defaults[i];
         if let Some(ref alias) = node.alias {
-            if let Some(&i) = use_lookup.aliases.get(alias).and_then(|map| map.get(&name)) {
+            let found = use_lookup.aliases.get(alias).and_then(|map| {
+                map.get_key_value(&name).or_else(|| map.iter()
+                    .find(|&(f, &i)| omits_args(&name, f, prelude.list[i].tys.len(), n) &&
+                                      prelude.list[i].accepts(n)))
+            });
+            if let Some((f, &i)) = found {
+                node.names[0] = f.clone();
                 node.lts = prelude.list[i].lts.clone();
                 continue;
             } else {
@@ -416,13 +516,30 @@ pub fn check(
             }
         }
         let i = match function_lookup.get(&name) {
-            Some(&i) => i,
+            Some(&i) => Some(i),
+            None => {
+                let found: Vec<(&Arc<String>, usize)> = function_lookup.iter()
+                    .filter(|&(f, &i)| omits_args(&name, f, function_args[i], n) && accepts(i))
+                    .map(|(f, &i)| (f, i)).collect();
+                try!(check_ambiguous(&name, found.iter().map(|&(f, _)| f), &node.source));
+                found.get(0).map(|&(f, i)| {
+                    node.names[0] = f.clone();
+                    i
+                })
+            }
+        };
+        let i = match i {
+            Some(i) => i,
             None => {
+                if let Some(cap) = prelude.capabilities.denies(&name) {
+                    return Err(node.source.wrap(
+                        format!("`{}` requires capability `{}`", name, cap.name())));
+                }
                 // Check whether it is a prelude function.
                 match prelude.functions.get(&name) {
                     Some(&pf) => {
                         node.lts = prelude.list[pf].lts.clone();
-                        if node.lts.len() != n {
+                        if !prelude.list[pf].accepts(n) {
                             return Err(node.source.wrap(
                                 format!("{}: Expected {} arguments, found {}",
                                 name, node.lts.len(), n)));
@@ -431,13 +548,24 @@ pub fn check(
                     }
                     None => {}
                 }
+                let found: Vec<(&Arc<String>, usize)> = prelude.functions.iter()
+                    .filter(|&(f, &pf)| omits_args(&name, f, prelude.list[pf].tys.len(), n) &&
+                                         prelude.list[pf].accepts(n))
+                    .map(|(f, &pf)| (f, pf)).collect();
+                try!(check_ambiguous(&name, found.iter().map(|&(f, _)| f), &node.source));
+                if let Some(&(f, pf)) = found.get(0) {
+                    node.names[0] = f.clone();
+                    node.lts = prelude.list[pf].lts.clone();
+                    continue;
+                }
                 let suggestions = suggestions(&**name, &function_lookup, prelude);
                 return Err(node.source.wrap(
                     format!("Could not find function `{}`{}", name, suggestions)));
             }
         };
-        // Check that number of arguments is the same as in declaration.
-        if function_args[i] != n {
+        // Check that number of arguments is the same as in declaration,
+        // except trailing arguments with default values.
+        if !accepts(i) {
         let suggestions = suggestions(&**name, &function_lookup, prelude);
             return Err(node.source.wrap(
                 format!("{}: Expected {} arguments, found {}{}",
@@ -533,6 +661,18 @@ pub fn check(
         }
     }
 
+    // Check the lifetime of expressions in match arms.
+    for i in 0..nodes.len() {
+        if nodes[i].kind != Kind::Arm { continue; }
+        if let Some(expr) = nodes[i].find_child_by_kind(&nodes, Kind::Expr) {
+            // Fake a local variable.
+            let ref lifetime_left = Some(Lifetime::Local(i));
+            let ref lifetime_right = nodes[expr].lifetime(&nodes, &arg_names);
+            try!(compare_lifetimes(lifetime_left, lifetime_right, &nodes)
+                    .map_err(|err| nodes[expr].source.wrap(err)));
+        }
+    }
+
     // Check the lifetime of returned values.
     for &i in &returns {
         let right = nodes[i].children[0];
@@ -543,6 +683,16 @@ pub fn check(
         );
     }
 
+    // Check that yielded values do not refer to variables of the suspended function.
+    for &i in &yields {
+        let right = nodes[i].children[0];
+        let ref lifetime_right = nodes[right].lifetime(&nodes, &arg_names);
+        try!(compare_lifetimes(
+            &Some(Lifetime::Return(vec![])), lifetime_right, &nodes)
+                .map_err(|err| nodes[right].source.wrap(err))
+        );
+    }
+
     // Check the lifetime of expressions that are mathematically declared.
     for &i in &math_expr {
         let ref lifetime_right = nodes[i].lifetime(&nodes, &arg_names);
@@ -575,23 +725,25 @@ pub fn check(
         }
     }
 
-    // Check that `go` functions does not have lifetime constraints.
+    // Check that `go` and `coroutine` functions does not have lifetime constraints.
     for &c in &calls {
         let call = &nodes[c];
-        if let Some(parent) = call.parent {
-            if nodes[parent].kind != Kind::Go { continue }
-        } else {
-            continue;
-        }
+        let keyword = match call.parent.map(|parent| nodes[parent].kind) {
+            Some(Kind::Go) => "go",
+            Some(Kind::Coroutine) => "coroutine",
+            _ => continue
+        };
         if let Some(declaration) = call.declaration {
             let function = &nodes[declaration];
             for (i, &a) in function.children.iter()
                 .enumerate()
                 .filter(|&(_, &i)| nodes[i].kind == Kind::Arg)  {
                 let arg = &nodes[a];
-                if arg.lifetime.is_some() {
+                // Omitted arguments use default values.
+                if arg.lifetime.is_some() && i < call.children.len() {
                     return Err(nodes[call.children[i]].source.wrap(
-                        format!("Can not use `go` because this argument has a lifetime constraint")));
+                        format!("Can not use `{}` because this argument has a lifetime constraint",
+                            keyword)));
                 }
             }
         } else {
@@ -604,7 +756,8 @@ pub fn check(
                     Lt::Default => {}
                     _ => {
                         return Err(nodes[call.children[i]].source.wrap(
-                            format!("Can not use `go` because this argument has a lifetime constraint")));
+                            format!("Can not use `{}` because this argument has a lifetime constraint",
+                                keyword)));
                     }
                 }
             }
@@ -666,6 +819,8 @@ pub fn check(
                 .enumerate()
                 .filter(|&(_, &i)| nodes[i].kind == Kind::Arg)
                 .map(|(i, a)| (map_arg_call_arg_index(i), a)) {
+                // Omitted arguments use default values, which are not references.
+                if i >= call.children.len() { break; }
                 let arg = &nodes[a];
                 if let Some(ref lt) = arg.lifetime {
                     // When arguments should outlive the return value,
@@ -685,6 +840,7 @@ pub fn check(
                         // Compare the lifetime of the two arguments.
                         let (_, ind) = *arg_names.get(&(declaration, lt.clone()))
                             .expect("Expected argument name");
+                        if ind >= call.children.len() { continue; }
                         let left = call.children[ind];
                         let right = call.children[i];
                         let ref lifetime_left = nodes[left].lifetime(&nodes, &arg_names);
@@ -701,6 +857,7 @@ pub fn check(
             for (i, &lt) in
             call.lts.iter().enumerate()
                 .map(|(i, a)| (map_arg_call_arg_index(i), a)) {
+                if i >= call.children.len() { break; }
                 let arg = &nodes[call.children[i]];
                 match lt {
                     Lt::Default => {}
@@ -711,6 +868,7 @@ pub fn check(
                         }
                     }
                     Lt::Arg(ind) => {
+                        if ind >= call.children.len() { continue; }
                         if !is_reference(i) {
                             return Err(arg.source.wrap(
                                 format!("Requires reference to variable")));
@@ -730,16 +888,89 @@ pub fn check(
         }
     }
 
-    // Check that mutable locals are not immutable arguments.
+    // Check that constants are not changed.
+    for &(_, i) in mutated_locals.iter().chain(assigned_locals.iter()) {
+        let constant = match nodes[i].declaration {
+            Some(decl) => nodes[decl].kind == Kind::Const,
+            None => prelude_consts.contains(&i)
+        };
+        if constant {
+            return Err(nodes[i].source.wrap(
+                format!("Can not change constant `{}`", nodes[i].name().unwrap())));
+        }
+    }
+
+    // Check that mutable locals are not immutable arguments or loop elements.
     for &(_, i) in &mutated_locals {
         if let Some(decl) = nodes[i].declaration {
-            if nodes[decl].kind == Kind::Arg ||
-               nodes[decl].kind == Kind::Current {
-                if !nodes[decl].mutable {
-                    return Err(nodes[i].source.wrap(
-                        format!("Requires `mut {}`", nodes[i].name().unwrap())
-                    ));
-                }
+            if requires_mut(&nodes, decl, nodes[i].name().unwrap()) {
+                return Err(nodes[i].source.wrap(
+                    format!("Requires `mut {}`", nodes[i].name().unwrap())
+                ));
+            }
+        }
+    }
+
+    // Check that the collection of a mutable in-loop can be changed.
+    for (i, node) in nodes.iter().enumerate() {
+        if !node.kind.is_decl_in_loop() || !node.mutable { continue; }
+        let item = match in_loop_item(&nodes, i) {
+            Some(item) => item,
+            None => return Err(nodes[i].source.wrap(
+                format!("Expected item for `mut` in loop")))
+        };
+        let name = nodes[item].name().unwrap();
+        let constant = match nodes[item].declaration {
+            Some(decl) => nodes[decl].kind == Kind::Const,
+            None => prelude_consts.contains(&item)
+        };
+        if constant {
+            return Err(nodes[item].source.wrap(
+                format!("Can not change constant `{}`", name)));
+        }
+        if let Some(decl) = nodes[item].declaration {
+            if requires_mut(&nodes, decl, name) {
+                return Err(nodes[item].source.wrap(format!("Requires `mut {}`", name)));
+            }
+        }
+    }
+
+    // Check that the body of a mutable in-loop does not change the collection,
+    // since the collection is looked up again for every element.
+    for (i, node) in nodes.iter().enumerate() {
+        if !node.kind.is_decl_in_loop() || !node.mutable { continue; }
+        let coll = match node.find_child_by_kind(&nodes, Kind::In) {
+            Some(coll) => coll,
+            None => continue
+        };
+        // Variables used to look up the collection.
+        let mut decls = vec![];
+        let mut stack = vec![coll];
+        while let Some(j) = stack.pop() {
+            if nodes[j].kind == Kind::Item {
+                if let Some(decl) = nodes[j].declaration { decls.push(decl); }
+            }
+            stack.extend(nodes[j].children.iter().cloned());
+        }
+        let assigned = nodes.iter()
+            .filter(|n| n.op.is_some() && n.children.len() > 0 &&
+                        nodes[n.children[0]].children.len() > 0)
+            .map(|n| nodes[n.children[0]].children[0]);
+        for j in assigned.chain(mut_call_items(&nodes, &calls)) {
+            match nodes[j].declaration {
+                Some(decl) if decls.contains(&decl) => {}
+                _ => continue
+            }
+            // Find the child of the loop containing the change.
+            let mut child = j;
+            while let Some(parent) = nodes[child].parent {
+                if parent == i { break; }
+                child = parent;
+            }
+            if nodes[child].parent == Some(i) && child != coll {
+                return Err(nodes[j].source.wrap(format!(
+                    "Can not change `{}` in `mut` loop over it",
+                    nodes[j].name().unwrap())));
             }
         }
     }
@@ -764,10 +995,16 @@ pub fn check(
                           && nodes[n].mutable)
         {
             if let Some(n) = reference(arg) {
+                if prelude_consts.contains(&n) {
+                    return Err(nodes[n].source.wrap(
+                        format!("Can not change constant `{}`", nodes[n].name().unwrap())));
+                }
                 if let Some(decl) = nodes[n].declaration {
-                   if (nodes[decl].kind == Kind::Arg ||
-                       nodes[decl].kind == Kind::Current) &&
-                       !nodes[decl].mutable {
+                   if nodes[decl].kind == Kind::Const {
+                       return Err(nodes[n].source.wrap(
+                           format!("Can not change constant `{}`", nodes[n].name().unwrap())));
+                   }
+                   if requires_mut(&nodes, decl, nodes[n].name().unwrap()) {
                        return Err(nodes[n].source.wrap(
                            format!("Requires `mut {}`", nodes[n].name().unwrap())
                        ));
@@ -777,7 +1014,66 @@ pub fn check(
         }
     }
 
-    try!(typecheck::run(&mut nodes, prelude, &use_lookup));
+    // Check that parallel loops do not change variables declared outside the loop,
+    // since each worker thread changes its own copy.
+    if nodes.iter().any(|n| n.parallel) {
+        let mut changed: Vec<usize> = mutated_locals.iter().chain(assigned_locals.iter())
+            .map(|&(_, i)| i).collect();
+        changed.extend(mut_call_items(&nodes, &calls));
+        for (i, node) in nodes.iter().enumerate() {
+            if node.kind.is_decl_in_loop() && node.mutable {
+                if let Some(item) = in_loop_item(&nodes, i) { changed.push(item); }
+            }
+        }
+        for &i in &changed {
+            let par = match parallel_loop(&nodes, i) {
+                None => continue,
+                Some(par) => par
+            };
+            let inside = match nodes[i].declaration {
+                None => false,
+                Some(decl) => {
+                    let mut decl = Some(decl);
+                    while let Some(j) = decl {
+                        if j == par { break; }
+                        decl = nodes[j].parent;
+                    }
+                    decl.is_some()
+                }
+            };
+            if !inside {
+                return Err(nodes[i].source.wrap(format!(
+                    "Can not change `{}` in parallel loop", nodes[i].name().unwrap())));
+            }
+        }
+        for (i, node) in nodes.iter().enumerate() {
+            match node.kind {
+                Kind::Return | Kind::ReturnVoid => {
+                    if parallel_loop(&nodes, i).is_some() {
+                        return Err(node.source.wrap(
+                            "Can not return from parallel loop".into()));
+                    }
+                }
+                Kind::Break => {
+                    // Find the loop to break out of.
+                    let mut parent = node.parent;
+                    while let Some(j) = parent {
+                        let kind = nodes[j].kind;
+                        if kind == Kind::For || kind == Kind::Loop ||
+                           kind.is_decl_loop() || kind.is_decl_in_loop() { break; }
+                        parent = nodes[j].parent;
+                    }
+                    if parent.map(|j| nodes[j].parallel) == Some(true) {
+                        return Err(node.source.wrap(
+                            "Can not break out of parallel loop".into()));
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    try!(typecheck::run(&mut nodes, prelude, &use_lookup, warnings));
 
     // Copy refined return types to use in AST.
     let mut refined_rets: HashMap<Arc<String>, Type> = HashMap::new();
@@ -786,8 +1082,105 @@ pub fn check(
             refined_rets.insert(name.clone(), ty.clone());
         }
     }
+    let mut global_types: HashMap<Arc<String>, Type> = HashMap::new();
+    for &i in &globals {
+        if let (Some(name), Some(ty)) = (nodes[i].name(), nodes[i].ty.as_ref()) {
+            global_types.insert(name.clone(), ty.clone());
+        }
+    }
+
+    // Offsets of `sum` and `prod` loops adding up `i64` values.
+    let mut i64_loops: HashSet<usize> = HashSet::new();
+    for node in &nodes {
+        match node.kind {
+            Kind::Sum | Kind::Prod | Kind::SumIn if node.ty == Some(Type::I64) => {
+                i64_loops.insert(node.source.offset);
+            }
+            _ => {}
+        }
+    }
+
+    Ok((refined_rets, global_types, i64_loops))
+}
 
-    Ok(refined_rets)
+// Whether changing a variable requires `mut` on its declaration.
+//
+// Only the element of an in-loop can be declared `mut`, not the index or key.
+fn requires_mut(nodes: &[Node], decl: usize, name: &Arc<String>) -> bool {
+    match nodes[decl].kind {
+        Kind::Arg | Kind::Current => !nodes[decl].mutable,
+        x if x.is_decl_in_loop() =>
+            !nodes[decl].mutable || nodes[decl].names.last() != Some(name),
+        _ => false
+    }
+}
+
+// Find the item of the collection in an in-loop.
+fn in_loop_item(nodes: &[Node], i: usize) -> Option<usize> {
+    let mut item = nodes[i].find_child_by_kind(nodes, Kind::In);
+    while let Some(j) = item {
+        match nodes[j].kind {
+            Kind::Item => break,
+            Kind::In | Kind::Expr | Kind::Add | Kind::Mul | Kind::Val
+                if nodes[j].children.len() == 1 => item = Some(nodes[j].children[0]),
+            _ => item = None
+        }
+    }
+    item
+}
+
+// Find the items passed as mutable arguments to calls.
+fn mut_call_items(nodes: &[Node], calls: &[usize]) -> Vec<usize> {
+    let mut items = vec![];
+    for &c in calls {
+        for &arg in nodes[c].children.iter()
+            .filter(|&&n| nodes[n].kind == Kind::CallArg && nodes[n].mutable)
+        {
+            // Item is 2 levels down inside call_arg/item
+            let mut n = arg;
+            for _ in 0..2 {
+                if nodes[n].kind == Kind::Item { items.push(n); break; }
+                if nodes[n].children.len() == 0 { break; }
+                n = nodes[n].children[0];
+            }
+        }
+    }
+    items
+}
+
+// Find the innermost parallel loop containing a node.
+fn parallel_loop(nodes: &[Node], i: usize) -> Option<usize> {
+    let mut parent = nodes[i].parent;
+    while let Some(j) = parent {
+        if nodes[j].parallel { return Some(j); }
+        parent = nodes[j].parent;
+    }
+    None
+}
+
+// Search for a variable bound by a pattern in a match arm.
+fn find_binding(nodes: &[Node], i: usize, name: &Arc<String>) -> Option<usize> {
+    if nodes[i].names.iter().any(|n| n == name && &***n != "_") {
+        return Some(i);
+    }
+    for &j in &nodes[i].children {
+        if !nodes[j].kind.is_pattern() { continue; }
+        if let Some(j) = find_binding(nodes, j, name) { return Some(j); }
+    }
+    None
+}
+
+// Reports an error when a call omitting arguments matches more than one function.
+fn check_ambiguous<'a, I>(name: &str, found: I, source: &Range) -> Result<(), Range<String>>
+    where I: Iterator<Item = &'a Arc<String>>
+{
+    let mut found: Vec<&Arc<String>> = found.collect();
+    if found.len() > 1 {
+        found.sort();
+        return Err(source.wrap(
+            format!("Ambiguous call `{}`, could be `{}` or `{}`", name, found[0], found[1])));
+    }
+    Ok(())
 }
 
 // Search for suggestions using matching function signature.
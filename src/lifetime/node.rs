@@ -5,7 +5,7 @@ use super::piston_meta::bootstrap::Convert;
 use super::lt::{arg_lifetime, Lifetime};
 use super::kind::Kind;
 use super::ArgNames;
-use ast::{AssignOp, BinOp};
+use ast::{parse_i64, AssignOp, BinOp};
 use Lt;
 use Type;
 
@@ -21,6 +21,8 @@ pub struct Node {
     pub ty: Option<Type>,
     /// Whether the argument or call argument is mutable.
     pub mutable: bool,
+    /// Whether the short loop runs in parallel.
+    pub parallel: bool,
     /// Whether there is a `?` operator used on the node.
     pub try: bool,
     /// The grab level.
@@ -43,6 +45,8 @@ pub struct Node {
     pub op: Option<AssignOp>,
     /// Binary operators.
     pub binops: Vec<BinOp>,
+    /// The enum variant of an enum value or pattern.
+    pub variant: Option<Arc<String>>,
     /// The argument lifetime constraints, one for each argument to a function.
     /// Just using an empty vector for nodes that are not functions.
     pub lts: Vec<Lt>,
@@ -97,7 +101,11 @@ impl Node {
             Pow | Sum | Prod | SumVec4 | Min | Max | Any | All |
             Vec4 | Vec4UnLoop | Swizzle |
             Assign | For | ForN | Link | LinkFor |
-            Closure | CallClosure | Grab | TryExpr | Norm => false,
+            ForIn | SumIn | MinIn | MaxIn | SiftIn | AnyIn | AllIn |
+            Closure | CallClosure | Grab | TryExpr | Norm |
+            // The payload of an enum value is deep cloned.
+            EnumValue => false,
+            Const | Global => false,
             Add | Mul | Compare => self.children.len() == 1,
             _ => true
         }
@@ -116,6 +124,9 @@ impl Node {
                     return arg_lifetime(declaration, &arg, nodes, arg_names);
                 } else if arg.kind == Kind::Current {
                     return Some(Lifetime::Current(declaration));
+                } else if arg.kind == Kind::Const || arg.kind == Kind::Global {
+                    // Constants and globals outlive all functions.
+                    return None;
                 } else {
                     return Some(Lifetime::Local(declaration));
                 }
@@ -165,6 +176,11 @@ impl Node {
                 (_, Kind::Swizzle) => {}
                 (_, Kind::Loop) => {}
                 (_, Kind::Go) => {}
+                (_, Kind::Coroutine) => {}
+                (_, Kind::Yield) => {
+                    // A yield does not return a value.
+                    continue
+                }
                 (_, Kind::For) => {}
                 (_, Kind::ForN) => {}
                 (_, Kind::Break) => {}
@@ -178,6 +194,7 @@ impl Node {
                 (_, Kind::Max) => {}
                 (_, Kind::Any) => {}
                 (_, Kind::All) => {}
+                (_, x) if x.is_decl_in_loop() => {}
                 (_, Kind::Vec4UnLoop) => {}
                 (_, Kind::Vec4) => {}
                 (_, Kind::Start) => { continue }
@@ -193,6 +210,7 @@ impl Node {
                 (_, Kind::CallClosure) => {}
                 (_, Kind::Grab) => {}
                 (_, Kind::TryExpr) => {}
+                (_, Kind::EnumValue) => {}
                 (_, Kind::Arg) => { continue }
                 (_, Kind::Current) => { continue }
                 (Kind::CallClosure, Kind::Item) => { continue }
@@ -208,6 +226,11 @@ impl Node {
                     // on the lifetime of the arguments.
                     continue
                 }
+                (Kind::Match, Kind::Expr) => {
+                    // The value is matched against patterns,
+                    // but the result is computed by the arms.
+                    continue
+                }
                 (_, Kind::Left) => {}
                 (_, Kind::Right) => {}
                 (_, Kind::Expr) => {}
@@ -223,6 +246,14 @@ impl Node {
                 (_, Kind::TrueBlock) => {}
                 (_, Kind::ElseIfBlock) => {}
                 (_, Kind::ElseBlock) => {}
+                (_, Kind::Match) => {}
+                (_, Kind::Arm) => {}
+                (_, Kind::Guard) => {
+                    // A guard controls the flow, but the result does not
+                    // depend on its lifetime.
+                    continue
+                }
+                (_, x) if x.is_pattern() => { continue }
                 (_, Kind::Cond) => {
                     // A condition controls the flow, but the result does not
                     // depend on its lifetime.
@@ -305,19 +336,25 @@ pub fn convert_meta_data(
                 let ty = match kind {
                     Kind::Array | Kind::ArrayFill => Some(Type::array()),
                     Kind::Vec4 | Kind::Vec4UnLoop => Some(Type::Vec4),
-                    Kind::Object => Some(Type::object()),
-                    Kind::Sift => Some(Type::array()),
-                    Kind::Sum | Kind::Prod => Some(Type::F64),
+                    Kind::Sift | Kind::SiftIn => Some(Type::array()),
                     Kind::Norm => Some(Type::F64),
                     Kind::Swizzle => Some(Type::F64),
                     Kind::Link | Kind::LinkFor => Some(Type::Link),
                     Kind::Any | Kind::All => Some(Type::Secret(Box::new(Type::Bool))),
                     Kind::Min | Kind::Max => Some(Type::Secret(Box::new(Type::F64))),
-                    Kind::For | Kind::ForN => Some(Type::Void),
+                    Kind::AnyIn | Kind::AllIn => Some(Type::Secret(Box::new(Type::Bool))),
+                    Kind::MinIn | Kind::MaxIn => Some(Type::Secret(Box::new(Type::F64))),
+                    Kind::For | Kind::ForN | Kind::ForIn => Some(Type::Void),
                     _ => None
                 };
 
                 let parent = parents.last().map(|i| *i);
+                if let Some(parent) = parent {
+                    if kind == Kind::Id && nodes[parent].kind == Kind::ItemExtra {
+                        // Mark computed id with empty field name.
+                        nodes[parent].names.push(Arc::new(String::new()));
+                    }
+                }
                 parents.push(nodes.len());
                 nodes.push(Node {
                     kind: kind,
@@ -325,6 +362,7 @@ pub fn convert_meta_data(
                     names: vec![],
                     ty: ty,
                     mutable: false,
+                    parallel: false,
                     try: false,
                     grab_level: 0,
                     source: Range::empty(0),
@@ -336,6 +374,7 @@ pub fn convert_meta_data(
                     declaration: None,
                     op: None,
                     binops: vec![],
+                    variant: None,
                     lts: vec![]
                 });
             }
@@ -382,6 +421,20 @@ pub fn convert_meta_data(
                         let i = *parents.last().unwrap();
                         nodes[i].lifetime = Some(val.clone());
                     }
+                    "id" => {
+                        // Object fields are used to check shape of objects.
+                        let i = *parents.last().unwrap();
+                        if nodes[i].kind == Kind::ItemExtra {
+                            nodes[i].names.push(val.clone());
+                        }
+                    }
+                    "key" => {
+                        // Object keys are used to infer shape of objects.
+                        let i = *parents.last().unwrap();
+                        if nodes[i].kind == Kind::KeyValue {
+                            nodes[i].names.push(val.clone());
+                        }
+                    }
                     "text" => {
                         let i = *parents.last().unwrap();
                         nodes[i].ty = Some(Type::Text);
@@ -390,6 +443,24 @@ pub fn convert_meta_data(
                         let i = *parents.last().unwrap();
                         nodes[i].ty = Some(Type::Vec4);
                     }
+                    "i64" => {
+                        if parse_i64(val).is_none() {
+                            return Err(d.range().wrap(format!(
+                                "Integer literal `{}i64` is out of range, \
+                                expected `{}i64` to `{}i64`",
+                                val, ::std::i64::MIN, ::std::i64::MAX)));
+                        }
+                        let i = *parents.last().unwrap();
+                        nodes[i].ty = Some(Type::I64);
+                    }
+                    "enum" => {
+                        let i = *parents.last().unwrap();
+                        nodes[i].ty = Some(Type::Enum(val.clone()));
+                    }
+                    "variant" => {
+                        let i = *parents.last().unwrap();
+                        nodes[i].variant = Some(val.clone());
+                    }
                     _ => {}
                 }
             }
@@ -431,6 +502,10 @@ pub fn convert_meta_data(
                         let i = *parents.last().unwrap();
                         nodes[i].mutable = _val;
                     }
+                    "par" => {
+                        let i = *parents.last().unwrap();
+                        nodes[i].parallel = _val;
+                    }
                     "try" | "try_item" => {
                         let i = *parents.last().unwrap();
                         nodes[i].try = _val;
@@ -439,6 +514,14 @@ pub fn convert_meta_data(
                         let i = *parents.last().unwrap();
                         nodes[i].ty = Some(Type::Bool);
                     }
+                    "none" => {
+                        let i = *parents.last().unwrap();
+                        nodes[i].ty = Some(Type::option());
+                    }
+                    "object" => {
+                        let i = *parents.last().unwrap();
+                        nodes[i].ty = Some(Type::object());
+                    }
                     "returns" => {
                         // Assuming this will be overwritten when
                         // type is parsed or inferred.
@@ -455,6 +538,26 @@ pub fn convert_meta_data(
                         let i = *parents.last().unwrap();
                         nodes[i].kind = Kind::ReturnVoid;
                     }
+                    "+" => {
+                        let i = *parents.last().unwrap();
+                        nodes[i].binops.push(BinOp::Add);
+                    }
+                    "-" => {
+                        let i = *parents.last().unwrap();
+                        nodes[i].binops.push(BinOp::Sub);
+                    }
+                    "||" => {
+                        let i = *parents.last().unwrap();
+                        nodes[i].binops.push(BinOp::OrElse);
+                    }
+                    "|" => {
+                        let i = *parents.last().unwrap();
+                        nodes[i].binops.push(BinOp::BitOr);
+                    }
+                    "xor" => {
+                        let i = *parents.last().unwrap();
+                        nodes[i].binops.push(BinOp::BitXor);
+                    }
                     "*." => {
                         let i = *parents.last().unwrap();
                         nodes[i].binops.push(BinOp::Dot);
@@ -479,15 +582,34 @@ pub fn convert_meta_data(
                         let i = *parents.last().unwrap();
                         nodes[i].binops.push(BinOp::AndAlso);
                     }
+                    "&" => {
+                        let i = *parents.last().unwrap();
+                        nodes[i].binops.push(BinOp::BitAnd);
+                    }
+                    "<<" => {
+                        let i = *parents.last().unwrap();
+                        nodes[i].binops.push(BinOp::Shl);
+                    }
+                    ">>" => {
+                        let i = *parents.last().unwrap();
+                        nodes[i].binops.push(BinOp::Shr);
+                    }
                     _ => {}
                 }
             }
             MetaData::F64(ref n, val) => {
                 match &***n {
-                    "num" => {
+                    "num" | "range_start" => {
                         let i = *parents.last().unwrap();
                         nodes[i].ty = Some(Type::F64);
                     }
+                    "id" => {
+                        // Mark array index with empty field name.
+                        let i = *parents.last().unwrap();
+                        if nodes[i].kind == Kind::ItemExtra {
+                            nodes[i].names.push(Arc::new(String::new()));
+                        }
+                    }
                     "grab_level" => {
                         if val < 1.0 {
                             return Err(d.range()
@@ -1,9 +1,13 @@
+use std::collections::HashMap;
+use std::sync::Arc;
 use range::Range;
 use super::node::Node;
 use super::kind::Kind;
 use Prelude;
 use Type;
-use ast::UseLookup;
+use ast::{self, AssignOp, UseLookup};
+
+type Enums = HashMap<Arc<String>, ast::Enum>;
 
 /// Runs type checking.
 ///
@@ -45,7 +49,14 @@ use ast::UseLookup;
 /// The type propagation step uses this assumption without checking the whole `if` expression.
 /// After type propagation, all blocks in the `if` expression should have some type information,
 /// but no further propagation is necessary, so it only need to check for consistency.
-pub fn run(nodes: &mut Vec<Node>, prelude: &Prelude, use_lookup: &UseLookup) -> Result<(), Range<String>> {
+pub fn run(
+    nodes: &mut Vec<Node>,
+    prelude: &Prelude,
+    use_lookup: &UseLookup,
+    warnings: &mut Vec<Range<String>>
+) -> Result<(), Range<String>> {
+    let enums = try!(enums(nodes, prelude));
+
     // Type propagation.
     let mut changed;
     loop {
@@ -64,6 +75,33 @@ pub fn run(nodes: &mut Vec<Node>, prelude: &Prelude, use_lookup: &UseLookup) ->
                         }
                     }
                 }
+                Kind::Coroutine => {
+                    // Infer coroutine type from values yielded by function.
+                    if nodes[i].children.len() > 0 {
+                        let ch = nodes[i].children[0];
+                        if let Some(decl) = nodes[ch].declaration {
+                            match yield_type(decl, nodes) {
+                                None => continue 'node,
+                                Some(ty) => this_ty = Some(Type::Coroutine(Box::new(ty)))
+                            }
+                        } else {
+                            this_ty = Some(Type::coroutine());
+                        }
+                    }
+                }
+                Kind::Yield => {
+                    if nodes[i].children.len() == 0 { continue 'node; }
+                    let ch = nodes[i].children[0];
+                    match nodes[ch].ty {
+                        None => continue 'node,
+                        Some(Type::Void) => {
+                            return Err(nodes[ch].source.wrap(
+                                format!("Type mismatch (#330):\n\
+                                    Expected something, found `void`")));
+                        }
+                        Some(_) => this_ty = Some(Type::Void)
+                    }
+                }
                 Kind::Fn => {
                     if let Some(ch) = nodes[i].find_child_by_kind(nodes, Kind::Expr) {
                         // If the block is unreachable at the end,
@@ -250,6 +288,28 @@ pub fn run(nodes: &mut Vec<Node>, prelude: &Prelude, use_lookup: &UseLookup) ->
                                 this_ty = Some(nodes[i].inner_type(nodes[decl].ty.as_ref()
                                     .unwrap_or(&Type::Any)));
                             }
+                            x if x.is_decl_in_loop() => {
+                                let coll = match nodes[decl].find_child_by_kind(nodes, Kind::In) {
+                                    None => continue 'node,
+                                    Some(x) => x
+                                };
+                                let mut coll_ty = match nodes[coll].ty {
+                                    None => continue 'node,
+                                    Some(ref ty) => ty.clone()
+                                };
+                                if let Type::AdHoc(_, ty) = coll_ty { coll_ty = *ty; }
+                                // The first of two names is the index or key.
+                                let names = &nodes[decl].names;
+                                let is_key = names.len() > 1 && nodes[i].name() == names.first();
+                                let ty = match coll_ty {
+                                    Type::Array(_) | Type::Link if is_key => Type::F64,
+                                    Type::Array(ty) => *ty,
+                                    Type::Object | Type::Shape(_)
+                                        if is_key || names.len() == 1 => Type::Text,
+                                    _ => Type::Any
+                                };
+                                this_ty = Some(nodes[i].inner_type(&ty));
+                            }
                             _ => {
                                 if let Some(ref ty) = nodes[decl].ty {
                                     this_ty = Some(nodes[i].inner_type(ty));
@@ -276,7 +336,8 @@ pub fn run(nodes: &mut Vec<Node>, prelude: &Prelude, use_lookup: &UseLookup) ->
                 }
                 Kind::Return | Kind::Val | Kind::Expr | Kind::Cond |
                 Kind::Exp | Kind::Base | Kind::Left | Kind::Right |
-                Kind::ElseIfCond | Kind::UnOp | Kind::Grab
+                Kind::ElseIfCond | Kind::UnOp | Kind::Grab | Kind::Guard |
+                Kind::Payload | Kind::In
                  => {
                      // TODO: Report error for expected unary operator.
                     if nodes[i].children.len() == 0 { continue 'node; }
@@ -323,18 +384,29 @@ pub fn run(nodes: &mut Vec<Node>, prelude: &Prelude, use_lookup: &UseLookup) ->
                     }
                 }
                 Kind::Add => {
+                    if nodes[i].binops.len() + 1 != nodes[i].children.len() {
+                        return Err(nodes[i].source.wrap(
+                            format!("Type mismatch (#380):\n\
+                            Missing binary operator for node when converting meta data")
+                        ))
+                    }
+
                     // Require type to be inferred from all children.
+                    let mut bin_ind = 0;
                     let mut it_ty: Option<Type> = None;
                     for &ch in &nodes[i].children {
                         if nodes[ch].item_ids() { continue 'node; }
                         if let Some(ref ty) = nodes[ch].ty {
                             it_ty = if let Some(ref it) = it_ty {
-                                match it.add(ty) {
+                                match it.add(ty, nodes[i].binops[bin_ind]) {
                                     None => return Err(nodes[ch].source.wrap(
                                         format!("Type mismatch (#400):\n\
                                             Binary operator can not be used with `{}` and `{}`",
                                             it.description(), ty.description()))),
-                                    x => x
+                                    x => {
+                                        bin_ind += 1;
+                                        x
+                                    }
                                 }
                             } else {
                                 Some(ty.clone())
@@ -465,6 +537,18 @@ pub fn run(nodes: &mut Vec<Node>, prelude: &Prelude, use_lookup: &UseLookup) ->
                     }
                     this_ty = expr_type;
                 }
+                Kind::Sum | Kind::Prod | Kind::SumIn => {
+                    // The sum or product of `i64` values is `i64`, otherwise a number.
+                    let ch = match nodes[i].find_child_by_kind(nodes, Kind::Block) {
+                        None => continue 'node,
+                        Some(ch) => ch
+                    };
+                    this_ty = match nodes[ch].ty {
+                        None => continue 'node,
+                        Some(Type::I64) => Some(Type::I64),
+                        Some(_) => Some(Type::F64)
+                    };
+                }
                 Kind::If => {
                     let tb = match nodes[i].find_child_by_kind(nodes, Kind::TrueBlock) {
                         None => continue 'node,
@@ -478,9 +562,78 @@ pub fn run(nodes: &mut Vec<Node>, prelude: &Prelude, use_lookup: &UseLookup) ->
 
                     this_ty = Some(true_type);
                 }
+                Kind::Object => {
+                    // Infer shape from the types of the values.
+                    use ty::Field;
+
+                    let mut fields = vec![];
+                    for &ch in &nodes[i].children {
+                        if nodes[ch].kind != Kind::KeyValue { continue; }
+                        let name = match nodes[ch].name() {
+                            None => continue 'node,
+                            Some(name) => name.clone()
+                        };
+                        let val = match nodes[ch].find_child_by_kind(nodes, Kind::Val) {
+                            None => continue 'node,
+                            Some(val) => val
+                        };
+                        if nodes[val].item_ids() { continue 'node; }
+                        match nodes[val].ty {
+                            None => continue 'node,
+                            Some(ref ty) => fields.push(Field {
+                                name: name,
+                                ty: ty.clone(),
+                                optional: false,
+                            })
+                        }
+                    }
+                    this_ty = Some(Type::Shape(fields));
+                }
+                Kind::Match => {
+                    // Infer type from the first arm that does not return.
+                    let mut match_ty = Type::Unreachable;
+                    for &ch in &nodes[i].children {
+                        if nodes[ch].kind != Kind::Arm { continue; }
+                        match nodes[ch].ty {
+                            None => continue 'node,
+                            Some(Type::Unreachable) => {}
+                            Some(ref ty) => {
+                                if match_ty == Type::Unreachable {
+                                    match_ty = ty.clone();
+                                }
+                            }
+                        }
+                    }
+                    this_ty = Some(match_ty);
+                }
+                Kind::Arm => {
+                    if let Some(ch) = nodes[i].find_child_by_kind(nodes, Kind::Expr) {
+                        this_ty = nodes[ch].ty.clone();
+                    }
+                }
+                Kind::Pattern | Kind::SomePattern | Kind::OkPattern |
+                Kind::ErrPattern | Kind::FieldPattern | Kind::PayloadPattern => {
+                    if nodes[i].names.len() > 0 {
+                        // Variables get the type of the matched value.
+                        this_ty = matched_type(i, nodes, &enums);
+                    } else {
+                        this_ty = pattern_type(i, nodes);
+                    }
+                }
                 Kind::Arg => {
                     this_ty = Some(Type::Any);
                 }
+                Kind::Const | Kind::Global => {
+                    // Infer type from value when there is no declared type.
+                    if let Some(ch) = nodes[i].find_child_by_kind(nodes, Kind::Expr) {
+                        if nodes[ch].ty == Some(Type::Void) {
+                            return Err(nodes[ch].source.wrap(
+                                format!("Type mismatch (#2700):\n\
+                                    Expected something, found `void`")));
+                        }
+                        this_ty = nodes[ch].ty.clone();
+                    }
+                }
                 Kind::Closure => {
                     let mut lts = vec![];
                     let mut tys = vec![];
@@ -508,7 +661,8 @@ pub fn run(nodes: &mut Vec<Node>, prelude: &Prelude, use_lookup: &UseLookup) ->
                         this_ty = Some(Type::Closure(Box::new(Dfn {
                             lts: lts,
                             tys: tys,
-                            ret: ret.unwrap()
+                            ret: ret.unwrap(),
+                            defaults: vec![]
                         })));
                     }
                 }
@@ -519,6 +673,19 @@ pub fn run(nodes: &mut Vec<Node>, prelude: &Prelude, use_lookup: &UseLookup) ->
                 changed = true;
             }
         }
+        if !changed {
+            // Objects with values of unknown type have unknown shape.
+            // Sums and products of values of unknown type are numbers.
+            for i in 0..nodes.len() {
+                if nodes[i].ty.is_some() { continue; }
+                match nodes[i].kind {
+                    Kind::Object => nodes[i].ty = Some(Type::object()),
+                    Kind::Sum | Kind::Prod | Kind::SumIn => nodes[i].ty = Some(Type::F64),
+                    _ => continue
+                }
+                changed = true;
+            }
+        }
         if !changed { break; }
     }
 
@@ -560,6 +727,47 @@ pub fn run(nodes: &mut Vec<Node>, prelude: &Prelude, use_lookup: &UseLookup) ->
                     ));
                 }
             }
+            Kind::Const | Kind::Global => {
+                // Check declared type against value.
+                if let (Some(ref ty), Some(ch)) =
+                    (nodes[i].ty.as_ref(), nodes[i].find_child_by_kind(nodes, Kind::Expr)) {
+                    if let Some(ref ch_ty) = nodes[ch].ty {
+                        if !ty.goes_with(ch_ty) {
+                            return Err(nodes[ch].source.wrap(
+                                format!("Type mismatch (#2750):\nExpected `{}`, found `{}`",
+                                    ty.description(), ch_ty.description())
+                            ));
+                        }
+                    }
+                }
+            }
+            Kind::Default => {
+                // Check declared type of argument against default value.
+                if let Some(parent) = nodes[i].parent {
+                    if let (&Some(ref ty), &Some(ref val_ty)) = (&nodes[parent].ty, &nodes[i].ty) {
+                        if !ty.goes_with(val_ty) {
+                            return Err(nodes[i].source.wrap(
+                                format!("Type mismatch (#2800):\nExpected `{}`, found `{}`",
+                                    ty.description(), val_ty.description())
+                            ));
+                        }
+                    }
+                }
+            }
+            Kind::In => {
+                // Check that an in-loop iterates over a collection.
+                if let Some(ref ty) = nodes[i].ty {
+                    if !ty.goes_with(&Type::array()) &&
+                       !ty.goes_with(&Type::object()) &&
+                       !ty.goes_with(&Type::Link) {
+                        return Err(nodes[i].source.wrap(
+                            format!("Type mismatch (#2850):\n\
+                                Expected `[]`, `{{}}` or `link`, found `{}`",
+                                ty.description())
+                        ));
+                    }
+                }
+            }
             Kind::Go => {
                 if nodes[i].children.len() > 0 {
                     if let Some(decl) = nodes[nodes[i].children[0]].declaration {
@@ -578,9 +786,33 @@ pub fn run(nodes: &mut Vec<Node>, prelude: &Prelude, use_lookup: &UseLookup) ->
             Kind::If => {
                 try!(check_if(i, nodes))
             }
+            Kind::Match => {
+                try!(check_match(i, nodes, &enums, warnings))
+            }
+            Kind::EnumValue => {
+                try!(check_enum_value(i, nodes, &enums))
+            }
+            Kind::Item => {
+                if nodes[i].item_ids() {
+                    try!(check_fields(i, nodes))
+                }
+            }
+            Kind::Pattern | Kind::SomePattern | Kind::OkPattern | Kind::ErrPattern |
+            Kind::PayloadPattern => {
+                if nodes[i].variant.is_some() {
+                    try!(check_enum_value(i, nodes, &enums));
+                }
+                if nodes[i].names.len() > 0 { continue; }
+                if let (Some(ty), Some(matched_ty)) =
+                    (pattern_type(i, nodes), matched_type(i, nodes, &enums)) {
+                    if !ty.goes_with(&matched_ty) {
+                        return Err(nodes[i].source.wrap(
+                            format!("Type mismatch (#1900):\nExpected `{}`, found `{}`",
+                                matched_ty.description(), ty.description())));
+                    }
+                }
+            }
             Kind::Assign => {
-                use ast::AssignOp;
-
                 match nodes[i].op {
                     Some(AssignOp::Add) | Some(AssignOp::Sub) => {
                         let left = nodes[i].find_child_by_kind(nodes, Kind::Left).unwrap();
@@ -643,6 +875,31 @@ pub fn run(nodes: &mut Vec<Node>, prelude: &Prelude, use_lookup: &UseLookup) ->
     Ok(())
 }
 
+/// Infers the type of values yielded by a function.
+/// Returns `None` if the type of a yielded value is not known yet.
+fn yield_type(f: usize, nodes: &[Node]) -> Option<Type> {
+    let mut ty: Option<Type> = None;
+    for (i, node) in nodes.iter().enumerate() {
+        if node.kind != Kind::Yield { continue; }
+        // Find the function or closure the yield belongs to.
+        let mut p = i;
+        while let Some(parent) = nodes[p].parent {
+            p = parent;
+            if nodes[p].kind == Kind::Fn || nodes[p].kind == Kind::Closure { break; }
+        }
+        if p != f { continue; }
+        let yield_ty = match node.children.get(0).and_then(|&ch| nodes[ch].ty.as_ref()) {
+            None => return None,
+            Some(ty) => ty,
+        };
+        ty = match ty {
+            None => Some(yield_ty.clone()),
+            Some(ty) => if &ty == yield_ty { Some(ty) } else { Some(Type::Any) },
+        };
+    }
+    Some(ty.unwrap_or(Type::Any))
+}
+
 /// Checks all returns recursively in function.
 fn check_fn(
     n: usize,
@@ -740,3 +997,328 @@ fn check_if(n: usize, nodes: &Vec<Node>) -> Result<(), Range<String>> {
 
     Ok(())
 }
+
+/// Returns the assign operator if an item is assigned to.
+fn assigned_to(n: usize, nodes: &[Node]) -> Option<(usize, AssignOp)> {
+    let left = match nodes[n].parent {
+        Some(left) if nodes[left].kind == Kind::Left => left,
+        _ => return None
+    };
+    match nodes[left].parent {
+        Some(assign) if nodes[assign].kind == Kind::Assign =>
+            nodes[assign].op.map(|op| (assign, op)),
+        _ => None
+    }
+}
+
+/// Checks the fields of objects with known shape.
+///
+/// Fields inserted with `:=` are allowed even if they are not declared.
+fn check_fields(n: usize, nodes: &Vec<Node>) -> Result<(), Range<String>> {
+    let decl = match nodes[n].declaration {
+        None => return Ok(()),
+        Some(decl) => decl
+    };
+    // Only check the declared types of arguments,
+    // since other objects can get new fields.
+    match nodes[decl].kind {
+        Kind::Arg | Kind::Current => {}
+        _ => return Ok(())
+    }
+    let extra = match nodes[n].find_child_by_kind(nodes, Kind::ItemExtra) {
+        None => return Ok(()),
+        Some(extra) => extra
+    };
+    let inserts = |name: &Arc<String>| nodes.iter().enumerate().any(|(j, node)| {
+        node.kind == Kind::Item && node.declaration == Some(decl) &&
+        assigned_to(j, nodes).map(|(_, op)| op) == Some(AssignOp::Assign) &&
+        node.find_child_by_kind(nodes, Kind::ItemExtra)
+            .and_then(|extra| nodes[extra].names.get(0)) == Some(name)
+    });
+    let assign = assigned_to(n, nodes);
+    let names = &nodes[extra].names;
+    let mut ty = match nodes[decl].ty {
+        None => return Ok(()),
+        Some(ref ty) => ty
+    };
+    for (j, name) in names.iter().enumerate() {
+        let fields = match *ty {
+            Type::Shape(ref fields) => fields,
+            _ => return Ok(())
+        };
+        if name.len() == 0 { return Ok(()); }
+        match fields.iter().find(|field| &field.name == name) {
+            Some(field) => ty = &field.ty,
+            None => {
+                let last = j + 1 == names.len();
+                if last && assign.map(|(_, op)| op) == Some(AssignOp::Assign) {
+                    return Ok(());
+                }
+                if j == 0 && inserts(name) { return Ok(()); }
+                return Err(nodes[n].source.wrap(
+                    format!("Type mismatch (#2000):\nExpected field `{}` in `{}`",
+                        name, ty.description())));
+            }
+        }
+    }
+    // Check the type of assigned value.
+    match assign {
+        Some((assign, AssignOp::Assign)) | Some((assign, AssignOp::Set)) => {
+            if let Some(right) = nodes[assign].find_child_by_kind(nodes, Kind::Right) {
+                if let Some(ref right_ty) = nodes[right].ty {
+                    if !ty.goes_with(right_ty) {
+                        return Err(nodes[right].source.wrap(
+                            format!("Type mismatch (#2100):\nExpected `{}`, found `{}`",
+                                ty.description(), right_ty.description())));
+                    }
+                }
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Returns the type of values that a pattern can match,
+/// when the pattern does not bind a variable.
+fn pattern_type(n: usize, nodes: &[Node]) -> Option<Type> {
+    for &ch in &nodes[n].children {
+        match nodes[ch].kind {
+            Kind::SomePattern => return Some(Type::option()),
+            Kind::OkPattern | Kind::ErrPattern => return Some(Type::result()),
+            _ => {}
+        }
+    }
+    nodes[n].ty.clone()
+}
+
+/// Collects enums declared in the prelude and in the source.
+fn enums(nodes: &[Node], prelude: &Prelude) -> Result<Enums, Range<String>> {
+    let mut enums = prelude.enums.clone();
+    for i in 0..nodes.len() {
+        if nodes[i].kind != Kind::Enum { continue; }
+        let name = match nodes[i].name() {
+            None => continue,
+            Some(name) => name.clone()
+        };
+        if !name.chars().next().map(|c| c.is_uppercase()).unwrap_or(false) {
+            return Err(nodes[i].source.wrap(
+                format!("Type mismatch (#2200):\n\
+                    Enum name `{}` must start with an uppercase letter", name)));
+        }
+        let mut variants: Vec<ast::Variant> = vec![];
+        for &ch in &nodes[i].children {
+            if nodes[ch].kind != Kind::Variant { continue; }
+            let variant = match nodes[ch].name() {
+                None => continue,
+                Some(variant) => variant.clone()
+            };
+            if variants.iter().any(|v| v.name == variant) {
+                return Err(nodes[ch].source.wrap(
+                    format!("Type mismatch (#2250):\n\
+                        Variant `{}::{}` is declared twice", name, variant)));
+            }
+            variants.push(ast::Variant {
+                name: variant,
+                ty: nodes[ch].ty.clone(),
+            });
+        }
+        enums.insert(name.clone(), ast::Enum {
+            name: name,
+            variants: variants,
+            source_range: nodes[i].source,
+        });
+    }
+    Ok(enums)
+}
+
+/// Checks that an enum value or pattern refers to a declared variant,
+/// with payload if the variant has one.
+fn check_enum_value(n: usize, nodes: &[Node], enums: &Enums) -> Result<(), Range<String>> {
+    let name = match nodes[n].ty {
+        Some(Type::Enum(ref name)) => name,
+        _ => return Ok(())
+    };
+    let variant = match nodes[n].variant {
+        None => return Ok(()),
+        Some(ref variant) => variant
+    };
+    let enum_decl = match enums.get(name) {
+        None => return Err(nodes[n].source.wrap(
+            format!("Type mismatch (#2300):\nUnknown enum `{}`", name))),
+        Some(enum_decl) => enum_decl
+    };
+    let ty = match enum_decl.variant(variant) {
+        None => return Err(nodes[n].source.wrap(
+            format!("Type mismatch (#2400):\nEnum `{}` has no variant `{}`", name, variant))),
+        Some(v) => &v.ty
+    };
+    let payload = nodes[n].children.iter()
+        .find(|&&ch| nodes[ch].kind == Kind::Payload || nodes[ch].kind == Kind::PayloadPattern);
+    match (ty, payload) {
+        (&Some(ref ty), None) => Err(nodes[n].source.wrap(
+            format!("Type mismatch (#2500):\nExpected payload `{}` for `{}::{}`",
+                ty.description(), name, variant))),
+        (&None, Some(&ch)) => Err(nodes[ch].source.wrap(
+            format!("Type mismatch (#2500):\nExpected no payload for `{}::{}`",
+                name, variant))),
+        (&Some(ref ty), Some(&ch)) if nodes[ch].kind == Kind::Payload => {
+            match nodes[ch].ty {
+                Some(ref ch_ty) if !ty.goes_with(ch_ty) => Err(nodes[ch].source.wrap(
+                    format!("Type mismatch (#2600):\nExpected `{}`, found `{}`",
+                        ty.description(), ch_ty.description()))),
+                _ => Ok(())
+            }
+        }
+        _ => Ok(())
+    }
+}
+
+/// Returns the type of the value matched against a pattern.
+fn matched_type(n: usize, nodes: &[Node], enums: &Enums) -> Option<Type> {
+    let parent = match nodes[n].parent {
+        None => return None,
+        Some(parent) => parent
+    };
+    match nodes[n].kind {
+        Kind::Pattern if nodes[parent].kind == Kind::Arm => {
+            let expr = nodes[parent].parent
+                .and_then(|m| nodes[m].find_child_by_kind(nodes, Kind::Expr));
+            match expr {
+                None => None,
+                Some(expr) => nodes[expr].ty.clone()
+            }
+        }
+        Kind::SomePattern => match matched_type(parent, nodes, enums) {
+            None => None,
+            Some(Type::Option(ty)) => Some(*ty),
+            Some(_) => Some(Type::Any)
+        },
+        Kind::OkPattern => match matched_type(parent, nodes, enums) {
+            None => None,
+            Some(Type::Result(ty)) => Some(*ty),
+            Some(_) => Some(Type::Any)
+        },
+        Kind::PayloadPattern => match (&nodes[parent].ty, &nodes[parent].variant) {
+            (&Some(Type::Enum(ref name)), &Some(ref variant)) => {
+                Some(enums.get(name)
+                    .and_then(|enum_decl| enum_decl.variant(variant))
+                    .and_then(|v| v.ty.clone())
+                    .unwrap_or(Type::Any))
+            }
+            _ => Some(Type::Any)
+        },
+        // Error messages and object fields can be anything.
+        _ => Some(Type::Any)
+    }
+}
+
+fn check_match(
+    n: usize,
+    nodes: &Vec<Node>,
+    enums: &Enums,
+    warnings: &mut Vec<Range<String>>
+) -> Result<(), Range<String>> {
+    // The type of matches are inferred from the first arm.
+    let match_ty = nodes[n].ty.clone();
+
+    // Whether the arms covers `some(_)`, `none()`, `ok(_)` and `err(_)`.
+    let mut is_opt = false;
+    let mut is_res = false;
+    match nodes[n].find_child_by_kind(nodes, Kind::Expr).and_then(|ch| nodes[ch].ty.as_ref()) {
+        Some(&Type::Option(_)) => is_opt = true,
+        Some(&Type::Result(_)) => is_res = true,
+        _ => {}
+    }
+    let mut some = false;
+    let mut none = false;
+    let mut ok = false;
+    let mut err = false;
+    let mut irrefutable = false;
+    // The enum matched by patterns and the variants covered by arms.
+    let mut enum_name: Option<Arc<String>> = None;
+    let mut variants: Vec<Arc<String>> = vec![];
+
+    for &arm in &nodes[n].children {
+        if nodes[arm].kind != Kind::Arm { continue; }
+        let guard = nodes[arm].find_child_by_kind(nodes, Kind::Guard);
+        if let Some(ch) = guard {
+            if let Some(ref guard_ty) = nodes[ch].ty {
+                if !Type::Bool.goes_with(guard_ty) {
+                    return Err(nodes[ch].source.wrap(
+                        format!("Type mismatch (#1850):\nExpected `{}`, found `{}`",
+                            Type::Bool.description(), guard_ty.description())));
+                }
+            }
+        }
+        if let (&Some(ref arm_ty), &Some(ref match_ty)) = (&nodes[arm].ty, &match_ty) {
+            if !arm_ty.goes_with(match_ty) {
+                return Err(nodes[arm].source.wrap(
+                    format!("Type mismatch (#1800):\nExpected `{}`, found `{}`",
+                        match_ty.description(), arm_ty.description())));
+            }
+        }
+
+        let pattern = match nodes[arm].find_child_by_kind(nodes, Kind::Pattern) {
+            None => continue,
+            Some(pattern) => pattern
+        };
+        for &ch in &nodes[pattern].children {
+            match nodes[ch].kind {
+                Kind::SomePattern => {
+                    is_opt = true;
+                    if guard.is_none() && nodes[ch].names.len() > 0 { some = true; }
+                }
+                Kind::OkPattern => {
+                    is_res = true;
+                    if guard.is_none() && nodes[ch].names.len() > 0 { ok = true; }
+                }
+                Kind::ErrPattern => {
+                    is_res = true;
+                    if guard.is_none() && nodes[ch].names.len() > 0 { err = true; }
+                }
+                _ => {}
+            }
+        }
+        if nodes[pattern].children.len() == 0 &&
+           nodes[pattern].ty == Some(Type::option()) &&
+           nodes[pattern].names.len() == 0 {
+            is_opt = true;
+            if guard.is_none() { none = true; }
+        }
+        if let (&Some(Type::Enum(ref name)), &Some(ref variant)) =
+            (&nodes[pattern].ty, &nodes[pattern].variant) {
+            enum_name = Some(name.clone());
+            let payload = nodes[pattern].find_child_by_kind(nodes, Kind::PayloadPattern);
+            if guard.is_none() && payload.map(|p| nodes[p].names.len() > 0).unwrap_or(true) {
+                variants.push(variant.clone());
+            }
+        }
+        if guard.is_none() && nodes[pattern].names.len() > 0 { irrefutable = true; }
+    }
+
+    if irrefutable { return Ok(()); }
+    let missing: Option<String> = if is_opt && !some { Some("some(_)".into()) }
+        else if is_opt && !none { Some("none()".into()) }
+        else if is_res && !ok { Some("ok(_)".into()) }
+        else if is_res && !err { Some("err(_)".into()) }
+        else { None };
+    let missing = missing.or_else(|| {
+        let enum_decl = match enum_name.as_ref().and_then(|name| enums.get(name)) {
+            None => return None,
+            Some(enum_decl) => enum_decl
+        };
+        enum_decl.variants.iter().find(|v| !variants.contains(&v.name)).map(|v| {
+            if v.ty.is_some() {
+                format!("{}::{}(_)", enum_decl.name, v.name)
+            } else {
+                format!("{}::{}", enum_decl.name, v.name)
+            }
+        })
+    });
+    if let Some(missing) = missing {
+        warnings.push(nodes[n].source.wrap(
+            format!("Non-exhaustive match:\nMissing arm for `{}`", missing)));
+    }
+    Ok(())
+}
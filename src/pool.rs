@@ -0,0 +1,281 @@
+//! Thread pool for running `go` calls and parallel loops.
+//!
+//! The pool is owned by the embedder and set with `Runtime::pool`.
+//! Without a pool, each task runs on a new thread.
+//!
+//! Joining a task that has not started yet runs it on the joining thread,
+//! such that tasks waiting for other tasks can not use up all the workers.
+//! A runtime blocked in `join`, `wait_all`, `recv`, `select` or `sleep`
+//! also runs queued tasks while waiting, such that a task blocking on
+//! another task that is not started does not deadlock a small pool.
+//! Blocking outside the runtime, e.g. in an external function,
+//! still holds on to the worker.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use Variable;
+use stack_guard;
+
+/// A job returning the result of a task.
+pub type Job = Box<FnOnce() -> Result<Variable, String> + Send>;
+
+enum State {
+    /// Waiting to be run.
+    Pending(Job),
+    /// Running on some thread.
+    Running,
+    /// Running on a thread started outside the pool,
+    /// sending the result when completed.
+    Handle(Receiver<Result<Variable, String>>),
+    /// Completed, waiting to be joined.
+    Done(Result<Variable, String>),
+    /// The result is taken.
+    Joined,
+}
+
+/// A task running a job on a thread pool or its own thread.
+pub struct Task {
+    state: Mutex<State>,
+    done: Condvar,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl Task {
+    /// Creates a new task that is not started.
+    ///
+    /// The job should stop when the `cancelled` flag is set.
+    pub fn new(job: Job, cancelled: Arc<AtomicBool>) -> Task {
+        Task {
+            state: Mutex::new(State::Pending(job)),
+            done: Condvar::new(),
+            cancelled: cancelled,
+        }
+    }
+
+    /// Creates a task from a thread that is already started.
+    pub fn from_handle(handle: JoinHandle<Result<Variable, String>>) -> Task {
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let _ = tx.send(match handle.join() {
+                Ok(res) => res,
+                Err(_) => Err("Thread did not exit successfully".into())
+            });
+        });
+        Task {
+            state: Mutex::new(State::Handle(rx)),
+            done: Condvar::new(),
+            cancelled: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Runs the job if it is not started yet.
+    pub fn run(&self) {
+        use std::mem::replace;
+
+        let job = {
+            let mut state = self.state.lock().unwrap();
+            match replace(&mut *state, State::Running) {
+                State::Pending(job) => job,
+                x => {
+                    *state = x;
+                    return;
+                }
+            }
+        };
+        let res = match panic::catch_unwind(AssertUnwindSafe(job)) {
+            Ok(res) => res,
+            Err(_) => Err("Thread did not exit successfully".into())
+        };
+        *self.state.lock().unwrap() = State::Done(res);
+        self.done.notify_all();
+    }
+
+    /// Waits for the task to complete and takes the result.
+    ///
+    /// Runs the job on the current thread if it is not started yet.
+    pub fn join(&self) -> Result<Variable, String> {
+        self.wait(None, true).expect("Expected result when waiting without timeout")
+    }
+
+    /// Waits for the task to complete and takes the result,
+    /// or returns `None` if the task is still running after the timeout.
+    ///
+    /// Runs the job on the current thread if it is not started yet.
+    pub fn join_timeout(&self, timeout: Duration) -> Option<Result<Variable, String>> {
+        self.wait(Some(timeout), true)
+    }
+
+    /// Waits for the task to complete and takes the result,
+    /// or returns `None` if the task is not completed after the timeout.
+    ///
+    /// Unlike `join_timeout`, a task that is not started is left to the pool.
+    pub fn wait_timeout(&self, timeout: Duration) -> Option<Result<Variable, String>> {
+        self.wait(Some(timeout), false)
+    }
+
+    fn wait(&self, timeout: Option<Duration>, run: bool) -> Option<Result<Variable, String>> {
+        use std::mem::replace;
+
+        if run { self.run(); }
+        let mut state = self.state.lock().unwrap();
+        let mut timed_out = false;
+        loop {
+            match replace(&mut *state, State::Joined) {
+                x @ State::Pending(_) | x @ State::Running => {
+                    *state = x;
+                    if timed_out { return None; }
+                    match timeout {
+                        None => state = self.done.wait(state).unwrap(),
+                        Some(timeout) => {
+                            let (s, res) = self.done.wait_timeout(state, timeout).unwrap();
+                            state = s;
+                            timed_out = res.timed_out();
+                        }
+                    }
+                }
+                State::Handle(rx) => {
+                    let res = match timeout {
+                        None => rx.recv().ok(),
+                        Some(timeout) => match rx.recv_timeout(timeout) {
+                            Ok(res) => Some(res),
+                            Err(RecvTimeoutError::Timeout) => {
+                                *state = State::Handle(rx);
+                                return None;
+                            }
+                            Err(RecvTimeoutError::Disconnected) => None,
+                        }
+                    };
+                    return Some(res.unwrap_or_else(||
+                        Err("Thread did not exit successfully".into())));
+                }
+                State::Done(res) => return Some(res),
+                State::Joined => return Some(Err("The Thread has already been joined".into())),
+            }
+        }
+    }
+
+    /// Cancels the task.
+    ///
+    /// A task that is not started never runs.
+    /// A running task stops with an error at the next safe point.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+        let mut state = self.state.lock().unwrap();
+        if let State::Pending(_) = *state {
+            *state = State::Done(Err("Thread was cancelled".into()));
+            self.done.notify_all();
+        }
+    }
+}
+
+impl fmt::Debug for Task {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "task")
+    }
+}
+
+struct Queue {
+    tasks: VecDeque<Arc<Task>>,
+    stopped: bool,
+}
+
+struct Inner {
+    queue: Mutex<Queue>,
+    cond: Condvar,
+}
+
+/// Stops the workers when the last handle to the pool is dropped.
+struct Stop(Arc<Inner>);
+
+impl Drop for Stop {
+    fn drop(&mut self) {
+        self.0.queue.lock().unwrap().stopped = true;
+        self.0.cond.notify_all();
+    }
+}
+
+/// A fixed number of worker threads running tasks.
+///
+/// Clones refer to the same pool.
+/// The workers complete the remaining tasks and exit when the pool is dropped.
+#[derive(Clone)]
+pub struct ThreadPool {
+    inner: Arc<Inner>,
+    _stop: Arc<Stop>,
+}
+
+impl ThreadPool {
+    /// Creates a new pool with a number of worker threads.
+    pub fn new(threads: usize) -> ThreadPool {
+        let inner = Arc::new(Inner {
+            queue: Mutex::new(Queue {
+                tasks: VecDeque::new(),
+                stopped: false,
+            }),
+            cond: Condvar::new(),
+        });
+        for _ in 0..threads {
+            let inner = inner.clone();
+            stack_guard::spawn(move || {
+                loop {
+                    let task = {
+                        let mut queue = inner.queue.lock().unwrap();
+                        loop {
+                            if let Some(task) = queue.tasks.pop_front() { break task; }
+                            if queue.stopped { return; }
+                            queue = inner.cond.wait(queue).unwrap();
+                        }
+                    };
+                    task.run();
+                }
+            });
+        }
+        ThreadPool {
+            _stop: Arc::new(Stop(inner.clone())),
+            inner: inner,
+        }
+    }
+
+    /// Schedules a task to run on the pool.
+    pub fn spawn(&self, task: Arc<Task>) {
+        self.inner.queue.lock().unwrap().tasks.push_back(task);
+        self.inner.cond.notify_one();
+    }
+
+    /// Runs a queued task on the current thread.
+    ///
+    /// Returns `false` if there are no queued tasks.
+    pub fn run_pending(&self) -> bool {
+        let task = self.inner.queue.lock().unwrap().tasks.pop_front();
+        match task {
+            Some(task) => {
+                task.run();
+                true
+            }
+            None => false
+        }
+    }
+}
+
+impl fmt::Debug for ThreadPool {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "thread pool")
+    }
+}
+
+/// Runs a task on the pool, or on a new thread when there is no pool.
+pub fn spawn(pool: Option<&ThreadPool>, task: Arc<Task>) {
+    match pool {
+        Some(pool) => pool.spawn(task),
+        None => {
+            stack_guard::spawn(move || task.run());
+        }
+    }
+}
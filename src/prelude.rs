@@ -3,8 +3,10 @@ use std::collections::HashMap;
 
 use ast;
 use intrinsics;
+use Capabilities;
 use Module;
 use Type;
+use Variable;
 
 /// Argument lifetime constraint.
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
@@ -14,6 +16,40 @@ pub enum Lt {
     Default,
 }
 
+/// Default value of an argument that can be omitted.
+#[derive(Clone, PartialEq, Debug)]
+pub enum DefaultValue {
+    F64(f64),
+    I64(i64),
+    Bool(bool),
+    Text(Arc<String>),
+    Vec4([f32; 4]),
+}
+
+impl DefaultValue {
+    /// Returns the default value of a number, bool, string or vec4.
+    pub fn from_variable(v: &Variable) -> Option<DefaultValue> {
+        match *v {
+            Variable::F64(val, None) => Some(DefaultValue::F64(val)),
+            Variable::I64(val) => Some(DefaultValue::I64(val)),
+            Variable::Bool(val, None) => Some(DefaultValue::Bool(val)),
+            Variable::Text(ref val) => Some(DefaultValue::Text(val.clone())),
+            Variable::Vec4(val) => Some(DefaultValue::Vec4(val)),
+            _ => None
+        }
+    }
+
+    pub fn to_variable(&self) -> Variable {
+        match *self {
+            DefaultValue::F64(val) => Variable::f64(val),
+            DefaultValue::I64(val) => Variable::I64(val),
+            DefaultValue::Bool(val) => Variable::bool(val),
+            DefaultValue::Text(ref val) => Variable::Text(val.clone()),
+            DefaultValue::Vec4(val) => Variable::Vec4(val),
+        }
+    }
+}
+
 /// Stores preloaded function constraints.
 /// These are already checked.
 #[derive(Clone, PartialEq, Debug)]
@@ -21,6 +57,8 @@ pub struct Dfn {
     pub lts: Vec<Lt>,
     pub tys: Vec<Type>,
     pub ret: Type,
+    /// Default values of the last arguments, which calls can omit.
+    pub defaults: Vec<DefaultValue>,
 }
 
 impl Dfn {
@@ -45,20 +83,82 @@ impl Dfn {
                 lts.push(Lt::Default);
             }
         }
+        let n = f.args.iter().rev().take_while(|arg| arg.default.is_some()).count();
         Dfn {
             lts: lts,
             tys: tys,
             ret: f.ret.clone(),
+            defaults: f.args[f.args.len() - n..].iter()
+                .filter_map(|arg| arg.default.clone())
+                .collect(),
         }
     }
 
     pub fn returns(&self) -> bool { self.ret != Type::Void }
+
+    /// Returns `true` if the function can be called with `n` arguments,
+    /// using default values for the omitted trailing arguments.
+    pub fn accepts(&self, n: usize) -> bool {
+        n <= self.tys.len() && self.tys.len() - n <= self.defaults.len()
+    }
+}
+
+/// Returns `true` if a call can omit trailing arguments of a function with `args` arguments.
+///
+/// A named call may leave out trailing words,
+/// e.g. `season(players: a)` calls `season__players_goals` when `goals` has a default value.
+/// The words of the call must be the first `n` arguments of the function,
+/// so arguments can only be omitted when the function name has one word per argument.
+/// The mutability of the `n` passed arguments must match the declaration.
+pub fn omits_args(call: &str, name: &str, args: usize, n: usize) -> bool {
+    fn split(name: &str) -> (&str, Option<&str>) {
+        match name.find('(') {
+            None => (name, None),
+            Some(i) => (&name[..i], Some(&name[i + 1..name.len() - 1]))
+        }
+    }
+
+    // Splits function name from argument words.
+    fn words(name: &str) -> (&str, Vec<&str>) {
+        match name.find("__") {
+            None => (name, vec![]),
+            Some(i) => (&name[..i], name[i + 2..].split('_').collect())
+        }
+    }
+
+    let (call_base, call_mut) = split(call);
+    let (base, mutable) = split(name);
+    if call_base != base {
+        let (call_f, call_words) = words(call_base);
+        let (f, f_words) = words(base);
+        if !call_base.contains("__") || call_f != f || n > args ||
+           call_words.len() != n || f_words.len() != args ||
+           call_words[..] != f_words[..n] { return false; }
+    }
+    let mutable: Vec<&str> = match mutable {
+        None => vec![],
+        Some(mutable) => mutable.split(',').take(n).collect()
+    };
+    let call_mut: Vec<&str> = match call_mut {
+        None => vec![],
+        Some(call_mut) => call_mut.split(',').collect()
+    };
+    let immutable = |muts: &[&str]| muts.iter().all(|&m| m == "_");
+    mutable == call_mut ||
+    call_mut.len() == 0 && immutable(&mutable) ||
+    mutable.len() == 0 && immutable(&call_mut)
 }
 
 pub struct Prelude {
     pub functions: HashMap<Arc<String>, usize>,
     pub list: Vec<Dfn>,
     pub namespaces: Vec<(Arc<Vec<Arc<String>>>, Arc<String>)>,
+    /// Capabilities allowed for intrinsics.
+    pub capabilities: Arc<Capabilities>,
+    /// Enums declared in loaded sources.
+    pub enums: HashMap<Arc<String>, ast::Enum>,
+    /// Mutability and type of constants and globals declared in loaded sources.
+    pub globals: HashMap<Arc<String>, (bool, Type)>,
 }
 
 impl Prelude {
@@ -82,6 +182,9 @@ impl Prelude {
             functions: HashMap::new(),
             list: vec![],
             namespaces: vec![],
+            capabilities: Arc::new(Capabilities::all()),
+            enums: HashMap::new(),
+            globals: HashMap::new(),
         }
     }
 
@@ -94,12 +197,19 @@ impl Prelude {
     pub fn from_module(module: &Module) -> Prelude {
         let mut prelude = Prelude::new();
         intrinsics::standard(&mut prelude);
-        for f in &*module.ext_prelude {
+        prelude.capabilities = module.capabilities.clone();
+        for f in &module.ext_prelude {
             prelude.insert(Arc::new(vec![]), f.name.clone(), f.p.clone());
         }
         for f in &module.functions {
             prelude.insert(f.namespace.clone(), f.name.clone(), Dfn::new(f));
         }
+        for e in &module.enums {
+            prelude.enums.insert(e.name.clone(), e.clone());
+        }
+        for g in &module.globals {
+            prelude.globals.insert(g.name.clone(), (g.mutable, g.init.ret.clone()));
+        }
         prelude
     }
 }
@@ -0,0 +1,272 @@
+//! Profiler support.
+//!
+//! Records call counts and time spent in functions and loops.
+//! While a profiler is attached, functions compiled to bytecode
+//! run with instructions that time their loops.
+//! Threads started by `go` record to the profiler of the runtime starting them.
+
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use runtime::Call;
+
+/// Statistics of a function.
+#[derive(Debug, Clone)]
+pub struct FnStats {
+    pub calls: u64,
+    /// Time spent in the function, including calls to other functions.
+    ///
+    /// Recursive calls are counted once.
+    pub inclusive: Duration,
+    /// Time spent in the function, excluding calls to other functions.
+    pub exclusive: Duration,
+}
+
+/// Statistics of a loop.
+#[derive(Debug, Clone)]
+pub struct LoopStats {
+    /// The line number, starting at 1.
+    pub line: usize,
+    /// The column number in bytes, starting at 1.
+    pub column: usize,
+    /// Number of times the loop was executed.
+    pub runs: u64,
+    /// Time spent in the loop.
+    pub time: Duration,
+}
+
+/// Recorded profiling data.
+#[derive(Debug, Clone, Default)]
+pub struct Profile {
+    /// Statistics by function name and file.
+    pub functions: HashMap<(Arc<String>, Option<Arc<String>>), FnStats>,
+    /// Statistics by file and offset of loop.
+    pub loops: HashMap<(Arc<String>, usize), LoopStats>,
+    /// Exclusive time by function names on the call stack, outermost first.
+    pub stacks: HashMap<Vec<Arc<String>>, Duration>,
+}
+
+impl Profile {
+    /// Writes a report with the slowest functions and loops first.
+    pub fn write_text<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let mut functions: Vec<_> = self.functions.iter().collect();
+        functions.sort_by(|a, b| b.1.exclusive.cmp(&a.1.exclusive));
+        try!(writeln!(w, "{:>10} {:>14} {:>14}  function", "calls", "inclusive ms", "exclusive ms"));
+        for &(&(ref name, ref file), stats) in &functions {
+            try!(write!(w, "{:>10} {:>14.3} {:>14.3}  {}", stats.calls,
+                        millis(stats.inclusive), millis(stats.exclusive), name));
+            if let Some(ref file) = *file {
+                try!(write!(w, " ({})", file));
+            }
+            try!(writeln!(w, ""));
+        }
+
+        let mut loops: Vec<_> = self.loops.iter().collect();
+        loops.sort_by(|a, b| b.1.time.cmp(&a.1.time));
+        try!(writeln!(w, ""));
+        try!(writeln!(w, "{:>10} {:>14}  loop", "runs", "time ms"));
+        for &(&(ref file, _), stats) in &loops {
+            try!(writeln!(w, "{:>10} {:>14.3}  {}:{}:{}", stats.runs, millis(stats.time),
+                          file, stats.line, stats.column));
+        }
+        Ok(())
+    }
+
+    /// Writes exclusive time in microseconds by call stack,
+    /// in the folded format used by flame graph tools.
+    pub fn write_folded<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let mut stacks: Vec<(String, u64)> = self.stacks.iter().map(|(names, &time)| {
+            let names: Vec<&str> = names.iter().map(|name| &***name).collect();
+            (names.join(";"), micros(time))
+        }).collect();
+        stacks.sort();
+        for &(ref stack, time) in &stacks {
+            try!(writeln!(w, "{} {}", stack, time));
+        }
+        Ok(())
+    }
+}
+
+fn millis(d: Duration) -> f64 {
+    d.as_secs() as f64 * 1000.0 + d.subsec_nanos() as f64 / 1_000_000.0
+}
+
+fn micros(d: Duration) -> u64 {
+    d.as_secs() * 1_000_000 + (d.subsec_nanos() / 1000) as u64
+}
+
+/// Records profiling data, shared with threads started by `go`.
+#[derive(Clone, Debug, Default)]
+pub struct Profiler {
+    profile: Arc<Mutex<Profile>>,
+}
+
+impl Profiler {
+    /// Creates a new profiler.
+    pub fn new() -> Profiler { Profiler::default() }
+
+    /// Returns a copy of the recorded data.
+    pub fn profile(&self) -> Profile {
+        self.profile.lock().unwrap().clone()
+    }
+
+    /// Removes the recorded data.
+    pub fn clear(&self) {
+        *self.profile.lock().unwrap() = Profile::default();
+    }
+
+    /// Writes a report with the slowest functions and loops first.
+    pub fn write_text<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        self.profile.lock().unwrap().write_text(w)
+    }
+
+    /// Writes exclusive time in microseconds by call stack,
+    /// in the folded format used by flame graph tools.
+    pub fn write_folded<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        self.profile.lock().unwrap().write_folded(w)
+    }
+
+    fn record_call(
+        &self,
+        key: (Arc<String>, Option<Arc<String>>),
+        stack: Vec<Arc<String>>,
+        inclusive: Option<Duration>,
+        exclusive: Duration
+    ) {
+        let mut profile = self.profile.lock().unwrap();
+        {
+            let stats = profile.functions.entry(key).or_insert(FnStats {
+                calls: 0,
+                inclusive: Duration::new(0, 0),
+                exclusive: Duration::new(0, 0),
+            });
+            stats.calls += 1;
+            if let Some(inclusive) = inclusive { stats.inclusive += inclusive; }
+            stats.exclusive += exclusive;
+        }
+        *profile.stacks.entry(stack).or_insert(Duration::new(0, 0)) += exclusive;
+    }
+
+    fn record_loop(
+        &self,
+        file: Arc<String>,
+        source: &str,
+        offset: usize,
+        time: Option<Duration>
+    ) {
+        let mut profile = self.profile.lock().unwrap();
+        let stats = profile.loops.entry((file, offset)).or_insert_with(|| {
+            let before = &source[..offset.min(source.len())];
+            let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+            LoopStats {
+                line: before.matches('\n').count() + 1,
+                column: offset - line_start + 1,
+                runs: 0,
+                time: Duration::new(0, 0),
+            }
+        });
+        stats.runs += 1;
+        if let Some(time) = time { stats.time += time; }
+    }
+}
+
+/// Per-thread state of functions and loops being profiled.
+#[derive(Debug, Default)]
+pub struct Stack {
+    /// Start time and time spent in calls, for each call on the call stack.
+    frames: Vec<Option<(Instant, Duration)>>,
+    /// Active loops.
+    loops: Vec<Loop>,
+}
+
+/// A loop being profiled.
+#[derive(Debug)]
+struct Loop {
+    file: Arc<String>,
+    source: Arc<String>,
+    offset: usize,
+    /// Length of the call stack when entering the loop.
+    depth: usize,
+    start: Instant,
+}
+
+impl Stack {
+    /// Called after pushing a call to the call stack.
+    pub fn enter(&mut self, call_stack: &[Call]) {
+        self.frames.resize(call_stack.len() - 1, None);
+        self.frames.push(Some((Instant::now(), Duration::new(0, 0))));
+    }
+
+    /// Called before popping a call from the call stack.
+    ///
+    /// Exits the loops that are left by returning from the function.
+    pub fn exit(&mut self, profiler: &Profiler, call_stack: &[Call]) {
+        let n = call_stack.len();
+        while self.loops.last().map(|lp| lp.depth >= n).unwrap_or(false) {
+            let lp = self.loops.pop().unwrap();
+            self.record_loop(profiler, lp);
+        }
+        self.frames.truncate(n);
+        if self.frames.len() < n { return; }
+        let (start, children) = match self.frames.pop() {
+            Some(Some(frame)) => frame,
+            _ => return,
+        };
+        let inclusive = start.elapsed();
+        let exclusive = inclusive.checked_sub(children).unwrap_or(Duration::new(0, 0));
+        if let Some(&mut Some((_, ref mut parent_children))) = self.frames.last_mut() {
+            *parent_children += inclusive;
+        }
+        let call = &call_stack[n - 1];
+        let recursive = call_stack[..n - 1].iter()
+            .any(|c| c.fn_name == call.fn_name && c.file == call.file);
+        profiler.record_call(
+            (call.fn_name.clone(), call.file.clone()),
+            call_stack.iter().map(|c| c.fn_name.clone()).collect(),
+            if recursive { None } else { Some(inclusive) },
+            exclusive
+        );
+    }
+
+    /// Called before executing a loop in the function on top of the call stack.
+    pub fn enter_loop(
+        &mut self,
+        file: &Arc<String>,
+        source: &Arc<String>,
+        offset: usize,
+        call_stack: &[Call]
+    ) {
+        self.loops.push(Loop {
+            file: file.clone(),
+            source: source.clone(),
+            offset: offset,
+            depth: call_stack.len(),
+            start: Instant::now(),
+        });
+    }
+
+    /// Called after executing a loop in the function on top of the call stack.
+    ///
+    /// Exits the loops inside it that are left by `break` or `continue` to this loop.
+    pub fn exit_loop(&mut self, profiler: &Profiler, offset: usize, call_stack: &[Call]) {
+        let n = call_stack.len();
+        while let Some(lp) = self.loops.pop() {
+            if lp.depth < n {
+                self.loops.push(lp);
+                break;
+            }
+            let found = lp.depth == n && lp.offset == offset;
+            self.record_loop(profiler, lp);
+            if found { break; }
+        }
+    }
+
+    fn record_loop(&self, profiler: &Profiler, lp: Loop) {
+        let time = lp.start.elapsed();
+        let recursive = self.loops.iter().any(|x| x.offset == lp.offset && x.file == lp.file);
+        profiler.record_loop(lp.file, &lp.source, lp.offset,
+                             if recursive { None } else { Some(time) });
+    }
+}
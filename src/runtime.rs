@@ -1,12 +1,27 @@
 use std::sync::Arc;
-use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
 use rand;
 use range::Range;
 
 use ast;
+use bytecode;
+use debugger::{self, Debugger};
+use profiler::{self, Profiler};
+use coverage::Coverage;
+use coroutine::Coroutine;
+use shared::Shared;
+use pool::{self, Task, ThreadPool};
+use stack_guard;
 use intrinsics;
 use embed;
 
+use Capabilities;
+use Capability;
+use ClosureEnvironment;
+use Dfn;
+use EnumVariant;
 use FnIndex;
 use Module;
 use Variable;
@@ -31,6 +46,137 @@ pub enum Flow {
     Break(Option<Arc<String>>),
     /// Continue loop, with optional label.
     ContinueLoop(Option<Arc<String>>),
+    /// Suspends the runtime, keeping the stacks to resume later.
+    Suspend,
+}
+
+/// Tells why a call from the outside stopped running.
+#[derive(Debug)]
+pub enum Status {
+    /// The function returned, with value if any.
+    Done(Option<Variable>),
+    /// Yielded a value with `yield`.
+    Yielded(Variable),
+    /// Suspended by an external function or the host.
+    Suspended,
+    /// Ran out of fuel, which is added with `add_fuel` before resuming.
+    OutOfFuel,
+}
+
+/// Called when running out of fuel.
+///
+/// Returns more fuel to continue without stopping,
+/// or `None` to suspend or stop with an error.
+pub type OutOfFuel = Arc<Fn() -> Option<usize> + Send + Sync>;
+
+/// Number of steps taken from the shared fuel at a time.
+///
+/// This is also the number of steps between checking for interrupts.
+const FUEL_CHUNK: usize = 1024;
+
+/// Milliseconds between checking for interrupts while waiting in `sleep`,
+/// `recv`, `select` and `join`.
+const WAIT_SLICE_MS: u64 = 10;
+
+/// Interrupts or suspends a runtime from another thread.
+///
+/// The runtime and threads started by `go` stop at the next safe point
+/// with an error containing `Interrupted`.
+/// Waiting in `sleep`, `recv`, `select` and `join` also stops.
+#[derive(Clone, Debug, Default)]
+pub struct InterruptHandle {
+    flag: Arc<AtomicBool>,
+    suspend: Arc<AtomicBool>,
+}
+
+impl InterruptHandle {
+    /// Interrupts the runtime.
+    pub fn interrupt(&self) {
+        self.flag.store(true, Ordering::SeqCst);
+    }
+
+    /// Returns `true` if the runtime is interrupted.
+    pub fn is_interrupted(&self) -> bool {
+        self.flag.load(Ordering::SeqCst)
+    }
+
+    /// Clears the interrupt, such that the runtime can run again.
+    pub fn clear(&self) {
+        self.flag.store(false, Ordering::SeqCst);
+    }
+
+    /// Requests the runtime to suspend at the next safe point, e.g. inside a loop.
+    ///
+    /// This can be used to limit the time spent per frame.
+    /// The call returns `Status::Suspended` and continues with `Runtime::resume`.
+    /// The request waits while the runtime can not be suspended,
+    /// e.g. in a closure or a call made by an external function.
+    pub fn suspend(&self) {
+        self.suspend.store(true, Ordering::SeqCst);
+    }
+
+    /// Returns `true` if suspending is requested, and clears the request.
+    fn take_suspend(&self) -> bool {
+        self.suspend.swap(false, Ordering::SeqCst)
+    }
+
+    /// Returns a handle sharing the interrupt, with its own request to suspend.
+    fn with_own_suspend(&self) -> InterruptHandle {
+        InterruptHandle {
+            flag: self.flag.clone(),
+            suspend: Arc::new(AtomicBool::new(false)),
+        }
+    }
+}
+
+/// Limits on recursion and memory, `None` for no limit.
+///
+/// Independent of the limits, calls stop with an error when the native stack
+/// is about to overflow, in threads spawned by Dyon and on Linux.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Limits {
+    /// Maximum number of function calls on the call stack.
+    pub call_depth: Option<usize>,
+    /// Maximum number of variables on the stack.
+    pub stack: Option<usize>,
+    /// Approximate maximum number of bytes used by arrays, objects,
+    /// strings and links.
+    ///
+    /// This is measured from the variables on the stack of each runtime,
+    /// such that every thread has its own budget.
+    /// Values only held by closures, threads, channels, shared values,
+    /// coroutines or Rust objects are not counted.
+    pub heap: Option<usize>,
+}
+
+/// A function compiled to bytecode, suspended at an instruction.
+#[derive(Debug, Clone, Copy)]
+struct Frame {
+    /// The index of the function in module.
+    index: usize,
+    /// Whether the function runs with instructions for the debugger, profiler and coverage.
+    hooks: bool,
+    /// The next instruction, or the call that is suspended.
+    pc: usize,
+    /// Length of the operand stack when called.
+    obase: usize,
+    /// Start of the function frame on the stack.
+    sbase: usize,
+    /// Start of the function frame on the local stack.
+    lbase: usize,
+    /// Length of the current stack when called.
+    cbase: usize,
+}
+
+/// A call from the outside that is suspended.
+struct Suspended {
+    call: ast::Call,
+    /// The module the call is suspended in.
+    module: usize,
+    /// Length of the stack before the globals were pushed.
+    stack_len: usize,
+    /// Position of the globals before the call.
+    globals: usize,
 }
 
 #[derive(Debug)]
@@ -53,10 +199,70 @@ pub struct Runtime {
     pub call_stack: Vec<Call>,
     pub local_stack: Vec<(Arc<String>, usize)>,
     pub current_stack: Vec<(Arc<String>, usize)>,
+    /// Position on the stack of the constants and globals of the running module.
+    pub globals: usize,
+    /// Values of the globals of modules run by this runtime,
+    /// kept between calls from the outside.
+    /// The modules are identified by `Module::id`.
+    pub module_globals: Vec<(usize, Vec<Variable>)>,
+    /// Operands of functions compiled to bytecode.
+    pub operands: Vec<Option<Variable>>,
+    /// Fuel shared with threads started by `go`, `None` for no limit.
+    pub fuel: Option<Arc<AtomicUsize>>,
+    /// Steps left before taking more fuel.
+    pub steps: usize,
+    /// Called when running out of fuel.
+    pub out_of_fuel: Option<OutOfFuel>,
+    /// Shared with threads started by `go`.
+    pub interrupt: InterruptHandle,
+    /// Set when the thread started by `go` running on this runtime is cancelled.
+    pub cancelled: Option<Arc<AtomicBool>>,
+    /// Limits on recursion and memory.
+    pub limits: Limits,
+    /// Maximum number of worker threads used by parallel loops.
+    pub workers: usize,
+    /// Runs threads started by `go` and parallel loops,
+    /// `None` to start a new thread for each.
+    pub pool: Option<ThreadPool>,
+    /// Approximate bytes used, measured from the stack
+    /// when exceeding the heap limit.
+    pub heap: usize,
+    /// Bytes of arrays being built by `sift` loops, which are not on the stack yet.
+    pub building: usize,
+    /// Capabilities of modules calling into other modules with `call` or `call_ret`.
+    pub caller_capabilities: Vec<Arc<Capabilities>>,
+    /// Pauses execution at breakpoints and when stepping.
+    pub debugger: Option<Debugger>,
+    /// Records time spent in functions and loops, shared with threads started by `go`.
+    pub profiler: Option<Profiler>,
+    /// Functions and loops being profiled.
+    pub profile_stack: profiler::Stack,
+    /// Records executed expressions, blocks and branches, shared with threads started by `go`.
+    pub coverage: Option<Coverage>,
+    /// Set for the runtime of a coroutine, which is suspended by `yield`.
+    coroutine: bool,
+    /// Set by `suspend` to suspend when the external function returns.
+    pub suspend_requested: bool,
+    /// Number of calls on the native stack that can not be suspended,
+    /// e.g. closures or expressions evaluated by the tree-walking interpreter.
+    /// The runtime suspends only when this is zero.
+    pinned: usize,
+    /// Set while a call from the outside runs.
+    running: bool,
+    /// Frames being suspended from inner to outer,
+    /// such that resuming takes the outer frame first.
+    frames: Vec<Frame>,
+    /// Why the runtime is being suspended.
+    suspending: Option<Status>,
+    /// The call from the outside that is suspended, if any.
+    suspended: Option<Suspended>,
+    /// Shared values being updated by this runtime.
+    pub updating: Vec<Shared>,
     pub ret: Arc<String>,
     pub rng: rand::StdRng,
     pub text_type: Variable,
     pub f64_type: Variable,
+    pub i64_type: Variable,
     pub vec4_type: Variable,
     pub return_type: Variable,
     pub bool_type: Variable,
@@ -69,6 +275,9 @@ pub struct Runtime {
     pub option_type: Variable,
     pub result_type: Variable,
     pub thread_type: Variable,
+    pub coroutine_type: Variable,
+    pub channel_type: Variable,
+    pub shared_type: Variable,
     pub closure_type: Variable,
 }
 
@@ -80,6 +289,103 @@ fn resolve<'a>(stack: &'a Vec<Variable>, var: &'a Variable) -> &'a Variable {
     }
 }
 
+// Computes approximate heap size of variable,
+// counting shared memory once.
+fn heap_size(var: &Variable, seen: &mut HashSet<usize>) -> usize {
+    use std::mem::size_of;
+    use link::Block;
+
+    match *var {
+        Variable::Text(ref t) => {
+            if !seen.insert(&**t as *const _ as usize) { return 0; }
+            t.len()
+        }
+        Variable::Array(ref arr) => {
+            if !seen.insert(&**arr as *const _ as usize) { return 0; }
+            arr.capacity() * size_of::<Variable>() +
+            arr.iter().map(|v| heap_size(v, seen)).sum::<usize>()
+        }
+        Variable::Object(ref obj) => {
+            if !seen.insert(&**obj as *const _ as usize) { return 0; }
+            obj.iter().map(|(k, v)| {
+                size_of::<(Arc<String>, Variable)>() + k.len() + heap_size(v, seen)
+            }).sum()
+        }
+        Variable::Link(ref link) => link.slices.len() * size_of::<Block>(),
+        Variable::Option(Some(ref v)) |
+        Variable::Result(Ok(ref v)) => size_of::<Variable>() + heap_size(v, seen),
+        Variable::Result(Err(ref err)) =>
+            size_of::<::Error>() + heap_size(&err.message, seen),
+        Variable::Bool(_, Some(ref sec)) |
+        Variable::F64(_, Some(ref sec)) =>
+            sec.capacity() * size_of::<Variable>() +
+            sec.iter().map(|v| heap_size(v, seen)).sum::<usize>(),
+        _ => 0
+    }
+}
+
+// Formats the accepted number of arguments, e.g. `2` or `1..=2`.
+fn arg_range(min: usize, max: usize) -> String {
+    if min == max { format!("{}", max) } else { format!("{}..={}", min, max) }
+}
+
+// Drops an operand of bytecode, skipping the destructor when not needed.
+#[inline(always)]
+fn drop_operand(var: Option<Variable>) {
+    use std::mem;
+
+    match var {
+        None | Some(Variable::Ref(_)) => mem::forget(var),
+        Some(Variable::F64(_, sec)) | Some(Variable::Bool(_, sec)) => {
+            if sec.is_some() { drop(sec) } else { mem::forget(sec) }
+        }
+        x => drop(x)
+    }
+}
+
+/// Returns the start value of a sum or product of a short loop.
+fn acc_start(acc: bytecode::Acc, i64_acc: bool) -> Variable {
+    use bytecode::Acc;
+
+    match (acc, i64_acc) {
+        (Acc::Sum, false) => Variable::f64(0.0),
+        (Acc::Prod, false) => Variable::f64(1.0),
+        (Acc::Sum, true) => Variable::I64(0),
+        (Acc::Prod, true) => Variable::I64(1),
+        _ => panic!("Expected sum or product")
+    }
+}
+
+/// Adds a value to the sum or product of a short loop.
+///
+/// When the type checker does not know that the loop adds up `i64` values,
+/// it starts with a number, which is replaced by the first value when it is `i64`.
+/// Returns `false` if the value is not a number or does not match the previous values.
+fn accumulate(acc: bytecode::Acc, res: &mut Variable, first: bool, val: &Variable) -> bool {
+    use bytecode::Acc;
+
+    match (&mut *res, val) {
+        (&mut Variable::F64(ref mut x, _), &Variable::F64(val, _)) => match acc {
+            Acc::Sum => *x += val,
+            Acc::Prod => *x *= val,
+            _ => return false
+        },
+        (&mut Variable::I64(ref mut x), &Variable::I64(val)) => match acc {
+            Acc::Sum => *x = x.wrapping_add(val),
+            Acc::Prod => *x = x.wrapping_mul(val),
+            _ => return false
+        },
+        (&mut Variable::F64(..), &Variable::I64(val)) if first => *res = Variable::I64(val),
+        (&mut Variable::Vec4(ref mut x), &Variable::Vec4(val)) => match acc {
+            Acc::SumVec4 => for i in 0..4 { x[i] += val[i] },
+            Acc::ProdVec4 => for i in 0..4 { x[i] *= val[i] },
+            _ => return false
+        },
+        _ => return false
+    }
+    true
+}
+
 // Looks up an item from a variable property.
 fn item_lookup(
     module: &Module,
@@ -167,6 +473,16 @@ fn item_lookup(
                                 *expr_j += 1;
                                 id
                             }
+                            &mut Variable::I64(id) => {
+                                *expr_j += 1;
+                                if id < 0 {
+                                    return Err(module.error_fnindex(prop.source_range(),
+                                        &format!("{}\nOut of bounds `{}`",
+                                            stack_trace(call_stack), id),
+                                            call_stack.last().unwrap().index));
+                                }
+                                id as f64
+                            }
                             _ => return Err(module.error_fnindex(prop.source_range(),
                                             &format!("{}\nExpected number",
                                                 stack_trace(call_stack)),
@@ -213,10 +529,37 @@ impl Runtime {
             call_stack: vec![],
             local_stack: vec![],
             current_stack: vec![],
+            globals: 0,
+            module_globals: vec![],
+            operands: vec![],
+            fuel: None,
+            steps: FUEL_CHUNK,
+            out_of_fuel: None,
+            interrupt: InterruptHandle::default(),
+            cancelled: None,
+            limits: Limits::default(),
+            workers: ::std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+            pool: None,
+            heap: 0,
+            building: 0,
+            caller_capabilities: vec![],
+            debugger: None,
+            profiler: None,
+            profile_stack: profiler::Stack::default(),
+            coverage: None,
+            coroutine: false,
+            suspend_requested: false,
+            pinned: 0,
+            running: false,
+            frames: vec![],
+            suspending: None,
+            suspended: None,
+            updating: vec![],
             ret: Arc::new("return".into()),
             rng: rand::StdRng::new().unwrap(),
             text_type: Variable::Text(Arc::new("string".into())),
             f64_type: Variable::Text(Arc::new("number".into())),
+            i64_type: Variable::Text(Arc::new("i64".into())),
             vec4_type: Variable::Text(Arc::new("vec4".into())),
             return_type: Variable::Text(Arc::new("return".into())),
             bool_type: Variable::Text(Arc::new("boolean".into())),
@@ -229,10 +572,377 @@ impl Runtime {
             option_type: Variable::Text(Arc::new("option".into())),
             result_type: Variable::Text(Arc::new("result".into())),
             thread_type: Variable::Text(Arc::new("thread".into())),
+            coroutine_type: Variable::Text(Arc::new("coroutine".into())),
+            channel_type: Variable::Text(Arc::new("channel".into())),
+            shared_type: Variable::Text(Arc::new("shared".into())),
             closure_type: Variable::Text(Arc::new("closure".into())),
         }
     }
 
+    /// Sets the fuel, `None` for no limit.
+    ///
+    /// Every expression or instruction executed uses one unit of fuel.
+    /// The fuel is shared with threads started by `go`.
+    ///
+    /// Running out of fuel suspends the call with `Status::OutOfFuel`,
+    /// unless `out_of_fuel` gives more.
+    /// Where the runtime can not be suspended, e.g. in threads started by `go`
+    /// or in closures, running out of fuel stops with an error instead.
+    pub fn set_fuel(&mut self, fuel: Option<usize>) {
+        match fuel {
+            None => {
+                self.fuel = None;
+                self.steps = FUEL_CHUNK;
+            }
+            Some(fuel) => {
+                self.fuel = Some(Arc::new(AtomicUsize::new(fuel)));
+                self.steps = 0;
+            }
+        }
+    }
+
+    /// Returns the fuel left, `None` if there is no limit.
+    pub fn fuel(&self) -> Option<usize> {
+        self.fuel.as_ref().map(|fuel| fuel.load(Ordering::SeqCst) + self.steps)
+    }
+
+    /// Adds fuel, e.g. before resuming a call that ran out of fuel.
+    ///
+    /// Does nothing if there is no limit.
+    pub fn add_fuel(&mut self, fuel: usize) {
+        if let Some(ref f) = self.fuel {
+            f.fetch_add(fuel, Ordering::SeqCst);
+        }
+    }
+
+    /// Sets function to call when running out of fuel.
+    pub fn set_out_of_fuel(&mut self, f: Option<OutOfFuel>) {
+        self.out_of_fuel = f;
+    }
+
+    /// Returns a handle that can interrupt the runtime from another thread.
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        self.interrupt.clone()
+    }
+
+    /// Suspends the runtime when the external function calling this returns.
+    ///
+    /// The call from the host returns `Status::Suspended`,
+    /// and continues with `resume` with the stack, locals and currents intact.
+    /// When the runtime can not be suspended, e.g. in a closure,
+    /// it suspends at the next safe point where it can.
+    /// To suspend without calling an external function, use `InterruptHandle::suspend`.
+    pub fn suspend(&mut self) {
+        self.suspend_requested = true;
+    }
+
+    /// Attaches or removes debugger.
+    pub fn set_debugger(&mut self, debugger: Option<Debugger>) {
+        self.debugger = debugger;
+    }
+
+    /// Attaches or removes profiler.
+    pub fn set_profiler(&mut self, profiler: Option<Profiler>) {
+        self.profiler = profiler;
+    }
+
+    /// Attaches or removes coverage recording.
+    pub fn set_coverage(&mut self, coverage: Option<Coverage>) {
+        self.coverage = coverage;
+    }
+
+    /// Returns `true` if functions should run with instructions for
+    /// the debugger, profiler and coverage.
+    fn hooks(&self) -> bool {
+        self.debugger.is_some() || self.profiler.is_some() || self.coverage.is_some()
+    }
+
+    #[inline(never)]
+    fn cover_expression(&self, range: Range, module: &Module) {
+        if let (Some(ref coverage), Some(call)) = (self.coverage.as_ref(), self.call_stack.last()) {
+            coverage.expression(&module.functions[call.index].file, range);
+        }
+    }
+
+    #[inline(never)]
+    fn cover_block(&self, range: Range, module: &Module) {
+        if let (Some(ref coverage), Some(call)) = (self.coverage.as_ref(), self.call_stack.last()) {
+            coverage.block(&module.functions[call.index].file, range);
+        }
+    }
+
+    #[inline(never)]
+    fn cover_branch(&self, range: Range, branches: usize, branch: usize, module: &Module) {
+        if let (Some(ref coverage), Some(call)) = (self.coverage.as_ref(), self.call_stack.last()) {
+            coverage.branch(&module.functions[call.index].file, range, branches, branch);
+        }
+    }
+
+    /// Times a loop when profiling.
+    #[inline(always)]
+    fn profile_loop<F>(
+        &mut self,
+        range: Range,
+        module: &Module,
+        f: F
+    ) -> Result<(Option<Variable>, Flow), String>
+        where F: FnOnce(&mut Runtime) -> Result<(Option<Variable>, Flow), String>
+    {
+        if self.profiler.is_none() { return f(self); }
+        self.enter_loop(range, module);
+        let res = f(self);
+        self.exit_loop(range);
+        res
+    }
+
+    /// Starts timing a loop when profiling.
+    #[inline(never)]
+    fn enter_loop(&mut self, range: Range, module: &Module) {
+        if self.profiler.is_none() { return; }
+        if let Some(call) = self.call_stack.last() {
+            let func = &module.functions[call.index];
+            self.profile_stack.enter_loop(&func.file, &func.source, range.offset, &self.call_stack);
+        }
+    }
+
+    /// Stops timing a loop when profiling.
+    #[inline(never)]
+    fn exit_loop(&mut self, range: Range) {
+        if let Some(ref profiler) = self.profiler {
+            self.profile_stack.exit_loop(profiler, range.offset, &self.call_stack);
+        }
+    }
+
+    #[inline(never)]
+    fn debug_pause(&mut self, range: Range, module: &Module) -> Result<(), String> {
+        let mut d = self.debugger.take().unwrap();
+        let res = debugger::pause(&mut d, self, range, module);
+        self.debugger = Some(d);
+        res
+    }
+
+    /// Uses one unit of fuel.
+    #[inline(always)]
+    fn step(&mut self, range: Range, module: &Module) -> Result<(), String> {
+        if self.steps == 0 { try!(self.safe_point(range, false, module)); }
+        self.steps -= 1;
+        Ok(())
+    }
+
+    /// Returns an error if the runtime is interrupted or the thread is cancelled.
+    fn check_interrupt(&self, range: Range, module: &Module) -> Result<(), String> {
+        if self.interrupt.is_interrupted() {
+            return Err(module.error(range,
+                &format!("{}\nInterrupted", self.stack_trace()), self));
+        }
+        if self.cancelled.as_ref().map(|c| c.load(Ordering::SeqCst)) == Some(true) {
+            return Err(module.error(range,
+                &format!("{}\nCancelled", self.stack_trace()), self));
+        }
+        Ok(())
+    }
+
+    /// Waits until `f` returns a value, stopping when interrupted or cancelled.
+    ///
+    /// `f` is called repeatedly with the time to wait,
+    /// and returns `None` when nothing happened within that time.
+    /// In between, queued tasks of the thread pool are run on this thread.
+    pub fn wait<T, F>(&self, range: Range, module: &Module, mut f: F) -> Result<T, String>
+        where F: FnMut(Duration) -> Option<T>
+    {
+        let slice = Duration::from_millis(WAIT_SLICE_MS);
+        loop {
+            try!(self.check_interrupt(range, module));
+            if let Some(x) = f(slice) { return Ok(x); }
+            // Help with queued tasks, since they might be what is waited for.
+            // A task could need the lock of a shared value being updated,
+            // which is held by this thread.
+            if self.updating.is_empty() {
+                if let Some(ref pool) = self.pool { pool.run_pending(); }
+            }
+        }
+    }
+
+    /// Waits for a task to complete and takes the result.
+    ///
+    /// A task that is not started runs on this thread,
+    /// unless a shared value is being updated, since the task could need its lock.
+    pub fn join_task(
+        &self,
+        task: &Task,
+        range: Range,
+        module: &Module
+    ) -> Result<Result<Variable, String>, String> {
+        let run = self.updating.is_empty();
+        self.wait(range, module, |slice| {
+            if run { task.join_timeout(slice) } else { task.wait_timeout(slice) }
+        })
+    }
+
+    /// Checks for interrupts and suspend requests, and takes more steps from the shared fuel.
+    ///
+    /// Returns `true` to suspend, which is only done when `resumable` is `true`.
+    /// The steps are left at zero, such that the safe point is checked again when resumed.
+    #[inline(never)]
+    fn safe_point(&mut self, range: Range, resumable: bool, module: &Module) -> Result<bool, String> {
+        use std::cmp::min;
+
+        try!(self.check_interrupt(range, module));
+        if resumable && (self.suspend_requested || self.interrupt.take_suspend()) {
+            self.suspend_requested = false;
+            self.suspending = Some(Status::Suspended);
+            return Ok(true);
+        }
+        let fuel = match self.fuel {
+            None => {
+                self.steps = FUEL_CHUNK;
+                return Ok(false);
+            }
+            Some(ref fuel) => fuel.clone()
+        };
+        loop {
+            let left = fuel.load(Ordering::SeqCst);
+            if left > 0 {
+                let take = min(left, FUEL_CHUNK);
+                if fuel.compare_exchange(left, left - take, Ordering::SeqCst, Ordering::SeqCst)
+                       .is_ok() {
+                    self.steps = take;
+                    return Ok(false);
+                }
+                continue;
+            }
+            match self.out_of_fuel.as_ref().and_then(|f| f()) {
+                Some(more) if more > 0 => { fuel.fetch_add(more, Ordering::SeqCst); }
+                _ if resumable => {
+                    self.suspending = Some(Status::OutOfFuel);
+                    return Ok(true);
+                }
+                _ => return Err(module.error(range,
+                        &format!("{}\nOut of fuel", self.stack_trace()), self))
+            }
+        }
+    }
+
+    /// Checks call depth, stack length and native stack before calling a function.
+    fn check_call(&self, range: Range, module: &Module) -> Result<(), String> {
+        if !stack_guard::has_room() {
+            return Err(module.error(range, &format!(
+                "{}\nNative stack overflow at call depth {}",
+                self.stack_trace(), self.call_stack.len()), self));
+        }
+        if let Some(depth) = self.limits.call_depth {
+            if self.call_stack.len() >= depth {
+                return Err(module.error(range, &format!(
+                    "{}\nCall depth exceeds limit of {}",
+                    self.stack_trace(), depth), self));
+            }
+        }
+        if let Some(n) = self.limits.stack {
+            if self.stack.len() > n {
+                return Err(module.error(range, &format!(
+                    "{}\nStack length exceeds limit of {}",
+                    self.stack_trace(), n), self));
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds bytes to the approximate heap size.
+    ///
+    /// When exceeding the heap limit, the heap size is measured
+    /// from the variables on the stack.
+    #[inline(always)]
+    pub fn alloc(&mut self, bytes: usize, range: Range, module: &Module) -> Result<(), String> {
+        if let Some(max) = self.limits.heap {
+            self.heap = self.heap.saturating_add(bytes);
+            if self.heap > max { try!(self.measure_heap(bytes, max, range, module)); }
+        }
+        Ok(())
+    }
+
+    /// Adds bytes of an array being built by a loop to the heap size.
+    fn alloc_building(&mut self, bytes: usize, range: Range, module: &Module) -> Result<(), String> {
+        try!(self.alloc(bytes, range, module));
+        if self.limits.heap.is_some() { self.building += bytes; }
+        Ok(())
+    }
+
+    /// Runs a loop that builds an array, counting it as heap until the loop ends.
+    fn building<F>(&mut self, f: F) -> Result<(Option<Variable>, Flow), String>
+        where F: FnOnce(&mut Runtime) -> Result<(Option<Variable>, Flow), String>
+    {
+        let building = self.building;
+        let res = f(self);
+        self.building = building;
+        res
+    }
+
+    #[inline(never)]
+    fn measure_heap(
+        &mut self,
+        bytes: usize,
+        max: usize,
+        range: Range,
+        module: &Module
+    ) -> Result<(), String> {
+        let mut seen = HashSet::new();
+        let mut size = 0;
+        for v in &self.stack {
+            size += heap_size(v, &mut seen);
+        }
+        for v in &self.operands {
+            if let Some(ref v) = *v { size += heap_size(v, &mut seen); }
+        }
+        self.heap = size.saturating_add(self.building).saturating_add(bytes);
+        if self.heap > max {
+            return Err(module.error(range, &format!(
+                "{}\nOut of memory, heap exceeds limit of {} bytes",
+                self.stack_trace(), max), self));
+        }
+        Ok(())
+    }
+
+    /// Checks that capability is allowed by module and the modules calling into it.
+    pub fn require(
+        &self,
+        cap: Capability,
+        range: Range,
+        module: &Module
+    ) -> Result<(), String> {
+        if module.capabilities.has(cap) &&
+           self.caller_capabilities.iter().all(|c| c.has(cap)) {
+            Ok(())
+        } else {
+            Err(module.error(range, &format!("{}\nRequires capability `{}`",
+                self.stack_trace(), cap.name()), self))
+        }
+    }
+
+    /// Checks that intrinsic is allowed by module and the modules calling into it.
+    pub fn require_intrinsic(&self, call: &ast::Call, module: &Module) -> Result<(), String> {
+        let denied = module.capabilities.denies(&call.name).or_else(||
+            self.caller_capabilities.iter().filter_map(|c| c.denies(&call.name)).next());
+        match denied {
+            None => Ok(()),
+            Some(cap) => Err(module.error(call.source_range, &format!(
+                "{}\n`{}` requires capability `{}`",
+                self.stack_trace(), call.name, cap.name()), self))
+        }
+    }
+
+    /// Checks that file is inside the directories allowed by module
+    /// and the modules calling into it.
+    pub fn require_path(&self, file: &str, range: Range, module: &Module) -> Result<(), String> {
+        if module.capabilities.allows_path(file) &&
+           self.caller_capabilities.iter().all(|c| c.allows_path(file)) {
+            Ok(())
+        } else {
+            Err(module.error(range, &format!(
+                "{}\nFile `{}` is outside the allowed directories",
+                self.stack_trace(), file), self))
+        }
+    }
+
     pub fn pop<T: embed::PopVariable>(&mut self) -> Result<T, String> {
         let v = self.stack.pop().unwrap_or_else(|| panic!(TINVOTS));
         T::pop_var(self, self.resolve(&v))
@@ -309,12 +1019,24 @@ impl Runtime {
             local_len: lc,
             current_len: cu,
         });
+        if self.profiler.is_some() {
+            self.profile_stack.enter(&self.call_stack);
+        }
     }
     pub fn pop_fn(&mut self, name: Arc<String>) {
+        self.pop_call(&name)
+    }
+
+    /// Pops a call from the call stack, like `pop_fn`, without cloning the name.
+    #[inline(always)]
+    fn pop_call(&mut self, name: &Arc<String>) {
+        if let Some(ref profiler) = self.profiler {
+            self.profile_stack.exit(profiler, &self.call_stack);
+        }
         match self.call_stack.pop() {
             None => panic!("Did not call `{}`", name),
             Some(Call { fn_name, stack_len: st, local_len: lc, current_len: cu, .. }) => {
-                if name != fn_name {
+                if *name != fn_name {
                     panic!("Calling `{}`, did not call `{}`", fn_name, name);
                 }
                 self.stack.truncate(st);
@@ -332,6 +1054,10 @@ impl Runtime {
     ) -> Result<(Option<Variable>, Flow), String> {
         use ast::Expression::*;
 
+        try!(self.step(expr.source_range(), module));
+        if self.coverage.is_some() {
+            self.cover_expression(expr.source_range(), module);
+        }
         match *expr {
             Link(ref link) => self.link(link, module),
             Object(ref obj) => self.object(obj, module),
@@ -352,6 +1078,8 @@ impl Runtime {
             Break(ref b) => Ok((None, Flow::Break(b.label.clone()))),
             Continue(ref b) => Ok((None, Flow::ContinueLoop(b.label.clone()))),
             Go(ref go) => self.go(go, module),
+            Coroutine(ref go) => self.coroutine(go, module),
+            Yield(ref expr) => self.yield_expr(expr, module),
             Call(ref call) => {
                 let loader = false;
                 self.call_internal(call, loader, module)
@@ -365,19 +1093,51 @@ impl Runtime {
             Vec4(ref vec4) => self.vec4(vec4, side, module),
             Text(ref text) => Ok((Some(::Variable::Text(text.text.clone())), Flow::Continue)),
             Bool(ref b) => Ok((Some(::Variable::bool(b.val)), Flow::Continue)),
-            For(ref for_expr) => self.for_expr(for_expr, module),
-            ForN(ref for_n_expr) => self.for_n_expr(for_n_expr, module),
-            Sum(ref for_n_expr) => self.sum_n_expr(for_n_expr, module),
-            SumVec4(ref for_n_expr) => self.sum_vec4_n_expr(for_n_expr, module),
-            Prod(ref for_n_expr) => self.prod_n_expr(for_n_expr, module),
-            ProdVec4(ref for_n_expr) => self.prod_vec4_n_expr(for_n_expr, module),
-            Min(ref for_n_expr) => self.min_n_expr(for_n_expr, module),
-            Max(ref for_n_expr) => self.max_n_expr(for_n_expr, module),
-            Sift(ref for_n_expr) => self.sift_n_expr(for_n_expr, module),
-            Any(ref for_n_expr) => self.any_n_expr(for_n_expr, module),
-            All(ref for_n_expr) => self.all_n_expr(for_n_expr, module),
-            LinkFor(ref for_n_expr) => self.link_for_n_expr(for_n_expr, module),
+            For(ref for_expr) => self.profile_loop(expr.source_range(), module,
+                |rt| rt.for_expr(for_expr, module)),
+            ForN(ref for_n_expr) => self.profile_loop(expr.source_range(), module,
+                |rt| rt.for_n_expr(for_n_expr, module)),
+            Sum(ref for_n_expr) | Min(ref for_n_expr) | Max(ref for_n_expr) |
+            Sift(ref for_n_expr) | Any(ref for_n_expr) | All(ref for_n_expr)
+                if for_n_expr.parallel => self.profile_loop(expr.source_range(), module,
+                    |rt| rt.par_n_expr(expr, for_n_expr, module)),
+            Sum(ref for_n_expr) => self.profile_loop(expr.source_range(), module,
+                |rt| rt.sum_n_expr(for_n_expr, module)),
+            SumVec4(ref for_n_expr) => self.profile_loop(expr.source_range(), module,
+                |rt| rt.sum_vec4_n_expr(for_n_expr, module)),
+            Prod(ref for_n_expr) => self.profile_loop(expr.source_range(), module,
+                |rt| rt.prod_n_expr(for_n_expr, module)),
+            ProdVec4(ref for_n_expr) => self.profile_loop(expr.source_range(), module,
+                |rt| rt.prod_vec4_n_expr(for_n_expr, module)),
+            Min(ref for_n_expr) => self.profile_loop(expr.source_range(), module,
+                |rt| rt.min_n_expr(for_n_expr, module)),
+            Max(ref for_n_expr) => self.profile_loop(expr.source_range(), module,
+                |rt| rt.max_n_expr(for_n_expr, module)),
+            Sift(ref for_n_expr) => self.profile_loop(expr.source_range(), module,
+                |rt| rt.building(|rt| rt.sift_n_expr(for_n_expr, module))),
+            Any(ref for_n_expr) => self.profile_loop(expr.source_range(), module,
+                |rt| rt.any_n_expr(for_n_expr, module)),
+            All(ref for_n_expr) => self.profile_loop(expr.source_range(), module,
+                |rt| rt.all_n_expr(for_n_expr, module)),
+            LinkFor(ref for_n_expr) => self.profile_loop(expr.source_range(), module,
+                |rt| rt.link_for_n_expr(for_n_expr, module)),
+            ForIn(ref for_in_expr) => self.profile_loop(expr.source_range(), module,
+                |rt| rt.for_in_expr(for_in_expr, module)),
+            SumIn(ref for_in_expr) => self.profile_loop(expr.source_range(), module,
+                |rt| rt.sum_in_expr(for_in_expr, module)),
+            MinIn(ref for_in_expr) => self.profile_loop(expr.source_range(), module,
+                |rt| rt.min_in_expr(for_in_expr, module)),
+            MaxIn(ref for_in_expr) => self.profile_loop(expr.source_range(), module,
+                |rt| rt.max_in_expr(for_in_expr, module)),
+            SiftIn(ref for_in_expr) => self.profile_loop(expr.source_range(), module,
+                |rt| rt.building(|rt| rt.sift_in_expr(for_in_expr, module))),
+            AnyIn(ref for_in_expr) => self.profile_loop(expr.source_range(), module,
+                |rt| rt.any_in_expr(for_in_expr, module)),
+            AllIn(ref for_in_expr) => self.profile_loop(expr.source_range(), module,
+                |rt| rt.all_in_expr(for_in_expr, module)),
             If(ref if_expr) => self.if_expr(if_expr, module),
+            Match(ref match_expr) => self.match_expr(match_expr, module),
+            EnumValue(ref enum_value) => self.enum_value(enum_value, module),
             Compare(ref compare) => self.compare(compare, module),
             Variable(_, ref var) => Ok((Some(var.clone()), Flow::Continue)),
             Try(ref expr) => self.try(expr, side, module),
@@ -430,7 +1190,6 @@ impl Runtime {
     fn closure(&mut self, closure: &ast::Closure, module: &Arc<Module>)
     -> Result<(Option<Variable>, Flow), String> {
         use grab::{self, Grabbed};
-        use ClosureEnvironment;
 
         // Create closure.
         let relative = self.call_stack.last().map(|c| c.index).unwrap_or(0);
@@ -577,7 +1336,11 @@ impl Runtime {
         }
     }
 
-    pub fn run(&mut self, module: &Arc<Module>) -> Result<(), String> {
+    /// Runs `main`.
+    ///
+    /// Returns `Status::Suspended` when suspended by an external function or the host,
+    /// which continues with `resume`.
+    pub fn run(&mut self, module: &Arc<Module>) -> Result<Status, String> {
         use std::cell::Cell;
 
         let name: Arc<String> = Arc::new("main".into());
@@ -597,14 +1360,39 @@ impl Runtime {
                                "`main` should not have arguments", self))
                 }
                 let loader = false;
-                try!(self.call_internal(&call, loader, &module));
-                Ok(())
+                let resumable = true;
+                self.call_outside(&call, loader, resumable, module)
             }
             _ => return Err(module.error(call.source_range,
                                "Could not find function `main`", self))
         }
     }
 
+    /// Resumes the suspended call from the outside, e.g. by `run` or `call_str`,
+    /// with the stack, locals and currents intact.
+    ///
+    /// The module must be the one the call was suspended in.
+    pub fn resume(&mut self, module: &Arc<Module>) -> Result<Status, String> {
+        if self.running {
+            return Err("Can not resume while running".into());
+        }
+        match self.suspended {
+            None => return Err("There is no suspended call to resume".into()),
+            Some(ref s) if s.module != module.id =>
+                return Err("Expected the module of the suspended call".into()),
+            Some(_) => {}
+        }
+        let s = self.suspended.take().unwrap();
+        self.running = true;
+        let res = self.resume_loaded(&s.call, module);
+        self.return_outside(res, &s.call, s.stack_len, s.globals, false, module)
+    }
+
+    /// Returns `true` if a call from the outside is suspended.
+    pub fn is_suspended(&self) -> bool {
+        self.suspended.is_some()
+    }
+
     fn block(
         &mut self,
         block: &ast::Block,
@@ -614,7 +1402,13 @@ impl Runtime {
         let st = self.stack.len();
         let lc = self.local_stack.len();
         let cu = self.current_stack.len();
+        if self.coverage.is_some() {
+            self.cover_block(block.source_range, module);
+        }
         for e in &block.expressions {
+            if self.debugger.is_some() {
+                try!(self.debug_pause(e.source_range(), module));
+            }
             expect = match try!(self.expression(e, Side::Right, module)) {
                 (x, Flow::Continue) => x,
                 x => {
@@ -633,107 +1427,327 @@ impl Runtime {
     }
 
     pub fn go(&mut self, go: &ast::Go, module: &Arc<Module>) -> Result<(Option<Variable>, Flow), String> {
-        use std::thread::{self, JoinHandle};
-        use std::cell::Cell;
         use Thread;
 
-        let n = go.call.args.len();
-        let mut stack = vec![];
-        let relative = self.call_stack.last().map(|c| c.index).unwrap();
-        let mut fake_call = ast::Call {
-            alias: go.call.alias.clone(),
-            name: go.call.name.clone(),
-            f_index: Cell::new(module.find_function(&go.call.name, relative)),
-            args: Vec::with_capacity(n),
-            custom_source: None,
-            source_range: go.call.source_range,
-        };
-        // Evaluate the arguments and put a deep clone on the new stack.
-        // This prevents the arguments from containing any reference to other variables.
-        for (i, arg) in go.call.args.iter().enumerate() {
-            let v = match try!(self.expression(arg, Side::Right, module)) {
-                (Some(x), Flow::Continue) => x,
-                (x, Flow::Return) => { return Ok((x, Flow::Return)); }
-                _ => return Err(module.error(arg.source_range(),
-                                &format!("{}\nExpected something. \
-                                Expression did not return a value.",
-                                self.stack_trace()), self))
-            };
-            stack.push(v.deep_clone(&self.stack));
-            fake_call.args.push(ast::Expression::Variable(
-                go.call.args[i].source_range(), Variable::Ref(n-i-1)));
-        }
-        stack.reverse();
-
-        let last_call = self.call_stack.last().unwrap();
-        let new_rt = Runtime {
-            stack: stack,
-            local_stack: vec![],
-            current_stack: vec![],
-            // Add last call because of loaded functions
-            // use relative index to the function it is calling from.
-            call_stack: vec![Call {
-                fn_name: last_call.fn_name.clone(),
-                index: last_call.index,
-                file: last_call.file.clone(),
-                stack_len: 0,
-                local_len: 0,
-                current_len: 0,
-            }],
-            rng: self.rng.clone(),
-            ret: self.ret.clone(),
-            ref_type: self.ref_type.clone(),
-            option_type: self.option_type.clone(),
-            array_type: self.array_type.clone(),
-            link_type: self.link_type.clone(),
-            bool_type: self.bool_type.clone(),
-            object_type: self.object_type.clone(),
-            text_type: self.text_type.clone(),
-            f64_type: self.f64_type.clone(),
-            thread_type: self.thread_type.clone(),
-            unsafe_ref_type: self.unsafe_ref_type.clone(),
-            return_type: self.return_type.clone(),
-            rust_object_type: self.rust_object_type.clone(),
-            vec4_type: self.vec4_type.clone(),
-            result_type: self.result_type.clone(),
-            closure_type: self.closure_type.clone(),
+        try!(self.require(Capability::Threads, go.source_range, module));
+        let (mut new_rt, fake_call) = match try!(self.thread_call(&go.call, module)) {
+            Ok(x) => x,
+            Err(x) => return Ok(x),
         };
+        let cancelled = Arc::new(AtomicBool::new(false));
+        new_rt.cancelled = Some(cancelled.clone());
         let new_module: Module = (**module).clone();
-        let handle: JoinHandle<Result<Variable, String>> = thread::spawn(move || {
+        let task = Arc::new(Task::new(Box::new(move || {
             let mut new_rt = new_rt;
             let new_module = Arc::new(new_module);
             let fake_call = fake_call;
             let loader = false;
-            Ok(match new_rt.call_internal(&fake_call, loader, &new_module) {
+            let res = new_rt.call_internal(&fake_call, loader, &new_module);
+            // Give back fuel that was not used.
+            if let Some(ref fuel) = new_rt.fuel {
+                fuel.fetch_add(new_rt.steps, Ordering::SeqCst);
+            }
+            Ok(match res {
                 Err(err) => return Err(err),
                 Ok((None, _)) => {
                     new_rt.stack.pop().expect(TINVOTS)
                 }
                 Ok((Some(x), _)) => x,
             }.deep_clone(&new_rt.stack))
-        });
-        Ok((Some(Variable::Thread(Thread::new(handle))), Flow::Continue))
+        }), cancelled));
+        pool::spawn(self.pool.as_ref(), task.clone());
+        Ok((Some(Variable::Thread(Thread::from_task(task))), Flow::Continue))
     }
 
-    pub fn call_closure(
+    /// Creates a suspended coroutine from a function call.
+    pub fn coroutine(
         &mut self,
-        call: &ast::CallClosure,
+        go: &ast::Go,
         module: &Arc<Module>
     ) -> Result<(Option<Variable>, Flow), String> {
-        // Find item.
-        let item = match try!(self.item(&call.item, Side::Right, module)) {
-            (Some(x), Flow::Continue) => x,
-            (x, Flow::Return) => { return Ok((x, Flow::Return)); }
-            _ => return Err(module.error(call.item.source_range,
-                            &format!("{}\nExpected something. \
-                            Check that item returns a value.",
-                            self.stack_trace()), self))
+        let (new_rt, fake_call) = match try!(self.thread_call(&go.call, module)) {
+            Ok(x) => x,
+            Err(x) => return Ok(x),
         };
+        let loader = false;
+        let co = self.new_coroutine(new_rt, fake_call, loader, module);
+        Ok((Some(Variable::Coroutine(co)), Flow::Continue))
+    }
 
-        let (f, env) = match self.resolve(&item) {
-            &Variable::Closure(ref f, ref env) => (f.clone(), env.clone()),
-            x => return Err(module.error(call.source_range,
-                    &self.expected(x, "closure"), self))
+    /// Creates a suspended coroutine calling a function with arguments.
+    ///
+    /// The coroutine runs with its own runtime on the thread resuming it,
+    /// and is resumed with `Coroutine::resume`.
+    pub fn coroutine_str(
+        &mut self,
+        function: &str,
+        args: &[Variable],
+        module: &Arc<Module>
+    ) -> Result<Coroutine, String> {
+        use std::cell::Cell;
+
+        let name: Arc<String> = Arc::new(function.into());
+        match module.find_function(&name, 0) {
+            FnIndex::Loaded(f_index) => {
+                let call = ast::Call {
+                    alias: None,
+                    name: name.clone(),
+                    f_index: Cell::new(FnIndex::Loaded(f_index)),
+                    args: args.iter()
+                            .map(|arg| ast::Expression::Variable(
+                                Range::empty(0), arg.deep_clone(&self.stack)))
+                            .collect(),
+                    custom_source: None,
+                    source_range: Range::empty(0),
+                };
+                let globals = try!(self.copy_globals(module));
+                let new_rt = self.thread_runtime(globals);
+                let loader = true;
+                Ok(self.new_coroutine(new_rt, call, loader, module))
+            }
+            _ => return Err(format!("Could not find function `{}`",function))
+        }
+    }
+
+    /// Creates a coroutine calling a function on its own runtime,
+    /// which can be suspended and has its own request to suspend.
+    fn new_coroutine(
+        &self,
+        new_rt: Runtime,
+        call: ast::Call,
+        loader: bool,
+        module: &Arc<Module>
+    ) -> Coroutine {
+        let mut new_rt = new_rt;
+        new_rt.coroutine = true;
+        new_rt.pinned = 0;
+        new_rt.interrupt = self.interrupt.with_own_suspend();
+        Coroutine::new(new_rt, call, loader, module.clone())
+    }
+
+    /// Runs a coroutine until it yields, is suspended or returns.
+    ///
+    /// When `start` is `true`, the function is called,
+    /// otherwise the suspended call is resumed.
+    pub(crate) fn run_coroutine(
+        &mut self,
+        call: &ast::Call,
+        loader: bool,
+        start: bool,
+        module: &Arc<Module>
+    ) -> Result<Status, String> {
+        self.running = true;
+        let res = if start {
+            self.call_internal(call, loader, module)
+        } else {
+            self.resume_loaded(call, module)
+        };
+        self.running = false;
+        // Give back fuel that was not used.
+        self.safe_point_soon();
+        match try!(res) {
+            (_, Flow::Suspend) => Ok(self.suspending.take().expect("Expected suspend status")),
+            (x, _) => Ok(Status::Done(x.map(|x| x.deep_clone(&self.stack)))),
+        }
+    }
+
+    /// Handles a request to suspend after an external function returns.
+    ///
+    /// Returns `true` to suspend, which is only done when `resumable` is `true`.
+    /// Otherwise, the runtime suspends at the next safe point where it can.
+    #[inline(never)]
+    fn suspend_external(&mut self, resumable: bool) -> bool {
+        if resumable {
+            self.suspend_requested = false;
+            self.suspending = Some(Status::Suspended);
+            return true;
+        }
+        self.safe_point_soon();
+        false
+    }
+
+    /// Checks the next safe point after the current step, giving back steps to the fuel.
+    fn safe_point_soon(&mut self) {
+        if let Some(ref fuel) = self.fuel {
+            fuel.fetch_add(self.steps, Ordering::SeqCst);
+        }
+        self.steps = 0;
+    }
+
+    fn yield_expr(
+        &mut self,
+        expr: &ast::Expression,
+        module: &Arc<Module>
+    ) -> Result<(Option<Variable>, Flow), String> {
+        let v = match try!(self.expression(expr, Side::Right, module)) {
+            (Some(x), Flow::Continue) => x,
+            (x, Flow::Return) => { return Ok((x, Flow::Return)); }
+            _ => return Err(module.error(expr.source_range(),
+                            &format!("{}\nExpected something. \
+                            Expression did not return a value.",
+                            self.stack_trace()), self))
+        };
+        // The tree-walking interpreter can not be suspended.
+        let resumable = false;
+        self.yield_value(v, resumable, expr.source_range(), module).map(|_| (None, Flow::Continue))
+    }
+
+    /// Suspends the coroutine running on this runtime with a yielded value.
+    ///
+    /// Returns an error when not running a coroutine,
+    /// or when `resumable` is `false`, e.g. in a closure.
+    /// The value is deep cloned, such that it does not refer to the suspended stack.
+    fn yield_value(
+        &mut self,
+        v: Variable,
+        resumable: bool,
+        range: Range,
+        module: &Module
+    ) -> Result<(), String> {
+        if !self.coroutine {
+            return Err(module.error(range, &format!(
+                "{}\n`yield` can only be used inside a coroutine", self.stack_trace()), self));
+        }
+        if !resumable {
+            return Err(module.error(range, &format!(
+                "{}\n`yield` can not suspend here, e.g. inside a closure \
+                or a call made by an external function", self.stack_trace()), self));
+        }
+        self.suspending = Some(Status::Yielded(v.deep_clone(&self.stack)));
+        Ok(())
+    }
+
+    /// Evaluates the arguments of a call to run on another thread.
+    ///
+    /// Returns a runtime for the thread with deep clones of the arguments on the stack,
+    /// and a call referring to them.
+    /// Returns `Err` when the evaluation of an argument returns from the function.
+    fn thread_call(
+        &mut self,
+        call: &ast::Call,
+        module: &Arc<Module>
+    ) -> Result<Result<(Runtime, ast::Call), (Option<Variable>, Flow)>, String> {
+        use std::cell::Cell;
+
+        let n = call.args.len();
+        // The thread gets a copy of the globals, followed by the arguments.
+        let mut stack = try!(self.copy_globals(module));
+        let globals = stack.len();
+        let relative = self.call_stack.last().map(|c| c.index).unwrap();
+        let mut fake_call = ast::Call {
+            alias: call.alias.clone(),
+            name: call.name.clone(),
+            f_index: Cell::new(module.find_function_defaults(&call.name, call.arg_len(), relative)),
+            args: Vec::with_capacity(n),
+            custom_source: None,
+            source_range: call.source_range,
+        };
+        // Evaluate the arguments and put a deep clone on the new stack.
+        // This prevents the arguments from containing any reference to other variables.
+        for (i, arg) in call.args.iter().enumerate() {
+            let v = match try!(self.expression(arg, Side::Right, module)) {
+                (Some(x), Flow::Continue) => x,
+                (x, Flow::Return) => { return Ok(Err((x, Flow::Return))); }
+                _ => return Err(module.error(arg.source_range(),
+                                &format!("{}\nExpected something. \
+                                Expression did not return a value.",
+                                self.stack_trace()), self))
+            };
+            stack.push(v.deep_clone(&self.stack));
+            fake_call.args.push(ast::Expression::Variable(
+                call.args[i].source_range(), Variable::Ref(globals+n-i-1)));
+        }
+        stack[globals..].reverse();
+        Ok(Ok((self.thread_runtime(stack), fake_call)))
+    }
+
+    /// Creates a runtime for another thread, sharing fuel, interrupts,
+    /// profiling and coverage with this runtime.
+    fn thread_runtime(&self, stack: Vec<Variable>) -> Runtime {
+        Runtime {
+            stack: stack,
+            local_stack: vec![],
+            current_stack: vec![],
+            globals: 0,
+            module_globals: vec![],
+            operands: vec![],
+            fuel: self.fuel.clone(),
+            steps: 0,
+            out_of_fuel: self.out_of_fuel.clone(),
+            interrupt: self.interrupt.clone(),
+            cancelled: None,
+            limits: self.limits,
+            workers: self.workers,
+            pool: self.pool.clone(),
+            heap: 0,
+            building: 0,
+            caller_capabilities: self.caller_capabilities.clone(),
+            debugger: None,
+            profiler: self.profiler.clone(),
+            profile_stack: profiler::Stack::default(),
+            coverage: self.coverage.clone(),
+            coroutine: false,
+            suspend_requested: false,
+            // Threads can not be suspended.
+            pinned: 1,
+            running: false,
+            frames: vec![],
+            suspending: None,
+            suspended: None,
+            updating: vec![],
+            // Add last call because of loaded functions
+            // use relative index to the function it is calling from.
+            call_stack: self.call_stack.last().map(|last_call| Call {
+                fn_name: last_call.fn_name.clone(),
+                index: last_call.index,
+                file: last_call.file.clone(),
+                stack_len: 0,
+                local_len: 0,
+                current_len: 0,
+            }).into_iter().collect(),
+            rng: self.rng.clone(),
+            ret: self.ret.clone(),
+            ref_type: self.ref_type.clone(),
+            option_type: self.option_type.clone(),
+            array_type: self.array_type.clone(),
+            link_type: self.link_type.clone(),
+            bool_type: self.bool_type.clone(),
+            object_type: self.object_type.clone(),
+            text_type: self.text_type.clone(),
+            f64_type: self.f64_type.clone(),
+            i64_type: self.i64_type.clone(),
+            thread_type: self.thread_type.clone(),
+            coroutine_type: self.coroutine_type.clone(),
+            channel_type: self.channel_type.clone(),
+            shared_type: self.shared_type.clone(),
+            unsafe_ref_type: self.unsafe_ref_type.clone(),
+            return_type: self.return_type.clone(),
+            rust_object_type: self.rust_object_type.clone(),
+            vec4_type: self.vec4_type.clone(),
+            result_type: self.result_type.clone(),
+            closure_type: self.closure_type.clone(),
+        }
+    }
+
+    pub fn call_closure(
+        &mut self,
+        call: &ast::CallClosure,
+        module: &Arc<Module>
+    ) -> Result<(Option<Variable>, Flow), String> {
+        // Find item.
+        let item = match try!(self.item(&call.item, Side::Right, module)) {
+            (Some(x), Flow::Continue) => x,
+            (x, Flow::Return) => { return Ok((x, Flow::Return)); }
+            _ => return Err(module.error(call.item.source_range,
+                            &format!("{}\nExpected something. \
+                            Check that item returns a value.",
+                            self.stack_trace()), self))
+        };
+
+        let (f, env) = match self.resolve(&item) {
+            &Variable::Closure(ref f, ref env) => (f.clone(), env.clone()),
+            x => return Err(module.error(call.source_range,
+                    &self.expected(x, "closure"), self))
         };
 
         if call.arg_len() != f.args.len() {
@@ -763,7 +1777,51 @@ impl Runtime {
                                 self.stack_trace()), self))
             };
         }
+        self.closure_body(&call.item.name, &f, &env, call.source_range, st, lc, cu, module)
+    }
+
+    /// Calls a closure with arguments from Rust, e.g. in an intrinsic.
+    pub fn call_closure_args(
+        &mut self,
+        closure: &Variable,
+        args: Vec<Variable>,
+        range: Range,
+        module: &Arc<Module>
+    ) -> Result<Option<Variable>, String> {
+        let (f, env) = match self.resolve(closure) {
+            &Variable::Closure(ref f, ref env) => (f.clone(), env.clone()),
+            x => return Err(module.error(range, &self.expected(x, "closure"), self))
+        };
+        if args.len() != f.args.len() {
+            return Err(module.error(range,
+                &format!("{}\nExpected {} arguments but found {}",
+                self.stack_trace(),
+                f.args.len(),
+                args.len()), self));
+        }
+        if f.returns() {
+            self.stack.push(Variable::Return);
+        }
+        let st = self.stack.len();
+        let lc = self.local_stack.len();
+        let cu = self.current_stack.len();
+        self.stack.extend(args);
+        let name = Arc::new("closure".into());
+        Ok(try!(self.closure_body(&name, &f, &env, range, st, lc, cu, module)).0)
+    }
 
+    /// Runs the body of a closure after the arguments are pushed on the stack.
+    fn closure_body(
+        &mut self,
+        name: &Arc<String>,
+        f: &Arc<ast::Closure>,
+        env: &ClosureEnvironment,
+        source_range: Range,
+        st: usize,
+        lc: usize,
+        cu: usize,
+        module: &Arc<Module>
+    ) -> Result<(Option<Variable>, Flow), String> {
         // Look for variable in current stack.
         if f.currents.len() > 0 {
             for current in &f.currents {
@@ -778,14 +1836,15 @@ impl Runtime {
                     self.local_stack.push((current.name.clone(), self.stack.len()));
                     self.stack.push(Variable::Ref(ind));
                 } else {
-                    return Err(module.error(call.source_range, &format!(
+                    return Err(module.error(source_range, &format!(
                         "{}\nCould not find current variable `{}`",
                             self.stack_trace(), current.name), self));
                 }
             }
         }
 
-        self.push_fn(call.item.name.clone(), env.relative, Some(f.file.clone()), st, lc, cu);
+        try!(self.check_call(source_range, module));
+        self.push_fn(name.clone(), env.relative, Some(f.file.clone()), st, lc, cu);
         if f.returns() {
             self.local_stack.push((self.ret.clone(), st - 1));
         }
@@ -793,36 +1852,39 @@ impl Runtime {
             // Do not resolve locals to keep fixed length from end of stack.
             self.local_stack.push((arg.name.clone(), st + i));
         }
-        let (x, flow) = try!(self.expression(&f.expr, Side::Right, &env.module));
+        self.pinned += 1;
+        let res = self.expression(&f.expr, Side::Right, &env.module);
+        self.pinned -= 1;
+        let (x, flow) = try!(res);
         match flow {
             Flow::Break(None) =>
-                return Err(module.error(call.source_range,
+                return Err(module.error(source_range,
                            &format!("{}\nCan not break from function",
                                 self.stack_trace()), self)),
             Flow::ContinueLoop(None) =>
-                return Err(module.error(call.source_range,
+                return Err(module.error(source_range,
                            &format!("{}\nCan not continue from function",
                                 self.stack_trace()), self)),
             Flow::Break(Some(ref label)) =>
-                return Err(module.error(call.source_range,
+                return Err(module.error(source_range,
                     &format!("{}\nThere is no loop labeled `{}`",
                              self.stack_trace(), label), self)),
             Flow::ContinueLoop(Some(ref label)) =>
-                return Err(module.error(call.source_range,
+                return Err(module.error(source_range,
                     &format!("{}\nThere is no loop labeled `{}`",
                             self.stack_trace(), label), self)),
             _ => {}
         }
-        self.pop_fn(call.item.name.clone());
+        self.pop_fn(name.clone());
         match (f.returns(), x) {
             (true, None) => {
                 match self.stack.pop().expect(TINVOTS) {
                     Variable::Return => {
                         return Err(module.error(
-                            call.source_range, &format!(
+                            source_range, &format!(
                             "{}\nFunction `{}` did not return a value",
                             self.stack_trace(),
-                            call.item.name), self))
+                            name), self))
                     }
                     x => {
                         // This happens when return is only
@@ -832,21 +1894,21 @@ impl Runtime {
                 };
             }
             (false, Some(_)) => {
-                return Err(module.error(call.source_range,
+                return Err(module.error(source_range,
                     &format!(
                         "{}\nFunction `{}` should not return a value",
                         self.stack_trace(),
-                        call.item.name), self))
+                        name), self))
             }
             (true, Some(Variable::Return)) => {
                 // TODO: Could return the last value on the stack.
                 //       Requires .pop_fn delayed after.
-                return Err(module.error(call.source_range,
+                return Err(module.error(source_range,
                     &format!(
                     "{}\nFunction `{}` did not return a value. \
                     Did you forget a `return`?",
                         self.stack_trace(),
-                        call.item.name), self))
+                        name), self))
             }
             (returns, b) => {
                 if returns { self.stack.pop(); }
@@ -856,12 +1918,242 @@ impl Runtime {
     }
 
     /// Called from the outside, e.g. a loader script by `call` or `call_ret` intrinsic.
+    ///
+    /// The constants and globals of the module keep their values between calls.
+    /// The call runs until it returns, without being suspended.
     pub fn call(
         &mut self,
         call: &ast::Call,
         module: &Arc<Module>
     ) -> Result<(Option<Variable>, Flow), String> {
-        self.call_internal(call, true, module)
+        let loader = true;
+        let resumable = false;
+        match try!(self.call_outside(call, loader, resumable, module)) {
+            Status::Done(x) => Ok((x, Flow::Continue)),
+            x => panic!("Expected call to return, found {:?}", x)
+        }
+    }
+
+    /// Calls a function from the outside.
+    ///
+    /// When `resumable` is `true`, the call can be suspended,
+    /// unless another call runs or is suspended.
+    fn call_outside(
+        &mut self,
+        call: &ast::Call,
+        loader: bool,
+        resumable: bool,
+        module: &Arc<Module>
+    ) -> Result<Status, String> {
+        let running = self.running;
+        let resumable = resumable && !running && self.suspended.is_none();
+        let st = self.stack.len();
+        let globals = try!(self.push_globals(module));
+        self.running = true;
+        if !resumable { self.pinned += 1; }
+        let res = self.call_internal(call, loader, module);
+        if !resumable { self.pinned -= 1; }
+        self.return_outside(res, call, st, globals, running, module)
+    }
+
+    /// Returns from a call from the outside, or keeps it to resume.
+    fn return_outside(
+        &mut self,
+        res: Result<(Option<Variable>, Flow), String>,
+        call: &ast::Call,
+        st: usize,
+        globals: usize,
+        running: bool,
+        module: &Arc<Module>
+    ) -> Result<Status, String> {
+        self.running = running;
+        let x = match res {
+            Ok((_, Flow::Suspend)) => {
+                self.suspended = Some(Suspended {
+                    call: call.clone(),
+                    module: module.id,
+                    stack_len: st,
+                    globals: globals,
+                });
+                return Ok(self.suspending.take().expect("Expected suspend status"));
+            }
+            // Copy returned reference to a global, since the globals are removed.
+            Ok((Some(Variable::Ref(ind)), _)) if ind >= st => Ok(Some(self.stack[ind].clone())),
+            Ok((x, _)) => Ok(x),
+            Err(err) => Err(err),
+        };
+        self.pop_globals(globals, st, module);
+        // A request to suspend has no effect when the call returns.
+        if !running { self.suspend_requested = false; }
+        x.map(Status::Done)
+    }
+
+    /// Pushes the constants and globals of a module on the stack.
+    ///
+    /// Evaluates the globals the first time the module runs on this runtime.
+    /// Returns the previous position of the globals.
+    fn push_globals(&mut self, module: &Arc<Module>) -> Result<usize, String> {
+        let values = try!(self.module_globals(module));
+        let globals = self.globals;
+        self.globals = self.stack.len();
+        self.stack.extend(values);
+        Ok(globals)
+    }
+
+    /// Keeps the values of the globals for the next call,
+    /// and removes the constants and globals from the stack.
+    fn pop_globals(&mut self, globals: usize, st: usize, module: &Arc<Module>) {
+        let values: Vec<Variable> = module.globals.iter().enumerate()
+            .filter(|&(_, g)| g.mutable)
+            .map(|(i, _)| self.stack[self.globals + i].deep_clone(&self.stack))
+            .collect();
+        if let Some(ind) = self.find_module_globals(module) {
+            self.module_globals[ind].1 = values;
+        }
+        self.stack.truncate(st);
+        self.globals = globals;
+    }
+
+    fn find_module_globals(&self, module: &Module) -> Option<usize> {
+        self.module_globals.iter().position(|&(id, _)| id == module.id)
+    }
+
+    /// Returns the values of the constants and globals of a module,
+    /// evaluating the globals the first time the module runs on this runtime.
+    fn module_globals(&mut self, module: &Arc<Module>) -> Result<Vec<Variable>, String> {
+        let mut kept = match self.find_module_globals(module) {
+            Some(ind) => self.module_globals[ind].1.clone().into_iter(),
+            None => vec![].into_iter()
+        };
+        let mut values = Vec::with_capacity(module.globals.len());
+        for g in &module.globals {
+            match g.value {
+                Some(ref val) => values.push(val.clone()),
+                None if g.mutable => match kept.next() {
+                    Some(val) => values.push(val),
+                    None => break
+                },
+                // Not evaluated when checked by `check_str`.
+                None => break
+            }
+        }
+        if values.len() < module.globals.len() {
+            values = try!(self.eval_globals(values, false, module));
+            let kept: Vec<Variable> = module.globals.iter().zip(values.iter())
+                .filter(|&(g, _)| g.mutable)
+                .map(|(_, val)| val.clone())
+                .collect();
+            match self.find_module_globals(module) {
+                Some(ind) => self.module_globals[ind].1 = kept,
+                None => self.module_globals.push((module.id, kept))
+            }
+        }
+        Ok(values)
+    }
+
+    /// Copies the constants and globals for another thread.
+    ///
+    /// Uses the current values when running.
+    fn copy_globals(&mut self, module: &Arc<Module>) -> Result<Vec<Variable>, String> {
+        if self.call_stack.len() == 0 {
+            return self.module_globals(module);
+        }
+        let n = module.globals.len();
+        Ok(self.stack[self.globals..self.globals + n].iter()
+            .map(|v| v.deep_clone(&self.stack)).collect())
+    }
+
+    /// Evaluates the constants of a module not evaluated yet, when loading.
+    ///
+    /// Returns the values of all constants, in order of declaration,
+    /// or `None` for globals.
+    pub(crate) fn eval_consts(module: &Module) -> Result<Vec<Option<Variable>>, String> {
+        let module = Arc::new(module.clone());
+        let values = try!(Runtime::new().eval_globals(vec![], true, &module));
+        Ok(module.globals.iter().zip(values.into_iter())
+            .map(|(g, val)| if g.mutable { None } else { Some(val) })
+            .collect())
+    }
+
+    /// Evaluates the constants and globals following `values`, in order of declaration.
+    ///
+    /// Constants evaluated when loaded keep their values.
+    /// When `consts` is `true`, the globals are not evaluated.
+    ///
+    /// The functions evaluating the values are placed after the functions
+    /// loaded before them, which they are resolved relative to.
+    fn eval_globals(
+        &mut self,
+        values: Vec<Variable>,
+        consts: bool,
+        module: &Arc<Module>
+    ) -> Result<Vec<Variable>, String> {
+        use std::cell::Cell;
+
+        let start = values.len();
+        let n = module.globals.len();
+        let globals = self.globals;
+        let st = self.stack.len();
+        self.globals = st;
+        self.pinned += 1;
+        self.stack.extend(values);
+        for _ in start..n { self.stack.push(Variable::Return); }
+        let mut res = Ok(());
+        let mut m: Option<Arc<Module>> = None;
+        for i in start..n {
+            let g = &module.globals[i];
+            if let Some(ref val) = g.value {
+                self.stack[st + i] = val.clone();
+                continue;
+            }
+            if consts && g.mutable { continue; }
+            let (functions, j) = g.position.get().expect("Expected resolved global");
+            if j == 0 || m.is_none() {
+                let mut new_module = (**module).clone();
+                new_module.functions.truncate(functions);
+                for (k, g2) in module.globals[i - j..].iter().enumerate() {
+                    if g2.position.get() != Some((functions, k)) { break; }
+                    new_module.functions.push(g2.init.clone());
+                }
+                m = Some(Arc::new(new_module));
+            }
+            let new_module = m.as_ref().unwrap().clone();
+            let call = ast::Call {
+                alias: None,
+                name: g.name.clone(),
+                f_index: Cell::new(FnIndex::Loaded((functions + j) as isize)),
+                args: vec![],
+                custom_source: None,
+                source_range: g.source_range,
+            };
+            let v = match self.call_internal(&call, true, &new_module) {
+                Ok((Some(x), _)) => x,
+                Ok((None, _)) => {
+                    res = Err(module.error_source(g.source_range,
+                        &format!("Expected value of `{}`", g.name), &g.init.source));
+                    break;
+                }
+                Err(err) => {
+                    res = Err(err);
+                    break;
+                }
+            };
+            let v = self.resolve(&v).deep_clone(&self.stack);
+            if let Variable::Return = v {
+                // Globals are not evaluated when evaluating constants.
+                res = Err(module.error_source(g.source_range,
+                    &format!("Constant `{}` can not use globals", g.name), &g.init.source));
+                break;
+            }
+            self.stack[st + i] = v;
+        }
+        self.pinned -= 1;
+        self.globals = globals;
+        if let Err(err) = res {
+            self.stack.truncate(st);
+            return Err(err);
+        }
+        Ok(self.stack.drain(st..).collect())
     }
 
     /// Used internally because loaded functions are resolved
@@ -892,194 +2184,1012 @@ impl Runtime {
                                         self.stack_trace()), self))
                     };
                 }
-                try!((f)(self).map_err(|err|
-                    module.error(call.source_range, &err, self)));
-                return Ok((None, Flow::Continue));
-            }
-            FnIndex::ExternalReturn(FnExternalRef(f)) => {
-                for arg in &call.args {
-                    match try!(self.expression(arg, Side::Right, module)) {
-                        (Some(x), Flow::Continue) => self.stack.push(x),
-                        (x, Flow::Return) => { return Ok((x, Flow::Return)); }
-                        _ => return Err(module.error(arg.source_range(),
-                                        &format!("{}\nExpected something. \
-                                        Expression did not return a value.",
+                try!((f)(self).map_err(|err|
+                    module.error(call.source_range, &err, self)));
+                if self.suspend_requested {
+                    self.suspend_external(false);
+                }
+                return Ok((None, Flow::Continue));
+            }
+            FnIndex::ExternalReturn(FnExternalRef(f)) => {
+                for arg in &call.args {
+                    match try!(self.expression(arg, Side::Right, module)) {
+                        (Some(x), Flow::Continue) => self.stack.push(x),
+                        (x, Flow::Return) => { return Ok((x, Flow::Return)); }
+                        _ => return Err(module.error(arg.source_range(),
+                                        &format!("{}\nExpected something. \
+                                        Expression did not return a value.",
+                                        self.stack_trace()), self))
+                    };
+                }
+                try!((f)(self).map_err(|err|
+                    module.error(call.source_range, &err, self)));
+                if self.suspend_requested {
+                    self.suspend_external(false);
+                }
+                return Ok((Some(self.stack.pop().expect(TINVOTS)), Flow::Continue));
+            }
+            FnIndex::ExternalDefaults(i) => {
+                for arg in &call.args {
+                    match try!(self.expression(arg, Side::Right, module)) {
+                        (Some(x), Flow::Continue) => self.stack.push(x),
+                        (x, Flow::Return) => { return Ok((x, Flow::Return)); }
+                        _ => return Err(module.error(arg.source_range(),
+                                        &format!("{}\nExpected something. \
+                                        Expression did not return a value.",
+                                        self.stack_trace()), self))
+                    };
+                }
+                let x = try!(self.call_external_defaults(call, i, module));
+                if self.suspend_requested {
+                    self.suspend_external(false);
+                }
+                Ok((x, Flow::Continue))
+            }
+            FnIndex::Loaded(f_index) => {
+                let relative = if loader {0} else {
+                    self.call_stack.last().map(|c| c.index).unwrap_or(0)
+                };
+                let new_index = (f_index + relative as isize) as usize;
+                let f = &module.functions[new_index];
+                if !f.accepts(call.arg_len()) {
+                    let defaults = f.args.iter().rev().take_while(|arg| arg.default.is_some())
+                        .count();
+                    return Err(module.error(call.source_range,
+                        &format!("{}\nExpected {} arguments but found {}",
+                        self.stack_trace(),
+                        arg_range(f.args.len() - defaults, f.args.len()),
+                        call.arg_len()), self));
+                }
+                // Arguments must be computed.
+                if f.returns() {
+                    // Add return value before arguments on the stack.
+                    // The stack value should remain, but the local should not.
+                    self.stack.push(Variable::Return);
+                }
+                let st = self.stack.len();
+                let lc = self.local_stack.len();
+                let cu = self.current_stack.len();
+                for arg in &call.args {
+                    match try!(self.expression(arg, Side::Right, module)) {
+                        (Some(x), Flow::Continue) => self.stack.push(x),
+                        (None, Flow::Continue) => {}
+                        (x, Flow::Return) => { return Ok((x, Flow::Return)); }
+                        _ => return Err(module.error(arg.source_range(),
+                                        &format!("{}\nExpected something. \
+                                        Check that expression returns a value.",
+                                        self.stack_trace()), self))
+                    };
+                }
+                // Push default values of omitted arguments.
+                for arg in &f.args[call.arg_len()..] {
+                    self.stack.push(arg.default.as_ref().unwrap().to_variable());
+                }
+
+                self.call_loaded(call, new_index, st, lc, cu, module)
+            }
+            FnIndex::None => {
+                return Err(module.error(call.source_range,
+                    &format!("{}\nUnknown function `{}`", self.stack_trace(), call.name), self))
+            }
+        }
+    }
+
+    /// Calls an external function after the arguments are pushed on the stack,
+    /// pushing default values of the omitted arguments.
+    fn call_external_defaults(
+        &mut self,
+        call: &ast::Call,
+        i: usize,
+        module: &Arc<Module>
+    ) -> Result<Option<Variable>, String> {
+        let f = &module.ext_prelude[i];
+        let omitted = f.p.tys.len() - call.arg_len();
+        for val in &f.p.defaults[f.p.defaults.len() - omitted..] {
+            self.stack.push(val.to_variable());
+        }
+        try!((f.f)(self).map_err(|err|
+            module.error(call.source_range, &err, self)));
+        Ok(if f.p.returns() { Some(self.stack.pop().expect(TINVOTS)) } else { None })
+    }
+
+    /// Calls a loaded function after the arguments are pushed on the stack.
+    fn call_loaded(
+        &mut self,
+        call: &ast::Call,
+        new_index: usize,
+        st: usize,
+        lc: usize,
+        cu: usize,
+        module: &Arc<Module>
+    ) -> Result<(Option<Variable>, Flow), String> {
+        let f = &module.functions[new_index];
+        // Look for variable in current stack.
+        if f.currents.len() > 0 {
+            for current in &f.currents {
+                let mut res = None;
+                for &(ref cname, ind) in self.current_stack.iter().rev() {
+                    if cname == &current.name {
+                        res = Some(ind);
+                        break;
+                    }
+                }
+                if let Some(ind) = res {
+                    self.local_stack.push((current.name.clone(), self.stack.len()));
+                    self.stack.push(Variable::Ref(ind));
+                } else {
+                    return Err(module.error(call.source_range, &format!(
+                        "{}\nCould not find current variable `{}`",
+                            self.stack_trace(), current.name), self));
+                }
+            }
+        }
+
+        try!(self.check_call(call.source_range, module));
+        self.push_fn(call.name.clone(), new_index, Some(f.file.clone()), st, lc, cu);
+        if f.returns() {
+            self.local_stack.push((self.ret.clone(), st - 1));
+        }
+        for (i, arg) in f.args.iter().enumerate() {
+            // Do not resolve locals to keep fixed length from end of stack.
+            self.local_stack.push((arg.name.clone(), st + i));
+        }
+        let hooks = self.hooks();
+        let chunk = if hooks { &f.bytecode_hooks } else { &f.bytecode };
+        let res = match *chunk {
+            Some(ref chunk) => try!(self.bytecode(chunk, new_index, hooks, module)),
+            None => {
+                self.pinned += 1;
+                let res = self.block(&f.block, module);
+                self.pinned -= 1;
+                try!(res)
+            }
+        };
+        self.return_loaded(call, new_index, res, module)
+    }
+
+    /// Returns from a loaded function called by `call_loaded`.
+    fn return_loaded(
+        &mut self,
+        call: &ast::Call,
+        new_index: usize,
+        (x, flow): (Option<Variable>, Flow),
+        module: &Arc<Module>
+    ) -> Result<(Option<Variable>, Flow), String> {
+        let f = &module.functions[new_index];
+        match flow {
+            // Keep the function on the call stack to resume it.
+            Flow::Suspend => return Ok((None, Flow::Suspend)),
+            Flow::Break(None) =>
+                return Err(module.error(call.source_range,
+                           &format!("{}\nCan not break from function",
+                                self.stack_trace()), self)),
+            Flow::ContinueLoop(None) =>
+                return Err(module.error(call.source_range,
+                           &format!("{}\nCan not continue from function",
+                                self.stack_trace()), self)),
+            Flow::Break(Some(ref label)) =>
+                return Err(module.error(call.source_range,
+                    &format!("{}\nThere is no loop labeled `{}`",
+                             self.stack_trace(), label), self)),
+            Flow::ContinueLoop(Some(ref label)) =>
+                return Err(module.error(call.source_range,
+                    &format!("{}\nThere is no loop labeled `{}`",
+                            self.stack_trace(), label), self)),
+            _ => {}
+        }
+        self.pop_call(&call.name);
+        match (f.returns(), x) {
+            (true, None) => {
+                match self.stack.pop().expect(TINVOTS) {
+                    Variable::Return => {
+                        let source = call.custom_source.as_ref().unwrap_or(
+                            &module.functions[
+                                self.call_stack.last().unwrap().index
+                            ].source
+                        );
+                        return Err(module.error_source(
+                        call.source_range, &format!(
+                        "{}\nFunction `{}` did not return a value",
+                        self.stack_trace(),
+                        f.name), source))
+                    }
+                    x => {
+                        // This happens when return is only
+                        // assigned to `return = x`.
+                        return Ok((Some(x), Flow::Continue))
+                    }
+                };
+            }
+            (false, Some(_)) => {
+                let source = call.custom_source.as_ref().unwrap_or(
+                    &module.functions[self.call_stack.last().unwrap().index].source
+                );
+                return Err(module.error_source(call.source_range,
+                    &format!(
+                        "{}\nFunction `{}` should not return a value",
+                        self.stack_trace(),
+                        f.name), source))
+            }
+            (true, Some(Variable::Return)) => {
+                // TODO: Could return the last value on the stack.
+                //       Requires .pop_fn delayed after.
+                let source = call.custom_source.as_ref().unwrap_or(
+                    &module.functions[self.call_stack.last().unwrap().index].source
+                );
+                return Err(module.error_source(call.source_range,
+                    &format!(
+                    "{}\nFunction `{}` did not return a value. \
+                    Did you forget a `return`?",
+                        self.stack_trace(),
+                        f.name), source))
+            }
+            (returns, b) => {
+                if returns { self.stack.pop(); }
+                return Ok((b, Flow::Continue))
+            }
+        }
+    }
+
+    /// Executes a function body compiled to bytecode.
+    ///
+    /// Expects the return value, arguments and currents to be pushed on the stack.
+    fn bytecode(
+        &mut self,
+        chunk: &bytecode::Chunk,
+        index: usize,
+        hooks: bool,
+        module: &Arc<Module>
+    ) -> Result<(Option<Variable>, Flow), String> {
+        let frame = Frame {
+            index: index,
+            hooks: hooks,
+            pc: 0,
+            obase: self.operands.len(),
+            sbase: self.stack.len() - chunk.frame,
+            lbase: self.local_stack.len() - chunk.frame,
+            cbase: self.current_stack.len(),
+        };
+        let res = self.bytecode_ops(chunk, frame, module);
+        if let Ok((_, Flow::Suspend)) = res {} else {
+            self.operands.truncate(frame.obase);
+        }
+        res
+    }
+
+    /// Resumes the outer suspended frame, and returns from the function like `call_loaded`.
+    fn resume_loaded(
+        &mut self,
+        call: &ast::Call,
+        module: &Arc<Module>
+    ) -> Result<(Option<Variable>, Flow), String> {
+        let mut frame = self.frames.pop().expect("Expected suspended frame");
+        let f = &module.functions[frame.index];
+        let chunk = if frame.hooks { &f.bytecode_hooks } else { &f.bytecode };
+        let chunk = chunk.as_ref().expect("Expected bytecode");
+        if self.frames.len() > 0 {
+            // Resume the call this function is suspended in.
+            let inner = match chunk.ops[frame.pc] {
+                bytecode::Op::Call(i) => &chunk.calls[i],
+                _ => panic!("Expected call")
+            };
+            match self.resume_loaded(inner, module) {
+                Ok((x, Flow::Continue)) => {
+                    self.operands.push(x);
+                    frame.pc += 1;
+                }
+                Ok((_, Flow::Suspend)) => {
+                    self.frames.push(frame);
+                    return Ok((None, Flow::Suspend));
+                }
+                res => {
+                    self.operands.truncate(frame.obase);
+                    return res;
+                }
+            }
+        }
+        let res = self.bytecode_ops(chunk, frame, module);
+        if let Ok((_, Flow::Suspend)) = res {} else {
+            self.operands.truncate(frame.obase);
+        }
+        self.return_loaded(call, frame.index, try!(res), module)
+    }
+
+    /// Keeps a frame to resume at an instruction, and suspends.
+    #[inline(never)]
+    fn suspend_frame(&mut self, frame: Frame, pc: usize) -> Result<(Option<Variable>, Flow), String> {
+        self.frames.push(Frame { pc: pc, ..frame });
+        Ok((None, Flow::Suspend))
+    }
+
+    fn bytecode_ops(
+        &mut self,
+        chunk: &bytecode::Chunk,
+        frame: Frame,
+        module: &Arc<Module>
+    ) -> Result<(Option<Variable>, Flow), String> {
+        use bytecode::{Acc, Base, Op};
+
+        let Frame { mut pc, obase, sbase, lbase, cbase, .. } = frame;
+        while pc < chunk.ops.len() {
+            if self.steps == 0 {
+                let resumable = self.pinned == 0;
+                if try!(self.safe_point(chunk.ranges[pc], resumable, module)) {
+                    return self.suspend_frame(frame, pc);
+                }
+            }
+            self.steps -= 1;
+            match chunk.ops[pc] {
+                Op::Const(ref v) => self.operands.push(Some(v.clone())),
+                Op::Number(val) => self.operands.push(Some(Variable::f64(val))),
+                Op::Void => self.operands.push(None),
+                Op::Local(slot) => {
+                    let ind = sbase + slot;
+                    let ind = if let Variable::Ref(ref_id) = self.stack[ind] {
+                        ref_id
+                    } else {
+                        ind
+                    };
+                    self.operands.push(Some(Variable::Ref(ind)));
+                }
+                Op::Global(ind) => {
+                    let ind = self.base_index(Base::Global(ind), sbase);
+                    self.operands.push(Some(Variable::Ref(ind)));
+                }
+                Op::Item(base, i) => {
+                    let item = &chunk.items[i];
+                    let ind = self.base_index(base, sbase);
+                    let start = self.stack.len() - bytecode::computed_ids(item);
+                    match try!(self.item_ids(item, ind, start, Side::Right, module)) {
+                        (x, Flow::Continue) => self.operands.push(x),
+                        x => return Ok(x)
+                    }
+                }
+                Op::Eval(i) => {
+                    let (ref expr, ref loop_ids) = chunk.exprs[i];
+                    self.pinned += 1;
+                    let res = self.expression(expr, Side::Right, module);
+                    self.pinned -= 1;
+                    let (label, is_break) = match try!(res) {
+                        (x, Flow::Continue) => {
+                            self.operands.push(x);
+                            pc += 1;
+                            continue;
+                        }
+                        (x, Flow::Return) => return Ok((x, Flow::Return)),
+                        (_, Flow::Break(label)) => (label, true),
+                        (_, Flow::ContinueLoop(label)) => (label, false),
+                        (_, Flow::Suspend) => panic!("Expected no suspend"),
+                    };
+                    let loop_ids = match *loop_ids {
+                        Some(ref loop_ids) => loop_ids,
+                        None => return Err(module.error(expr.source_range(),
+                                    &format!("{}\nExpected something",
+                                        self.stack_trace()), self))
+                    };
+                    match bytecode::find_loop(&chunk.loops, loop_ids, &label) {
+                        Some(id) => {
+                            let lp = &chunk.loops[id];
+                            self.truncate_scope(&lp.scope, sbase, lbase, cbase);
+                            self.operands.truncate(obase + lp.scope.operands);
+                            pc = if is_break { lp.end } else { lp.next };
+                            continue;
+                        }
+                        None => {
+                            return Ok((None, if is_break {
+                                Flow::Break(label)
+                            } else {
+                                Flow::ContinueLoop(label)
+                            }));
+                        }
+                    }
+                }
+                Op::Pop => drop_operand(self.operands.pop().expect(TINVOTS)),
+                Op::Nothing(range, msg) => {
+                    match self.operands.pop().expect(TINVOTS) {
+                        None => {}
+                        Some(_) => return Err(module.error(range,
+                            &format!("{}\n{}", self.stack_trace(), msg), self))
+                    }
+                }
+                Op::Declare { ref name, current, range } => {
+                    let v = match self.operands.pop().expect(TINVOTS) {
+                        // Use a shallow clone of a reference.
+                        Some(Variable::Ref(ind)) => self.stack[ind].clone(),
+                        Some(x) => x,
+                        None => return Err(module.error(range,
+                                    &format!("{}\nExpected something from the right side",
+                                        self.stack_trace()), self))
+                    };
+                    self.local_stack.push((name.clone(), self.stack.len()));
+                    if current {
+                        self.current_stack.push((name.clone(), self.stack.len()));
+                    }
+                    self.stack.push(v);
+                }
+                Op::AssignLocal { op, slot, left, right } => {
+                    let b = self.operands.pop().expect(TINVOTS);
+                    let ind = sbase + slot;
+                    let ind = if let Variable::Ref(ref_id) = self.stack[ind] {
+                        ref_id
+                    } else {
+                        ind
+                    };
+                    let r = UnsafeRef(&mut self.stack[ind] as *mut Variable);
+                    match b {
+                        Some(ref b) => try!(self.assign_variable(op, r, b, left, module)),
+                        None => return Err(module.error(right,
+                                    &format!("{}\nExpected something from the right side",
+                                        self.stack_trace()), self))
+                    }
+                    drop_operand(b);
+                }
+                Op::AssignItem { op, base, item, left, right } => {
+                    let b = match self.operands.pop().expect(TINVOTS) {
+                        Some(x) => x,
+                        None => return Err(module.error(right,
+                                    &format!("{}\nExpected something from the right side",
+                                        self.stack_trace()), self))
+                    };
+                    let item = &chunk.items[item];
+                    let ind = self.base_index(base, sbase);
+                    let r = if item.ids.len() == 0 {
+                        UnsafeRef(&mut self.stack[ind] as *mut Variable)
+                    } else {
+                        let start = self.stack.len() - bytecode::computed_ids(item);
+                        let side = Side::LeftInsert(op == ast::AssignOp::Assign);
+                        match try!(self.item_ids(item, ind, start, side, module)) {
+                            (Some(Variable::UnsafeRef(r)), Flow::Continue) => r,
+                            (x, Flow::Return) => return Ok((x, Flow::Return)),
+                            _ => panic!("Expected unsafe reference")
+                        }
+                    };
+                    if op == ast::AssignOp::Assign {
+                        let v = match b {
+                            // Use a shallow clone of a reference.
+                            Variable::Ref(ind) => self.stack[ind].clone(),
+                            x => x
+                        };
+                        unsafe { *r.0 = v }
+                    } else {
+                        // If reference, use a shallow clone to type check,
+                        // without affecting the original object.
+                        unsafe {
+                            if let Variable::Ref(ind) = *r.0 {
+                                *r.0 = self.stack[ind].clone()
+                            }
+                        }
+                        try!(self.assign_variable(op, r, &b, left, module));
+                        drop_operand(Some(b));
+                    }
+                }
+                Op::Norm(range) => {
+                    let x = self.operands.pop().expect(TINVOTS);
+                    let v = match x {
+                        Some(ref x) => match *self.resolve(x) {
+                            Variable::Vec4(b) => Variable::f64(
+                                (b[0] * b[0] + b[1] * b[1] + b[2] * b[2]).sqrt() as f64),
+                            ref x => return Err(module.error(range,
+                                &self.expected(x, "vec4"), self))
+                        },
+                        None => return Err(module.error(range,
+                            &format!("{}\nExpected something from unary argument",
+                                self.stack_trace()), self))
+                    };
+                    drop_operand(x);
+                    self.operands.push(Some(v));
+                }
+                Op::UnOp(op, range) => {
+                    let x = self.operands.pop().expect(TINVOTS);
+                    let v = match x {
+                        Some(ref x) => try!(self.unop_variable(op, range, x, module)),
+                        None => return Err(module.error(range,
+                            &format!("{}\nExpected something from unary argument",
+                                self.stack_trace()), self))
+                    };
+                    drop_operand(x);
+                    self.operands.push(Some(v));
+                }
+                Op::BinOp(op, range) => {
+                    let right = self.operands.pop().expect(TINVOTS);
+                    let left = self.operands.pop().expect(TINVOTS);
+                    let v = match (&left, &right) {
+                        (&Some(ref a), &Some(ref b)) =>
+                            try!(self.binop_variables(op, range, a, b, module)),
+                        (None, _) => return Err(module.error(range,
+                            &format!("{}\nExpected something from left argument",
+                                self.stack_trace()), self)),
+                        (_, None) => return Err(module.error(range,
+                            &format!("{}\nExpected something from right argument",
+                                self.stack_trace()), self)),
+                    };
+                    drop_operand(left);
+                    drop_operand(right);
+                    if let Variable::Text(ref t) = v {
+                        try!(self.alloc(t.len(), range, module));
+                    }
+                    self.operands.push(Some(v));
+                }
+                Op::Lazy(op, range, target) => {
+                    let v = match *self.operands.last().expect(TINVOTS) {
+                        Some(ref x) => {
+                            match (op, self.resolve(x)) {
+                                (ast::BinOp::OrElse, &Variable::Bool(true, ref sec)) =>
+                                    Some(Variable::Bool(true, sec.clone())),
+                                (ast::BinOp::AndAlso, &Variable::Bool(false, ref sec)) =>
+                                    Some(Variable::Bool(false, sec.clone())),
+                                _ => None
+                            }
+                        }
+                        None => return Err(module.error(range,
+                            &format!("{}\nExpected something from left argument",
+                                self.stack_trace()), self))
+                    };
+                    if let Some(v) = v {
+                        *self.operands.last_mut().unwrap() = Some(v);
+                        pc = target;
+                        continue;
+                    }
+                }
+                Op::Compare { op, range, left, right } => {
+                    let b = self.operands.pop().expect(TINVOTS);
+                    let a = self.operands.pop().expect(TINVOTS);
+                    let v = match (&a, &b) {
+                        (&Some(ref a), &Some(ref b)) =>
+                            try!(self.compare_variables(op, range, a, b, module)),
+                        (None, _) => return Err(module.error(left,
+                            &format!("{}\nExpected something from the left argument",
+                                self.stack_trace()), self)),
+                        (_, None) => return Err(module.error(right,
+                            &format!("{}\nExpected something from the right argument",
+                                self.stack_trace()), self)),
+                    };
+                    drop_operand(a);
+                    drop_operand(b);
+                    self.operands.push(Some(v));
+                }
+                Op::Jump(target) => {
+                    pc = target;
+                    continue;
+                }
+                Op::JumpIf(cond, target, range, msg) => {
+                    let x = self.operands.pop().expect(TINVOTS);
+                    let val = match x {
+                        Some(ref x) => {
+                            match *self.resolve(x) {
+                                Variable::Bool(val, _) => Some(val),
+                                _ => None
+                            }
+                        }
+                        None => None
+                    };
+                    drop_operand(x);
+                    match val {
+                        Some(val) => {
+                            if val == cond {
+                                pc = target;
+                                continue;
+                            }
+                        }
+                        None => return Err(module.error(range,
+                            &format!("{}\n{}", self.stack_trace(), msg), self))
+                    }
+                }
+                Op::Truncate(ref scope) => self.truncate_scope(scope, sbase, lbase, cbase),
+                Op::PushReturn => self.stack.push(Variable::Return),
+                Op::Push(range, msg) => {
+                    match self.operands.pop().expect(TINVOTS) {
+                        Some(x) => self.stack.push(x),
+                        None => return Err(module.error(range,
+                                    &format!("{}\n{}", self.stack_trace(), msg), self))
+                    }
+                }
+                Op::Vec4(ref ranges) => {
+                    let mut v = [0.0; 4];
+                    let st = self.stack.len() - 4;
+                    for i in 0..4 {
+                        v[i] = match *self.resolve(&self.stack[st + i]) {
+                            Variable::F64(val, _) => val as f32,
+                            ref x => return Err(module.error(ranges[i],
+                                &self.expected(x, "number"), self))
+                        };
+                    }
+                    self.stack.truncate(st);
+                    self.operands.push(Some(Variable::Vec4(v)));
+                }
+                Op::Array(n, range) => {
+                    use std::mem::size_of;
+
+                    try!(self.alloc(n * size_of::<Variable>(), range, module));
+                    let st = self.stack.len() - n;
+                    let array: Vec<Variable> = self.stack.drain(st..).collect();
+                    self.operands.push(Some(Variable::Array(Arc::new(array))));
+                }
+                Op::Object(ref keys, range) => {
+                    use std::mem::size_of;
+
+                    try!(self.alloc(keys.len() * size_of::<(Arc<String>, Variable)>(),
+                                    range, module));
+                    let st = self.stack.len() - keys.len();
+                    let values: Vec<Variable> = self.stack.drain(st..).collect();
+                    let mut object: HashMap<_, _> = HashMap::new();
+                    for (&(ref key, val_range), x) in keys.iter().zip(values) {
+                        if object.insert(key.clone(), x).is_some() {
+                            return Err(module.error(val_range,
+                                &format!("{}\nDuplicate key in object `{}`",
+                                    self.stack_trace(), key), self));
+                        }
+                    }
+                    self.operands.push(Some(Variable::Object(Arc::new(object))));
+                }
+                Op::Call(i) => {
+                    let call = &chunk.calls[i];
+                    let f_index = match call.f_index.get() {
+                        FnIndex::Loaded(f_index) => f_index,
+                        _ => panic!("Expected loaded function")
+                    };
+                    let relative = self.call_stack.last().map(|c| c.index).unwrap_or(0);
+                    let new_index = (f_index + relative as isize) as usize;
+                    let st = self.stack.len() - module.functions[new_index].args.len();
+                    let lc = self.local_stack.len();
+                    let cu = self.current_stack.len();
+                    match try!(self.call_loaded(call, new_index, st, lc, cu, module)) {
+                        (x, Flow::Continue) => self.operands.push(x),
+                        (_, Flow::Suspend) => return self.suspend_frame(frame, pc),
+                        x => return Ok(x)
+                    }
+                }
+                Op::CallIntrinsic(index, i) => {
+                    let x = try!(intrinsics::call_pushed(self, index, &chunk.calls[i], module));
+                    self.operands.push(x);
+                }
+                Op::CallExternal(i) => {
+                    use FnExternalRef;
+
+                    let call = &chunk.calls[i];
+                    let (f, returns) = match call.f_index.get() {
+                        FnIndex::ExternalVoid(FnExternalRef(f)) => (f, false),
+                        FnIndex::ExternalReturn(FnExternalRef(f)) => (f, true),
+                        _ => panic!("Expected external function")
+                    };
+                    try!((f)(self).map_err(|err|
+                        module.error(call.source_range, &err, self)));
+                    let x = if returns { Some(self.stack.pop().expect(TINVOTS)) } else { None };
+                    self.operands.push(x);
+                    if self.suspend_requested {
+                        let resumable = self.pinned == 0;
+                        if self.suspend_external(resumable) {
+                            return self.suspend_frame(frame, pc + 1);
+                        }
+                    }
+                }
+                Op::CallExternalDefaults(index, i) => {
+                    let call = &chunk.calls[i];
+                    let x = try!(self.call_external_defaults(call, index, module));
+                    self.operands.push(x);
+                    if self.suspend_requested {
+                        let resumable = self.pinned == 0;
+                        if self.suspend_external(resumable) {
+                            return self.suspend_frame(frame, pc + 1);
+                        }
+                    }
+                }
+                Op::Return(range) => {
+                    return match self.operands.pop().expect(TINVOTS) {
+                        Some(x) => Ok((Some(x), Flow::Return)),
+                        None => Err(module.error(range,
+                                    &format!("{}\nExpected something",
+                                        self.stack_trace()), self))
+                    };
+                }
+                Op::ReturnVoid => return Ok((None, Flow::Return)),
+                Op::Yield(range) => {
+                    let v = match self.operands.pop().expect(TINVOTS) {
+                        Some(x) => x,
+                        None => return Err(module.error(range,
+                                    &format!("{}\nExpected something",
                                         self.stack_trace()), self))
                     };
+                    let resumable = self.pinned == 0;
+                    try!(self.yield_value(v, resumable, range, module));
+                    // `yield` has no value.
+                    self.operands.push(None);
+                    return self.suspend_frame(frame, pc + 1);
                 }
-                try!((f)(self).map_err(|err|
-                    module.error(call.source_range, &err, self)));
-                return Ok((Some(self.stack.pop().expect(TINVOTS)), Flow::Continue));
-            }
-            FnIndex::Loaded(f_index) => {
-                let relative = if loader {0} else {
-                    self.call_stack.last().map(|c| c.index).unwrap_or(0)
-                };
-                let new_index = (f_index + relative as isize) as usize;
-                let f = &module.functions[new_index];
-                if call.arg_len() != f.args.len() {
-                    return Err(module.error(call.source_range,
-                        &format!("{}\nExpected {} arguments but found {}",
-                        self.stack_trace(),
-                        f.args.len(),
-                        call.arg_len()), self));
-                }
-                // Arguments must be computed.
-                if f.returns() {
-                    // Add return value before arguments on the stack.
-                    // The stack value should remain, but the local should not.
-                    self.stack.push(Variable::Return);
+                Op::Break(id) | Op::Continue(id) => {
+                    let lp = &chunk.loops[id];
+                    self.truncate_scope(&lp.scope, sbase, lbase, cbase);
+                    self.operands.truncate(obase + lp.scope.operands);
+                    pc = if let Op::Break(_) = chunk.ops[pc] { lp.end } else { lp.next };
+                    continue;
                 }
-                let st = self.stack.len();
-                let lc = self.local_stack.len();
-                let cu = self.current_stack.len();
-                for arg in &call.args {
-                    match try!(self.expression(arg, Side::Right, module)) {
-                        (Some(x), Flow::Continue) => self.stack.push(x),
-                        (None, Flow::Continue) => {}
-                        (x, Flow::Return) => { return Ok((x, Flow::Return)); }
-                        _ => return Err(module.error(arg.source_range(),
-                                        &format!("{}\nExpected something. \
-                                        Check that expression returns a value.",
-                                        self.stack_trace()), self))
+                Op::BreakOut(ref label) => return Ok((None, Flow::Break(label.clone()))),
+                Op::ContinueOut(ref label) => return Ok((None, Flow::ContinueLoop(label.clone()))),
+                Op::ForNInit { ref name, range } => {
+                    let end = self.operands.pop().expect(TINVOTS);
+                    let start = self.operands.pop().expect(TINVOTS);
+                    let start = match start {
+                        Some(x) => match *self.resolve(&x) {
+                            Variable::F64(val, _) => val,
+                            ref x => return Err(module.error(range,
+                                        &self.expected(x, "number"), self))
+                        },
+                        None => return Err(module.error(range,
+                            &format!("{}\nExpected number from for start",
+                                self.stack_trace()), self))
+                    };
+                    let end = match end {
+                        Some(x) => match *self.resolve(&x) {
+                            Variable::F64(val, _) => val,
+                            ref x => return Err(module.error(range,
+                                        &self.expected(x, "number"), self))
+                        },
+                        None => return Err(module.error(range,
+                            &format!("{}\nExpected number from for end",
+                                self.stack_trace()), self))
                     };
+                    // Initialize counter.
+                    self.local_stack.push((name.clone(), self.stack.len()));
+                    self.stack.push(Variable::f64(start));
+                    self.operands.push(Some(Variable::f64(end)));
                 }
-
-                // Look for variable in current stack.
-                if f.currents.len() > 0 {
-                    for current in &f.currents {
-                        let mut res = None;
-                        for &(ref cname, ind) in self.current_stack.iter().rev() {
-                            if cname == &current.name {
-                                res = Some(ind);
-                                break;
+                Op::ForNCond { slot, end, range } => {
+                    let end_val = match self.operands.last() {
+                        Some(&Some(Variable::F64(val, _))) => val,
+                        _ => panic!("Expected end of loop")
+                    };
+                    match self.stack[sbase + slot] {
+                        Variable::F64(val, _) => {
+                            if !(val < end_val) {
+                                pc = end;
+                                continue;
                             }
                         }
-                        if let Some(ind) = res {
-                            self.local_stack.push((current.name.clone(), self.stack.len()));
-                            self.stack.push(Variable::Ref(ind));
-                        } else {
-                            return Err(module.error(call.source_range, &format!(
-                                "{}\nCould not find current variable `{}`",
-                                    self.stack_trace(), current.name), self));
-                        }
+                        ref x => return Err(module.error(range,
+                                    &self.expected(x, "number"), self))
                     }
                 }
-
-                self.push_fn(call.name.clone(), new_index, Some(f.file.clone()), st, lc, cu);
-                if f.returns() {
-                    self.local_stack.push((self.ret.clone(), st - 1));
-                }
-                for (i, arg) in f.args.iter().enumerate() {
-                    // Do not resolve locals to keep fixed length from end of stack.
-                    self.local_stack.push((arg.name.clone(), st + i));
-                }
-                let (x, flow) = try!(self.block(&f.block, module));
-                match flow {
-                    Flow::Break(None) =>
-                        return Err(module.error(call.source_range,
-                                   &format!("{}\nCan not break from function",
-                                        self.stack_trace()), self)),
-                    Flow::ContinueLoop(None) =>
-                        return Err(module.error(call.source_range,
-                                   &format!("{}\nCan not continue from function",
-                                        self.stack_trace()), self)),
-                    Flow::Break(Some(ref label)) =>
-                        return Err(module.error(call.source_range,
-                            &format!("{}\nThere is no loop labeled `{}`",
-                                     self.stack_trace(), label), self)),
-                    Flow::ContinueLoop(Some(ref label)) =>
-                        return Err(module.error(call.source_range,
-                            &format!("{}\nThere is no loop labeled `{}`",
-                                    self.stack_trace(), label), self)),
-                    _ => {}
-                }
-                self.pop_fn(call.name.clone());
-                match (f.returns(), x) {
-                    (true, None) => {
-                        match self.stack.pop().expect(TINVOTS) {
-                            Variable::Return => {
-                                let source = call.custom_source.as_ref().unwrap_or(
-                                    &module.functions[
-                                        self.call_stack.last().unwrap().index
-                                    ].source
-                                );
-                                return Err(module.error_source(
-                                call.source_range, &format!(
-                                "{}\nFunction `{}` did not return a value",
-                                self.stack_trace(),
-                                f.name), source))
-                            }
-                            x => {
-                                // This happens when return is only
-                                // assigned to `return = x`.
-                                return Ok((Some(x), Flow::Continue))
+                Op::ForNStep { slot, start, range } => {
+                    let end_val = match self.operands.last() {
+                        Some(&Some(Variable::F64(val, _))) => val,
+                        _ => panic!("Expected end of loop")
+                    };
+                    let val = if let Variable::F64(ref mut val, _) = self.stack[sbase + slot] {
+                        *val += 1.0;
+                        Some(*val)
+                    } else { None };
+                    match val {
+                        Some(val) => {
+                            if val < end_val {
+                                pc = start;
+                                continue;
                             }
-                        };
+                        }
+                        None => return Err(module.error(range,
+                                    &self.expected(&self.stack[sbase + slot], "number"), self))
                     }
-                    (false, Some(_)) => {
-                        let source = call.custom_source.as_ref().unwrap_or(
-                            &module.functions[self.call_stack.last().unwrap().index].source
-                        );
-                        return Err(module.error_source(call.source_range,
-                            &format!(
-                                "{}\nFunction `{}` should not return a value",
-                                self.stack_trace(),
-                                f.name), source))
+                }
+                Op::CoverExpression(range) => {
+                    if self.coverage.is_some() { self.cover_expression(range, module); }
+                }
+                Op::CoverBlock(range) => {
+                    if self.coverage.is_some() { self.cover_block(range, module); }
+                }
+                Op::CoverBranch { range, branches, branch } => {
+                    if self.coverage.is_some() {
+                        self.cover_branch(range, branches, branch, module);
                     }
-                    (true, Some(Variable::Return)) => {
-                        // TODO: Could return the last value on the stack.
-                        //       Requires .pop_fn delayed after.
-                        let source = call.custom_source.as_ref().unwrap_or(
-                            &module.functions[self.call_stack.last().unwrap().index].source
-                        );
-                        return Err(module.error_source(call.source_range,
-                            &format!(
-                            "{}\nFunction `{}` did not return a value. \
-                            Did you forget a `return`?",
-                                self.stack_trace(),
-                                f.name), source))
+                }
+                Op::Pause(range) => {
+                    if self.debugger.is_some() { try!(self.debug_pause(range, module)); }
+                }
+                Op::EnterLoop(range) => self.enter_loop(range, module),
+                Op::ExitLoop(range) => self.exit_loop(range),
+                Op::Accumulate(Acc::Sift, range) => {
+                    use std::mem::size_of;
+
+                    let v = match self.operands.pop().expect(TINVOTS) {
+                        // Use a shallow clone of a reference.
+                        Some(Variable::Ref(ind)) => self.stack[ind].clone(),
+                        Some(x) => x,
+                        None => return Err(module.error(range, "Expected variable", self))
+                    };
+                    // The array is on the operand stack, which is counted by the heap size.
+                    try!(self.alloc(size_of::<Variable>(), range, module));
+                    let n = self.operands.len();
+                    match self.operands[n - 2] {
+                        Some(Variable::Array(ref mut arr)) => Arc::make_mut(arr).push(v),
+                        _ => panic!("Expected array")
                     }
-                    (returns, b) => {
-                        if returns { self.stack.pop(); }
-                        return Ok((b, Flow::Continue))
+                }
+                Op::Accumulate(acc, range) => {
+                    let ty = match acc {
+                        Acc::SumVec4 | Acc::ProdVec4 => "vec4",
+                        _ => "number"
+                    };
+                    let x = self.operands.pop().expect(TINVOTS);
+                    let val = match x {
+                        Some(ref x) => self.resolve(x).clone(),
+                        None => return Err(module.error(range,
+                                    &format!("Expected `{}`", ty), self))
+                    };
+                    drop_operand(x);
+                    // The end of loop is on top of the accumulated value.
+                    let n = self.operands.len();
+                    let ok = match self.operands[n - 2] {
+                        Some(ref mut res) => {
+                            // The first `i64` value replaces the start value of the loop.
+                            let first = match (&*res, acc) {
+                                (&Variable::F64(x, _), Acc::Sum) => x == 0.0,
+                                (&Variable::F64(x, _), Acc::Prod) => x == 1.0,
+                                _ => false
+                            };
+                            accumulate(acc, res, first, &val)
+                        }
+                        None => false
+                    };
+                    if !ok {
+                        return Err(module.error(range, &self.expected(&val, ty), self));
+                    }
+                }
+                Op::Pick { acc, slot, end, range } => {
+                    let x = self.operands.pop().expect(TINVOTS);
+                    let minmax = acc == Acc::Min || acc == Acc::Max;
+                    let picked = {
+                        let ind = match self.stack[sbase + slot] {
+                            Variable::F64(val, _) => Variable::f64(val),
+                            ref x => return Err(module.error(range,
+                                        &self.expected(x, "number"), self))
+                        };
+                        let val = match x {
+                            Some(ref x) => self.resolve(x),
+                            None => return Err(module.error(range, if minmax {
+                                        "Expected `number or option`"
+                                    } else {
+                                        "Expected `boolean`"
+                                    }, self))
+                        };
+                        let n = self.operands.len();
+                        match (val, &self.operands[n - 2]) {
+                            (&Variable::F64(val, ref sec), &Some(Variable::F64(res, _)))
+                            if minmax => {
+                                let better = if acc == Acc::Min { res > val }
+                                             else { res < val };
+                                if res.is_nan() || better {
+                                    Some(Variable::F64(val, push_secret(sec, &ind)))
+                                } else { None }
+                            }
+                            (&Variable::Bool(val, ref sec), _) if !minmax => {
+                                if val == (acc == Acc::Any) {
+                                    Some(Variable::Bool(val, push_secret(sec, &ind)))
+                                } else { None }
+                            }
+                            (x, _) => return Err(module.error(range,
+                                &self.expected(x, if minmax { "number" } else { "boolean" }),
+                                self))
+                        }
+                    };
+                    drop_operand(x);
+                    if let Some(v) = picked {
+                        let n = self.operands.len();
+                        self.operands[n - 2] = Some(v);
+                        if !minmax {
+                            pc = end;
+                            continue;
+                        }
                     }
                 }
             }
-            FnIndex::None => {
-                return Err(module.error(call.source_range,
-                    &format!("{}\nUnknown function `{}`", self.stack_trace(), call.name), self))
-            }
+            pc += 1;
         }
+        Ok((self.operands.pop().expect(TINVOTS), Flow::Continue))
+    }
+
+    /// Truncates the stacks to a scope inside a function compiled to bytecode.
+    /// Returns the stack index of the variable an item starts from.
+    fn base_index(&self, base: bytecode::Base, sbase: usize) -> usize {
+        let ind = match base {
+            bytecode::Base::Local(slot) => sbase + slot,
+            bytecode::Base::Global(ind) => self.globals + ind,
+        };
+        if let Variable::Ref(ref_id) = self.stack[ind] { ref_id } else { ind }
+    }
+
+    fn truncate_scope(
+        &mut self,
+        scope: &bytecode::Scope,
+        sbase: usize,
+        lbase: usize,
+        cbase: usize
+    ) {
+        self.stack.truncate(sbase + scope.stack);
+        self.local_stack.truncate(lbase + scope.locals);
+        self.current_stack.truncate(cbase + scope.currents);
     }
 
     /// Calls function by name.
+    ///
+    /// Returns `Status::Suspended` when suspended by an external function or the host,
+    /// which continues with `resume`.
     pub fn call_str(
         &mut self,
         function: &str,
         args: &[Variable],
         module: &Arc<Module>
-    ) -> Result<(), String> {
+    ) -> Result<Status, String> {
+        let resumable = true;
+        self.call_str_internal(function, args, resumable, module)
+    }
+
+    /// Calls function by name and converts the returned value.
+    ///
+    /// Functions with named arguments are called by their full name, e.g. `foo__bar_baz`.
+    /// Trailing arguments with default values can be omitted, e.g. `foo__bar`.
+    /// Loaded, external and intrinsic functions can be called.
+    /// The arguments are checked against the declaration before the function runs.
+    /// The call runs until it returns, without being suspended.
+    pub fn call_str_ret<T: embed::PopVariable>(
+        &mut self,
+        function: &str,
+        args: &[Variable],
+        module: &Arc<Module>
+    ) -> Result<T, String> {
+        let resumable = false;
+        match try!(self.call_str_internal(function, args, resumable, module)) {
+            Status::Done(Some(v)) => T::pop_var(self, self.resolve(&v)),
+            _ => Err(format!("Function `{}` did not return a value", function))
+        }
+    }
+
+    fn call_str_internal(
+        &mut self,
+        function: &str,
+        args: &[Variable],
+        resumable: bool,
+        module: &Arc<Module>
+    ) -> Result<Status, String> {
         use std::cell::Cell;
 
         let name: Arc<String> = Arc::new(function.into());
-        match module.find_function(&name, 0) {
+        let f_index = module.find_function_defaults(&name, args.len(), 0);
+        let loaded;
+        let dfn = match f_index {
             FnIndex::Loaded(f_index) => {
-                let call = ast::Call {
-                    alias: None,
-                    name: name.clone(),
-                    f_index: Cell::new(FnIndex::Loaded(f_index)),
-                    args: args.iter()
-                            .map(|arg| ast::Expression::Variable(Range::empty(0), arg.clone()))
-                            .collect(),
-                    custom_source: None,
-                    source_range: Range::empty(0),
-                };
-                try!(self.call(&call, &module));
-                Ok(())
+                loaded = Dfn::new(&module.functions[f_index as usize]);
+                &loaded
+            }
+            FnIndex::ExternalVoid(_) | FnIndex::ExternalReturn(_) => {
+                let i = module.find_external(&name, args.len()).expect("Expected external function");
+                &module.ext_prelude[i].p
+            }
+            FnIndex::ExternalDefaults(i) => &module.ext_prelude[i].p,
+            FnIndex::Intrinsic(index) => intrinsics::dfn(index),
+            FnIndex::None => return Err(format!("Could not find function `{}`", function))
+        };
+        if !dfn.accepts(args.len()) {
+            return Err(format!("Expected {} arguments to `{}` but found {}",
+                arg_range(dfn.tys.len() - dfn.defaults.len(), dfn.tys.len()),
+                function, args.len()));
+        }
+        for (i, (arg, ty)) in args.iter().zip(dfn.tys.iter()).enumerate() {
+            if !ty.matches_value(arg) {
+                return Err(format!("Type mismatch in argument {} to `{}`:\n\
+                    Expected `{}`, found `{}`",
+                    i + 1, function, ty.description(), self.typeof_var(arg)));
             }
-            _ => return Err(format!("Could not find function `{}`",function))
         }
+        let call = ast::Call {
+            alias: None,
+            name: name.clone(),
+            f_index: Cell::new(f_index),
+            args: args.iter()
+                    .map(|arg| ast::Expression::Variable(Range::empty(0), arg.clone()))
+                    .collect(),
+            custom_source: None,
+            source_range: Range::empty(0),
+        };
+        let loader = true;
+        self.call_outside(&call, loader, resumable, module)
     }
 
     fn swizzle(&mut self, sw: &ast::Swizzle, module: &Arc<Module>) -> Result<Flow, String> {
@@ -1111,6 +3221,8 @@ impl Runtime {
         link: &ast::Link,
         module: &Arc<Module>
     ) -> Result<(Option<Variable>, Flow), String> {
+        use std::mem::size_of;
+        use link::Block;
         use Link;
 
         Ok((Some(if link.items.len() == 0 {
@@ -1138,6 +3250,8 @@ impl Runtime {
             self.stack.truncate(st);
             self.local_stack.truncate(lc);
             self.current_stack.truncate(cu);
+            try!(self.alloc(new_link.slices.len() * size_of::<Block>(),
+                            link.source_range, module));
             Variable::Link(Box::new(new_link))
         }), Flow::Continue))
     }
@@ -1147,6 +3261,10 @@ impl Runtime {
         obj: &ast::Object,
         module: &Arc<Module>
     ) -> Result<(Option<Variable>, Flow), String> {
+        use std::mem::size_of;
+
+        try!(self.alloc(obj.key_values.len() * size_of::<(Arc<String>, Variable)>(),
+                        obj.source_range, module));
         let mut object: HashMap<_, _> = HashMap::new();
         for &(ref key, ref expr) in &obj.key_values {
             let x = match try!(self.expression(expr, Side::Right, module)) {
@@ -1171,6 +3289,9 @@ impl Runtime {
         arr: &ast::Array,
         module: &Arc<Module>
     ) -> Result<(Option<Variable>, Flow), String> {
+        use std::mem::size_of;
+
+        try!(self.alloc(arr.items.len() * size_of::<Variable>(), arr.source_range, module));
         let mut array: Vec<Variable> = Vec::new();
         for item in &arr.items {
             array.push(match try!(self.expression(item, Side::Right, module)) {
@@ -1189,6 +3310,8 @@ impl Runtime {
         array_fill: &ast::ArrayFill,
         module: &Arc<Module>
     ) -> Result<(Option<Variable>, Flow), String> {
+        use std::mem::size_of;
+
         let fill = match try!(self.expression(&array_fill.fill, Side::Right, module)) {
             (x, Flow::Return) => return Ok((x, Flow::Return)),
             (Some(x), Flow::Continue) => x,
@@ -1203,14 +3326,15 @@ impl Runtime {
                             &format!("{}\nExpected something",
                                 self.stack_trace()), self))
         };
-        let v = match (self.resolve(&fill), self.resolve(&n)) {
-            (x, &Variable::F64(n, _)) => {
-                Variable::Array(Arc::new(vec![x.clone(); n as usize]))
-            }
+        let n = match *self.resolve(&n) {
+            Variable::F64(n, _) => n as usize,
             _ => return Err(module.error(array_fill.n.source_range(),
                 &format!("{}\nExpected number for length in `[value; length]`",
                     self.stack_trace()), self))
         };
+        try!(self.alloc(n.saturating_mul(size_of::<Variable>()),
+                        array_fill.source_range, module));
+        let v = Variable::Array(Arc::new(vec![self.resolve(&fill).clone(); n]));
         Ok((Some(v), Flow::Continue))
     }
 
@@ -1259,447 +3383,502 @@ impl Runtime {
                 x => panic!("Expected reference, found `{}`", self.typeof_var(&x))
             };
 
-            match *self.resolve(&b) {
-                Variable::F64(b, ref sec) => {
-                    unsafe {
-                        match *r.0 {
-                            Variable::F64(ref mut n, ref mut n_sec) => {
-                                match op {
-                                    Set => *n = b,
-                                    Add => *n += b,
-                                    Sub => *n -= b,
-                                    Mul => *n *= b,
-                                    Div => *n /= b,
-                                    Rem => *n %= b,
-                                    Pow => *n = n.powf(b),
-                                    Assign => {}
-                                };
-                                *n_sec = sec.clone()
-                            }
-                            Variable::Return => {
-                                if let Set = op {
-                                    *r.0 = Variable::F64(b, sec.clone())
-                                } else {
-                                    return Err(module.error(
-                                        left.source_range(),
-                                        &format!("{}\nReturn has no value",
-                                            self.stack_trace()), self))
-                                }
-                            }
-                            Variable::Link(ref mut n) => {
-                                if let Add = op {
-                                    try!(n.push(&Variable::f64(b)));
-                                } else {
-                                    return Err(module.error(
-                                        left.source_range(),
-                                        &format!("{}\nCan not use this assignment \
-                                        operator with `link` and `number`",
-                                            self.stack_trace()), self));
-                                }
-                            }
-                            _ => return Err(module.error(
-                                    left.source_range(),
-                                    &format!("{}\nExpected assigning to a number",
+            try!(self.assign_variable(op, r, &b, left.source_range(), module));
+            Ok((None, Flow::Continue))
+        } else {
+            return match *left {
+                Expression::Item(ref item) => {
+                    let x = match try!(self.expression(right, Side::Right, module)) {
+                        (x, Flow::Return) => return Ok((x, Flow::Return)),
+                        (Some(x), Flow::Continue) => x,
+                        _ => return Err(module.error(right.source_range(),
+                                    &format!("{}\nExpected something from the right side",
+                                        self.stack_trace()), self))
+                    };
+                    let v = match x {
+                        // Use a shallow clone of a reference.
+                        Variable::Ref(ind) => self.stack[ind].clone(),
+                        x => x
+                    };
+                    if item.ids.len() != 0 {
+                        let x = match try!(self.expression(left, Side::LeftInsert(true),
+                                                   module)) {
+                            (Some(x), Flow::Continue) => x,
+                            (x, Flow::Return) => return Ok((x, Flow::Return)),
+                            _ => return Err(module.error(left.source_range(),
+                                    &format!("{}\nExpected something from the left side",
                                         self.stack_trace()), self))
                         };
+                        match x {
+                            Variable::UnsafeRef(r) => {
+                                unsafe { *r.0 = v }
+                            }
+                            _ => panic!("Expected unsafe reference")
+                        }
+                    } else {
+                        self.local_stack.push((item.name.clone(), self.stack.len()));
+                        if item.current {
+                            self.current_stack.push((item.name.clone(), self.stack.len()));
+                        }
+                        self.stack.push(v);
                     }
+                    Ok((None, Flow::Continue))
                 }
-                Variable::Vec4(b) => {
-                    unsafe {
-                        match *r.0 {
-                            Variable::Vec4(ref mut n) => {
-                                match op {
-                                    Set => *n = b,
-                                    Add => *n = [n[0] + b[0], n[1] + b[1],
-                                                 n[2] + b[2], n[3] + b[3]],
-                                    Sub => *n = [n[0] - b[0], n[1] - b[1],
-                                                 n[2] - b[2], n[3] - b[3]],
-                                    Mul => *n = [n[0] * b[0], n[1] * b[1],
-                                                 n[2] * b[2], n[3] * b[3]],
-                                    Div => *n = [n[0] / b[0], n[1] / b[1],
-                                                 n[2] / b[2], n[3] / b[3]],
-                                    Rem => *n = [n[0] % b[0], n[1] % b[1],
-                                                 n[2] % b[2], n[3] % b[3]],
-                                    Pow => *n = [n[0].powf(b[0]), n[1].powf(b[1]),
-                                                 n[2].powf(b[2]), n[3].powf(b[3])],
-                                    Assign => {}
-                                }
+                _ => return Err(module.error(left.source_range(),
+                                &format!("{}\nExpected item",
+                                    self.stack_trace()), self))
+            }
+        }
+    }
+    /// Uses an assignment operator, except `:=`, on a variable.
+    fn assign_variable(
+        &mut self,
+        op: ast::AssignOp,
+        r: UnsafeRef,
+        b: &Variable,
+        left_range: Range,
+        module: &Arc<Module>
+    ) -> Result<(), String> {
+        use ast::AssignOp::*;
+
+        if let Add = op {
+            let len = if let Variable::Text(ref b) = *self.resolve(b) { b.len() } else { 0 };
+            try!(self.alloc(len, left_range, module));
+        }
+        match *self.resolve(b) {
+            Variable::F64(b, ref sec) => {
+                unsafe {
+                    match *r.0 {
+                        Variable::F64(ref mut n, ref mut n_sec) => {
+                            match op {
+                                Set => *n = b,
+                                Add => *n += b,
+                                Sub => *n -= b,
+                                Mul => *n *= b,
+                                Div => *n /= b,
+                                Rem => *n %= b,
+                                Pow => *n = n.powf(b),
+                                Assign => {}
+                            };
+                            *n_sec = sec.clone()
+                        }
+                        Variable::Return => {
+                            if let Set = op {
+                                *r.0 = Variable::F64(b, sec.clone())
+                            } else {
+                                return Err(module.error(
+                                    left_range,
+                                    &format!("{}\nReturn has no value",
+                                        self.stack_trace()), self))
                             }
-                            Variable::Return => {
-                                if let Set = op {
-                                    *r.0 = Variable::Vec4(b)
-                                } else {
-                                    return Err(module.error(
-                                        left.source_range(),
-                                        &format!("{}\nReturn has no value",
-                                            self.stack_trace()), self))
-                                }
+                        }
+                        Variable::Link(ref mut n) => {
+                            if let Add = op {
+                                try!(n.push(&Variable::f64(b)));
+                            } else {
+                                return Err(module.error(
+                                    left_range,
+                                    &format!("{}\nCan not use this assignment \
+                                    operator with `link` and `number`",
+                                        self.stack_trace()), self));
+                            }
+                        }
+                        _ => return Err(module.error(
+                                left_range,
+                                &format!("{}\nExpected assigning to a number",
+                                    self.stack_trace()), self))
+                    };
+                }
+            }
+            Variable::I64(b) => {
+                unsafe {
+                    match *r.0 {
+                        Variable::I64(ref mut n) => {
+                            let binop = match op {
+                                Set => { *n = b; return Ok(()) }
+                                Add => ast::BinOp::Add,
+                                Sub => ast::BinOp::Sub,
+                                Mul => ast::BinOp::Mul,
+                                Div => ast::BinOp::Div,
+                                Rem => ast::BinOp::Rem,
+                                Pow => ast::BinOp::Pow,
+                                Assign => return Ok(())
+                            };
+                            if let Variable::I64(x) = try!(self.binop_variables(
+                                binop, left_range, &Variable::I64(*n), &Variable::I64(b), module)
+                            ) {
+                                *n = x;
                             }
-                            _ => return Err(module.error(
-                                    left.source_range(),
-                                    &format!("{}\nExpected assigning to a vec4",
+                        }
+                        Variable::Return => {
+                            if let Set = op {
+                                *r.0 = Variable::I64(b)
+                            } else {
+                                return Err(module.error(
+                                    left_range,
+                                    &format!("{}\nReturn has no value",
                                         self.stack_trace()), self))
-                        };
-                    }
+                            }
+                        }
+                        _ => return Err(module.error(
+                                left_range,
+                                &format!("{}\nExpected assigning to an i64",
+                                    self.stack_trace()), self))
+                    };
                 }
-                Variable::Bool(b, ref sec) => {
-                    unsafe {
-                        match *r.0 {
-                            Variable::Bool(ref mut n, ref mut n_sec) => {
-                                match op {
-                                    Set => *n = b,
-                                    _ => unimplemented!()
-                                };
-                                *n_sec = sec.clone();
-                            }
-                            Variable::Return => {
-                                if let Set = op {
-                                    *r.0 = Variable::Bool(b, sec.clone())
-                                } else {
-                                    return Err(module.error(
-                                        left.source_range(),
-                                        &format!("{}\nReturn has no value",
-                                            self.stack_trace()), self))
-                                }
+            }
+            Variable::Vec4(b) => {
+                unsafe {
+                    match *r.0 {
+                        Variable::Vec4(ref mut n) => {
+                            match op {
+                                Set => *n = b,
+                                Add => *n = [n[0] + b[0], n[1] + b[1],
+                                             n[2] + b[2], n[3] + b[3]],
+                                Sub => *n = [n[0] - b[0], n[1] - b[1],
+                                             n[2] - b[2], n[3] - b[3]],
+                                Mul => *n = [n[0] * b[0], n[1] * b[1],
+                                             n[2] * b[2], n[3] * b[3]],
+                                Div => *n = [n[0] / b[0], n[1] / b[1],
+                                             n[2] / b[2], n[3] / b[3]],
+                                Rem => *n = [n[0] % b[0], n[1] % b[1],
+                                             n[2] % b[2], n[3] % b[3]],
+                                Pow => *n = [n[0].powf(b[0]), n[1].powf(b[1]),
+                                             n[2].powf(b[2]), n[3].powf(b[3])],
+                                Assign => {}
                             }
-                            Variable::Link(ref mut n) => {
-                                if let Add = op {
-                                    try!(n.push(&Variable::bool(b)));
-                                } else {
-                                    return Err(module.error(
-                                        left.source_range(),
-                                        &format!("{}\nCan not use this assignment \
-                                        operator with `link` and `bool`",
-                                            self.stack_trace()), self));
-                                }
+                        }
+                        Variable::Return => {
+                            if let Set = op {
+                                *r.0 = Variable::Vec4(b)
+                            } else {
+                                return Err(module.error(
+                                    left_range,
+                                    &format!("{}\nReturn has no value",
+                                        self.stack_trace()), self))
                             }
-                            _ => return Err(module.error(
-                                    left.source_range(),
-                                    &format!("{}\nExpected assigning to a bool",
+                        }
+                        _ => return Err(module.error(
+                                left_range,
+                                &format!("{}\nExpected assigning to a vec4",
+                                    self.stack_trace()), self))
+                    };
+                }
+            }
+            Variable::Bool(b, ref sec) => {
+                unsafe {
+                    match *r.0 {
+                        Variable::Bool(ref mut n, ref mut n_sec) => {
+                            match op {
+                                Set => *n = b,
+                                _ => unimplemented!()
+                            };
+                            *n_sec = sec.clone();
+                        }
+                        Variable::Return => {
+                            if let Set = op {
+                                *r.0 = Variable::Bool(b, sec.clone())
+                            } else {
+                                return Err(module.error(
+                                    left_range,
+                                    &format!("{}\nReturn has no value",
                                         self.stack_trace()), self))
-                        };
-                    }
+                            }
+                        }
+                        Variable::Link(ref mut n) => {
+                            if let Add = op {
+                                try!(n.push(&Variable::bool(b)));
+                            } else {
+                                return Err(module.error(
+                                    left_range,
+                                    &format!("{}\nCan not use this assignment \
+                                    operator with `link` and `bool`",
+                                        self.stack_trace()), self));
+                            }
+                        }
+                        _ => return Err(module.error(
+                                left_range,
+                                &format!("{}\nExpected assigning to a bool",
+                                    self.stack_trace()), self))
+                    };
                 }
-                Variable::Text(ref b) => {
-                    unsafe {
-                        match *r.0 {
-                            Variable::Text(ref mut n) => {
-                                match op {
-                                    Set => *n = b.clone(),
-                                    Add => Arc::make_mut(n).push_str(b),
-                                    _ => unimplemented!()
-                                }
+            }
+            Variable::Text(ref b) => {
+                unsafe {
+                    match *r.0 {
+                        Variable::Text(ref mut n) => {
+                            match op {
+                                Set => *n = b.clone(),
+                                Add => Arc::make_mut(n).push_str(b),
+                                _ => unimplemented!()
                             }
-                            Variable::Return => {
-                                if let Set = op {
-                                    *r.0 = Variable::Text(b.clone())
-                                } else {
-                                    return Err(module.error(
-                                        left.source_range(),
-                                        &format!("{}\nReturn has no value",
-                                            self.stack_trace()), self))
-                                }
+                        }
+                        Variable::Return => {
+                            if let Set = op {
+                                *r.0 = Variable::Text(b.clone())
+                            } else {
+                                return Err(module.error(
+                                    left_range,
+                                    &format!("{}\nReturn has no value",
+                                        self.stack_trace()), self))
                             }
-                            Variable::Link(ref mut n) => {
-                                if let Add = op {
-                                    try!(n.push(&Variable::Text(b.clone())));
-                                } else {
-                                    return Err(module.error(
-                                        left.source_range(),
-                                        &format!("{}\nCan not use this assignment \
-                                        operator with `link` and `text`",
-                                            self.stack_trace()), self));
-                                }
+                        }
+                        Variable::Link(ref mut n) => {
+                            if let Add = op {
+                                try!(n.push(&Variable::Text(b.clone())));
+                            } else {
+                                return Err(module.error(
+                                    left_range,
+                                    &format!("{}\nCan not use this assignment \
+                                    operator with `link` and `text`",
+                                        self.stack_trace()), self));
                             }
-                            _ => return Err(module.error(
-                                left.source_range(),
-                                &format!("{}\nExpected assigning to text",
-                                    self.stack_trace()), self))
                         }
+                        _ => return Err(module.error(
+                            left_range,
+                            &format!("{}\nExpected assigning to text",
+                                self.stack_trace()), self))
                     }
                 }
-                Variable::Object(ref b) => {
-                    unsafe {
-                        match *r.0 {
-                            Variable::Object(ref mut n) => {
-                                if let Set = op {
-                                    // Check address to avoid unsafe
-                                    // reading and writing to same memory.
-                                    let n_addr = n as *const _ as usize;
-                                    let b_addr = b as *const _ as usize;
-                                    if n_addr != b_addr {
-                                        *r.0 = Variable::Object(b.clone())
-                                    }
-                                    // *n = obj.clone()
-                                } else {
-                                    unimplemented!()
-                                }
-                            }
-                            Variable::Return => {
-                                if let Set = op {
+            }
+            Variable::Object(ref b) => {
+                unsafe {
+                    match *r.0 {
+                        Variable::Object(ref mut n) => {
+                            if let Set = op {
+                                // Check address to avoid unsafe
+                                // reading and writing to same memory.
+                                let n_addr = n as *const _ as usize;
+                                let b_addr = b as *const _ as usize;
+                                if n_addr != b_addr {
                                     *r.0 = Variable::Object(b.clone())
-                                } else {
-                                    return Err(module.error(
-                                        left.source_range(),
-                                        &format!("{}\nReturn has no value",
-                                            self.stack_trace()), self))
                                 }
+                                // *n = obj.clone()
+                            } else {
+                                unimplemented!()
+                            }
+                        }
+                        Variable::Return => {
+                            if let Set = op {
+                                *r.0 = Variable::Object(b.clone())
+                            } else {
+                                return Err(module.error(
+                                    left_range,
+                                    &format!("{}\nReturn has no value",
+                                        self.stack_trace()), self))
                             }
-                            _ => return Err(module.error(
-                                left.source_range(),
-                                &format!("{}\nExpected assigning to object",
-                                    self.stack_trace()), self))
                         }
+                        _ => return Err(module.error(
+                            left_range,
+                            &format!("{}\nExpected assigning to object",
+                                self.stack_trace()), self))
                     }
                 }
-                Variable::Array(ref b) => {
-                    unsafe {
-                        match *r.0 {
-                            Variable::Array(ref mut n) => {
-                                if let Set = op {
-                                    // Check address to avoid unsafe
-                                    // reading and writing to same memory.
-                                    let n_addr = n as *const _ as usize;
-                                    let b_addr = b as *const _ as usize;
-                                    if n_addr != b_addr {
-                                        *r.0 = Variable::Array(b.clone())
-                                    }
-                                    // *n = arr.clone();
-                                } else {
-                                    unimplemented!()
-                                }
-                            }
-                            Variable::Return => {
-                                if let Set = op {
+            }
+            Variable::Array(ref b) => {
+                unsafe {
+                    match *r.0 {
+                        Variable::Array(ref mut n) => {
+                            if let Set = op {
+                                // Check address to avoid unsafe
+                                // reading and writing to same memory.
+                                let n_addr = n as *const _ as usize;
+                                let b_addr = b as *const _ as usize;
+                                if n_addr != b_addr {
                                     *r.0 = Variable::Array(b.clone())
-                                } else {
-                                    return Err(module.error(
-                                        left.source_range(),
-                                        &format!("{}\nReturn has no value",
-                                            self.stack_trace()), self))
                                 }
+                                // *n = arr.clone();
+                            } else {
+                                unimplemented!()
+                            }
+                        }
+                        Variable::Return => {
+                            if let Set = op {
+                                *r.0 = Variable::Array(b.clone())
+                            } else {
+                                return Err(module.error(
+                                    left_range,
+                                    &format!("{}\nReturn has no value",
+                                        self.stack_trace()), self))
                             }
-                            _ => return Err(module.error(
-                                left.source_range(),
-                                &format!("{}\nExpected assigning to array",
-                                    self.stack_trace()), self))
                         }
+                        _ => return Err(module.error(
+                            left_range,
+                            &format!("{}\nExpected assigning to array",
+                                self.stack_trace()), self))
                     }
                 }
-                Variable::Link(ref b) => {
-                    unsafe {
-                        match *r.0 {
-                            Variable::Link(ref mut n) => {
-                                match op {
-                                    Set => *n = b.clone(),
-                                    Add => **n = n.add(b),
-                                    Sub => **n = b.add(n),
-                                    _ => unimplemented!()
-                                }
+            }
+            Variable::Link(ref b) => {
+                unsafe {
+                    match *r.0 {
+                        Variable::Link(ref mut n) => {
+                            match op {
+                                Set => *n = b.clone(),
+                                Add => **n = n.add(b),
+                                Sub => **n = b.add(n),
+                                _ => unimplemented!()
                             }
-                            Variable::Return => {
-                                if let Set = op {
-                                    *r.0 = Variable::Link(b.clone())
-                                } else {
-                                    return Err(module.error(
-                                        left.source_range(),
-                                        &format!("{}\nReturn has no value",
-                                            self.stack_trace()), self))
-                                }
+                        }
+                        Variable::Return => {
+                            if let Set = op {
+                                *r.0 = Variable::Link(b.clone())
+                            } else {
+                                return Err(module.error(
+                                    left_range,
+                                    &format!("{}\nReturn has no value",
+                                        self.stack_trace()), self))
                             }
-                            _ => return Err(module.error(
-                                left.source_range(),
-                                &format!("{}\nExpected assigning to link",
-                                    self.stack_trace()), self))
                         }
+                        _ => return Err(module.error(
+                            left_range,
+                            &format!("{}\nExpected assigning to link",
+                                self.stack_trace()), self))
                     }
                 }
-                Variable::Option(ref b) => {
-                    unsafe {
-                        match *r.0 {
-                            Variable::Option(ref mut n) => {
-                                if let Set = op {
-                                    // Check address to avoid unsafe
-                                    // reading and writing to same memory.
-                                    let n_addr = n as *const _ as usize;
-                                    let b_addr = b as *const _ as usize;
-                                    if n_addr != b_addr {
-                                        *r.0 = Variable::Option(b.clone())
-                                    }
-                                } else {
-                                    unimplemented!()
-                                }
-                            }
-                            Variable::Return => {
-                                if let Set = op {
+            }
+            Variable::Option(ref b) => {
+                unsafe {
+                    match *r.0 {
+                        Variable::Option(ref mut n) => {
+                            if let Set = op {
+                                // Check address to avoid unsafe
+                                // reading and writing to same memory.
+                                let n_addr = n as *const _ as usize;
+                                let b_addr = b as *const _ as usize;
+                                if n_addr != b_addr {
                                     *r.0 = Variable::Option(b.clone())
-                                } else {
-                                    return Err(module.error(
-                                        left.source_range(),
-                                        &format!("{}\nReturn has no value",
-                                            self.stack_trace()), self))
                                 }
+                            } else {
+                                unimplemented!()
+                            }
+                        }
+                        Variable::Return => {
+                            if let Set = op {
+                                *r.0 = Variable::Option(b.clone())
+                            } else {
+                                return Err(module.error(
+                                    left_range,
+                                    &format!("{}\nReturn has no value",
+                                        self.stack_trace()), self))
                             }
-                            _ => return Err(module.error(
-                                left.source_range(),
-                                &format!("{}\nExpected assigning to option",
-                                    self.stack_trace()), self))
                         }
+                        _ => return Err(module.error(
+                            left_range,
+                            &format!("{}\nExpected assigning to option",
+                                self.stack_trace()), self))
                     }
                 }
-                Variable::Result(ref b) => {
-                    unsafe {
-                        match *r.0 {
-                            Variable::Result(ref mut n) => {
-                                if let Set = op {
-                                    // Check address to avoid unsafe
-                                    // reading and writing to same memory.
-                                    let n_addr = n as *const _ as usize;
-                                    let b_addr = b as *const _ as usize;
-                                    if n_addr != b_addr {
-                                        *r.0 = Variable::Result(b.clone())
-                                    }
-                                } else {
-                                    unimplemented!()
-                                }
-                            }
-                            Variable::Return => {
-                                if let Set = op {
+            }
+            Variable::Result(ref b) => {
+                unsafe {
+                    match *r.0 {
+                        Variable::Result(ref mut n) => {
+                            if let Set = op {
+                                // Check address to avoid unsafe
+                                // reading and writing to same memory.
+                                let n_addr = n as *const _ as usize;
+                                let b_addr = b as *const _ as usize;
+                                if n_addr != b_addr {
                                     *r.0 = Variable::Result(b.clone())
-                                } else {
-                                    return Err(module.error(
-                                        left.source_range(),
-                                        &format!("{}\nReturn has no value",
-                                            self.stack_trace()), self))
                                 }
+                            } else {
+                                unimplemented!()
+                            }
+                        }
+                        Variable::Return => {
+                            if let Set = op {
+                                *r.0 = Variable::Result(b.clone())
+                            } else {
+                                return Err(module.error(
+                                    left_range,
+                                    &format!("{}\nReturn has no value",
+                                        self.stack_trace()), self))
                             }
-                            _ => return Err(module.error(
-                                left.source_range(),
-                                &format!("{}\nExpected assigning to result",
-                                    self.stack_trace()), self))
                         }
+                        _ => return Err(module.error(
+                            left_range,
+                            &format!("{}\nExpected assigning to result",
+                                self.stack_trace()), self))
                     }
                 }
-                Variable::RustObject(ref b) => {
-                    unsafe {
-                        match *r.0 {
-                            Variable::RustObject(ref mut n) => {
-                                if let Set = op {
-                                    // Check address to avoid unsafe
-                                    // reading and writing to same memory.
-                                    let n_addr = n as *const _ as usize;
-                                    let b_addr = b as *const _ as usize;
-                                    if n_addr != b_addr {
-                                        *r.0 = Variable::RustObject(b.clone())
-                                    }
-                                } else {
-                                    unimplemented!()
-                                }
-                            }
-                            Variable::Return => {
-                                if let Set = op {
+            }
+            Variable::RustObject(ref b) => {
+                unsafe {
+                    match *r.0 {
+                        Variable::RustObject(ref mut n) => {
+                            if let Set = op {
+                                // Check address to avoid unsafe
+                                // reading and writing to same memory.
+                                let n_addr = n as *const _ as usize;
+                                let b_addr = b as *const _ as usize;
+                                if n_addr != b_addr {
                                     *r.0 = Variable::RustObject(b.clone())
-                                } else {
-                                    return Err(module.error(
-                                        left.source_range(),
-                                        &format!("{}\nReturn has no value",
-                                            self.stack_trace()), self))
                                 }
+                            } else {
+                                unimplemented!()
+                            }
+                        }
+                        Variable::Return => {
+                            if let Set = op {
+                                *r.0 = Variable::RustObject(b.clone())
+                            } else {
+                                return Err(module.error(
+                                    left_range,
+                                    &format!("{}\nReturn has no value",
+                                        self.stack_trace()), self))
                             }
-                            _ => return Err(module.error(
-                                left.source_range(),
-                                &format!(
-                                    "{}\nExpected assigning to rust_object",
-                                    self.stack_trace()), self))
                         }
+                        _ => return Err(module.error(
+                            left_range,
+                            &format!(
+                                "{}\nExpected assigning to rust_object",
+                                self.stack_trace()), self))
                     }
                 }
-                Variable::Closure(ref b, ref env) => {
-                    unsafe {
-                        match *r.0 {
-                            Variable::Closure(ref mut n, _) => {
-                                if let Set = op {
-                                    // Check address to avoid unsafe
-                                    // reading and writing to same memory.
-                                    let n_addr = n as *const _ as usize;
-                                    let b_addr = b as *const _ as usize;
-                                    if n_addr != b_addr {
-                                        *r.0 = Variable::Closure(b.clone(), env.clone())
-                                    }
-                                } else {
-                                    unimplemented!()
-                                }
-                            }
-                            Variable::Return => {
-                                if let Set = op {
+            }
+            Variable::Closure(ref b, ref env) => {
+                unsafe {
+                    match *r.0 {
+                        Variable::Closure(ref mut n, _) => {
+                            if let Set = op {
+                                // Check address to avoid unsafe
+                                // reading and writing to same memory.
+                                let n_addr = n as *const _ as usize;
+                                let b_addr = b as *const _ as usize;
+                                if n_addr != b_addr {
                                     *r.0 = Variable::Closure(b.clone(), env.clone())
-                                } else {
-                                    return Err(module.error(
-                                        left.source_range(),
-                                        &format!("{}\nReturn has no value",
-                                            self.stack_trace()), self))
                                 }
+                            } else {
+                                unimplemented!()
                             }
-                            _ => return Err(module.error(
-                                left.source_range(),
-                                &format!(
-                                    "{}\nExpected assigning to closure",
-                                    self.stack_trace()), self))
                         }
-                    }
-                }
-                ref x => {
-                    return Err(module.error(
-                        left.source_range(),
-                        &format!("{}\nCan not use this assignment operator with `{}`",
-                            self.stack_trace(), self.typeof_var(x)), self));
-                }
-            };
-            Ok((None, Flow::Continue))
-        } else {
-            return match *left {
-                Expression::Item(ref item) => {
-                    let x = match try!(self.expression(right, Side::Right, module)) {
-                        (x, Flow::Return) => return Ok((x, Flow::Return)),
-                        (Some(x), Flow::Continue) => x,
-                        _ => return Err(module.error(right.source_range(),
-                                    &format!("{}\nExpected something from the right side",
-                                        self.stack_trace()), self))
-                    };
-                    let v = match x {
-                        // Use a shallow clone of a reference.
-                        Variable::Ref(ind) => self.stack[ind].clone(),
-                        x => x
-                    };
-                    if item.ids.len() != 0 {
-                        let x = match try!(self.expression(left, Side::LeftInsert(true),
-                                                   module)) {
-                            (Some(x), Flow::Continue) => x,
-                            (x, Flow::Return) => return Ok((x, Flow::Return)),
-                            _ => return Err(module.error(left.source_range(),
-                                    &format!("{}\nExpected something from the left side",
+                        Variable::Return => {
+                            if let Set = op {
+                                *r.0 = Variable::Closure(b.clone(), env.clone())
+                            } else {
+                                return Err(module.error(
+                                    left_range,
+                                    &format!("{}\nReturn has no value",
                                         self.stack_trace()), self))
-                        };
-                        match x {
-                            Variable::UnsafeRef(r) => {
-                                unsafe { *r.0 = v }
                             }
-                            _ => panic!("Expected unsafe reference")
-                        }
-                    } else {
-                        self.local_stack.push((item.name.clone(), self.stack.len()));
-                        if item.current {
-                            self.current_stack.push((item.name.clone(), self.stack.len()));
                         }
-                        self.stack.push(v);
+                        _ => return Err(module.error(
+                            left_range,
+                            &format!(
+                                "{}\nExpected assigning to closure",
+                                self.stack_trace()), self))
                     }
-                    Ok((None, Flow::Continue))
                 }
-                _ => return Err(module.error(left.source_range(),
-                                &format!("{}\nExpected item",
-                                    self.stack_trace()), self))
             }
-        }
+            ref x => {
+                return Err(module.error(
+                    left_range,
+                    &format!("{}\nCan not use this assignment operator with `{}`",
+                        self.stack_trace(), self.typeof_var(x)), self));
+            }
+        };
+        Ok(())
     }
     // `insert` is true for `:=` and false for `=`.
     // This works only on objects, but does not have to check since it is
@@ -1754,7 +3933,9 @@ impl Runtime {
         use ast::Id;
 
         let locals = self.local_stack.len() - self.call_stack.last().unwrap().local_len;
-        let stack_id = {
+        let stack_id = if let Some(ind) = item.global.get() {
+            self.globals + ind
+        } else {
             if cfg!(not(feature = "debug_resolve")) {
                 self.stack.len() - item.static_stack_id.get().unwrap()
             } else {
@@ -1851,6 +4032,21 @@ impl Runtime {
                 };
             }
         }
+        self.item_ids(item, stack_id, start_stack_len, side, module)
+    }
+
+    /// Looks up the properties of an item, starting from a variable on the stack.
+    ///
+    /// Expects the values of computed properties to be pushed on the stack,
+    /// starting at `start_stack_len`, and removes them afterwards.
+    fn item_ids(
+        &mut self,
+        item: &ast::Item,
+        stack_id: usize,
+        start_stack_len: usize,
+        side: Side,
+        module: &Module
+    ) -> Result<(Option<Variable>, Flow), String> {
         let &mut Runtime {
             ref mut stack,
             ref mut call_stack,
@@ -2006,6 +4202,7 @@ impl Runtime {
         let v = match var {
             &Variable::Text(_) => self.text_type.clone(),
             &Variable::F64(_, _) => self.f64_type.clone(),
+            &Variable::I64(_) => self.i64_type.clone(),
             &Variable::Vec4(_) => self.vec4_type.clone(),
             &Variable::Return => self.return_type.clone(),
             &Variable::Bool(_, _) => self.bool_type.clone(),
@@ -2014,10 +4211,17 @@ impl Runtime {
             &Variable::Link(_) => self.link_type.clone(),
             &Variable::Ref(_) => self.ref_type.clone(),
             &Variable::UnsafeRef(_) => self.unsafe_ref_type.clone(),
-            &Variable::RustObject(_) => self.rust_object_type.clone(),
+            &Variable::RustObject(ref obj) => match embed::rust_type_name(obj) {
+                Some(name) => Variable::Text(name),
+                None => self.rust_object_type.clone(),
+            },
             &Variable::Option(_) => self.option_type.clone(),
             &Variable::Result(_) => self.result_type.clone(),
+            &Variable::Enum(ref enum_variant) => Variable::Text(enum_variant.name.clone()),
             &Variable::Thread(_) => self.thread_type.clone(),
+            &Variable::Coroutine(_) => self.coroutine_type.clone(),
+            &Variable::Channel(_) => self.channel_type.clone(),
+            &Variable::Shared(_) => self.shared_type.clone(),
             &Variable::Closure(_, _) => self.closure_type.clone(),
         };
         match v {
@@ -2031,9 +4235,36 @@ impl Runtime {
         compare: &ast::Compare,
         module: &Arc<Module>
     ) -> Result<(Option<Variable>, Flow), String> {
+        let left = match try!(self.expression(&compare.left, Side::Right, module)) {
+            (Some(x), Flow::Continue) => x,
+            (x, Flow::Return) => { return Ok((x, Flow::Return)); }
+            _ => return Err(module.error(compare.left.source_range(),
+                &format!("{}\nExpected something from the left argument",
+                    self.stack_trace()), self))
+        };
+        let right = match try!(self.expression(&compare.right, Side::Right, module)) {
+            (Some(x), Flow::Continue) => x,
+            (x, Flow::Return) => return Ok((x, Flow::Return)),
+            _ => return Err(module.error(compare.right.source_range(),
+                &format!("{}\nExpected something from the right argument",
+                    self.stack_trace()), self))
+        };
+        let v = try!(self.compare_variables(compare.op, compare.source_range, &left, &right, module));
+        Ok((Some(v), Flow::Continue))
+    }
+    /// Compares two variables.
+    fn compare_variables(
+        &self,
+        op: ast::CompareOp,
+        source_range: Range,
+        left: &Variable,
+        right: &Variable,
+        module: &Arc<Module>
+    ) -> Result<Variable, String> {
         fn sub_compare(
             rt: &Runtime,
-            compare: &ast::Compare,
+            op: ast::CompareOp,
+            source_range: Range,
             module: &Module,
             a: &Variable,
             b: &Variable
@@ -2042,7 +4273,7 @@ impl Runtime {
 
             match (rt.resolve(&b), rt.resolve(&a)) {
                 (&Variable::F64(b, _), &Variable::F64(a, ref sec)) => {
-                    Ok(Variable::Bool(match compare.op {
+                    Ok(Variable::Bool(match op {
                         Less => a < b,
                         LessOrEqual => a <= b,
                         Greater => a > b,
@@ -2051,8 +4282,18 @@ impl Runtime {
                         NotEqual => a != b
                     }, sec.clone()))
                 }
+                (&Variable::I64(b), &Variable::I64(a)) => {
+                    Ok(Variable::bool(match op {
+                        Less => a < b,
+                        LessOrEqual => a <= b,
+                        Greater => a > b,
+                        GreaterOrEqual => a >= b,
+                        Equal => a == b,
+                        NotEqual => a != b
+                    }))
+                }
                 (&Variable::Text(ref b), &Variable::Text(ref a)) => {
-                    Ok(Variable::bool(match compare.op {
+                    Ok(Variable::bool(match op {
                         Less => a < b,
                         LessOrEqual => a <= b,
                         Greater => a > b,
@@ -2062,33 +4303,33 @@ impl Runtime {
                     }))
                 }
                 (&Variable::Bool(b, _), &Variable::Bool(a, ref sec)) => {
-                    Ok(Variable::Bool(match compare.op {
+                    Ok(Variable::Bool(match op {
                         Equal => a == b,
                         NotEqual => a != b,
-                        x => return Err(module.error(compare.source_range,
+                        x => return Err(module.error(source_range,
                             &format!("{}\n`{}` can not be used with bools",
                                 rt.stack_trace(),
                                 x.symbol()), rt))
                     }, sec.clone()))
                 }
                 (&Variable::Vec4(ref b), &Variable::Vec4(ref a)) => {
-                    Ok(Variable::bool(match compare.op {
+                    Ok(Variable::bool(match op {
                         Equal => a == b,
                         NotEqual => a != b,
-                        x => return Err(module.error(compare.source_range,
+                        x => return Err(module.error(source_range,
                             &format!("{}\n`{}` can not be used with vec4s",
                                 rt.stack_trace(),
                                 x.symbol()), rt))
                     }))
                 }
                 (&Variable::Object(ref b), &Variable::Object(ref a)) => {
-                    Ok(Variable::bool(match compare.op {
+                    Ok(Variable::bool(match op {
                         Equal => {
                             a.len() == b.len() &&
                             a.iter().all(|a| {
                                 if let Some(b_val) = b.get(a.0) {
                                     if let Ok(Variable::Bool(true, _)) =
-                                        sub_compare(rt, compare, module, &a.1, b_val) {true}
+                                        sub_compare(rt, op, source_range, module, &a.1, b_val) {true}
                                     else {false}
                                 } else {false}
                             })
@@ -2098,64 +4339,64 @@ impl Runtime {
                             a.iter().any(|a| {
                                 if let Some(b_val) = b.get(a.0) {
                                     if let Ok(Variable::Bool(false, _)) =
-                                        sub_compare(rt, compare, module, &a.1, b_val) {false}
+                                        sub_compare(rt, op, source_range, module, &a.1, b_val) {false}
                                     else {true}
                                 } else {true}
                             })
                         }
-                        x => return Err(module.error(compare.source_range,
+                        x => return Err(module.error(source_range,
                             &format!("{}\n`{}` can not be used with objects",
                                 rt.stack_trace(),
                                 x.symbol()), rt))
                     }))
                 }
                 (&Variable::Array(ref b), &Variable::Array(ref a)) => {
-                    Ok(Variable::bool(match compare.op {
+                    Ok(Variable::bool(match op {
                         Equal => {
                             a.len() == b.len() &&
                             a.iter().zip(b.iter()).all(|(a, b)| {
                                 if let Ok(Variable::Bool(true, _)) =
-                                    sub_compare(rt, compare, module, a, b) {true} else {false}
+                                    sub_compare(rt, op, source_range, module, a, b) {true} else {false}
                             })
                         }
                         NotEqual => {
                             a.len() != b.len() ||
                             a.iter().zip(b.iter()).any(|(a, b)| {
                                 if let Ok(Variable::Bool(false, _)) =
-                                    sub_compare(rt, compare, module, a, b) {false} else {true}
+                                    sub_compare(rt, op, source_range, module, a, b) {false} else {true}
                             })
                         }
-                        x => return Err(module.error(compare.source_range,
+                        x => return Err(module.error(source_range,
                             &format!("{}\n`{}` can not be used with arrays",
                                 rt.stack_trace(),
                                 x.symbol()), rt))
                     }))
                 }
                 (&Variable::Option(None), &Variable::Option(None)) => {
-                    Ok(Variable::bool(match compare.op {
+                    Ok(Variable::bool(match op {
                         Equal => true,
                         NotEqual => false,
-                        x => return Err(module.error(compare.source_range,
+                        x => return Err(module.error(source_range,
                             &format!("{}\n`{}` can not be used with options",
                                 rt.stack_trace(),
                                 x.symbol()), rt))
                     }))
                 }
                 (&Variable::Option(None), &Variable::Option(_)) => {
-                    Ok(Variable::bool(match compare.op {
+                    Ok(Variable::bool(match op {
                         Equal => false,
                         NotEqual => true,
-                        x => return Err(module.error(compare.source_range,
+                        x => return Err(module.error(source_range,
                             &format!("{}\n`{}` can not be used with options",
                                 rt.stack_trace(),
                                 x.symbol()), rt))
                     }))
                 }
                 (&Variable::Option(_), &Variable::Option(None)) => {
-                    Ok(Variable::bool(match compare.op {
+                    Ok(Variable::bool(match op {
                         Equal => false,
                         NotEqual => true,
-                        x => return Err(module.error(compare.source_range,
+                        x => return Err(module.error(source_range,
                             &format!("{}\n`{}` can not be used with options",
                                 rt.stack_trace(),
                                 x.symbol()), rt))
@@ -2163,33 +4404,38 @@ impl Runtime {
                 }
                 (&Variable::Option(Some(ref b)),
                  &Variable::Option(Some(ref a))) => {
-                    sub_compare(rt, compare, module, a, b)
+                    sub_compare(rt, op, source_range, module, a, b)
+                }
+                (&Variable::Enum(ref b), &Variable::Enum(ref a)) => {
+                    match op {
+                        Equal | NotEqual => {}
+                        x => return Err(module.error(source_range,
+                            &format!("{}\n`{}` can not be used with enums",
+                                rt.stack_trace(),
+                                x.symbol()), rt))
+                    }
+                    let same = a.name == b.name && a.variant == b.variant &&
+                               a.payload.is_some() == b.payload.is_some();
+                    match (same, &a.payload, &b.payload) {
+                        (true, &Some(ref a), &Some(ref b)) =>
+                            sub_compare(rt, op, source_range, module, a, b),
+                        (same, _, _) => Ok(Variable::bool(match op {
+                            Equal => same,
+                            _ => !same,
+                        })),
+                    }
                 }
-                (b, a) => return Err(module.error(compare.source_range,
+                (b, a) => return Err(module.error(source_range,
                     &format!(
                     "{}\n`{}` can not be used with `{}` and `{}`",
                     rt.stack_trace(),
-                    compare.op.symbol(),
+                    op.symbol(),
                     rt.typeof_var(a),
                     rt.typeof_var(b)), rt))
             }
         }
 
-        let left = match try!(self.expression(&compare.left, Side::Right, module)) {
-            (Some(x), Flow::Continue) => x,
-            (x, Flow::Return) => { return Ok((x, Flow::Return)); }
-            _ => return Err(module.error(compare.left.source_range(),
-                &format!("{}\nExpected something from the left argument",
-                    self.stack_trace()), self))
-        };
-        let right = match try!(self.expression(&compare.right, Side::Right, module)) {
-            (Some(x), Flow::Continue) => x,
-            (x, Flow::Return) => return Ok((x, Flow::Return)),
-            _ => return Err(module.error(compare.right.source_range(),
-                &format!("{}\nExpected something from the right argument",
-                    self.stack_trace()), self))
-        };
-        Ok((Some(try!(sub_compare(self, compare, module, &left, &right))), Flow::Continue))
+        sub_compare(self, op, source_range, module, left, right)
     }
     fn if_expr(
         &mut self,
@@ -2210,10 +4456,11 @@ impl Runtime {
                     self.stack_trace()), self))
         };
         if val {
+            if self.coverage.is_some() { self.cover_branch(if_expr.source_range, if_expr.else_if_blocks.len() + 2, 0, module); }
             return self.block(&if_expr.true_block, module);
         }
-        for (cond, body) in if_expr.else_if_conds.iter()
-            .zip(if_expr.else_if_blocks.iter()) {
+        for (i, (cond, body)) in if_expr.else_if_conds.iter()
+            .zip(if_expr.else_if_blocks.iter()).enumerate() {
             let else_if_cond = match try!(self.expression(cond, Side::Right, module)) {
                 (Some(x), Flow::Continue) => x,
                 (x, Flow::Return) => {
@@ -2226,6 +4473,7 @@ impl Runtime {
             match self.resolve(&else_if_cond) {
                 &Variable::Bool(false, _) => {}
                 &Variable::Bool(true, _) => {
+                    if self.coverage.is_some() { self.cover_branch(if_expr.source_range, if_expr.else_if_blocks.len() + 2, i + 1, module); }
                     return self.block(body, module);
                 }
                 _ => return Err(module.error(cond.source_range(),
@@ -2233,12 +4481,143 @@ impl Runtime {
                         self.stack_trace()), self))
             }
         }
+        if self.coverage.is_some() {
+            self.cover_branch(if_expr.source_range, if_expr.else_if_blocks.len() + 2,
+                if_expr.else_if_blocks.len() + 1, module);
+        }
         if let Some(ref block) = if_expr.else_block {
             self.block(block, module)
         } else {
             Ok((None, Flow::Continue))
         }
     }
+    fn enum_value(
+        &mut self,
+        enum_value: &ast::EnumValue,
+        module: &Arc<Module>
+    ) -> Result<(Option<Variable>, Flow), String> {
+        let payload = match enum_value.payload {
+            None => None,
+            Some(ref payload) => {
+                match try!(self.expression(payload, Side::Right, module)) {
+                    (Some(x), Flow::Continue) => {
+                        // Payload is deep cloned, like `some(x)`, so it does not contain references.
+                        Some(self.resolve(&x).deep_clone(&self.stack))
+                    }
+                    (x, Flow::Return) => { return Ok((x, Flow::Return)); }
+                    _ => return Err(module.error(payload.source_range(),
+                        &format!("{}\nExpected something from payload",
+                            self.stack_trace()), self))
+                }
+            }
+        };
+        Ok((Some(Variable::Enum(Box::new(EnumVariant {
+            name: enum_value.name.clone(),
+            variant: enum_value.variant.clone(),
+            payload: payload,
+        }))), Flow::Continue))
+    }
+    fn match_expr(
+        &mut self,
+        match_expr: &ast::Match,
+        module: &Arc<Module>
+    ) -> Result<(Option<Variable>, Flow), String> {
+        let val = match try!(self.expression(&match_expr.expr, Side::Right, module)) {
+            (Some(x), Flow::Continue) => x,
+            (x, Flow::Return) => { return Ok((x, Flow::Return)); }
+            _ => return Err(module.error(match_expr.expr.source_range(),
+                &format!("{}\nExpected something to match",
+                    self.stack_trace()), self))
+        };
+        let val = self.resolve(&val).clone();
+        let st = self.stack.len();
+        let lc = self.local_stack.len();
+        let cu = self.current_stack.len();
+        for arm in &match_expr.arms {
+            if !self.bind_pattern(&arm.pattern, &val) {
+                self.stack.truncate(st);
+                self.local_stack.truncate(lc);
+                continue;
+            }
+            if let Some(ref guard) = arm.guard {
+                let cond = match try!(self.expression(guard, Side::Right, module)) {
+                    (Some(x), Flow::Continue) => x,
+                    (x, Flow::Return) => {
+                        self.stack.truncate(st);
+                        self.local_stack.truncate(lc);
+                        self.current_stack.truncate(cu);
+                        return Ok((x, Flow::Return));
+                    }
+                    _ => return Err(module.error(guard.source_range(),
+                        &format!("{}\nExpected bool from match guard",
+                            self.stack_trace()), self))
+                };
+                let cond = match self.resolve(&cond) {
+                    &Variable::Bool(val, _) => val,
+                    _ => return Err(module.error(guard.source_range(),
+                        &format!("{}\nExpected bool from match guard",
+                            self.stack_trace()), self))
+                };
+                if !cond {
+                    self.stack.truncate(st);
+                    self.local_stack.truncate(lc);
+                    continue;
+                }
+            }
+            let res = self.expression(&arm.expr, Side::Right, module);
+            self.stack.truncate(st);
+            self.local_stack.truncate(lc);
+            self.current_stack.truncate(cu);
+            return res;
+        }
+        Err(module.error(match_expr.source_range,
+            &format!("{}\nNo arm matched the value `{}`",
+                self.stack_trace(), self.typeof_var(&val)), self))
+    }
+    /// Checks whether a value matches a pattern,
+    /// pushing the bound variables to the stack.
+    fn bind_pattern(&mut self, pattern: &ast::Pattern, val: &Variable) -> bool {
+        use ast::Pattern as P;
+
+        match (pattern, val) {
+            (&P::Wildcard, _) => true,
+            (&P::Bind(ref name), _) => {
+                self.local_stack.push((name.clone(), self.stack.len()));
+                self.stack.push(val.clone());
+                true
+            }
+            (&P::Number(a), &Variable::F64(b, _)) => a == b,
+            (&P::Range(start, end), &Variable::F64(b, _)) => start <= b && b < end,
+            (&P::Text(ref a), &Variable::Text(ref b)) => a == b,
+            (&P::Bool(a), &Variable::Bool(b, _)) => a == b,
+            (&P::None, &Variable::Option(None)) => true,
+            (&P::Some(ref p), &Variable::Option(Some(ref v))) => self.bind_pattern(p, v),
+            (&P::Ok(ref p), &Variable::Result(Ok(ref v))) => self.bind_pattern(p, v),
+            (&P::Err(ref p), &Variable::Result(Err(ref err))) =>
+                self.bind_pattern(p, &err.message),
+            (&P::Object(ref fields), &Variable::Object(ref obj)) => {
+                for &(ref key, ref p) in fields {
+                    match obj.get(key) {
+                        Some(v) => {
+                            let v = self.resolve(v).clone();
+                            if !self.bind_pattern(p, &v) { return false; }
+                        }
+                        None => return false,
+                    }
+                }
+                true
+            }
+            (&P::Enum(ref name, ref variant, ref p), &Variable::Enum(ref val)) => {
+                if name != &val.name || variant != &val.variant { return false; }
+                match (p, &val.payload) {
+                    (&None, &None) => true,
+                    (&Some(ref p), &Some(ref v)) => self.bind_pattern(p, v),
+                    _ => false,
+                }
+            }
+            _ => false,
+        }
+    }
     fn for_expr(
         &mut self,
         for_expr: &ast::For,
@@ -2273,6 +4652,7 @@ impl Runtime {
             if !val { break }
             match try!(self.block(&for_expr.block, module)) {
                 (x, Flow::Return) => return Ok((x, Flow::Return)),
+                (_, Flow::Suspend) => return Ok((None, Flow::Suspend)),
                 (_, Flow::Continue) => {}
                 (_, Flow::Break(x)) => {
                     match x {
@@ -2386,6 +4766,7 @@ impl Runtime {
             };
             match try!(self.block(&for_n_expr.block, module)) {
                 (x, Flow::Return) => { return Ok((x, Flow::Return)); }
+                (_, Flow::Suspend) => return Ok((None, Flow::Suspend)),
                 (_, Flow::Continue) => {}
                 (_, Flow::Break(x)) => {
                     match x {
@@ -2440,7 +4821,10 @@ impl Runtime {
     ) -> Result<(Option<Variable>, Flow), String> {
         let prev_st = self.stack.len();
         let prev_lc = self.local_stack.len();
-        let mut sum = 0.0;
+        use bytecode::Acc;
+
+        let mut sum = acc_start(Acc::Sum, for_n_expr.i64_acc.get());
+        let mut first = true;
 
         let start = if let Some(ref start) = for_n_expr.start {
             // Evaluate start such that it's on the stack.
@@ -2491,13 +4875,15 @@ impl Runtime {
             };
             match try!(self.block(&for_n_expr.block, module)) {
                 (Some(x), Flow::Continue) => {
-                    match self.resolve(&x) {
-                        &Variable::F64(val, _) => sum += val,
-                        x => return Err(module.error(for_n_expr.block.source_range,
+                    let x = self.resolve(&x);
+                    if !accumulate(Acc::Sum, &mut sum, first, x) {
+                        return Err(module.error(for_n_expr.block.source_range,
                                 &self.expected(x, "number"), self))
-                    };
+                    }
+                    first = false;
                 }
                 (x, Flow::Return) => { return Ok((x, Flow::Return)); }
+                (_, Flow::Suspend) => return Ok((None, Flow::Suspend)),
                 (None, Flow::Continue) => {
                     return Err(module.error(for_n_expr.block.source_range,
                                 "Expected `number`", self))
@@ -2546,7 +4932,7 @@ impl Runtime {
         };
         self.stack.truncate(prev_st);
         self.local_stack.truncate(prev_lc);
-        Ok((Some(Variable::f64(sum)), flow))
+        Ok((Some(sum), flow))
     }
     fn sum_vec4_n_expr(
         &mut self,
@@ -2617,6 +5003,7 @@ impl Runtime {
                     };
                 }
                 (x, Flow::Return) => { return Ok((x, Flow::Return)); }
+                (_, Flow::Suspend) => return Ok((None, Flow::Suspend)),
                 (None, Flow::Continue) => {
                     return Err(module.error(for_n_expr.block.source_range,
                                 "Expected `vec4`", self))
@@ -2674,7 +5061,10 @@ impl Runtime {
     ) -> Result<(Option<Variable>, Flow), String> {
         let prev_st = self.stack.len();
         let prev_lc = self.local_stack.len();
-        let mut prod = 1.0;
+        use bytecode::Acc;
+
+        let mut prod = acc_start(Acc::Prod, for_n_expr.i64_acc.get());
+        let mut first = true;
 
         let start = if let Some(ref start) = for_n_expr.start {
             // Evaluate start such that it's on the stack.
@@ -2725,13 +5115,15 @@ impl Runtime {
             };
             match try!(self.block(&for_n_expr.block, module)) {
                 (Some(x), Flow::Continue) => {
-                    match self.resolve(&x) {
-                        &Variable::F64(val, _) => prod *= val,
-                        x => return Err(module.error(for_n_expr.block.source_range,
+                    let x = self.resolve(&x);
+                    if !accumulate(Acc::Prod, &mut prod, first, x) {
+                        return Err(module.error(for_n_expr.block.source_range,
                                 &self.expected(x, "number"), self))
-                    };
+                    }
+                    first = false;
                 }
                 (x, Flow::Return) => { return Ok((x, Flow::Return)); }
+                (_, Flow::Suspend) => return Ok((None, Flow::Suspend)),
                 (None, Flow::Continue) => {
                     return Err(module.error(for_n_expr.block.source_range,
                                 "Expected `number`", self))
@@ -2780,7 +5172,7 @@ impl Runtime {
         };
         self.stack.truncate(prev_st);
         self.local_stack.truncate(prev_lc);
-        Ok((Some(Variable::f64(prod)), flow))
+        Ok((Some(prod), flow))
     }
     fn prod_vec4_n_expr(
         &mut self,
@@ -2851,6 +5243,7 @@ impl Runtime {
                     };
                 }
                 (x, Flow::Return) => { return Ok((x, Flow::Return)); }
+                (_, Flow::Suspend) => return Ok((None, Flow::Suspend)),
                 (None, Flow::Continue) => {
                     return Err(module.error(for_n_expr.block.source_range,
                                 "Expected `vec4`", self))
@@ -2981,6 +5374,7 @@ impl Runtime {
                     };
                 }
                 (x, Flow::Return) => { return Ok((x, Flow::Return)); }
+                (_, Flow::Suspend) => return Ok((None, Flow::Suspend)),
                 (None, Flow::Continue) => {
                     return Err(module.error(for_n_expr.block.source_range,
                                 "Expected `number or option`", self))
@@ -3112,6 +5506,7 @@ impl Runtime {
                     };
                 }
                 (x, Flow::Return) => { return Ok((x, Flow::Return)); }
+                (_, Flow::Suspend) => return Ok((None, Flow::Suspend)),
                 (None, Flow::Continue) => {
                     return Err(module.error(for_n_expr.block.source_range,
                                 "Expected `number`", self))
@@ -3243,6 +5638,7 @@ impl Runtime {
                     };
                 }
                 (x, Flow::Return) => { return Ok((x, Flow::Return)); }
+                (_, Flow::Suspend) => return Ok((None, Flow::Suspend)),
                 (None, Flow::Continue) => {
                     return Err(module.error(for_n_expr.block.source_range,
                                 "Expected `boolean`", self))
@@ -3374,6 +5770,7 @@ impl Runtime {
                     };
                 }
                 (x, Flow::Return) => { return Ok((x, Flow::Return)); }
+                (_, Flow::Suspend) => return Ok((None, Flow::Suspend)),
                 (None, Flow::Continue) => {
                     return Err(module.error(for_n_expr.block.source_range,
                                 "Expected `boolean`", self))
@@ -3429,6 +5826,8 @@ impl Runtime {
         for_n_expr: &ast::ForN,
         module: &Arc<Module>
     ) -> Result<(Option<Variable>, Flow), String> {
+        use std::mem::size_of;
+        use link::Block;
         use Link;
 
         fn sub_link_for_n_expr(
@@ -3489,6 +5888,9 @@ impl Runtime {
                                     &rt.expected(x, "number"), rt))
                 };
 
+                if rt.coverage.is_some() {
+                    rt.cover_block(for_n_expr.block.source_range, module);
+                }
                 match for_n_expr.block.expressions[0] {
                     ast::Expression::Link(ref link) => {
                         // Evaluate link items directly.
@@ -3505,6 +5907,7 @@ impl Runtime {
                                     }
                                 }
                                 (x, Flow::Return) => { return Ok((x, Flow::Return)); }
+                                (_, Flow::Suspend) => return Ok((None, Flow::Suspend)),
                                 (None, Flow::Continue) => {}
                                 (_, Flow::Break(x)) => {
                                     match x {
@@ -3603,8 +6006,11 @@ impl Runtime {
 
         let mut res: Link = Link::new();
         match sub_link_for_n_expr(&mut res, self, for_n_expr, module) {
-            Ok((None, Flow::Continue)) =>
-                Ok((Some(Variable::Link(Box::new(res))), Flow::Continue)),
+            Ok((None, Flow::Continue)) => {
+                try!(self.alloc(res.slices.len() * size_of::<Block>(),
+                                for_n_expr.source_range, module));
+                Ok((Some(Variable::Link(Box::new(res))), Flow::Continue))
+            }
             x => x
         }
     }
@@ -3613,6 +6019,8 @@ impl Runtime {
         for_n_expr: &ast::ForN,
         module: &Arc<Module>
     ) -> Result<(Option<Variable>, Flow), String> {
+        use std::mem::size_of;
+
         let prev_st = self.stack.len();
         let prev_lc = self.local_stack.len();
         let mut res: Vec<Variable> = vec![];
@@ -3665,8 +6073,13 @@ impl Runtime {
                                 &self.expected(x, "number"), self))
             };
             match try!(self.block(&for_n_expr.block, module)) {
-                (Some(x), Flow::Continue) => res.push(x),
+                (Some(x), Flow::Continue) => {
+                    try!(self.alloc_building(size_of::<Variable>(),
+                        for_n_expr.block.source_range, module));
+                    res.push(x)
+                }
                 (x, Flow::Return) => { return Ok((x, Flow::Return)); }
+                (_, Flow::Suspend) => return Ok((None, Flow::Suspend)),
                 (None, Flow::Continue) => {
                     return Err(module.error(for_n_expr.block.source_range,
                                 "Expected variable", self))
@@ -3717,6 +6130,547 @@ impl Runtime {
         self.local_stack.truncate(prev_lc);
         Ok((Some(Variable::Array(Arc::new(res))), flow))
     }
+    /// Runs a short loop in parallel by splitting the range across worker threads.
+    ///
+    /// Each worker runs the loop on its part of the range with a deep clone
+    /// of the variables in the current function and the globals.
+    /// The results are combined in order of the range.
+    fn par_n_expr(
+        &mut self,
+        expr: &ast::Expression,
+        for_n_expr: &ast::ForN,
+        module: &Arc<Module>
+    ) -> Result<(Option<Variable>, Flow), String> {
+        use ast::Expression as E;
+
+        try!(self.require(Capability::Threads, for_n_expr.source_range, module));
+
+        let start = if let Some(ref start) = for_n_expr.start {
+            let start = match try!(self.expression(start, Side::Right, module)) {
+                (Some(x), Flow::Continue) => x,
+                (x, Flow::Return) => { return Ok((x, Flow::Return)); }
+                _ => return Err(module.error(for_n_expr.end.source_range(),
+                    &format!("{}\nExpected number from for start",
+                        self.stack_trace()), self))
+            };
+            let start = match self.resolve(&start) {
+                &Variable::F64(val, _) => val,
+                x => return Err(module.error(for_n_expr.end.source_range(),
+                                &self.expected(x, "number"), self))
+            };
+            start
+        } else { 0.0 };
+
+        let end = match try!(self.expression(&for_n_expr.end, Side::Right, module)) {
+            (Some(x), Flow::Continue) => x,
+            (x, Flow::Return) => { return Ok((x, Flow::Return)); }
+            _ => return Err(module.error(for_n_expr.end.source_range(),
+                &format!("{}\nExpected number from for end",
+                    self.stack_trace()), self))
+        };
+        let end = match self.resolve(&end) {
+            &Variable::F64(val, _) => val,
+            x => return Err(module.error(for_n_expr.end.source_range(),
+                            &self.expected(x, "number"), self))
+        };
+
+        // Creates a sequential loop over part of the range.
+        let part = |a: f64, b: f64| {
+            let for_n = ast::ForN {
+                name: for_n_expr.name.clone(),
+                start: Some(E::Variable(for_n_expr.source_range, Variable::f64(a))),
+                end: E::Variable(for_n_expr.source_range, Variable::f64(b)),
+                block: for_n_expr.block.clone(),
+                label: for_n_expr.label.clone(),
+                parallel: false,
+                i64_acc: for_n_expr.i64_acc.clone(),
+                source_range: for_n_expr.source_range,
+            };
+            match *expr {
+                E::Sum(_) => E::Sum(Box::new(for_n)),
+                E::Min(_) => E::Min(Box::new(for_n)),
+                E::Max(_) => E::Max(Box::new(for_n)),
+                E::Sift(_) => E::Sift(Box::new(for_n)),
+                E::Any(_) => E::Any(Box::new(for_n)),
+                E::All(_) => E::All(Box::new(for_n)),
+                _ => panic!("Expected parallel short loop")
+            }
+        };
+
+        let n = (end - start).ceil();
+        let workers = if n > 1.0 { ::std::cmp::min(self.workers, n as usize) } else { 1 };
+        if workers <= 1 {
+            return self.expression(&part(start, end), Side::Right, module);
+        }
+
+        // Copy the variables that can be referenced from the loop body,
+        // keeping their position on the stack.
+        let (stack_len, local_len, current_len) = {
+            let call = self.call_stack.last().unwrap();
+            (call.stack_len, call.local_len, call.current_len)
+        };
+        let globals = self.globals..self.globals + module.globals.len();
+        let mut tasks: Vec<Arc<Task>> = Vec::with_capacity(workers);
+        for w in 0..workers {
+            let a = start + (n * w as f64 / workers as f64).floor();
+            let b = if w + 1 == workers { end }
+                    else { start + (n * (w + 1) as f64 / workers as f64).floor() };
+            let stack = self.stack.iter().enumerate().map(|(i, v)| {
+                if i >= stack_len || globals.start <= i && i < globals.end ||
+                   self.current_stack.iter().any(|&(_, j)| i == j) {
+                    v.deep_clone(&self.stack)
+                } else {
+                    Variable::Return
+                }
+            }).collect();
+            let mut new_rt = self.thread_runtime(stack);
+            // Workers stop when the thread running the loop is cancelled.
+            new_rt.cancelled = self.cancelled.clone();
+            new_rt.globals = self.globals;
+            new_rt.local_stack = self.local_stack.clone();
+            new_rt.current_stack = self.current_stack.clone();
+            if let Some(call) = new_rt.call_stack.last_mut() {
+                call.stack_len = stack_len;
+                call.local_len = local_len;
+                call.current_len = current_len;
+            }
+            let part_expr = part(a, b);
+            let range = for_n_expr.source_range;
+            let new_module: Module = (**module).clone();
+            let cancelled = self.cancelled.clone()
+                .unwrap_or_else(|| Arc::new(AtomicBool::new(false)));
+            let task = Arc::new(Task::new(Box::new(move || {
+                let mut new_rt = new_rt;
+                let new_module = Arc::new(new_module);
+                let res = new_rt.expression(&part_expr, Side::Right, &new_module);
+                // Give back fuel that was not used.
+                if let Some(ref fuel) = new_rt.fuel {
+                    fuel.fetch_add(new_rt.steps, Ordering::SeqCst);
+                }
+                match try!(res) {
+                    (Some(x), Flow::Continue) => Ok(x.deep_clone(&new_rt.stack)),
+                    _ => Err(new_module.error(range,
+                        &format!("{}\nCan not leave parallel loop", new_rt.stack_trace()),
+                        &new_rt))
+                }
+            }), cancelled));
+            pool::spawn(self.pool.as_ref(), task.clone());
+            tasks.push(task);
+        }
+
+        // Wait for all workers before reporting errors.
+        let results: Vec<_> = tasks.iter().map(|task| task.join()).collect();
+        let mut parts = Vec::with_capacity(workers);
+        for res in results {
+            parts.push(try!(res));
+        }
+
+        // Combine the results in the same way as a sequential loop.
+        let res = match *expr {
+            E::Sum(ref for_n_expr) => {
+                let mut sum = acc_start(bytecode::Acc::Sum, for_n_expr.i64_acc.get());
+                for (i, x) in parts.iter().enumerate() {
+                    accumulate(bytecode::Acc::Sum, &mut sum, i == 0, x);
+                }
+                sum
+            }
+            E::Min(_) | E::Max(_) => {
+                let min = if let E::Min(_) = *expr { true } else { false };
+                let mut res = Variable::f64(::std::f64::NAN);
+                for x in parts {
+                    let better = match (&res, &x) {
+                        (&Variable::F64(a, _), &Variable::F64(b, _)) =>
+                            a.is_nan() || if min { a > b } else { a < b },
+                        _ => false
+                    };
+                    if better { res = x; }
+                }
+                res
+            }
+            E::Sift(_) => {
+                let len = parts.iter().map(|x| match x {
+                    &Variable::Array(ref x) => x.len(),
+                    _ => 0
+                }).sum::<usize>();
+                try!(self.alloc(len * ::std::mem::size_of::<Variable>(),
+                    for_n_expr.source_range, module));
+                let mut arr = Vec::with_capacity(len);
+                for x in parts {
+                    if let Variable::Array(x) = x { arr.extend(x.iter().cloned()); }
+                }
+                Variable::Array(Arc::new(arr))
+            }
+            E::Any(_) | E::All(_) => {
+                let any = if let E::Any(_) = *expr { true } else { false };
+                // The first part that decides the result tells where it was found.
+                match parts.into_iter().find(|x| match x {
+                    &Variable::Bool(val, _) => val == any,
+                    _ => false
+                }) {
+                    Some(x) => x,
+                    None => Variable::bool(!any)
+                }
+            }
+            _ => panic!("Expected parallel short loop")
+        };
+        Ok((Some(res), Flow::Continue))
+    }
+    /// Gets a pointer to the collection of a mutable `for in` loop.
+    fn for_in_ref(
+        &mut self,
+        for_in: &ast::ForIn,
+        module: &Arc<Module>
+    ) -> Result<*mut Variable, String> {
+        let mut r = match try!(self.expression(&for_in.expr, Side::LeftInsert(false), module)) {
+            (Some(Variable::UnsafeRef(r)), Flow::Continue) => r.0,
+            (Some(Variable::Ref(ind)), Flow::Continue) => &mut self.stack[ind] as *mut Variable,
+            _ => return Err(module.error(for_in.expr.source_range(),
+                &format!("{}\nExpected item for `mut` in loop",
+                    self.stack_trace()), self))
+        };
+        loop {
+            let ind = match unsafe { &*r } {
+                &Variable::Ref(ind) => ind,
+                _ => break
+            };
+            r = &mut self.stack[ind] as *mut Variable;
+        }
+        Ok(r)
+    }
+    /// Runs the body of a `for in` loop for every element of a collection.
+    ///
+    /// Calls `f` with position and value of body, which returns `true` to stop the loop.
+    fn for_in_loop<F>(
+        &mut self,
+        for_in: &ast::ForIn,
+        module: &Arc<Module>,
+        mut f: F
+    ) -> Result<(Option<Variable>, Flow), String>
+        where F: FnMut(&mut Runtime, &Variable, Option<Variable>) -> Result<bool, String>
+    {
+        let prev_st = self.stack.len();
+        let prev_lc = self.local_stack.len();
+
+        let coll = match try!(self.expression(&for_in.expr, Side::Right, module)) {
+            (x, Flow::Return) => { return Ok((x, Flow::Return)); }
+            (Some(x), Flow::Continue) => x,
+            _ => return Err(module.error(for_in.expr.source_range(),
+                &format!("{}\nExpected array, object or link from for in",
+                    self.stack_trace()), self))
+        };
+        let coll = self.resolve(&coll).clone();
+
+        // Object keys and link items are collected before the loop,
+        // such that the body can change the collection.
+        let mut keys: Vec<Arc<String>> = vec![];
+        let mut items: Vec<Variable> = vec![];
+        match coll {
+            Variable::Array(_) => {}
+            Variable::Object(ref obj) => {
+                keys = obj.keys().cloned().collect();
+                keys.sort();
+            }
+            Variable::Link(ref link) => {
+                for slice in &link.slices {
+                    for i in slice.start..slice.end {
+                        items.push(slice.block.var(i));
+                    }
+                }
+            }
+            ref x => return Err(module.error(for_in.expr.source_range(),
+                &self.expected(x, "array, object or link"), self))
+        }
+        // Looping over an object with one name binds the key.
+        let object_keys = if let Variable::Object(_) = coll { for_in.key.is_none() }
+                          else { false };
+        if for_in.mutable {
+            let msg = match coll {
+                Variable::Link(_) => Some("Can not change items of link"),
+                _ if object_keys => Some("Can not change keys of object"),
+                _ => None
+            };
+            if let Some(msg) = msg {
+                return Err(module.error(for_in.source_range,
+                    &format!("{}\n{}", self.stack_trace(), msg), self))
+            }
+        }
+        // A mutable loop accesses the collection directly to avoid copying it.
+        // The lifetime checker makes sure the body does not change the collection.
+        let coll = if for_in.mutable { None } else { Some(coll) };
+
+        let st = self.stack.len();
+        let lc = self.local_stack.len();
+        let mut flow = Flow::Continue;
+        let mut i = 0;
+        loop {
+            let elem = {
+                let coll: &Variable = match coll {
+                    Some(ref coll) => coll,
+                    None => unsafe { &*try!(self.for_in_ref(for_in, module)) }
+                };
+                match *coll {
+                    Variable::Array(ref arr) => {
+                        if i >= arr.len() { break; }
+                        Some((Variable::f64(i as f64), arr[i].clone()))
+                    }
+                    Variable::Object(ref obj) => {
+                        if i >= keys.len() { break; }
+                        // Skip keys that are removed by the body.
+                        obj.get(&keys[i]).map(|v| (Variable::Text(keys[i].clone()), v.clone()))
+                    }
+                    Variable::Link(_) => {
+                        if i >= items.len() { break; }
+                        Some((Variable::f64(i as f64), items[i].clone()))
+                    }
+                    ref x => return Err(module.error(for_in.expr.source_range(),
+                        &self.expected(x, "array, object or link"), self))
+                }
+            };
+            let (pos, val) = match elem {
+                None => {
+                    i += 1;
+                    continue;
+                }
+                Some(x) => x
+            };
+            if let Some(ref key) = for_in.key {
+                self.local_stack.push((key.clone(), self.stack.len()));
+                self.stack.push(pos.clone());
+            }
+            let ind = self.stack.len();
+            self.local_stack.push((for_in.name.clone(), ind));
+            self.stack.push(if object_keys { pos.clone() } else { val });
+            let res = try!(self.block(&for_in.block, module));
+            if for_in.mutable {
+                let val = self.resolve(&self.stack[ind]).clone();
+                self.stack.truncate(st);
+                self.local_stack.truncate(lc);
+                // Write back element to the collection.
+                let r = try!(self.for_in_ref(for_in, module));
+                match unsafe { &mut *r } {
+                    &mut Variable::Array(ref mut arr) => {
+                        if i < arr.len() { Arc::make_mut(arr)[i] = val; }
+                    }
+                    &mut Variable::Object(ref mut obj) => {
+                        Arc::make_mut(obj).insert(keys[i].clone(), val);
+                    }
+                    x => return Err(module.error(for_in.expr.source_range(),
+                        &self.expected(x, "array or object"), self))
+                }
+            }
+            match res {
+                (x, Flow::Return) => { return Ok((x, Flow::Return)); }
+                (_, Flow::Suspend) => return Ok((None, Flow::Suspend)),
+                (x, Flow::Continue) => {
+                    if try!(f(self, &pos, x)) { break; }
+                }
+                (_, Flow::Break(x)) => {
+                    match x {
+                        Some(label) => {
+                            let same =
+                            if let Some(ref for_label) = for_in.label {
+                                &label == for_label
+                            } else { false };
+                            if !same {
+                                flow = Flow::Break(Some(label))
+                            }
+                        }
+                        None => {}
+                    }
+                    break;
+                }
+                (_, Flow::ContinueLoop(x)) => {
+                    match x {
+                        Some(label) => {
+                            let same =
+                            if let Some(ref for_label) = for_in.label {
+                                &label == for_label
+                            } else { false };
+                            if !same {
+                                flow = Flow::ContinueLoop(Some(label));
+                                break;
+                            }
+                        }
+                        None => {}
+                    }
+                }
+            }
+            self.stack.truncate(st);
+            self.local_stack.truncate(lc);
+            i += 1;
+        }
+        self.stack.truncate(prev_st);
+        self.local_stack.truncate(prev_lc);
+        Ok((None, flow))
+    }
+    fn for_in_expr(
+        &mut self,
+        for_in: &ast::ForIn,
+        module: &Arc<Module>
+    ) -> Result<(Option<Variable>, Flow), String> {
+        self.for_in_loop(for_in, module, |_, _, _| Ok(false))
+    }
+    fn sum_in_expr(
+        &mut self,
+        for_in: &ast::ForIn,
+        module: &Arc<Module>
+    ) -> Result<(Option<Variable>, Flow), String> {
+        use bytecode::Acc;
+
+        let mut sum = acc_start(Acc::Sum, for_in.i64_acc.get());
+        let mut first = true;
+        let flow = match try!(self.for_in_loop(for_in, module, |rt, _, x| {
+            match x.as_ref().map(|x| rt.resolve(x)) {
+                Some(x) => if !accumulate(Acc::Sum, &mut sum, first, x) {
+                    return Err(module.error(for_in.block.source_range,
+                        &rt.expected(x, "number"), rt))
+                },
+                None => return Err(module.error(for_in.block.source_range,
+                    "Expected `number`", rt))
+            }
+            first = false;
+            Ok(false)
+        })) {
+            (x, Flow::Return) => { return Ok((x, Flow::Return)); }
+            (_, flow) => flow
+        };
+        Ok((Some(sum), flow))
+    }
+    fn min_in_expr(
+        &mut self,
+        for_in: &ast::ForIn,
+        module: &Arc<Module>
+    ) -> Result<(Option<Variable>, Flow), String> {
+        let mut min = ::std::f64::NAN;
+        let mut sec = None;
+        let flow = match try!(self.for_in_loop(for_in, module, |rt, pos, x| {
+            match x.as_ref().map(|x| rt.resolve(x)) {
+                Some(&Variable::F64(val, ref val_sec)) => {
+                    if min.is_nan() || min > val {
+                        min = val;
+                        sec = push_secret(val_sec, pos);
+                    }
+                }
+                Some(x) => return Err(module.error(for_in.block.source_range,
+                    &rt.expected(x, "number"), rt)),
+                None => return Err(module.error(for_in.block.source_range,
+                    "Expected `number`", rt))
+            }
+            Ok(false)
+        })) {
+            (x, Flow::Return) => { return Ok((x, Flow::Return)); }
+            (_, flow) => flow
+        };
+        Ok((Some(Variable::F64(min, sec)), flow))
+    }
+    fn max_in_expr(
+        &mut self,
+        for_in: &ast::ForIn,
+        module: &Arc<Module>
+    ) -> Result<(Option<Variable>, Flow), String> {
+        let mut max = ::std::f64::NAN;
+        let mut sec = None;
+        let flow = match try!(self.for_in_loop(for_in, module, |rt, pos, x| {
+            match x.as_ref().map(|x| rt.resolve(x)) {
+                Some(&Variable::F64(val, ref val_sec)) => {
+                    if max.is_nan() || max < val {
+                        max = val;
+                        sec = push_secret(val_sec, pos);
+                    }
+                }
+                Some(x) => return Err(module.error(for_in.block.source_range,
+                    &rt.expected(x, "number"), rt)),
+                None => return Err(module.error(for_in.block.source_range,
+                    "Expected `number`", rt))
+            }
+            Ok(false)
+        })) {
+            (x, Flow::Return) => { return Ok((x, Flow::Return)); }
+            (_, flow) => flow
+        };
+        Ok((Some(Variable::F64(max, sec)), flow))
+    }
+    fn any_in_expr(
+        &mut self,
+        for_in: &ast::ForIn,
+        module: &Arc<Module>
+    ) -> Result<(Option<Variable>, Flow), String> {
+        let mut any = false;
+        let mut sec = None;
+        let flow = match try!(self.for_in_loop(for_in, module, |rt, pos, x| {
+            match x.as_ref().map(|x| rt.resolve(x)) {
+                Some(&Variable::Bool(val, ref val_sec)) => {
+                    if val {
+                        any = true;
+                        sec = push_secret(val_sec, pos);
+                        return Ok(true);
+                    }
+                }
+                Some(x) => return Err(module.error(for_in.block.source_range,
+                    &rt.expected(x, "boolean"), rt)),
+                None => return Err(module.error(for_in.block.source_range,
+                    "Expected `boolean`", rt))
+            }
+            Ok(false)
+        })) {
+            (x, Flow::Return) => { return Ok((x, Flow::Return)); }
+            (_, flow) => flow
+        };
+        Ok((Some(Variable::Bool(any, sec)), flow))
+    }
+    fn all_in_expr(
+        &mut self,
+        for_in: &ast::ForIn,
+        module: &Arc<Module>
+    ) -> Result<(Option<Variable>, Flow), String> {
+        let mut all = true;
+        let mut sec = None;
+        let flow = match try!(self.for_in_loop(for_in, module, |rt, pos, x| {
+            match x.as_ref().map(|x| rt.resolve(x)) {
+                Some(&Variable::Bool(val, ref val_sec)) => {
+                    if !val {
+                        all = false;
+                        sec = push_secret(val_sec, pos);
+                        return Ok(true);
+                    }
+                }
+                Some(x) => return Err(module.error(for_in.block.source_range,
+                    &rt.expected(x, "boolean"), rt)),
+                None => return Err(module.error(for_in.block.source_range,
+                    "Expected `boolean`", rt))
+            }
+            Ok(false)
+        })) {
+            (x, Flow::Return) => { return Ok((x, Flow::Return)); }
+            (_, flow) => flow
+        };
+        Ok((Some(Variable::Bool(all, sec)), flow))
+    }
+    fn sift_in_expr(
+        &mut self,
+        for_in: &ast::ForIn,
+        module: &Arc<Module>
+    ) -> Result<(Option<Variable>, Flow), String> {
+        use std::mem::size_of;
+
+        let mut res: Vec<Variable> = vec![];
+        let flow = match try!(self.for_in_loop(for_in, module, |rt, _, x| {
+            match x {
+                Some(x) => {
+                    try!(rt.alloc_building(size_of::<Variable>(),
+                        for_in.block.source_range, module));
+                    res.push(rt.resolve(&x).clone())
+                }
+                None => return Err(module.error(for_in.block.source_range,
+                    "Expected variable", rt))
+            }
+            Ok(false)
+        })) {
+            (x, Flow::Return) => { return Ok((x, Flow::Return)); }
+            (_, flow) => flow
+        };
+        Ok((Some(Variable::Array(Arc::new(res))), flow))
+    }
     fn vec4(
         &mut self,
         vec4: &ast::Vec4,
@@ -3797,27 +6751,43 @@ impl Runtime {
                 &format!("{}\nExpected something from unary argument",
                     self.stack_trace()), self))
         };
-        let v = match self.resolve(&val) {
+        let v = try!(self.unop_variable(unop.op, unop.source_range, &val, module));
+        Ok((Some(v), Flow::Continue))
+    }
+    /// Computes unary operator on a variable.
+    fn unop_variable(
+        &self,
+        op: ast::UnOp,
+        source_range: Range,
+        val: &Variable,
+        module: &Arc<Module>
+    ) -> Result<Variable, String> {
+        Ok(match self.resolve(val) {
             &Variable::Bool(b, ref sec) => {
-                Variable::Bool(match unop.op {
+                Variable::Bool(match op {
                     ast::UnOp::Not => !b,
-                    _ => return Err(module.error(unop.source_range,
+                    _ => return Err(module.error(source_range,
                                     &format!("{}\nUnknown boolean unary operator",
                                              self.stack_trace()), self))
                 }, sec.clone())
             }
             &Variable::F64(v, ref sec) => {
-                Variable::F64(match unop.op {
+                Variable::F64(match op {
                     ast::UnOp::Neg => -v,
-                    _ => return Err(module.error(unop.source_range,
+                    _ => return Err(module.error(source_range,
                                     &format!("{}\nUnknown number unary operator",
                                              self.stack_trace()), self))
                 }, sec.clone())
             }
-            _ => return Err(module.error(unop.source_range,
+            &Variable::I64(v) => {
+                Variable::I64(match op {
+                    ast::UnOp::Neg => v.wrapping_neg(),
+                    ast::UnOp::Not => !v,
+                })
+            }
+            _ => return Err(module.error(source_range,
                 &format!("{}\nInvalid type, expected bool", self.stack_trace()), self))
-        };
-        Ok((Some(v), Flow::Continue))
+        })
     }
     fn binop(
         &mut self,
@@ -3857,23 +6827,77 @@ impl Runtime {
                 &format!("{}\nExpected something from right argument",
                     self.stack_trace()), self))
         };
-        let v = match (self.resolve(&left), self.resolve(&right)) {
+        let v = try!(self.binop_variables(binop.op, binop.source_range, &left, &right, module));
+        if let Variable::Text(ref t) = v {
+            try!(self.alloc(t.len(), binop.source_range, module));
+        }
+        Ok((Some(v), Flow::Continue))
+    }
+    /// Computes binary operator on two variables.
+    fn binop_variables(
+        &self,
+        op: ast::BinOp,
+        source_range: Range,
+        left: &Variable,
+        right: &Variable,
+        module: &Arc<Module>
+    ) -> Result<Variable, String> {
+        use ast::BinOp::*;
+
+        Ok(match (self.resolve(left), self.resolve(right)) {
             (&Variable::F64(a, ref sec), &Variable::F64(b, _)) => {
-                Variable::F64(match binop.op {
+                Variable::F64(match op {
                     Add => a + b,
                     Sub => a - b,
                     Mul => a * b,
                     Div => a / b,
                     Rem => a % b,
                     Pow => a.powf(b),
-                    _ => return Err(module.error(binop.source_range,
+                    _ => return Err(module.error(source_range,
                         &format!("{}\nUnknown number operator `{:?}`",
                             self.stack_trace(),
-                            binop.op.symbol()), self))
+                            op.symbol()), self))
                 }, sec.clone())
             }
+            (&Variable::I64(a), &Variable::I64(b)) => {
+                Variable::I64(match op {
+                    Add => a.wrapping_add(b),
+                    Sub => a.wrapping_sub(b),
+                    Mul => a.wrapping_mul(b),
+                    Div | Rem if b == 0 => return Err(module.error(source_range,
+                        &format!("{}\nDivision by zero", self.stack_trace()), self)),
+                    Div => a.wrapping_div(b),
+                    Rem => a.wrapping_rem(b),
+                    Pow => {
+                        if b < 0 || b > u32::max_value() as i64 {
+                            return Err(module.error(source_range,
+                                &format!("{}\nExpected exponent in range `0` to `{}`, \
+                                found `{}`", self.stack_trace(), u32::max_value(), b), self));
+                        }
+                        a.wrapping_pow(b as u32)
+                    }
+                    BitAnd => a & b,
+                    BitOr => a | b,
+                    BitXor => a ^ b,
+                    Shl | Shr if b < 0 => return Err(module.error(source_range,
+                        &format!("{}\nCan not shift by negative amount `{}`",
+                            self.stack_trace(), b), self)),
+                    // Shifting all bits out gives zero, or `-1` for negative numbers.
+                    Shl => if b < 64 { a << b } else { 0 },
+                    Shr => a >> ::std::cmp::min(b, 63),
+                    _ => return Err(module.error(source_range,
+                        &format!("{}\nUnknown integer operator `{:?}`",
+                            self.stack_trace(),
+                            op.symbol()), self))
+                })
+            }
+            (&Variable::I64(_), &Variable::F64(_, _)) |
+            (&Variable::F64(_, _), &Variable::I64(_)) =>
+                return Err(module.error(source_range,
+                &format!("{}\nCan not mix `i64` and `f64` in binary operator `{:?}`. \
+                Try the `i64` or `f64` function", self.stack_trace(), op.symbol()), self)),
             (&Variable::Vec4(a), &Variable::Vec4(b)) => {
-                match binop.op {
+                match op {
                     Add => Variable::Vec4([a[0] + b[0], a[1] + b[1], a[2] + b[2], a[3] + b[3]]),
                     Sub => Variable::Vec4([a[0] - b[0], a[1] - b[1], a[2] - b[2], a[3] - b[3]]),
                     Mul => Variable::Vec4([a[0] * b[0], a[1] * b[1], a[2] * b[2], a[3] * b[3]]),
@@ -3886,36 +6910,38 @@ impl Runtime {
                     Rem => Variable::Vec4([a[0] % b[0], a[1] % b[1], a[2] % b[2], a[3] % b[3]]),
                     Pow => Variable::Vec4([a[0].powf(b[0]), a[1].powf(b[1]),
                                            a[2].powf(b[2]), a[3].powf(b[3])]),
-                    AndAlso | OrElse => return Err(module.error(binop.source_range,
+                    AndAlso | OrElse | BitAnd | BitOr | BitXor | Shl | Shr =>
+                        return Err(module.error(source_range,
                         &format!("{}\nUnknown operator `{:?}` for `vec4` and `vec4`",
                             self.stack_trace(),
-                            binop.op.symbol_bool()), self)),
+                            op.symbol_bool()), self)),
                 }
             }
             (&Variable::Vec4(a), &Variable::F64(b, _)) => {
                 let b = b as f32;
-                match binop.op {
+                match op {
                     Add => Variable::Vec4([a[0] + b, a[1] + b, a[2] + b, a[3] + b]),
                     Sub => Variable::Vec4([a[0] - b, a[1] - b, a[2] - b, a[3] - b]),
                     Mul => Variable::Vec4([a[0] * b, a[1] * b, a[2] * b, a[3] * b]),
                     Dot => Variable::f64((a[0] * b + a[1] * b +
                                           a[2] * b + a[3] * b) as f64),
-                    Cross => return Err(module.error(binop.source_range,
+                    Cross => return Err(module.error(source_range,
                         &format!("{}\nExpected two vec4 for `{:?}`",
-                            self.stack_trace(), binop.op.symbol()), self)),
+                            self.stack_trace(), op.symbol()), self)),
                     Div => Variable::Vec4([a[0] / b, a[1] / b, a[2] / b, a[3] / b]),
                     Rem => Variable::Vec4([a[0] % b, a[1] % b, a[2] % b, a[3] % b]),
                     Pow => Variable::Vec4([a[0].powf(b), a[1].powf(b),
                                            a[2].powf(b), a[3].powf(b)]),
-                    AndAlso | OrElse => return Err(module.error(binop.source_range,
+                    AndAlso | OrElse | BitAnd | BitOr | BitXor | Shl | Shr =>
+                        return Err(module.error(source_range,
                         &format!("{}\nUnknown operator `{:?}` for `vec4` and `f64`",
                             self.stack_trace(),
-                            binop.op.symbol_bool()), self)),
+                            op.symbol_bool()), self)),
                 }
             }
             (&Variable::F64(a, _), &Variable::Vec4(b)) => {
                 let a = a as f32;
-                match binop.op {
+                match op {
                     Add => Variable::Vec4([a + b[0], a + b[1], a + b[2], a + b[3]]),
                     Sub => Variable::Vec4([a - b[0], a - b[1], a - b[2], a - b[3]]),
                     Mul => Variable::Vec4([a * b[0], a * b[1], a * b[2], a * b[3]]),
@@ -3925,69 +6951,83 @@ impl Runtime {
                     Rem => Variable::Vec4([a % b[0], a % b[1], a % b[2], a % b[3]]),
                     Pow => Variable::Vec4([a.powf(b[0]), a.powf(b[1]),
                                            a.powf(b[2]), a.powf(b[3])]),
-                    Cross => return Err(module.error(binop.source_range,
+                    Cross => return Err(module.error(source_range,
                         &format!("{}\nExpected two vec4 for `{:?}`",
-                            self.stack_trace(), binop.op.symbol()), self)),
-                    AndAlso | OrElse => return Err(module.error(binop.source_range,
+                            self.stack_trace(), op.symbol()), self)),
+                    AndAlso | OrElse | BitAnd | BitOr | BitXor | Shl | Shr =>
+                        return Err(module.error(source_range,
                         &format!("{}\nUnknown operator `{:?}` for `f64` and `vec4`",
                             self.stack_trace(),
-                            binop.op.symbol_bool()), self)),
+                            op.symbol_bool()), self)),
                 }
             }
             (&Variable::Bool(a, ref sec), &Variable::Bool(b, _)) => {
-                Variable::Bool(match binop.op {
+                Variable::Bool(match op {
                     Add | OrElse => a || b,
                     // Boolean subtraction with lazy precedence.
                     Sub => a && !b,
                     Mul | AndAlso => a && b,
                     Pow => a ^ b,
-                    _ => return Err(module.error(binop.source_range,
+                    _ => return Err(module.error(source_range,
                         &format!("{}\nUnknown boolean operator `{:?}`",
                             self.stack_trace(),
-                            binop.op.symbol_bool()), self))
+                            op.symbol_bool()), self))
                 }, sec.clone())
             }
             (&Variable::Text(ref a), &Variable::Text(ref b)) => {
-                match binop.op {
+                match op {
                     Add => {
                         let mut res = String::with_capacity(a.len() + b.len());
                         res.push_str(a);
                         res.push_str(b);
                         Variable::Text(Arc::new(res))
                     }
-                    _ => return Err(module.error(binop.source_range,
+                    _ => return Err(module.error(source_range,
                         &format!("{}\nThis operation can not be used with strings",
                             self.stack_trace()), self))
                 }
             }
             (&Variable::Text(_), _) =>
-                return Err(module.error(binop.source_range,
+                return Err(module.error(source_range,
                 &format!("{}\nThe right argument must be a string. \
                 Try the `str` function", self.stack_trace()), self)),
             (&Variable::Link(ref a), &Variable::Link(ref b)) => {
-                match binop.op {
+                match op {
                     Add => {
                         Variable::Link(Box::new(a.add(b)))
                     }
-                    _ => return Err(module.error(binop.source_range,
+                    _ => return Err(module.error(source_range,
                         &format!("{}\nThis operation can not be used with links",
                             self.stack_trace()), self))
                 }
             }
-            _ => return Err(module.error(binop.source_range, &format!(
+            _ => return Err(module.error(source_range, &format!(
                 "{}\nInvalid type for binary operator `{:?}`, \
                 expected numbers, vec4s, bools or strings",
                 self.stack_trace(),
-                binop.op.symbol()), self))
-        };
-
-        Ok((Some(v), Flow::Continue))
+                op.symbol()), self))
+        })
     }
     pub fn stack_trace(&self) -> String {
         stack_trace(&self.call_stack)
     }
 }
 
+/// Appends position of element in a `for in` loop to a secret.
+fn push_secret(
+    sec: &Option<Box<Vec<Variable>>>,
+    pos: &Variable
+) -> Option<Box<Vec<Variable>>> {
+    match sec {
+        &None => Some(Box::new(vec![pos.clone()])),
+        &Some(ref arr) => {
+            let mut arr = arr.clone();
+            arr.push(pos.clone());
+            Some(arr)
+        }
+    }
+}
+
 fn stack_trace(call_stack: &[Call]) -> String {
     let mut s = String::new();
     for call in call_stack.iter() {
@@ -0,0 +1,67 @@
+//! Shared values that can be changed by several threads.
+//!
+//! A shared value is created with `shared(v)` and passed as argument to `go` calls.
+//! Values are deep cloned when written, such that no references are shared between threads.
+//! An `update` holds the lock while calling the closure, so the change is atomic.
+//! The runtime keeps track of the values it is updating,
+//! such that using them inside the closure is an error instead of a deadlock.
+
+use std::fmt;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use Variable;
+
+/// A lock protected value shared between threads.
+///
+/// Clones refer to the same value.
+#[derive(Clone)]
+pub struct Shared {
+    inner: Arc<Mutex<Variable>>,
+}
+
+impl Shared {
+    /// Creates a new shared value.
+    pub fn new(v: Variable) -> Shared {
+        Shared { inner: Arc::new(Mutex::new(v)) }
+    }
+
+    /// Returns `true` if both refer to the same value.
+    pub fn ptr_eq(&self, other: &Shared) -> bool {
+        Arc::ptr_eq(&self.inner, &other.inner)
+    }
+
+    fn lock(&self) -> Result<MutexGuard<Variable>, String> {
+        self.inner.lock()
+            .map_err(|_| "A thread panicked while using the shared value".into())
+    }
+
+    /// Returns a copy of the value.
+    pub fn read(&self) -> Result<Variable, String> {
+        Ok(try!(self.lock()).clone())
+    }
+
+    /// Replaces the value.
+    pub fn write(&self, v: Variable) -> Result<(), String> {
+        *try!(self.lock()) = v;
+        Ok(())
+    }
+
+    /// Replaces the value with the result of a function,
+    /// while no other thread can use the value.
+    ///
+    /// Returns the new value.
+    pub fn update<F>(&self, f: F) -> Result<Variable, String>
+        where F: FnOnce(Variable) -> Result<Variable, String>
+    {
+        let mut guard = try!(self.lock());
+        let v = try!(f(guard.clone()));
+        *guard = v.clone();
+        Ok(v)
+    }
+}
+
+impl fmt::Debug for Shared {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "shared")
+    }
+}
@@ -0,0 +1,73 @@
+//! Checks of the native stack, such that deep recursion stops with an error
+//! instead of aborting the process with a stack overflow.
+//!
+//! Threads spawned by Dyon have a stack of `THREAD_STACK_SIZE`.
+//! For other threads, the stack bounds are read from the system on Linux,
+//! and are unknown on other platforms, where no check is done.
+
+use std::cell::Cell;
+use std::thread;
+
+/// Stack size of threads spawned by Dyon.
+pub const THREAD_STACK_SIZE: usize = 16 << 20;
+
+/// Native stack kept free for intrinsics, external functions and reporting errors.
+const MARGIN: usize = 256 << 10;
+
+thread_local! {
+    // Lowest address the stack can grow to before stopping, `0` when unknown.
+    static LIMIT: Cell<Option<usize>> = Cell::new(None);
+}
+
+/// Returns an address on the current stack.
+#[inline(never)]
+fn current() -> usize {
+    let x = 0u8;
+    &x as *const u8 as usize
+}
+
+/// Spawns a thread with a stack of `THREAD_STACK_SIZE`.
+pub fn spawn<F, T>(f: F) -> thread::JoinHandle<T>
+    where F: FnOnce() -> T + Send + 'static, T: Send + 'static
+{
+    thread::Builder::new().stack_size(THREAD_STACK_SIZE).spawn(move || {
+        let bottom = current().saturating_sub(THREAD_STACK_SIZE);
+        LIMIT.with(|limit| limit.set(Some(bottom + MARGIN)));
+        f()
+    }).expect("Could not spawn thread")
+}
+
+/// Returns `true` if there is enough native stack left to call a function.
+#[inline(always)]
+pub fn has_room() -> bool {
+    let limit = LIMIT.with(|limit| match limit.get() {
+        Some(x) => x,
+        None => {
+            let x = system_limit();
+            limit.set(Some(x));
+            x
+        }
+    });
+    current() > limit
+}
+
+#[cfg(target_os = "linux")]
+fn system_limit() -> usize {
+    use std::mem;
+    use std::ptr;
+    use libc;
+
+    unsafe {
+        let mut attr: libc::pthread_attr_t = mem::zeroed();
+        if libc::pthread_getattr_np(libc::pthread_self(), &mut attr) != 0 { return 0; }
+        let mut addr = ptr::null_mut();
+        let mut size = 0;
+        let res = libc::pthread_attr_getstack(&attr, &mut addr, &mut size);
+        libc::pthread_attr_destroy(&mut attr);
+        if res != 0 { return 0; }
+        addr as usize + MARGIN
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn system_limit() -> usize { 0 }
@@ -3,6 +3,8 @@ use std::sync::Arc;
 use piston_meta::bootstrap::Convert;
 use range::Range;
 use Dfn;
+use Variable;
+use embed::RustType;
 use ast::BinOp;
 
 #[derive(Debug, Clone, PartialEq)]
@@ -13,21 +15,48 @@ pub enum Type {
     Any,
     Bool,
     F64,
+    /// A 64-bit signed integer.
+    I64,
     Vec4,
     Text,
     Link,
     Array(Box<Type>),
-    // Object(HashMap<Arc<String>, Type>),
     Object,
-    // Rust(Arc<String>),
+    /// An object with known fields, e.g. `{name: str, pos: vec4}`.
+    ///
+    /// Shapes are open, so an object can have more fields than the shape.
+    /// They are only checked when loading, not when values of unknown type
+    /// are passed or returned at runtime.
+    Shape(Vec<Field>),
+    /// A Rust object with named type, see `embed::RustType`.
+    Rust(Arc<String>),
+    /// An enum declared in Dyon, e.g. `enum Shape { Circle(f64), Empty }`.
+    Enum(Arc<String>),
     Option(Box<Type>),
     Result(Box<Type>),
     Secret(Box<Type>),
     Thread(Box<Type>),
+    /// A coroutine with type of yielded values.
+    Coroutine(Box<Type>),
+    /// A channel with type of sent values.
+    Channel(Box<Type>),
+    /// A shared value with type of the value.
+    Shared(Box<Type>),
     AdHoc(Arc<String>, Box<Type>),
     Closure(Box<Dfn>),
 }
 
+/// A field of an object shape.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Field {
+    /// The name of the field.
+    pub name: Arc<String>,
+    /// The type of the field.
+    pub ty: Type,
+    /// Whether the object might not have the field, e.g. `{nick?: str}`.
+    pub optional: bool,
+}
+
 impl Type {
     pub fn description(&self) -> String {
         use Type::*;
@@ -38,6 +67,7 @@ impl Type {
             &Any => "any".into(),
             &Bool => "bool".into(),
             &F64 => "f64".into(),
+            &I64 => "i64".into(),
             &Vec4 => "vec4".into(),
             &Text => "str".into(),
             &Link => "link".into(),
@@ -52,6 +82,22 @@ impl Type {
                 }
             }
             &Object => "{}".into(),
+            &Rust(ref name) => (&**name).clone(),
+            &Enum(ref name) => (&**name).clone(),
+            &Shape(ref fields) => {
+                let mut res = String::from("{");
+                for (i, field) in fields.iter().enumerate() {
+                    res.push_str(&field.name);
+                    if field.optional { res.push('?'); }
+                    res.push_str(": ");
+                    res.push_str(&field.ty.description());
+                    if i + 1 < fields.len() {
+                        res.push_str(", ");
+                    }
+                }
+                res.push('}');
+                res
+            }
             &Option(ref ty) => {
                 if let Any = **ty {
                     "opt".into()
@@ -89,6 +135,36 @@ impl Type {
                     res
                 }
             }
+            &Coroutine(ref ty) => {
+                if let Any = **ty {
+                    "co".into()
+                } else {
+                    let mut res = String::from("co[");
+                    res.push_str(&ty.description());
+                    res.push(']');
+                    res
+                }
+            }
+            &Channel(ref ty) => {
+                if let Any = **ty {
+                    "chan".into()
+                } else {
+                    let mut res = String::from("chan[");
+                    res.push_str(&ty.description());
+                    res.push(']');
+                    res
+                }
+            }
+            &Shared(ref ty) => {
+                if let Any = **ty {
+                    "shared".into()
+                } else {
+                    let mut res = String::from("shared[");
+                    res.push_str(&ty.description());
+                    res.push(']');
+                    res
+                }
+            }
             &AdHoc(ref ad, ref ty) => {
                 (&**ad).clone() + " " + &ty.description()
             }
@@ -116,6 +192,11 @@ impl Type {
         Type::Object
     }
 
+    /// Returns the type of a named Rust object.
+    pub fn rust<T: RustType>() -> Type {
+        Type::Rust(Arc::new(T::name().into()))
+    }
+
     pub fn option() -> Type {
         Type::Option(Box::new(Type::Any))
     }
@@ -128,6 +209,66 @@ impl Type {
         Type::Thread(Box::new(Type::Any))
     }
 
+    pub fn coroutine() -> Type {
+        Type::Coroutine(Box::new(Type::Any))
+    }
+
+    pub fn channel() -> Type {
+        Type::Channel(Box::new(Type::Any))
+    }
+
+    pub fn shared() -> Type {
+        Type::Shared(Box::new(Type::Any))
+    }
+
+    /// Returns `true` if a value can be passed as argument of this type.
+    ///
+    /// Checks the items of arrays, options, results and fields of object shapes.
+    /// The values in threads, coroutines, channels and shared values are not checked.
+    pub fn matches_value(&self, v: &Variable) -> bool {
+        use self::Type::*;
+        use embed::rust_type_name;
+
+        match (self, v) {
+            (&Unreachable, _) | (&Any, _) => true,
+            (&Secret(ref ty), _) | (&AdHoc(_, ref ty), _) => ty.matches_value(v),
+            (&Bool, &Variable::Bool(_, _)) |
+            (&F64, &Variable::F64(_, _)) |
+            (&I64, &Variable::I64(_)) |
+            (&Vec4, &Variable::Vec4(_)) |
+            (&Text, &Variable::Text(_)) |
+            (&Link, &Variable::Link(_)) |
+            (&Object, &Variable::Object(_)) |
+            (&Thread(_), &Variable::Thread(_)) |
+            (&Coroutine(_), &Variable::Coroutine(_)) |
+            (&Channel(_), &Variable::Channel(_)) |
+            (&Shared(_), &Variable::Shared(_)) |
+            (&Closure(_), &Variable::Closure(_, _)) => true,
+            (&Array(ref ty), &Variable::Array(ref arr)) => {
+                arr.iter().all(|v| ty.matches_value(v))
+            }
+            (&Shape(ref fields), &Variable::Object(ref obj)) => {
+                fields.iter().all(|field| match obj.get(&field.name) {
+                    None => field.optional,
+                    Some(v) => field.ty.matches_value(v)
+                })
+            }
+            (&Rust(ref name), &Variable::RustObject(ref obj)) => {
+                rust_type_name(obj).map(|n| n == *name).unwrap_or(false)
+            }
+            (&Enum(ref name), &Variable::Enum(ref ev)) => ev.name == *name,
+            (&Option(ref ty), &Variable::Option(ref opt)) => match *opt {
+                None => true,
+                Some(ref v) => ty.matches_value(v)
+            },
+            (&Result(ref ty), &Variable::Result(ref res)) => match *res {
+                Ok(ref v) => ty.matches_value(v),
+                Err(_) => true
+            },
+            _ => false
+        }
+    }
+
     /// Returns `true` if a type goes with another type (directional check).
     ///
     /// - `bool` (argument) goes with `sec[bool]` (value)
@@ -168,12 +309,27 @@ impl Type {
                 }
             }
             &Object => {
-                if let &Object = other {
-                    true
-                } else if let &Any = other {
-                    true
-                } else {
-                    false
+                match *other {
+                    Object | Shape(_) | Any => true,
+                    _ => false
+                }
+            }
+            &Shape(ref fields) => {
+                match *other {
+                    Object | Any => true,
+                    Shape(ref other_fields) => {
+                        // The other object can have more fields.
+                        fields.iter().all(|field| {
+                            match other_fields.iter().find(|f| f.name == field.name) {
+                                None => field.optional,
+                                Some(other_field) => {
+                                    (field.optional || !other_field.optional) &&
+                                    field.ty.goes_with(&other_field.ty)
+                                }
+                            }
+                        })
+                    }
+                    _ => false
                 }
             }
             &Option(ref opt) => {
@@ -203,6 +359,33 @@ impl Type {
                     false
                 }
             }
+            &Coroutine(ref co) => {
+                if let &Coroutine(ref other_co) = other {
+                    co.goes_with(other_co)
+                } else if let &Any = other {
+                    true
+                } else {
+                    false
+                }
+            }
+            &Channel(ref ch) => {
+                if let &Channel(ref other_ch) = other {
+                    ch.goes_with(other_ch)
+                } else if let &Any = other {
+                    true
+                } else {
+                    false
+                }
+            }
+            &Shared(ref sh) => {
+                if let &Shared(ref other_sh) = other {
+                    sh.goes_with(other_sh)
+                } else if let &Any = other {
+                    true
+                } else {
+                    false
+                }
+            }
             &Closure(ref cl) => {
                 if let &Closure(ref other_cl) = other {
                     if cl.tys.len() != other_cl.tys.len() { return false; }
@@ -217,9 +400,19 @@ impl Type {
                     false
                 }
             }
+            &Enum(ref name) => {
+                match *other {
+                    Enum(ref other_name) => name == other_name,
+                    Any => true,
+                    _ => false
+                }
+            }
             &AdHoc(ref name, ref ty) => {
                 if let &AdHoc(ref other_name, ref other_ty) = other {
                     name == other_name && ty.goes_with(other_ty)
+                } else if let &Enum(ref other_name) = other {
+                    // Enums are declared with the same syntax as ad-hoc types.
+                    name == other_name
                 } else if let &Void = other {
                     false
                 } else {
@@ -233,14 +426,15 @@ impl Type {
         }
     }
 
-    pub fn add(&self, other: &Type) -> Option<Type> {
+    pub fn add(&self, other: &Type, binop: BinOp) -> Option<Type> {
         use self::Type::*;
 
+        if binop.is_bitwise() { return self.bitwise(other); }
         match (self, other) {
             (&AdHoc(ref name, ref ty), &AdHoc(ref other_name, ref other_ty)) => {
                 if name != other_name { return None; }
                 if !ty.goes_with(other_ty) { return None; }
-                if let Some(new_ty) = ty.add(other_ty) {
+                if let Some(new_ty) = ty.add(other_ty, binop) {
                     Some(AdHoc(name.clone(), Box::new(new_ty)))
                 } else {
                     None
@@ -260,6 +454,7 @@ impl Type {
                 Some(Secret(Box::new(F64))),
             (&Secret(ref a), &F64) if **a == Type::F64 => Some(Secret(Box::new(F64))),
             (&F64, &Secret(ref b)) if **b == Type::F64 => Some(F64),
+            (&I64, &I64) => if let BinOp::OrElse = binop { None } else { Some(I64) },
             (&Text, &Text) => Some(Text),
             (&Vec4, &F64) => Some(Vec4),
             (&F64, &Vec4) => Some(Vec4),
@@ -288,6 +483,7 @@ impl Type {
     pub fn mul(&self, other: &Type, binop: BinOp) -> Option<Type> {
         use self::Type::*;
 
+        if binop.is_bitwise() { return self.bitwise(other); }
         match (self, other) {
             (&Void, _) | (_, &Void) => None,
             (&Array(_), _) | (_, &Array(_)) => None,
@@ -303,6 +499,10 @@ impl Type {
                 Some(Secret(Box::new(F64))),
             (&Secret(ref a), &F64) if **a == Type::F64 => Some(Secret(Box::new(F64))),
             (&F64, &Secret(ref b)) if **b == Type::F64 => Some(F64),
+            (&I64, &I64) => match binop {
+                BinOp::Mul | BinOp::Div | BinOp::Rem => Some(I64),
+                _ => None
+            },
             (&Vec4, &F64) => Some(Vec4),
             (&F64, &Vec4) => Some(Vec4),
             (&Vec4, &Vec4) => {
@@ -336,6 +536,7 @@ impl Type {
                 Some(Secret(Box::new(F64))),
             (&Secret(ref a), &F64) if **a == Type::F64 => Some(Secret(Box::new(F64))),
             (&F64, &Secret(ref b)) if **b == Type::F64 => Some(F64),
+            (&I64, &I64) => Some(I64),
             (&Vec4, &F64) => Some(Vec4),
             (&F64, &Vec4) => Some(Vec4),
             (&Vec4, &Vec4) => Some(Vec4),
@@ -345,6 +546,17 @@ impl Type {
         }
     }
 
+    /// Bitwise and shift operators only work on integers.
+    fn bitwise(&self, other: &Type) -> Option<Type> {
+        use self::Type::*;
+
+        match (self, other) {
+            (&I64, &I64) => Some(I64),
+            (&Any, &I64) | (&I64, &Any) | (&Any, &Any) => Some(Any),
+            _ => None
+        }
+    }
+
     pub fn from_meta_data(node: &str, mut convert: Convert, ignored: &mut Vec<Range>)
     -> Result<(Range, Type), ()> {
         let start = convert.clone();
@@ -368,6 +580,9 @@ impl Type {
             } else if let Ok((range, _)) = convert.meta_bool("f64") {
                 convert.update(range);
                 ty = Some(Type::F64);
+            } else if let Ok((range, _)) = convert.meta_bool("i64") {
+                convert.update(range);
+                ty = Some(Type::I64);
             } else if let Ok((range, _)) = convert.meta_bool("sec_f64") {
                 convert.update(range);
                 ty = Some(Type::Secret(Box::new(Type::F64)));
@@ -395,6 +610,15 @@ impl Type {
             } else if let Ok((range, _)) = convert.meta_bool("thr_any") {
                 convert.update(range);
                 ty = Some(Type::Thread(Box::new(Type::Any)));
+            } else if let Ok((range, _)) = convert.meta_bool("co_any") {
+                convert.update(range);
+                ty = Some(Type::Coroutine(Box::new(Type::Any)));
+            } else if let Ok((range, _)) = convert.meta_bool("chan_any") {
+                convert.update(range);
+                ty = Some(Type::Channel(Box::new(Type::Any)));
+            } else if let Ok((range, _)) = convert.meta_bool("shared_any") {
+                convert.update(range);
+                ty = Some(Type::Shared(Box::new(Type::Any)));
             } else if let Ok((range, val)) = Type::from_meta_data(
                     "opt", convert, ignored) {
                 convert.update(range);
@@ -411,6 +635,18 @@ impl Type {
                     "thr", convert, ignored) {
                 convert.update(range);
                 ty = Some(Type::Thread(Box::new(val)));
+            } else if let Ok((range, val)) = Type::from_meta_data(
+                    "co", convert, ignored) {
+                convert.update(range);
+                ty = Some(Type::Coroutine(Box::new(val)));
+            } else if let Ok((range, val)) = Type::from_meta_data(
+                    "chan", convert, ignored) {
+                convert.update(range);
+                ty = Some(Type::Channel(Box::new(val)));
+            } else if let Ok((range, val)) = Type::from_meta_data(
+                    "shared", convert, ignored) {
+                convert.update(range);
+                ty = Some(Type::Shared(Box::new(val)));
             } else if let Ok((range, val)) = convert.meta_string("ad_hoc") {
                 convert.update(range);
                 let inner_ty = if let Ok((range, val)) = Type::from_meta_data(
@@ -421,6 +657,41 @@ impl Type {
                     Type::Object
                 };
                 ty = Some(Type::AdHoc(val, Box::new(inner_ty)));
+            } else if let Ok(range) = convert.start_node("obj_field") {
+                convert.update(range);
+                let mut name: Option<Arc<String>> = None;
+                let mut optional = false;
+                let mut field_ty: Option<Type> = None;
+                loop {
+                    if let Ok(range) = convert.end_node("obj_field") {
+                        convert.update(range);
+                        break;
+                    } else if let Ok((range, val)) = convert.meta_string("name") {
+                        convert.update(range);
+                        name = Some(val);
+                    } else if let Ok((range, val)) = convert.meta_bool("optional") {
+                        convert.update(range);
+                        optional = val;
+                    } else if let Ok((range, val)) = Type::from_meta_data(
+                            "type", convert, ignored) {
+                        convert.update(range);
+                        field_ty = Some(val);
+                    } else {
+                        let range = convert.ignore();
+                        convert.update(range);
+                        ignored.push(range);
+                    }
+                }
+                let field = Field {
+                    name: try!(name.ok_or(())),
+                    ty: try!(field_ty.ok_or(())),
+                    optional: optional,
+                };
+                if let Some(Type::Shape(ref mut fields)) = ty {
+                    fields.push(field);
+                    continue;
+                }
+                ty = Some(Type::Shape(vec![field]));
             } else if let Ok(range) = convert.start_node("closure_type") {
                 convert.update(range);
                 let mut lts = vec![];
@@ -437,7 +708,9 @@ impl Type {
                 convert.update(range);
                 let range = try!(convert.end_node("closure_type"));
                 convert.update(range);
-                ty = Some(Type::Closure(Box::new(Dfn { lts: lts, tys: tys, ret: ret })));
+                ty = Some(Type::Closure(Box::new(Dfn {
+                    lts: lts, tys: tys, ret: ret, defaults: vec![]
+                })));
             } else {
                 let range = convert.ignore();
                 convert.update(range);
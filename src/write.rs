@@ -33,6 +33,16 @@ pub fn write_variable<W>(
         Variable::F64(x, _) => {
             try!(write!(w, "{}", x));
         }
+        Variable::I64(x) => {
+            match escape_string {
+                EscapeString::Json => {
+                    try!(write!(w, "{}i64", x));
+                }
+                EscapeString::None => {
+                    try!(write!(w, "{}", x));
+                }
+            }
+        }
         Variable::Vec4(v) => {
             try!(write!(w, "({}, {}", v[0], v[1]));
             if v[2] != 0.0 || v[3] != 0.0 {
@@ -131,7 +141,18 @@ pub fn write_variable<W>(
                 }
             }
         }
+        Variable::Enum(ref enum_variant) => {
+            try!(write!(w, "{}::{}", enum_variant.name, enum_variant.variant));
+            if let Some(ref payload) = enum_variant.payload {
+                try!(write!(w, "("));
+                try!(write_variable(w, rt, payload, EscapeString::Json, tabs));
+                try!(write!(w, ")"));
+            }
+        }
         Variable::Thread(_) => try!(write!(w, "_thread")),
+        Variable::Coroutine(_) => try!(write!(w, "_coroutine")),
+        Variable::Channel(_) => try!(write!(w, "_channel")),
+        Variable::Shared(_) => try!(write!(w, "_shared")),
         Variable::Return => try!(write!(w, "_return")),
         Variable::UnsafeRef(_) => try!(write!(w, "_unsafe_ref")),
         Variable::RustObject(_) => try!(write!(w, "_rust_object")),
@@ -202,6 +223,10 @@ pub fn write_expr<W: io::Write>(
             try!(write_expr(w, rt, expr, tabs));
         }
         &E::ReturnVoid(_) => try!(write!(w, "return")),
+        &E::Yield(ref expr) => {
+            try!(write!(w, "yield "));
+            try!(write_expr(w, rt, expr, tabs));
+        }
         &E::Break(ref br) => {
             if let Some(ref label) = br.label {
                 try!(write!(w, "break '{}", label));
@@ -221,6 +246,10 @@ pub fn write_expr<W: io::Write>(
             try!(write!(w, "go "));
             try!(write_call(w, rt, &go.call, tabs));
         }
+        &E::Coroutine(ref go) => {
+            try!(write!(w, "coroutine "));
+            try!(write_call(w, rt, &go.call, tabs));
+        }
         &E::Assign(ref assign) => try!(write_assign(w, rt, assign, tabs)),
         &E::Vec4(ref vec4) => try!(write_vec4(w, rt, vec4, tabs)),
         &E::For(ref f) => try!(write_for(w, rt, f, tabs)),
@@ -230,6 +259,7 @@ pub fn write_expr<W: io::Write>(
             try!(write_for_n(w, rt, for_n, tabs));
         }
         &E::Sum(ref for_n) => {
+            if for_n.parallel { try!(write!(w, "par ")); }
             try!(write!(w, "sum "));
             try!(write_for_n(w, rt, for_n, tabs));
         }
@@ -246,22 +276,27 @@ pub fn write_expr<W: io::Write>(
             try!(write_for_n(w, rt, for_n, tabs));
         }
         &E::Min(ref for_n) => {
+            if for_n.parallel { try!(write!(w, "par ")); }
             try!(write!(w, "min "));
             try!(write_for_n(w, rt, for_n, tabs));
         }
         &E::Max(ref for_n) => {
+            if for_n.parallel { try!(write!(w, "par ")); }
             try!(write!(w, "max "));
             try!(write_for_n(w, rt, for_n, tabs));
         }
         &E::Sift(ref for_n) => {
+            if for_n.parallel { try!(write!(w, "par ")); }
             try!(write!(w, "sift "));
             try!(write_for_n(w, rt, for_n, tabs));
         }
         &E::Any(ref for_n) => {
+            if for_n.parallel { try!(write!(w, "par ")); }
             try!(write!(w, "any "));
             try!(write_for_n(w, rt, for_n, tabs));
         }
         &E::All(ref for_n) => {
+            if for_n.parallel { try!(write!(w, "par ")); }
             try!(write!(w, "all "));
             try!(write_for_n(w, rt, for_n, tabs));
         }
@@ -269,7 +304,44 @@ pub fn write_expr<W: io::Write>(
             try!(write!(w, "link "));
             try!(write_for_n(w, rt, for_n, tabs));
         }
+        &E::ForIn(ref for_in) => {
+            try!(write!(w, "for "));
+            try!(write_for_in(w, rt, for_in, tabs));
+        }
+        &E::SumIn(ref for_in) => {
+            try!(write!(w, "sum "));
+            try!(write_for_in(w, rt, for_in, tabs));
+        }
+        &E::MinIn(ref for_in) => {
+            try!(write!(w, "min "));
+            try!(write_for_in(w, rt, for_in, tabs));
+        }
+        &E::MaxIn(ref for_in) => {
+            try!(write!(w, "max "));
+            try!(write_for_in(w, rt, for_in, tabs));
+        }
+        &E::SiftIn(ref for_in) => {
+            try!(write!(w, "sift "));
+            try!(write_for_in(w, rt, for_in, tabs));
+        }
+        &E::AnyIn(ref for_in) => {
+            try!(write!(w, "any "));
+            try!(write_for_in(w, rt, for_in, tabs));
+        }
+        &E::AllIn(ref for_in) => {
+            try!(write!(w, "all "));
+            try!(write_for_in(w, rt, for_in, tabs));
+        }
         &E::If(ref if_expr) => try!(write_if(w, rt, if_expr, tabs)),
+        &E::Match(ref match_expr) => try!(write_match(w, rt, match_expr, tabs)),
+        &E::EnumValue(ref enum_value) => {
+            try!(write!(w, "{}::{}", enum_value.name, enum_value.variant));
+            if let Some(ref payload) = enum_value.payload {
+                try!(write!(w, "("));
+                try!(write_expr(w, rt, payload, tabs));
+                try!(write!(w, ")"));
+            }
+        }
         &E::Norm(ref norm) => try!(write_norm(w, rt, norm, tabs)),
         &E::UnOp(ref unop) => try!(write_unop(w, rt, unop, tabs)),
         &E::Try(ref expr) => {
@@ -678,6 +750,25 @@ pub fn write_for_n<W: io::Write>(
     Ok(())
 }
 
+pub fn write_for_in<W: io::Write>(
+    w: &mut W,
+    rt: &Runtime,
+    for_in: &ast::ForIn,
+    tabs: u32
+) -> Result<(), io::Error> {
+    if let Some(ref key) = for_in.key {
+        try!(write!(w, "{}, ", key));
+    }
+    if for_in.mutable {
+        try!(write!(w, "mut "));
+    }
+    try!(write!(w, "{} in ", for_in.name));
+    try!(write_expr(w, rt, &for_in.expr, tabs));
+    try!(write!(w, " "));
+    try!(write_block(w, rt, &for_in.block, tabs + 1));
+    Ok(())
+}
+
 pub fn write_if<W: io::Write>(
     w: &mut W,
     rt: &Runtime,
@@ -702,6 +793,82 @@ pub fn write_if<W: io::Write>(
     Ok(())
 }
 
+pub fn write_match<W: io::Write>(
+    w: &mut W,
+    rt: &Runtime,
+    match_expr: &ast::Match,
+    tabs: u32,
+) -> Result<(), io::Error> {
+    try!(write!(w, "match "));
+    try!(write_expr(w, rt, &match_expr.expr, tabs));
+    try!(writeln!(w, " {{"));
+    for arm in &match_expr.arms {
+        try!(write_tabs(w, tabs + 1));
+        try!(write_pattern(w, &arm.pattern));
+        if let Some(ref guard) = arm.guard {
+            try!(write!(w, " if "));
+            try!(write_expr(w, rt, guard, tabs + 1));
+        }
+        try!(write!(w, " => "));
+        try!(write_expr(w, rt, &arm.expr, tabs + 1));
+        try!(writeln!(w, ""));
+    }
+    try!(write_tabs(w, tabs));
+    try!(write!(w, "}}"));
+    Ok(())
+}
+
+pub fn write_pattern<W: io::Write>(
+    w: &mut W,
+    pattern: &ast::Pattern,
+) -> Result<(), io::Error> {
+    use ast::Pattern as P;
+
+    match *pattern {
+        P::Wildcard => try!(write!(w, "_")),
+        P::Bind(ref name) => try!(write!(w, "{}", name)),
+        P::Number(num) => try!(write!(w, "{}", num)),
+        P::Range(start, end) => try!(write!(w, "[{}, {})", start, end)),
+        P::Text(ref text) => try!(json::write_string(w, text)),
+        P::Bool(val) => try!(write!(w, "{}", val)),
+        P::Some(ref p) => {
+            try!(write!(w, "some("));
+            try!(write_pattern(w, p));
+            try!(write!(w, ")"));
+        }
+        P::None => try!(write!(w, "none()")),
+        P::Ok(ref p) => {
+            try!(write!(w, "ok("));
+            try!(write_pattern(w, p));
+            try!(write!(w, ")"));
+        }
+        P::Err(ref p) => {
+            try!(write!(w, "err("));
+            try!(write_pattern(w, p));
+            try!(write!(w, ")"));
+        }
+        P::Object(ref fields) => {
+            try!(write!(w, "{{"));
+            for (i, &(ref key, ref p)) in fields.iter().enumerate() {
+                if i > 0 { try!(write!(w, ", ")); }
+                try!(json::write_string(w, key));
+                try!(write!(w, ": "));
+                try!(write_pattern(w, p));
+            }
+            try!(write!(w, "}}"));
+        }
+        P::Enum(ref name, ref variant, ref p) => {
+            try!(write!(w, "{}::{}", name, variant));
+            if let Some(ref p) = *p {
+                try!(write!(w, "("));
+                try!(write_pattern(w, p));
+                try!(write!(w, ")"));
+            }
+        }
+    }
+    Ok(())
+}
+
 pub fn write_grab<W: io::Write>(
     w: &mut W,
     rt: &Runtime,
@@ -1,6 +1,8 @@
 extern crate piston_meta;
 extern crate dyon;
 
+use std::sync::Arc;
+
 use dyon::*;
 
 pub fn test_src(source: &str) {
@@ -22,6 +24,16 @@ pub fn test_fail_src(source: &str) {
     };
 }
 
+pub fn test_run_src(source: &str) {
+    let mut module = Module::new();
+    load(source, &mut module).unwrap_or_else(|err| {
+        panic!("In `{}`:\n{}", source, err);
+    });
+    Runtime::new().run(&Arc::new(module)).unwrap_or_else(|err| {
+        panic!("In `{}`:\n{}", source, err);
+    });
+}
+
 pub fn debug_src(source: &str) {
     let mut module = Module::new();
     load(source, &mut module).unwrap_or_else(|err| {
@@ -70,6 +82,10 @@ fn test_syntax() {
     test_fail_src("source/syntax/lifetime_18.dyon");
     test_fail_src("source/syntax/lifetime_19.dyon");
     test_fail_src("source/syntax/lifetime_20.dyon");
+    test_fail_src("source/syntax/lifetime_21.dyon");
+    test_fail_src("source/syntax/lifetime_22.dyon");
+    test_fail_src("source/syntax/lifetime_23.dyon");
+    test_fail_src("source/syntax/lifetime_24.dyon");
     test_src("source/syntax/insert.dyon");
     test_src("source/syntax/named_call.dyon");
     test_src("source/syntax/max_min.dyon");
@@ -118,6 +134,18 @@ fn test_syntax() {
     test_src("source/syntax/try_expr.dyon");
     test_src("source/syntax/start_true.dyon");
     test_fail_src("source/syntax/push_ref.dyon");
+    test_src("source/syntax/coroutine.dyon");
+    test_fail_src("source/syntax/coroutine_2.dyon");
+    test_src("source/syntax/match.dyon");
+    test_fail_src("source/syntax/match_2.dyon");
+    test_src("source/syntax/i64.dyon");
+    test_src("source/syntax/enum.dyon");
+    test_src("source/syntax/global.dyon");
+    test_src("source/syntax/default_args.dyon");
+    test_src("source/syntax/for_in.dyon");
+    test_src("source/syntax/channel.dyon");
+    test_src("source/syntax/par.dyon");
+    test_src("source/syntax/shared.dyon");
 }
 
 #[test]
@@ -150,6 +178,23 @@ fn test_typechk() {
     test_src("source/typechk/call_4.dyon");
     test_src("source/typechk/obj.dyon");
     test_fail_src("source/typechk/go.dyon");
+    test_fail_src("source/typechk/yield.dyon");
+    test_fail_src("source/typechk/match.dyon");
+    test_src("source/typechk/shape.dyon");
+    test_fail_src("source/typechk/shape_2.dyon");
+    test_fail_src("source/typechk/shape_3.dyon");
+    test_fail_src("source/typechk/shape_4.dyon");
+    test_fail_src("source/typechk/i64.dyon");
+    test_fail_src("source/typechk/i64_2.dyon");
+    test_fail_src("source/typechk/enum.dyon");
+    test_fail_src("source/typechk/enum_2.dyon");
+    test_fail_src("source/typechk/enum_3.dyon");
+    test_fail_src("source/typechk/const.dyon");
+    test_fail_src("source/typechk/const_2.dyon");
+    test_fail_src("source/typechk/default_args.dyon");
+    test_fail_src("source/typechk/for_in.dyon");
+    test_fail_src("source/typechk/channel.dyon");
+    test_fail_src("source/typechk/shared.dyon");
     test_fail_src("source/typechk/unused_result.dyon");
     test_fail_src("source/typechk/unused_result_2.dyon");
     test_src("source/typechk/res.dyon");
@@ -199,6 +244,11 @@ fn test_functions() {
     test_src("source/functions/functions.dyon");
 }
 
+#[test]
+fn test_namespace() {
+    test_run_src("source/namespace/alias/loader.dyon");
+}
+
 #[test]
 fn test_error() {
     test_src("source/error/propagate.dyon");
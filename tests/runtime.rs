@@ -0,0 +1,785 @@
+//! Tests of running scripts from Rust, using sources in `source/runtime`.
+
+extern crate dyon;
+
+use std::sync::Arc;
+
+use dyon::*;
+
+/// Loads sources into a new module.
+fn load_module(sources: &[&str]) -> Arc<Module> {
+    let mut module = Module::new();
+    for source in sources {
+        load(source, &mut module).unwrap_or_else(|err| {
+            panic!("In `{}`:\n{}", source, err);
+        });
+    }
+    Arc::new(module)
+}
+
+#[test]
+fn bytecode() {
+    use dyon::bytecode::Op;
+    use dyon::coverage::Coverage;
+
+    let module = load_module(&[
+        "source/runtime/bytecode/counts.dyon",
+        "source/runtime/bytecode/items.dyon"
+    ]);
+    // Items, literals and calls do not fall back to the tree-walking interpreter.
+    let f = module.functions.iter().find(|f| &**f.name == "main").unwrap();
+    let chunk = f.bytecode.as_ref().expect("Expected bytecode");
+    assert!(!chunk.ops.iter().any(|op| if let Op::Eval(_) = *op { true } else { false }));
+    Runtime::new().run(&module).unwrap();
+    // Recording coverage runs the bytecode with instructions for it.
+    let chunk = f.bytecode_hooks.as_ref().expect("Expected bytecode with hooks");
+    assert!(chunk.ops.iter().any(|op| if let Op::CoverBlock(_) = *op { true } else { false }));
+    assert!(!chunk.ops.iter().any(|op| if let Op::Eval(_) = *op { true } else { false }));
+    let coverage = Coverage::new();
+    let mut rt = Runtime::new();
+    rt.set_coverage(Some(coverage.clone()));
+    rt.run(&module).unwrap();
+    assert_eq!(coverage.counts().blocks[&(f.file.clone(), f.block.source_range)], 1);
+}
+
+#[test]
+fn fuel() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let module = load_module(&["source/runtime/fuel/forever.dyon"]);
+    let mut rt = Runtime::new();
+    rt.set_fuel(Some(1000));
+    match rt.run(&module) { Ok(Status::OutOfFuel) => {}, x => panic!("{:?}", x) }
+    assert_eq!(rt.fuel(), Some(0));
+    assert!(rt.is_suspended());
+
+    // Adding fuel resumes where the call ran out of fuel.
+    let module = load_module(&["source/runtime/fuel/sum.dyon"]);
+    let mut rt = Runtime::new();
+    rt.set_fuel(Some(100));
+    let mut status = rt.call_str("sum", &[Variable::f64(1000.0)], &module).unwrap();
+    let mut refills = 0;
+    while let Status::OutOfFuel = status {
+        refills += 1;
+        rt.add_fuel(100);
+        status = rt.resume(&module).unwrap();
+    }
+    match status {
+        Status::Done(Some(Variable::F64(x, _))) => assert_eq!(x, 499500.0),
+        x => panic!("{:?}", x)
+    }
+    assert!(refills > 10);
+    assert!(rt.stack.is_empty());
+
+    // Closures can not be suspended, so they stop with an error.
+    let err = rt.call_str("closure", &[], &module).unwrap_err();
+    assert!(err.contains("Out of fuel"));
+
+    // Refill fuel without suspending.
+    let module = load_module(&["source/runtime/fuel/count.dyon"]);
+    let refills = Arc::new(AtomicUsize::new(0));
+    let r = refills.clone();
+    let mut rt = Runtime::new();
+    rt.set_fuel(Some(100));
+    rt.set_out_of_fuel(Some(Arc::new(move || {
+        r.fetch_add(1, Ordering::SeqCst);
+        Some(100)
+    })));
+    match rt.run(&module) { Ok(Status::Done(None)) => {}, x => panic!("{:?}", x) }
+    assert!(refills.load(Ordering::SeqCst) > 0);
+}
+
+/// Runs a source on a thread with a large native stack,
+/// such that the limits are reached before the native stack overflows.
+fn run_limits(source: &str, limits: Limits) -> Result<(), String> {
+    let source = source.to_string();
+    std::thread::Builder::new().stack_size(64 << 20).spawn(move || {
+        let module = load_module(&[&source]);
+        let mut rt = Runtime::new();
+        rt.limits = limits;
+        rt.workers = 2;
+        rt.run(&module).map(|_| ())
+    }).unwrap().join().unwrap()
+}
+
+#[test]
+fn limits() {
+    let recursion = "source/runtime/limits/recursion.dyon";
+    let err = run_limits(recursion, Limits {
+        call_depth: Some(100), ..Limits::default()
+    }).unwrap_err();
+    assert!(err.contains("Call depth exceeds limit of 100"));
+    let err = run_limits(recursion, Limits {
+        stack: Some(100), ..Limits::default()
+    }).unwrap_err();
+    assert!(err.contains("Stack length exceeds limit of 100"));
+    // Without limits, recursion stops before overflowing the native stack.
+    let err = run_limits(recursion, Limits::default()).unwrap_err();
+    assert!(err.contains("Native stack overflow"), "{}", err);
+    run_limits("source/runtime/limits/deep.dyon", Limits::default()).unwrap();
+
+    let heap = Limits { heap: Some(1_000_000), ..Limits::default() };
+    for source in &["fill", "push"] {
+        let err = run_limits(&format!("source/runtime/limits/{}.dyon", source), heap)
+            .unwrap_err();
+        assert!(err.contains("Out of memory"), "{}", source);
+    }
+    run_limits("source/runtime/limits/reuse.dyon", heap).unwrap();
+
+    // Arrays built by `sift` count before they are stored.
+    let heap = Limits { heap: Some(600_000), ..Limits::default() };
+    for source in &["sift", "sift_in", "par_sift"] {
+        let source = format!("source/runtime/limits/{}.dyon", source);
+        run_limits(&source, Limits::default()).unwrap();
+        let err = run_limits(&source, heap).unwrap_err();
+        assert!(err.contains("Out of memory"), "{}", source);
+    }
+}
+
+#[test]
+fn capabilities() {
+    use std::sync::Mutex;
+
+    fn load_caps(source: &str, caps: Capabilities) -> Result<Module, String> {
+        let mut module = Module::new();
+        module.set_capabilities(caps);
+        try!(load(&format!("source/runtime/capabilities/{}.dyon", source), &mut module));
+        Ok(module)
+    }
+
+    let err = load_caps("read_line", Capabilities::none()).err().unwrap();
+    assert!(err.contains("`read_line` requires capability `stdin`"));
+    let err = load_caps("go", Capabilities::none()).err().unwrap();
+    assert!(err.contains("`go` requires capability `threads`"));
+
+    // Capabilities of the caller apply when using `call`.
+    let trusted = load_caps("trusted", Capabilities::all()).unwrap();
+    let sandboxed = Arc::new(load_caps("sandboxed", Capabilities::none()).unwrap());
+    let mut rt = Runtime::new();
+    let m = Variable::RustObject(Arc::new(Mutex::new(Arc::new(trusted))));
+    let err = rt.call_str("main", &[m], &sandboxed).unwrap_err();
+    assert!(err.contains("`sleep` requires capability `sleep`"));
+
+    let dir = std::env::temp_dir().join("dyon_capabilities");
+    std::fs::create_dir_all(&dir).unwrap();
+    let module = Arc::new(load_caps("save", Capabilities {
+        fs_roots: Some(vec![dir.clone()]),
+        ..Capabilities::all()
+    }).unwrap());
+    let save = |file: std::path::PathBuf| {
+        let file = Variable::Text(Arc::new(file.to_str().unwrap().into()));
+        Runtime::new().call_str("main", &[file], &module)
+    };
+    save(dir.join("test.txt")).unwrap();
+    let err = save(dir.join("..").join("test.txt")).unwrap_err();
+    assert!(err.contains("outside the allowed directories"));
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn interrupt() {
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    // Waiting for threads, channels and time stops when interrupted.
+    for source in &["loop", "sleep", "recv", "select", "join"] {
+        let module = load_module(&[&format!("source/runtime/interrupt/{}.dyon", source)]);
+        let mut rt = Runtime::new();
+        let handle = rt.interrupt_handle();
+        let h = handle.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            h.interrupt();
+        });
+        let start = Instant::now();
+        let err = rt.run(&module).unwrap_err();
+        assert!(err.contains("Interrupted"), "{}", source);
+        assert!(start.elapsed() < Duration::from_secs(10), "{}", source);
+        assert!(handle.is_interrupted());
+        handle.clear();
+        assert!(!rt.interrupt_handle().is_interrupted());
+    }
+}
+
+#[test]
+fn debugger() {
+    use std::sync::Mutex;
+    use dyon::debugger::*;
+
+    struct Record(Arc<Mutex<Vec<(usize, usize, Vec<Arc<String>>)>>>, Step);
+
+    impl DebugHandler for Record {
+        fn pause(&mut self, rt: &Runtime, pos: &Position, _: &mut Vec<Breakpoint>) -> Step {
+            let frames = frames(rt);
+            let locals = frames.last().unwrap().locals.iter()
+                .map(|&(ref name, _)| name.clone()).collect();
+            self.0.lock().unwrap().push((pos.line, pos.depth, locals));
+            self.1
+        }
+    }
+
+    let source = "source/runtime/debugger/main.dyon";
+    let module = load_module(&[source]);
+
+    let log = Arc::new(Mutex::new(vec![]));
+    let mut rt = Runtime::new();
+    rt.set_debugger(Some(Debugger::new(Box::new(Record(log.clone(), Step::Over)))));
+    rt.run(&module).unwrap();
+    let lines: Vec<usize> = log.lock().unwrap().iter().map(|x| x.0).collect();
+    assert_eq!(lines, vec![2, 3, 4]);
+
+    let run_to_breakpoint = |file: String| {
+        let log = Arc::new(Mutex::new(vec![]));
+        let mut rt = Runtime::new();
+        rt.set_debugger(Some(Debugger::with_breakpoints(
+            Box::new(Record(log.clone(), Step::Continue)),
+            vec![Breakpoint { file: file, line: 9 }]
+        )));
+        rt.run(&module).unwrap();
+        let log = log.lock().unwrap();
+        log.clone()
+    };
+    let log = run_to_breakpoint("main.dyon".into());
+    assert_eq!(log.len(), 1);
+    assert_eq!(log[0].0, 9);
+    assert_eq!(log[0].1, 2);
+    let names: Vec<&str> = log[0].2.iter().map(|n| &***n).collect();
+    assert_eq!(names, vec!["return", "x", "y", "z"]);
+
+    // Paths match by whole components, or by leading to the same file.
+    let absolute = std::env::current_dir().unwrap().join(source);
+    assert_eq!(run_to_breakpoint(absolute.to_str().unwrap().into()).len(), 1);
+    assert_eq!(run_to_breakpoint(format!("./{}", source)).len(), 1);
+    assert_eq!(run_to_breakpoint("debugger/main.dyon".into()).len(), 1);
+    assert_eq!(run_to_breakpoint("ger/main.dyon".into()).len(), 0);
+    assert_eq!(run_to_breakpoint("source/runtime/main.dyon".into()).len(), 0);
+}
+
+#[test]
+fn profiler() {
+    use dyon::profiler::Profiler;
+
+    let source = "source/runtime/profiler/main.dyon";
+    let module = load_module(&[source]);
+    let profiler = Profiler::new();
+    let mut rt = Runtime::new();
+    rt.set_profiler(Some(profiler.clone()));
+    rt.run(&module).unwrap();
+
+    let profile = profiler.profile();
+    let file = Some(Arc::new(source.into()));
+    let calls = |name: &str| profile.functions[&(Arc::new(name.into()), file.clone())].calls;
+    assert_eq!(calls("main"), 1);
+    assert_eq!(calls("fib"), 177);
+    assert_eq!(calls("work"), 1);
+    let main = &profile.functions[&(Arc::new("main".into()), file.clone())];
+    assert!(main.inclusive >= main.exclusive);
+
+    assert_eq!(profile.loops.len(), 1);
+    let stats = profile.loops.values().next().unwrap();
+    assert_eq!((stats.line, stats.column, stats.runs), (12, 12, 1));
+
+    let mut folded = vec![];
+    profiler.write_folded(&mut folded).unwrap();
+    let folded = String::from_utf8(folded).unwrap();
+    assert!(folded.lines().any(|line| line.starts_with("main;fib;fib ")));
+    assert!(folded.lines().any(|line| line.starts_with("main;work ")));
+
+    let mut text = vec![];
+    profiler.write_text(&mut text).unwrap();
+    let text = String::from_utf8(text).unwrap();
+    assert!(text.contains(&format!("fib ({})", source)));
+    assert!(text.contains(&format!("{}:12:12", source)));
+}
+
+#[test]
+fn coverage() {
+    use dyon::coverage::Coverage;
+
+    let source = "source/runtime/coverage/main.dyon";
+    let module = load_module(&[source]);
+    let coverage = Coverage::new();
+    let mut rt = Runtime::new();
+    rt.set_coverage(Some(coverage.clone()));
+    rt.run(&module).unwrap();
+
+    let counts = coverage.counts();
+    let mut branches: Vec<_> = counts.branches.iter()
+        .map(|(&(_, range), taken)| (range.offset, taken.clone())).collect();
+    branches.sort();
+    assert_eq!(branches[0].1, vec![0, 1, 0]);
+    assert_eq!(branches[1].1, vec![3, 1]);
+    assert!(counts.blocks.values().any(|&n| n == 4));
+
+    let mut lcov = vec![];
+    coverage.write_lcov(&mut lcov, &module).unwrap();
+    let lcov = String::from_utf8(lcov).unwrap();
+    let lines: Vec<&str> = lcov.lines().collect();
+    assert!(lines.contains(&&*format!("SF:{}", source)));
+    assert!(lines.contains(&"FNDA:1,main"));
+    assert!(lines.contains(&"FNDA:0,unused"));
+    assert!(lines.contains(&"BRDA:3,0,0,0"));
+    assert!(lines.contains(&"BRDA:3,0,1,1"));
+    assert!(lines.contains(&"BRDA:9,1,0,3"));
+    assert!(lines.contains(&"DA:4,0"));
+    assert!(lines.contains(&"DA:6,1"));
+    assert!(lines.contains(&"DA:9,4"));
+    assert!(lines.contains(&"DA:14,0"));
+    assert!(lines.contains(&"end_of_record"));
+}
+
+#[test]
+fn coroutine() {
+    let module = load_module(&["source/runtime/coroutine/count.dyon"]);
+    let mut rt = Runtime::new();
+    let co = rt.coroutine_str("count", &[Variable::f64(2.0)], &module).unwrap();
+    assert!(!co.is_done());
+    match co.resume() { Ok(Some(Variable::F64(x, _))) => assert_eq!(x, 0.0), x => panic!("{:?}", x) }
+    match co.resume() { Ok(Some(Variable::F64(x, _))) => assert_eq!(x, 1.0), x => panic!("{:?}", x) }
+    assert!(!co.is_done());
+    match co.resume() { Ok(Some(Variable::F64(x, _))) => assert_eq!(x, 2.0), x => panic!("{:?}", x) }
+    assert!(co.is_done());
+    assert!(co.resume().is_err());
+
+    // Coroutines run on the thread resuming them.
+    let mut module = Module::new();
+    module.set_capabilities(Capabilities::none());
+    load("source/runtime/coroutine/count.dyon", &mut module).unwrap();
+    load("source/runtime/coroutine/start.dyon", &mut module).unwrap();
+    let module = Arc::new(module);
+    let co = rt.coroutine_str("count", &[Variable::f64(2.0)], &module).unwrap();
+    match co.resume() { Ok(Some(Variable::F64(x, _))) => assert_eq!(x, 0.0), x => panic!("{:?}", x) }
+
+    // Yielding suspends the calls of the coroutine.
+    let module = load_module(&["source/runtime/coroutine/nested.dyon"]);
+    let co = rt.coroutine_str("walk", &[Variable::f64(3.0)], &module).unwrap();
+    for i in 1..4 {
+        match co.resume() {
+            Ok(Some(Variable::F64(x, _))) => assert_eq!(x, i as f64),
+            x => panic!("{:?}", x)
+        }
+    }
+    match co.run() { Ok(Status::Done(None)) => {}, x => panic!("{:?}", x) }
+    assert!(co.is_done());
+    let err = rt.call_str("walk", &[Variable::f64(1.0)], &module).unwrap_err();
+    assert!(err.contains("`yield` can only be used inside a coroutine"));
+    // Closures can not be suspended.
+    let co = rt.coroutine_str("closure", &[], &module).unwrap();
+    assert!(co.resume().unwrap_err().contains("`yield` can not suspend here"));
+}
+
+#[test]
+fn suspend() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static FRAME: AtomicUsize = AtomicUsize::new(0);
+
+    fn frame(rt: &mut Runtime) -> Result<(), String> {
+        let n: f64 = try!(rt.pop());
+        FRAME.store(n as usize, Ordering::SeqCst);
+        rt.suspend();
+        Ok(())
+    }
+
+    let mut module = Module::new();
+    module.add(Arc::new("frame".into()), frame, Dfn {
+        lts: vec![Lt::Default],
+        tys: vec![Type::F64],
+        ret: Type::Void,
+        defaults: vec![]
+    });
+    load("source/runtime/suspend/main.dyon", &mut module).unwrap();
+    load("source/runtime/suspend/closure.dyon", &mut module).unwrap();
+    let module = Arc::new(module);
+
+    let mut rt = Runtime::new();
+    match rt.run(&module) { Ok(Status::Suspended) => {}, x => panic!("{:?}", x) }
+    assert_eq!(FRAME.load(Ordering::SeqCst), 1);
+    for i in 2..4 {
+        match rt.resume(&module) { Ok(Status::Suspended) => {}, x => panic!("{:?}", x) }
+        assert_eq!(FRAME.load(Ordering::SeqCst), i);
+    }
+    match rt.resume(&module) { Ok(Status::Done(None)) => {}, x => panic!("{:?}", x) }
+    assert!(!rt.is_suspended());
+    assert!(rt.stack.is_empty());
+    assert!(rt.resume(&module).unwrap_err().contains("There is no suspended call"));
+
+    // Closures can not be suspended, so the runtime suspends after the closure returns.
+    match rt.call_str("closure", &[], &module) { Ok(Status::Suspended) => {}, x => panic!("{:?}", x) }
+    assert_eq!(FRAME.load(Ordering::SeqCst), 4);
+    // Calls made while suspended run until they return.
+    rt.call_str("check", &[Variable::f64(2.0)], &module).unwrap();
+    match rt.resume(&module) { Ok(Status::Done(None)) => {}, x => panic!("{:?}", x) }
+
+    // The host can suspend a loop at a safe point.
+    let module = load_module(&["source/runtime/suspend/busy.dyon"]);
+    let handle = rt.interrupt_handle();
+    handle.suspend();
+    match rt.run(&module) { Ok(Status::Suspended) => {}, x => panic!("{:?}", x) }
+    for _ in 0..3 {
+        handle.suspend();
+        match rt.resume(&module) { Ok(Status::Suspended) => {}, x => panic!("{:?}", x) }
+    }
+    assert!(rt.is_suspended());
+}
+
+#[test]
+fn match_warnings() {
+    let mut module = Module::new();
+    load("source/runtime/match/main.dyon", &mut module).unwrap();
+    assert_eq!(module.warnings.len(), 1);
+    assert!(module.warnings[0].contains("Missing arm for `some(_)`"));
+    let module = Arc::new(module);
+    let mut rt = Runtime::new();
+    rt.run(&module).unwrap();
+    // The value that is not covered fails at runtime.
+    let value = Variable::Option(Some(Box::new(Variable::f64(-1.0))));
+    let err = rt.call_str("first", &[value], &module).unwrap_err();
+    assert!(err.contains("No arm matched the value `option`"));
+}
+
+#[test]
+fn shapes() {
+    // Objects can have more fields than the shape.
+    let module = load_module(&["source/runtime/shapes/open.dyon"]);
+    Runtime::new().run(&module).unwrap();
+    // Shapes are checked when loading, not at runtime.
+    let module = load_module(&["source/runtime/shapes/dynamic.dyon"]);
+    let err = Runtime::new().run(&module).unwrap_err();
+    assert!(err.contains("Object has no key `name`"));
+}
+
+#[test]
+fn rust_types() {
+    use dyon::embed::{PushVariable, RustType};
+
+    #[derive(Clone)]
+    struct Image(f64);
+    impl RustType for Image { fn name() -> &'static str { "Image" } }
+
+    #[derive(Clone)]
+    struct Sound;
+    impl RustType for Sound { fn name() -> &'static str { "Sound" } }
+
+    fn new_image(rt: &mut Runtime) -> Result<(), String> {
+        rt.push(Image(2.0));
+        Ok(())
+    }
+    fn new_sound(rt: &mut Runtime) -> Result<(), String> {
+        rt.push(Sound);
+        Ok(())
+    }
+    fn width(rt: &mut Runtime) -> Result<(), String> {
+        let img: Image = try!(rt.pop());
+        rt.push(img.0);
+        Ok(())
+    }
+
+    fn module(source: &str) -> Result<Module, String> {
+        let mut module = Module::new();
+        module.add(Arc::new("new_image".into()), new_image, Dfn {
+            lts: vec![], tys: vec![], ret: Type::rust::<Image>(),
+            defaults: vec![]
+        });
+        module.add(Arc::new("new_sound".into()), new_sound, Dfn {
+            lts: vec![], tys: vec![], ret: Type::rust::<Sound>(),
+            defaults: vec![]
+        });
+        module.add(Arc::new("width".into()), width, Dfn {
+            lts: vec![Lt::Default], tys: vec![Type::rust::<Image>()], ret: Type::F64,
+            defaults: vec![]
+        });
+        try!(load(&format!("source/runtime/rust_types/{}.dyon", source), &mut module));
+        Ok(module)
+    }
+
+    let mut rt = Runtime::new();
+    rt.run(&Arc::new(module("main").unwrap())).unwrap();
+    let img = Image(1.0).push_var();
+    assert_eq!(&**rt.typeof_var(&img), "Image");
+
+    let err = module("wrong").err().unwrap();
+    assert!(err.contains("Expected `Image`, found `Sound`"));
+
+    // Values of unknown type are checked at runtime.
+    let err = rt.run(&Arc::new(module("wrong_any").unwrap())).unwrap_err();
+    assert!(err.contains("Expected `Image`, found `Sound`"));
+}
+
+#[test]
+fn i64() {
+    fn hash(rt: &mut Runtime) -> Result<(), String> {
+        rt.push(u64::max_value() - 1);
+        Ok(())
+    }
+    fn check_hash(rt: &mut Runtime) -> Result<(), String> {
+        let h: u64 = try!(rt.pop());
+        if h == u64::max_value() - 1 { Ok(()) } else { Err("Wrong hash".into()) }
+    }
+
+    let mut module = Module::new();
+    module.add(Arc::new("hash".into()), hash, Dfn {
+        lts: vec![], tys: vec![], ret: Type::I64,
+        defaults: vec![]
+    });
+    module.add(Arc::new("check_hash".into()), check_hash, Dfn {
+        lts: vec![Lt::Default], tys: vec![Type::I64], ret: Type::Void,
+        defaults: vec![]
+    });
+    load("source/runtime/i64/embed.dyon", &mut module).unwrap();
+    Runtime::new().run(&Arc::new(module)).unwrap();
+
+    let mut module = Module::new();
+    module.add(Arc::new("check_hash".into()), check_hash, Dfn {
+        lts: vec![Lt::Default], tys: vec![Type::I64], ret: Type::Void,
+        defaults: vec![]
+    });
+    let err = load("source/runtime/i64/embed_err.dyon", &mut module).unwrap_err();
+    assert!(err.contains("Expected `i64`, found `f64`"));
+
+    for source in &["index", "sums"] {
+        let module = load_module(&[&format!("source/runtime/i64/{}.dyon", source)]);
+        let mut rt = Runtime::new();
+        rt.workers = 2;
+        rt.run(&module).unwrap_or_else(|err| panic!("In `{}`:\n{}", source, err));
+    }
+    let module = load_module(&["source/runtime/i64/index_err.dyon"]);
+    let err = Runtime::new().run(&module).unwrap_err();
+    assert!(err.contains("Out of bounds `-1`"));
+
+    let err = load("source/runtime/i64/overflow.dyon", &mut Module::new()).unwrap_err();
+    assert!(err.contains("Integer literal `9223372036854775808i64` is out of range, \
+        expected `-9223372036854775808i64` to `9223372036854775807i64`"));
+    let err = load("source/runtime/i64/sum_type_err.dyon", &mut Module::new()).unwrap_err();
+    assert!(err.contains("Binary operator can not be used with `i64` and `f64`"));
+}
+
+#[test]
+fn enum_warnings() {
+    let mut module = Module::new();
+    load("source/runtime/enums/shape.dyon", &mut module).unwrap();
+    assert_eq!(module.enums.len(), 1);
+    // Enums declared in other sources are known to the type checker.
+    load("source/runtime/enums/area.dyon", &mut module).unwrap();
+    assert_eq!(module.warnings.len(), 1);
+    assert!(module.warnings[0].contains("Missing arm for `Shape::Empty`"));
+    let err = load("source/runtime/enums/square.dyon", &mut module).unwrap_err();
+    assert!(err.contains("Enum `Shape` has no variant `Square`"));
+    let module = Arc::new(module);
+    let mut rt = Runtime::new();
+    rt.run(&module).unwrap();
+    // The variant that is not covered fails at runtime.
+    let err = rt.call_str("empty_area", &[], &module).unwrap_err();
+    assert!(err.contains("No arm matched the value `Shape`"));
+}
+
+#[test]
+fn globals() {
+    let mut module = Module::new();
+    load("source/runtime/globals/config.dyon", &mut module).unwrap();
+    assert_eq!(module.globals.len(), 2);
+    // Constants are evaluated when loaded.
+    match module.globals[0].value {
+        Some(Variable::F64(val, _)) => assert_eq!(val, 16.0),
+        _ => panic!("Expected value of `SIZE`")
+    }
+    assert!(module.globals[1].value.is_none());
+    // Globals declared in other sources are known to the type checker.
+    load("source/runtime/globals/main.dyon", &mut module).unwrap();
+    let err = load("source/runtime/globals/const_err.dyon", &mut module).unwrap_err();
+    assert!(err.contains("Can not change constant `SIZE`"));
+    let err = load("source/runtime/globals/type_err.dyon", &mut module).unwrap_err();
+    assert!(err.contains("Expected `str`, found `f64`"));
+    let err = load("source/runtime/globals/const_fail.dyon", &mut module).unwrap_err();
+    assert!(err.contains("Expected limit"));
+    let err = load("source/runtime/globals/const_global.dyon", &mut Module::new()).unwrap_err();
+    assert!(err.contains("Constant can not use global `frames`"));
+    let module = Arc::new(module);
+    let mut rt = Runtime::new();
+    rt.run(&module).unwrap();
+    rt.call_str("check_frames", &[Variable::f64(16.0)], &module).unwrap();
+    // The values are kept between calls from the host.
+    rt.call_str("main", &[], &module).unwrap();
+    rt.call_str("check_frames", &[Variable::f64(32.0)], &module).unwrap();
+    // Another runtime starts with the declared values.
+    Runtime::new().call_str("check_frames", &[Variable::f64(0.0)], &module).unwrap();
+
+    // The values are evaluated when running, using the fuel of the runtime.
+    let module = load_module(&["source/runtime/globals/forever.dyon"]);
+    let mut rt = Runtime::new();
+    rt.set_fuel(Some(1000));
+    let err = rt.run(&module).unwrap_err();
+    assert!(err.contains("Out of fuel"));
+}
+
+#[test]
+fn default_args() {
+    fn volume(rt: &mut Runtime) -> Result<(), String> {
+        let volume: f64 = try!(rt.pop());
+        let name: Arc<String> = try!(rt.pop());
+        rt.push(format!("{} {}", name, volume));
+        Ok(())
+    }
+
+    let mut module = Module::new();
+    module.add_with_defaults(Arc::new("play__name_volume".into()), volume, Dfn {
+        lts: vec![Lt::Default; 2],
+        tys: vec![Type::Text, Type::F64],
+        ret: Type::Text,
+        defaults: vec![]
+    }, vec![Variable::f64(0.5)]).unwrap();
+    let err = module.add_with_defaults(Arc::new("echo__text".into()), volume, Dfn {
+        lts: vec![Lt::Default],
+        tys: vec![Type::Any],
+        ret: Type::Any,
+        defaults: vec![]
+    }, vec![Variable::Array(Arc::new(vec![]))]).unwrap_err();
+    assert!(err.contains("Expected number, bool, string or vec4"));
+    load("source/runtime/default_args/main.dyon", &mut module).unwrap();
+    let err = load("source/runtime/default_args/not_trailing.dyon", &mut module).unwrap_err();
+    assert!(err.contains("Expected default value for argument `b`"));
+    // Only trailing arguments can be omitted.
+    let err = load("source/runtime/default_args/skip_first.dyon", &mut module).unwrap_err();
+    assert!(err.contains("Could not find function `play__volume`"));
+    // The words of the call must match whole arguments.
+    let err = load("source/runtime/default_args/prefix.dyon", &mut module).unwrap_err();
+    assert!(err.contains("Could not find function `fade__time`"));
+    let module = Arc::new(module);
+    let mut rt = Runtime::new();
+    rt.run(&module).unwrap();
+    // Calls from the host can omit arguments too.
+    rt.call_str("season__players_goals", &[Variable::f64(1.0)], &module).unwrap();
+}
+
+#[test]
+fn parallel_loops() {
+    let mut module = Module::new();
+    load("source/runtime/parallel/main.dyon", &mut module).unwrap();
+    let err = load("source/runtime/parallel/change.dyon", &mut module).unwrap_err();
+    assert!(err.contains("Can not change `n` in parallel loop"));
+    let err = load("source/runtime/parallel/push.dyon", &mut module).unwrap_err();
+    assert!(err.contains("Can not change `a` in parallel loop"));
+    let err = load("source/runtime/parallel/break.dyon", &mut module).unwrap_err();
+    assert!(err.contains("Can not break out of parallel loop"));
+    let module = Arc::new(module);
+    let mut rt = Runtime::new();
+    rt.workers = 3;
+    rt.run(&module).unwrap();
+}
+
+#[test]
+fn shared() {
+    let module = load_module(&["source/runtime/shared/update.dyon"]);
+    let err = Runtime::new().run(&module).unwrap_err();
+    assert!(err.contains("Can not use shared value while updating it"));
+    // The shared value can be used after the failed update.
+    let sh = dyon::shared::Shared::new(Variable::f64(1.0));
+    assert!(sh.update(|_| Err("failed".into())).is_err());
+    assert_eq!(sh.read().unwrap(), Variable::f64(1.0));
+    // A panic while holding the lock is reported as an error.
+    let sh2 = sh.clone();
+    let _ = std::thread::spawn(move || sh2.update(|_| panic!("Update failed"))).join();
+    assert!(sh.read().unwrap_err().contains("panicked"));
+
+    // Tasks waiting for the lock are not run by the thread holding it.
+    let module = load_module(&["source/runtime/shared/pool.dyon"]);
+    let mut rt = Runtime::new();
+    rt.pool = Some(dyon::pool::ThreadPool::new(1));
+    rt.run(&module).unwrap();
+}
+
+#[test]
+fn thread_pool() {
+    use std::sync::atomic::AtomicBool;
+    use std::thread;
+    use std::time::Duration;
+    use dyon::pool::{Task, ThreadPool};
+
+    let module = load_module(&["source/runtime/thread_pool/main.dyon"]);
+    let mut rt = Runtime::new();
+    rt.pool = Some(ThreadPool::new(2));
+    rt.run(&module).unwrap();
+
+    // A task that is cancelled before it starts never runs.
+    let cancelled = Arc::new(AtomicBool::new(false));
+    let task = Task::new(Box::new(|| panic!("Should not run")), cancelled);
+    task.cancel();
+    assert!(task.join().unwrap_err().contains("cancelled"));
+    assert!(task.join().unwrap_err().contains("already been joined"));
+
+    // A task blocking on a queued task does not deadlock a pool with one worker.
+    let module = load_module(&["source/runtime/thread_pool/blocking.dyon"]);
+    let mut rt = Runtime::new();
+    rt.pool = Some(ThreadPool::new(1));
+    let thread: Variable = rt.call_str_ret("start", &[], &module).unwrap();
+    let task = match thread {
+        Variable::Thread(th) => th.task.unwrap(),
+        _ => panic!("Expected thread")
+    };
+    // Give the worker time to start the task, instead of running it when joining.
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(task.join().unwrap(), Variable::f64(7.0));
+}
+
+#[test]
+fn call_str_ret() {
+    fn double(rt: &mut Runtime) -> Result<(), String> {
+        let x: f64 = try!(rt.pop());
+        rt.push(x * 2.0);
+        Ok(())
+    }
+
+    let mut module = Module::new();
+    module.add(Arc::new("double".into()), double, Dfn {
+        lts: vec![Lt::Default],
+        tys: vec![Type::F64],
+        ret: Type::F64,
+        defaults: vec![]
+    });
+    load("source/runtime/call_str_ret/main.dyon", &mut module).unwrap();
+    let module = Arc::new(module);
+    let mut rt = Runtime::new();
+    let x: f64 = rt.call_str_ret("add", &[Variable::f64(1.0), Variable::f64(2.0)], &module)
+        .unwrap();
+    assert_eq!(x, 3.0);
+    let name = Variable::Text(Arc::new("Bob".into()));
+    let text: String = rt.call_str_ret("greet__name", &[name.clone()], &module).unwrap();
+    assert_eq!(text, "hi Bob");
+    let text: String = rt.call_str_ret("greet__name_greeting",
+        &[name.clone(), Variable::Text(Arc::new("hello".into()))], &module).unwrap();
+    assert_eq!(text, "hello Bob");
+    let pos: Variable = rt.call_str_ret("pos", &[], &module).unwrap();
+    let x: f64 = rt.call_str_ret("x", &[Variable::Vec4([4.0, 0.0, 0.0, 0.0])], &module)
+        .unwrap();
+    assert_eq!(x, 4.0);
+    let n: usize = rt.call_str_ret("len", &[Variable::Array(Arc::new(vec![pos]))], &module)
+        .unwrap();
+    assert_eq!(n, 1);
+    let x: f64 = rt.call_str_ret("double", &[Variable::f64(4.0)], &module).unwrap();
+    assert_eq!(x, 8.0);
+    // Globals are kept between calls from the host.
+    let counter = load_module(&["source/runtime/call_str_ret/counter.dyon"]);
+    let n: f64 = rt.call_str_ret("next", &[], &counter).unwrap();
+    assert_eq!(n, 1.0);
+    let n: f64 = rt.call_str_ret("next", &[], &counter).unwrap();
+    assert_eq!(n, 2.0);
+
+    let err = rt.call_str_ret::<f64>("add", &[Variable::f64(1.0)], &module).unwrap_err();
+    assert!(err.contains("Expected 2 arguments to `add` but found 1"));
+    let err = rt.call_str_ret::<f64>("add", &[Variable::f64(1.0), name.clone()], &module)
+        .unwrap_err();
+    assert!(err.contains("Type mismatch in argument 2 to `add`"));
+    let err = rt.call_str_ret::<f64>("double", &[name.clone()], &module).unwrap_err();
+    assert!(err.contains("Expected `f64`, found `string`"));
+    let err = rt.call_str_ret::<f64>("add", &[Variable::f64(1.0), Variable::f64(2.0), name],
+        &module).unwrap_err();
+    assert!(err.contains("Expected 2 arguments"));
+    let err = rt.call_str_ret::<String>("greet__name_greeting", &[], &module).unwrap_err();
+    assert!(err.contains("Expected 1..=2 arguments to `greet__name_greeting` but found 0"));
+    let err = rt.call_str_ret::<f64>("missing", &[], &module).unwrap_err();
+    assert!(err.contains("Could not find function `missing`"));
+    // Errors in intrinsics called from the host have no source to point to.
+    let err = rt.call_str_ret::<f64>("unwrap", &[Variable::Option(None)], &module)
+        .unwrap_err();
+    assert!(err.contains("Expected `some(_)`"));
+}